@@ -1,7 +1,27 @@
+pub(crate) mod add;
 pub(crate) mod cat_file;
+pub(crate) mod checkout;
+pub(crate) mod cherry_pick;
 pub(crate) mod clone;
 pub(crate) mod commit_tree;
+pub(crate) mod daemon;
+pub(crate) mod diff;
+pub(crate) mod diff_tree;
+pub(crate) mod fetch;
 pub(crate) mod hash_object;
 pub(crate) mod init;
+pub(crate) mod ls_remote;
 pub(crate) mod ls_tree;
+pub(crate) mod merge;
+pub(crate) mod merge_base;
+pub(crate) mod merge_file;
+pub(crate) mod push;
+pub(crate) mod rebase;
+pub(crate) mod receive_pack;
+pub(crate) mod restore;
+pub(crate) mod revert;
+pub(crate) mod serve;
+pub(crate) mod show;
+pub(crate) mod stash;
+pub(crate) mod upload_pack;
 pub(crate) mod write_tree;
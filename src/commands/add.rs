@@ -0,0 +1,70 @@
+use crate::diff::worktree;
+use crate::index::{Index, IndexEntry};
+use crate::objects;
+use crate::pathspec;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// `add <pathspec>...`: stages the current content of matching files, including untracked
+/// ones, and stages the removal of tracked files that were deleted. Adding a conflicted
+/// file marks it resolved.
+pub(crate) fn add_invoke(pathspecs: &[String]) -> anyhow::Result<()> {
+    anyhow::ensure!(!pathspecs.is_empty(), "Nothing specified, nothing added.");
+
+    let mut index = Index::read().context("Reading the index")?;
+    let mut paths: BTreeSet<String> = index.entries.iter().map(|e| e.path.clone()).collect();
+    list_files(Path::new(""), &mut paths)?;
+    let matched: Vec<String> = paths
+        .into_iter()
+        .filter(|path| pathspec::matches_any(path, pathspecs))
+        .collect();
+    for spec in pathspecs {
+        anyhow::ensure!(
+            matched.iter().any(|path| pathspec::matches(path, spec)),
+            "pathspec '{spec}' did not match any files"
+        );
+    }
+
+    for path in &matched {
+        let Ok(meta) = fs::symlink_metadata(path) else {
+            index.remove(path);
+            continue;
+        };
+        if meta.is_dir() {
+            // A submodule, or a directory that replaced a tracked file
+            continue;
+        }
+        let data = worktree::read_worktree_file(path)?;
+        let hash = objects::write_blob(&data).with_context(|| format!("Adding {path}"))?;
+        index.add(IndexEntry::from_worktree(
+            path,
+            worktree::worktree_mode(&meta),
+            hash,
+        )?);
+    }
+    index.write().context("Writing the index")
+}
+
+/// Collects every file below `dir`, skipping `.git`.
+pub(crate) fn list_files(dir: &Path, out: &mut BTreeSet<String>) -> anyhow::Result<()> {
+    let read_from = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    for entry in fs::read_dir(read_from).with_context(|| format!("Listing {}", dir.display()))? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(&path, out)?;
+        } else {
+            out.insert(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(())
+}
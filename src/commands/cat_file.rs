@@ -1,23 +1,16 @@
 use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
 
 use crate::objects::{Kind, Object};
 
 pub(crate) fn cat_file_invoke(object_hash: &str, pretty_print: bool) -> Result<()> {
     anyhow::ensure!(pretty_print, "-p must  be added");
-    let mut obj = Object::read(object_hash).context("parsing blob file")?;
+    let obj = Object::read(object_hash).context("parsing blob file")?;
     match obj.kind {
         Kind::Blob => {
             let stdout = std::io::stdout();
             let mut stdout = stdout.lock();
-            let n = std::io::copy(&mut obj.reader, &mut stdout)
-                .context("write .git/objects to stdout")?;
-
-            anyhow::ensure!(
-                n == obj.expected_size,
-                ".git/objects was not the expected size: expected {}, actual {}",
-                obj.expected_size,
-                n
-            )
+            write_blob(obj, &mut stdout)?;
         }
         _ => {
             anyhow::bail!("Dont know how to print {}", obj.kind)
@@ -25,3 +18,16 @@ pub(crate) fn cat_file_invoke(object_hash: &str, pretty_print: bool) -> Result<(
     }
     Ok(())
 }
+
+/// Copies the content of a blob to `out` unchanged.
+pub(crate) fn write_blob(mut obj: Object<impl BufRead>, out: &mut impl Write) -> Result<()> {
+    let n = std::io::copy(&mut obj.reader, out).context("write .git/objects to stdout")?;
+
+    anyhow::ensure!(
+        n == obj.expected_size,
+        ".git/objects was not the expected size: expected {}, actual {}",
+        obj.expected_size,
+        n
+    );
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use crate::commands::restore::{self, RestoreOptions};
+
+/// `checkout [<tree-ish>] -- <paths>`: copies files from the index, or from `tree_ish`
+/// into both the index and the worktree. Files missing from `tree_ish` are kept.
+pub(crate) fn checkout_invoke(tree_ish: Option<&str>, pathspecs: &[String]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !pathspecs.is_empty(),
+        "only `checkout [<tree-ish>] -- <paths>` is supported; switching branches is not"
+    );
+
+    restore::restore_paths(
+        &RestoreOptions {
+            source: tree_ish,
+            staged: tree_ish.is_some(),
+            worktree: true,
+            overlay: true,
+        },
+        pathspecs,
+    )
+}
@@ -0,0 +1,22 @@
+use crate::sequencer::{self, Action, ReplayOptions};
+
+/// `cherry-pick [-n] [-x] [-m <parent>] <commit>...`: applies the changes each commit
+/// introduced on top of HEAD, committing them with their original author and message.
+pub(crate) fn cherry_pick_invoke(commits: &[String], opts: &ReplayOptions) -> anyhow::Result<()> {
+    sequencer::replay(Action::Pick, commits, opts)
+}
+
+/// `cherry-pick --continue`: commits the resolved conflicts and picks the remaining commits.
+pub(crate) fn cherry_pick_continue() -> anyhow::Result<()> {
+    sequencer::continue_replay(Action::Pick)
+}
+
+/// `cherry-pick --skip`: drops the commit that conflicted and picks the remaining ones.
+pub(crate) fn cherry_pick_skip() -> anyhow::Result<()> {
+    sequencer::skip_replay(Action::Pick)
+}
+
+/// `cherry-pick --abort`: goes back to the commit HEAD was on before picking started.
+pub(crate) fn cherry_pick_abort() -> anyhow::Result<()> {
+    sequencer::abort_replay()
+}
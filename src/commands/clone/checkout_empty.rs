@@ -1,6 +1,6 @@
-use crate::objects::Object;
+use crate::index::{self, Index, IndexEntry};
+use crate::objects::{self, Object};
 use anyhow::Context;
-use std::ffi::CStr;
 use std::fs::{self, File};
 use std::io::{BufRead, Read};
 use std::os::unix::fs::{symlink, PermissionsExt};
@@ -27,56 +27,50 @@ pub(crate) fn checkout_empty_invoke(commit_hash: &str) -> anyhow::Result<()> {
     recursively_populate_dir_structure(tree_hash, Path::new(""))
         .context("Calling the recursive function to establish the dir structure")?;
 
+    // Record what was checked out so that later commands can tell what is tracked.
+    // flatten_tree already yields paths in the byte order the index requires.
+    let mut index = Index::default();
+    for (path, entry) in objects::flatten_tree(tree_hash).context("Listing checked out files")? {
+        let mode = index::parse_mode(&entry.mode)?;
+        index
+            .entries
+            .push(IndexEntry::from_worktree(&path, mode, entry.hash)?);
+    }
+    index
+        .write()
+        .context("Writing the index for the checkout")?;
+
     Ok(())
 }
 
 fn recursively_populate_dir_structure(tree_hash: &str, parent_path: &Path) -> anyhow::Result<()> {
-    let mut tree_obj = Object::read(tree_hash).context("Reading tree hash")?;
-    let mut buf: Vec<u8> = Vec::new();
-    let mut hashbuf = [0; 20];
-    loop {
-        buf.clear();
-        let n = tree_obj
-            .reader
-            .read_until(0, &mut buf)
-            .context("Reading tree header from file")?;
-
-        if n == 0 {
-            break;
-        }
-        tree_obj
-            .reader
-            .read_exact(&mut hashbuf[..])
-            .context("Read tree entry hash")?;
-
-        let mode_and_name = CStr::from_bytes_with_nul(&buf).context("Invalid tree entry")?;
-        let mut bits = mode_and_name.to_bytes().splitn(2, |b| *b == b' ');
-        let mode = bits.next().expect("Split needs to yield once");
-        let name = bits
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Tree entry has no filename"))?;
+    for entry in objects::read_tree(tree_hash).context("Reading tree hash")? {
+        let hash = hex::encode(entry.hash);
 
-        let mode = std::str::from_utf8(mode).context("Converting the mode into a string")?;
-        let name = std::str::from_utf8(name).context("Converting the object name to string")?;
-        let hash = hex::encode(&hashbuf);
+        // println!("Processing tree entry: mode={}, name={}, hash={}", entry.mode, entry.name, hash);
 
-        // println!("Processing tree entry: mode={}, name={}, hash={}", mode, name, hash);
-
-        match mode {
+        match entry.mode.as_str() {
             "40000" => {
-                let path = parent_path.join(name);
+                let path = parent_path.join(&entry.name);
 
                 fs::create_dir_all(&path)
                     .context(format!("Creating directory {}", path.display()))?;
                 recursively_populate_dir_structure(&hash, &path).context("Running for sub-tree")?;
             }
-            _ => handle_file_creation(mode, name, &hash, parent_path)?,
+            mode => {
+                handle_file_creation(mode, &entry.name, &hash, parent_path)?;
+                let filepath = parent_path.join(&entry.name);
+                let abs_path = filepath.canonicalize().unwrap_or(filepath.clone());
+                println!("Created: {}", abs_path.display());
+            }
         }
     }
     Ok(())
 }
 
-fn handle_file_creation(
+/// Writes the blob `blob_hash` to `path/filename` as a regular file, executable or symlink
+/// according to `mode`, replacing whatever file or symlink is already there.
+pub(crate) fn handle_file_creation(
     mode: &str,
     filename: &str,
     blob_hash: &str,
@@ -92,6 +86,12 @@ fn handle_file_creation(
         ))?;
     }
 
+    // A stale symlink would otherwise be followed by File::create, or make symlink() fail
+    if fs::symlink_metadata(&filepath).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(&filepath)
+            .with_context(|| format!("Removing old {}", filepath.display()))?;
+    }
+
     match mode {
         "100755" | "100644" => {
             let mut file =
@@ -126,8 +126,6 @@ fn handle_file_creation(
         }
         _ => anyhow::bail!("Invalid mode for {}: {}", filepath.display(), mode),
     }
-    let abs_path = filepath.canonicalize().unwrap_or(filepath.clone());
-    println!("Created: {}", abs_path.display());
 
     Ok(())
 }
@@ -1,43 +1,327 @@
 use crate::commands;
 use crate::commands::clone::checkout_empty;
-use crate::commands::clone::handle_delta;
-use crate::commands::clone::unpack_objects;
+use crate::config;
+use crate::diff;
+use crate::ident;
+use crate::objects;
+use crate::promisor;
+use crate::protocol::local;
+use crate::protocol::{Deepen, FetchRequest, RemoteRef, Transport};
+use crate::refs;
+use crate::refspec::{self, Refspec};
 use anyhow::Context;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-pub(crate) fn clone_invoke(url: &str, dir_path: &Path) -> anyhow::Result<()> {
+const CONFIG: &str = ".git/config";
+
+#[derive(Debug, Default)]
+pub(crate) struct CloneOptions {
+    /// The branch or tag to check out instead of the one the remote's HEAD points at.
+    pub(crate) branch: Option<String>,
+    /// Only fetch, and later track, that branch.
+    pub(crate) single_branch: bool,
+    pub(crate) no_checkout: bool,
+    /// Make the directory itself the repository, without a worktree.
+    pub(crate) bare: bool,
+    /// Copy every ref as it is, and keep doing so on fetch. Implies `bare`.
+    pub(crate) mirror: bool,
+    /// Only fetch this many commits of history. Implies `single_branch`.
+    pub(crate) depth: Option<u32>,
+    /// Only fetch the history after this date. Implies `single_branch`.
+    pub(crate) shallow_since: Option<String>,
+    /// Leave out the objects this filter spec picks, and fetch them when they are needed.
+    pub(crate) filter: Option<String>,
+    /// Copy the objects of a repository reached by `file://` URL as if it were a path.
+    pub(crate) local: bool,
+    /// Copy the objects of a local repository rather than hard-linking them.
+    pub(crate) no_hardlinks: bool,
+    /// Borrow the objects of a local repository through alternates instead of copying them.
+    pub(crate) shared: bool,
+}
+
+/// `clone [-b <branch>] [--single-branch] [--no-checkout] [--bare | --mirror]
+/// [--depth <n> | --shallow-since <date>] [--filter <spec>] [-l] [--no-hardlinks] [-s]
+/// <url> [<dir>]`: fetches the remote's branches and tags into a new repository set up to
+/// track it as `origin`, and checks out its default branch. A repository on this machine
+/// has its objects hard-linked, copied or shared rather than fetched.
+pub(crate) fn clone_invoke(
+    url: &str,
+    dir_path: Option<&Path>,
+    opts: &CloneOptions,
+) -> anyhow::Result<()> {
+    let bare = opts.bare || opts.mirror;
+    anyhow::ensure!(opts.depth != Some(0), "depth 0 is not a positive number");
+    if let Some(filter) = &opts.filter {
+        promisor::check_filter(filter)?;
+    }
+    let mut deepen = match (opts.depth, &opts.shallow_since) {
+        (Some(depth), _) => Some(Deepen::Depth(depth)),
+        (None, Some(date)) => Some(Deepen::Since(ident::parse_timestamp(date)?)),
+        (None, None) => None,
+    };
+    let mut filter = opts.filter.clone();
+    let source = url;
+    let mut url = url.to_string();
+    if let Some(path) = local::local_path(&url) {
+        // All of a local repository's objects are copied, so history can't be cut short
+        let ignored = [
+            ("--depth", opts.depth.is_some()),
+            ("--shallow-since", opts.shallow_since.is_some()),
+            ("--filter", opts.filter.is_some()),
+        ];
+        for (option, _) in ignored.iter().filter(|(_, given)| *given) {
+            eprintln!("warning: {option} is ignored in local clones");
+        }
+        deepen = None;
+        filter = None;
+        // The path stays usable from inside the clone, and is what `origin` is set to
+        if !url.starts_with("file://") {
+            let path = fs::canonicalize(&path)
+                .ok()
+                .with_context(|| format!("repository '{url}' does not exist"))?;
+            url = path.to_string_lossy().into_owned();
+        }
+    }
+    let dir_path = match dir_path {
+        Some(dir_path) => dir_path.to_path_buf(),
+        None => default_dir(source, bare),
+    };
+    let not_empty = fs::read_dir(&dir_path).is_ok_and(|mut entries| entries.next().is_some());
+    anyhow::ensure!(
+        !not_empty,
+        "destination path '{}' already exists and is not an empty directory.",
+        dir_path.display()
+    );
 
     // Creating the target dir if it doesnt exist
-    if !dir_path.exists() {
-        std::fs::create_dir_all(dir_path)
+    let created = !dir_path.exists();
+    if created {
+        std::fs::create_dir_all(&dir_path)
             .with_context(|| format!("Failed to create directory: {}", dir_path.display()))?;
     }
+    let start = std::env::current_dir().context("Getting the current dir")?;
     // Shifting to the target directory
-    if dir_path != Path::new(".") {
-        std::env::set_current_dir(dir_path)
-            .context("Changing the current dir to the specified dir by the user ")?;
+    std::env::set_current_dir(&dir_path)
+        .context("Changing the current dir to the specified dir by the user ")?;
+
+    let result = clone_into(&url, bare, deepen, filter, opts);
+    if result.is_err() {
+        // Leave nothing half-cloned behind
+        std::env::set_current_dir(&start).context("Changing back to the starting dir")?;
+        let _ = fs::remove_dir_all(&dir_path);
+        if !created {
+            let _ = fs::create_dir(&dir_path);
+        }
     }
+    result
+}
 
-    // Calling git init
+/// Clones into the current directory, which is empty.
+fn clone_into(
+    url: &str,
+    bare: bool,
+    deepen: Option<Deepen>,
+    filter: Option<String>,
+    opts: &CloneOptions,
+) -> anyhow::Result<()> {
+    // Calling git init; a bare repository is set up in .git like any other and moved up
+    // a level once done
     commands::init::init_invoke()?;
+    let config = Path::new(CONFIG);
+    if filter.is_some() {
+        // Older versions of git can't read a repository that has missing objects
+        config::add_value(config, "core.repositoryformatversion", "1")?;
+    }
+    if bare {
+        config::add_value(config, "core.bare", "true")?;
+    }
+
+    // Shallow clones only fetch one branch, as with git
+    let single_branch = opts.single_branch || deepen.is_some();
+
+    let transport = Transport::connect(url).context("Connecting to the remote")?;
+    let remote_refs = transport.ls_refs(&[])?;
+    let head = remote_head(&remote_refs);
+
+    // What to check out: a branch, or a tag to detach HEAD at
+    let checkout = match &opts.branch {
+        Some(branch) => {
+            let names = [
+                format!("refs/heads/{branch}"),
+                format!("refs/tags/{branch}"),
+            ];
+            let found = names
+                .into_iter()
+                .find(|name| remote_refs.iter().any(|r| r.name == *name))
+                .with_context(|| format!("Remote branch {branch} not found in upstream origin"))?;
+            Some(found)
+        }
+        None => head.clone(),
+    };
+
+    // Which remote refs to copy, and where to
+    let (store, configured) = if opts.mirror {
+        let everything = "+refs/*:refs/*".to_string();
+        (vec![everything.clone()], Some(everything))
+    } else if single_branch {
+        let spec = match checkout.as_deref() {
+            Some(name) if bare || name.starts_with("refs/tags/") => format!("+{name}:{name}"),
+            Some(name) => format!("+{name}:refs/remotes/origin/{}", refspec::short_name(name)),
+            None => "+refs/heads/*:refs/remotes/origin/*".to_string(),
+        };
+        (vec![spec.clone()], Some(spec).filter(|_| !bare))
+    } else if bare {
+        let heads = "+refs/heads/*:refs/heads/*".to_string();
+        (vec![heads, "+refs/tags/*:refs/tags/*".to_string()], None)
+    } else {
+        let heads = "+refs/heads/*:refs/remotes/origin/*".to_string();
+        (
+            vec![heads.clone(), "+refs/tags/*:refs/tags/*".to_string()],
+            Some(heads),
+        )
+    };
+
+    config::add_value(config, "remote.origin.url", url)?;
+    if let Some(configured) = &configured {
+        config::add_value(config, "remote.origin.fetch", configured)?;
+    }
+    if opts.mirror {
+        config::add_value(config, "remote.origin.mirror", "true")?;
+    }
+    if let Some(filter) = &filter {
+        config::add_value(config, "remote.origin.promisor", "true")?;
+        config::add_value(config, "remote.origin.partialclonefilter", filter)?;
+    }
+
+    let mut updates: Vec<(String, String)> = Vec::new();
+    for spec in &store {
+        let spec = Refspec::parse(spec)?;
+        for remote_ref in remote_refs.iter().filter(|r| r.name != "HEAD") {
+            if let Some(dst) = spec.dst_for(&remote_ref.name) {
+                updates.push((dst, remote_ref.hash.clone()));
+            }
+        }
+    }
+
+    let mut wants: Vec<String> = Vec::new();
+    for (_, hash) in &updates {
+        if !wants.contains(hash) {
+            wants.push(hash.clone());
+        }
+    }
+    if wants.is_empty() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        refs::attach_head(checkout.as_deref().unwrap_or("refs/heads/main"))?;
+        return finish(bare);
+    }
+
+    let prefetching = !bare && !opts.no_checkout && filter.is_some();
+    match &transport {
+        Transport::Smart(connection) => {
+            // Downloading the pack file from git straight into .git/objects/pack
+            let request = FetchRequest {
+                wants,
+                deepen,
+                filter,
+                ..Default::default()
+            };
+            commands::clone::dowload_pack::download_pack(connection, &request)
+                .context("Making request to get the binary pack file data ")?;
+        }
+        Transport::Local(repo) if opts.shared => repo.share_objects()?,
+        Transport::Local(repo) => {
+            // Like git, a `file://` URL is treated as a remote unless `--local` says not to
+            let hard_links = !opts.no_hardlinks && (opts.local || !url.starts_with("file://"));
+            repo.copy_objects(hard_links)
+                .context("Copying the objects of the local repository")?;
+        }
+    }
+
+    for (name, hash) in &updates {
+        refs::update_ref(name, hash)?;
+    }
+    let checkout = checkout.context("The remote HEAD points at nothing to check out")?;
+    if !bare {
+        if let Some(head) = head.filter(|head| head.starts_with("refs/heads/")) {
+            let tracking = format!("refs/remotes/origin/{}", refspec::short_name(&head));
+            if updates.iter().any(|(name, _)| *name == tracking) {
+                refs::write_symref("refs/remotes/origin/HEAD", &tracking)?;
+            }
+        }
+    }
 
-    // Getting the hash for the latest commit on main/master
-    let hash = commands::clone::ls_remote::ls_remote_invoke(url)
-        .context("Attempting to get the master/main branch hash from remote git ")?;
+    let hash = remote_refs
+        .iter()
+        .find(|r| r.name == checkout)
+        .map(|r| r.hash.clone())
+        .context("The branch to check out is gone")?;
+    let commit = refs::peel_to_commit(&hash)?;
+    if let Some(branch) = checkout.strip_prefix("refs/heads/") {
+        if !bare {
+            refs::update_ref(&checkout, &commit)?;
+            config::add_value(config, &format!("branch.{branch}.remote"), "origin")?;
+            config::add_value(config, &format!("branch.{branch}.merge"), &checkout)?;
+        }
+        refs::attach_head(&checkout)?;
+    } else {
+        refs::update_ref("HEAD", &commit)?;
+    }
 
-    // Downloading pack files from git
-    let pack = commands::clone::dowload_pack::download_pack(url, &hash)
-        .context("Making request to get the binary pack file data ")?;
+    if !bare && !opts.no_checkout {
+        if prefetching {
+            prefetch(&commit)?;
+        }
+        checkout_empty::checkout_empty_invoke(&commit)
+            .context("creating actual directory structure")?;
+    }
+    finish(bare)
+}
 
-    let deltas = unpack_objects::unpack_objects_invoke(&pack)
-        .context("Getting list of all delta objects0")?;
+/// The directory a repository is cloned into by default: the last part of its URL,
+/// without `.git` unless the clone is bare.
+fn default_dir(url: &str, bare: bool) -> PathBuf {
+    let url = url.trim_end_matches('/');
+    let name = url.rsplit(['/', ':']).next().unwrap_or(url);
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    match bare {
+        true => PathBuf::from(format!("{name}.git")),
+        false => PathBuf::from(name),
+    }
+}
 
-    if deltas.len() != 0 {
-        handle_delta::process_delta(deltas).context("Processing delta objects")?;
+/// The branch the remote's HEAD points at. Servers that don't say are taken to point at
+/// a branch HEAD has the same commit as, preferring `main` and `master`.
+fn remote_head(remote_refs: &[RemoteRef]) -> Option<String> {
+    let head = remote_refs.iter().find(|r| r.name == "HEAD")?;
+    if let Some(target) = &head.symref_target {
+        return Some(target.clone());
     }
+    let same = |name: &&RemoteRef| name.name.starts_with("refs/heads/") && name.hash == head.hash;
+    ["refs/heads/main", "refs/heads/master"]
+        .into_iter()
+        .find(|name| remote_refs.iter().filter(same).any(|r| r.name == *name))
+        .map(str::to_string)
+        .or_else(|| remote_refs.iter().find(same).map(|r| r.name.clone()))
+}
 
-    checkout_empty::checkout_empty_invoke(hash.as_str())
-        .context("creating actual directory structure")?;
+/// Fetches the files of `commit` that a partial clone left out in one go, rather than one
+/// at a time as the checkout reads them.
+fn prefetch(commit: &str) -> anyhow::Result<()> {
+    let tree = objects::read_commit(commit)?.tree;
+    promisor::prefetch(diff::tree_entries(&tree)?.into_values())
+        .context("Fetching the files to check out")
+}
 
-    Ok(())
+/// Moves a bare repository out of `.git` into the directory itself.
+fn finish(bare: bool) -> anyhow::Result<()> {
+    if !bare {
+        return Ok(());
+    }
+    for entry in fs::read_dir(".git").context("Listing .git")? {
+        let entry = entry.context("Listing .git")?;
+        fs::rename(entry.path(), entry.file_name())
+            .with_context(|| format!("Moving {}", entry.path().display()))?;
+    }
+    fs::remove_dir(".git").context("Removing .git")
 }
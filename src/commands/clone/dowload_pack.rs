@@ -1,32 +1,23 @@
+use crate::pack::indexer;
+use crate::promisor;
+use crate::protocol::{Connection, FetchRequest};
+use crate::shallow;
 use anyhow::Context;
-use reqwest::blocking::Client;
-pub(crate) fn download_pack(url: &str, hash: &str) -> anyhow::Result<Vec<u8>> {
-    let request_url = format!("{}.git/git-upload-pack", url);
-    let mut content: Vec<u8> = Vec::new();
-    let want_line = format!("want {}\n", hash);
 
-    content.extend(b"0032");
-    content.extend(want_line.as_bytes());
-
-    content.extend(b"0000"); // flush packet
-    content.extend(b"0009done\n"); // done packet
-
-    let client = Client::new();
-
-    let res = client
-        .post(&request_url)
-        .header("Content-Type", "application/x-git-upload-pack-request")
-        .body(content)
-        .send()
-        .context("Failed to send POST to git-upload-pack")?;
-
-    let res = res
-        .bytes()
-        .context("Attempting to read the bytes in the response")?;
-    println!("Response size: {}", res.len());
-    println!(
-        "First few bytes: {:?}",
-        &res[..std::cmp::min(20, res.len())]
-    );
-    Ok(res.to_vec())
+/// Fetches the objects `request` asks for into a new pack, streaming it to disk as it
+/// arrives, and returns the pack's checksum. Where a shallow request cut history off is
+/// recorded in `.git/shallow`, and a filtered pack is marked as a promisor pack.
+pub(crate) fn download_pack(
+    connection: &Connection,
+    request: &FetchRequest,
+) -> anyhow::Result<String> {
+    let response = connection
+        .fetch(request)
+        .context("Failed to fetch from git-upload-pack")?;
+    let checksum = indexer::index_pack(response.pack).context("Storing the pack")?;
+    shallow::update(&response.shallow, &response.unshallow)?;
+    if request.filter.is_some() {
+        promisor::mark_pack(&checksum)?;
+    }
+    Ok(checksum)
 }
@@ -1,6 +1,4 @@
 pub(crate) mod checkout_empty;
+#[allow(clippy::module_inception)]
 pub(crate) mod clone;
 pub(crate) mod dowload_pack;
-pub(crate) mod ls_remote;
-pub(crate) mod unpack_objects;
-pub(crate) mod handle_delta;
\ No newline at end of file
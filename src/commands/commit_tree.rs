@@ -59,7 +59,7 @@ fn get_timezone() -> String {
     let offset = now.offset().fix().local_minus_utc(); // in seconds
     let hours = offset / 3600;
     let minutes = (offset.abs() % 3600) / 60;
-    let timezone = format!("{:+03}{}", hours, format!("{:02}", minutes));
+    let timezone = format!("{:+03}{:02}", hours, minutes);
 
     timezone
 }
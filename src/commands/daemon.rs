@@ -0,0 +1,28 @@
+use crate::server::daemon;
+use anyhow::Context;
+use std::net::TcpListener;
+use std::path::Path;
+
+#[derive(Debug)]
+pub(crate) struct DaemonOptions {
+    /// The address to listen on.
+    pub(crate) listen: String,
+    pub(crate) port: u16,
+    /// Serve repositories that don't have a `git-daemon-export-ok` file too.
+    pub(crate) export_all: bool,
+}
+
+/// `daemon [--listen <address>] [--port <port>] [--export-all] <base-path>`: serves the
+/// repositories under `<base-path>` read-only over the `git://` protocol, so that
+/// `git://<address>:<port>/<path>` can be cloned and fetched from with protocol version
+/// 0, 1 or 2.
+pub(crate) fn daemon_invoke(base: &Path, opts: &DaemonOptions) -> anyhow::Result<()> {
+    anyhow::ensure!(base.is_dir(), "'{}' is not a directory", base.display());
+    let listener = TcpListener::bind((opts.listen.as_str(), opts.port))
+        .with_context(|| format!("Listening on {}:{}", opts.listen, opts.port))?;
+    let address = listener
+        .local_addr()
+        .context("Finding the address listened on")?;
+    eprintln!("Serving {} on git://{address}/", base.display());
+    daemon::run(listener, base.to_path_buf(), opts.export_all)
+}
@@ -0,0 +1,174 @@
+use crate::commands::diff_tree::{self, Format};
+pub(crate) use crate::diff::lines::Algorithm;
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{self, tree, worktree, Change};
+use crate::index::Index;
+use crate::pathspec;
+use crate::promisor;
+use crate::refs;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct OutputOptions {
+    pub(crate) stat: bool,
+    pub(crate) numstat: bool,
+    pub(crate) name_only: bool,
+    pub(crate) name_status: bool,
+}
+
+/// `diff [--cached] [<commit> [<commit>]] [-- <paths>]`
+///
+/// Without commits the worktree is compared with the index, with `--cached` the index is
+/// compared with a commit (HEAD by default), with one commit that commit is compared with
+/// the worktree and with two the commits are compared with each other.
+pub(crate) fn diff_invoke(
+    cached: bool,
+    args: &[String],
+    pathspecs: &[String],
+    output: OutputOptions,
+    opts: &PatchOptions,
+    renames: Option<&RenameOptions>,
+) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    // Leading arguments that name revisions are revisions, the rest are paths
+    let mut revs = Vec::new();
+    let mut pathspecs = pathspecs.to_vec();
+    for (i, arg) in args.iter().enumerate() {
+        if let Some((a, b)) = arg.split_once("..") {
+            anyhow::ensure!(
+                !b.starts_with('.'),
+                "symmetric differences (A...B) are not supported"
+            );
+            revs.push(if a.is_empty() { "HEAD" } else { a }.to_string());
+            revs.push(if b.is_empty() { "HEAD" } else { b }.to_string());
+        } else if refs::resolve(arg).is_ok() {
+            revs.push(arg.clone());
+        } else {
+            pathspecs.extend(args[i..].iter().cloned());
+            break;
+        }
+    }
+
+    let copies_harder = renames.is_some_and(|r| r.copies_harder);
+    let index = Index::read().context("Reading the index")?;
+    // The complete old side, kept when unchanged files are wanted as copy sources
+    let mut old_entries = BTreeMap::new();
+    let changes = match (cached, revs.as_slice()) {
+        (_, [old, new]) => {
+            anyhow::ensure!(!cached, "--cached takes at most one commit");
+            let old = refs::peel_to_tree(&refs::resolve(old)?)?;
+            let new = refs::peel_to_tree(&refs::resolve(new)?)?;
+            if copies_harder {
+                old_entries = diff::tree_entries(&old)?;
+            }
+            tree::diff_trees(Some(&old), Some(&new), true)?
+        }
+        (true, revs) => {
+            old_entries = tree_or_empty(revs.first().map_or("HEAD", String::as_str))?;
+            diff::diff_entries(&old_entries, &diff::index_entries(&index))
+        }
+        (false, [rev]) => {
+            old_entries = tree_or_empty(rev)?;
+            diff::diff_entries(&old_entries, &worktree::worktree_entries(&index)?)
+        }
+        (false, []) => {
+            let mut unmerged: Vec<&str> = index
+                .entries
+                .iter()
+                .filter(|e| e.stage != 0)
+                .map(|e| e.path.as_str())
+                .collect();
+            unmerged.dedup();
+            for path in &unmerged {
+                if pathspec::matches_any(path, &pathspecs) {
+                    writeln!(stdout, "* Unmerged path {path}")?;
+                }
+            }
+            let mut current = worktree::worktree_entries(&index)?;
+            current.retain(|path, _| !unmerged.contains(&path.as_str()));
+            old_entries = diff::index_entries(&index);
+            diff::diff_entries(&old_entries, &current)
+        }
+        _ => anyhow::bail!("diff takes at most two commits"),
+    };
+
+    let changes: Vec<_> = changes
+        .into_iter()
+        .filter(|c| pathspec::matches_any(c.path(), &pathspecs))
+        .collect();
+    // A partial clone fetches the blobs the output needs at once; worktree files are not
+    // objects anyone has
+    if renames.is_some() || !(output.name_only || output.name_status) {
+        let worktree_new = !cached && revs.len() < 2;
+        promisor::prefetch(changes.iter().flat_map(|c| {
+            let new = (!worktree_new).then_some((c.new.mode, c.new.hash));
+            [Some((c.old.mode, c.old.hash)), new].into_iter().flatten()
+        }))?;
+    }
+    let changes = match renames {
+        Some(opts) => {
+            let unchanged = if opts.copies_harder {
+                rename::unchanged_sides(&old_entries, &changes, &pathspecs)
+            } else {
+                Vec::new()
+            };
+            rename::detect(changes, unchanged, opts).context("Detecting renames")?
+        }
+        None => changes,
+    };
+
+    write_changes(&mut stdout, &changes, output, opts)
+}
+
+/// Prints changes as patches, or in the summary form selected by `output`.
+pub(crate) fn write_changes(
+    out: &mut impl Write,
+    changes: &[Change],
+    output: OutputOptions,
+    opts: &PatchOptions,
+) -> anyhow::Result<()> {
+    if output.name_only || output.name_status {
+        let format = if output.name_only {
+            Format::NameOnly
+        } else {
+            Format::NameStatus
+        };
+        for change in changes {
+            diff_tree::print_change(out, change, format)?;
+        }
+        return Ok(());
+    }
+
+    if output.stat || output.numstat {
+        let stats = changes
+            .iter()
+            .map(|c| patch::file_stat(c, opts))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if output.numstat {
+            patch::write_numstat(out, &stats)?;
+        }
+        if output.stat && !stats.is_empty() {
+            patch::write_stat(out, &stats)?;
+        }
+        return Ok(());
+    }
+
+    for change in changes {
+        patch::write_patch(out, change, opts)
+            .with_context(|| format!("Writing patch for {}", change.path()))?;
+    }
+    Ok(())
+}
+
+fn tree_or_empty(rev: &str) -> anyhow::Result<BTreeMap<String, (u32, [u8; 20])>> {
+    if rev == "HEAD" && refs::head_commit()?.is_none() {
+        return Ok(BTreeMap::new());
+    }
+    let tree = refs::peel_to_tree(&refs::resolve(rev)?)?;
+    diff::tree_entries(&tree)
+}
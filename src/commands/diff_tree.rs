@@ -0,0 +1,89 @@
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{self, tree, Change};
+use crate::objects;
+use crate::pathspec;
+use crate::refs;
+use crate::shallow;
+use anyhow::Context;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Raw,
+    NameOnly,
+    NameStatus,
+}
+
+pub(crate) fn diff_tree_invoke(
+    recursive: bool,
+    root: bool,
+    format: Format,
+    old: &str,
+    new: Option<&str>,
+    pathspecs: &[String],
+    renames: Option<&RenameOptions>,
+) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    let old_hash = refs::resolve(old).with_context(|| format!("Resolving {old}"))?;
+    let (old_tree, new_tree) = match new {
+        Some(new) => {
+            let new_hash = refs::resolve(new).with_context(|| format!("Resolving {new}"))?;
+            (
+                Some(refs::peel_to_tree(&old_hash)?),
+                refs::peel_to_tree(&new_hash)?,
+            )
+        }
+        None => {
+            // With a single commit, show what it changed relative to its first parent
+            let commit_hash = refs::peel_to_commit(&old_hash)?;
+            let commit = objects::read_commit(&commit_hash)?;
+            // Commits at the shallow boundary count as roots, their parents being absent
+            let shallow = shallow::is_shallow(&commit_hash)?;
+            let parent_tree = match commit.parents.first().filter(|_| !shallow) {
+                Some(parent) => Some(objects::read_commit(parent)?.tree),
+                None if root => None,
+                None => return Ok(()),
+            };
+            writeln!(stdout, "{commit_hash}")?;
+            (parent_tree, commit.tree)
+        }
+    };
+
+    let changes: Vec<_> = tree::diff_trees(old_tree.as_deref(), Some(&new_tree), recursive)
+        .context("Comparing trees")?
+        .into_iter()
+        .filter(|c| pathspec::matches_any(c.path(), pathspecs))
+        .collect();
+    let changes = match renames {
+        Some(opts) => {
+            let unchanged = match &old_tree {
+                Some(tree) if opts.copies_harder => {
+                    rename::unchanged_sides(&diff::tree_entries(tree)?, &changes, pathspecs)
+                }
+                _ => Vec::new(),
+            };
+            rename::detect(changes, unchanged, opts).context("Detecting renames")?
+        }
+        None => changes,
+    };
+
+    for change in &changes {
+        print_change(&mut stdout, change, format)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn print_change(
+    out: &mut impl Write,
+    change: &Change,
+    format: Format,
+) -> anyhow::Result<()> {
+    match format {
+        Format::Raw => writeln!(out, "{}", change.raw())?,
+        Format::NameOnly => writeln!(out, "{}", change.path())?,
+        Format::NameStatus => writeln!(out, "{}\t{}", change.status.code(), change.paths())?,
+    }
+    Ok(())
+}
@@ -0,0 +1,460 @@
+use crate::config::Config;
+use crate::ident;
+use crate::merge::base::{self, CommitCache};
+use crate::objects::{self, Kind, Object};
+use crate::pack::indexer;
+use crate::promisor;
+use crate::protocol::{Connection, Deepen, FetchRequest, RemoteRef, Transport, INFINITE_DEPTH};
+use crate::refs;
+use crate::refspec::{self, Refspec};
+use crate::shallow;
+use anyhow::Context;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// How many of our commits are offered to the server at most while looking for history
+/// we have in common.
+const MAX_HAVES: usize = 256;
+
+#[derive(Debug, Default)]
+pub(crate) struct FetchOptions {
+    /// Delete remote-tracking refs whose branch is gone from the remote.
+    pub(crate) prune: bool,
+    /// Update refs even when that is not a fast-forward.
+    pub(crate) force: bool,
+    /// Cut history off this many commits from each fetched tip.
+    pub(crate) depth: Option<u32>,
+    /// Fetch this many more commits past where history is cut off.
+    pub(crate) deepen: Option<u32>,
+    /// Cut history off at this date.
+    pub(crate) shallow_since: Option<String>,
+    /// Fetch all of the history a shallow repository is missing.
+    pub(crate) unshallow: bool,
+}
+
+/// A remote ref that was fetched, and what to do with it.
+struct Update {
+    remote: RemoteRef,
+    /// The local ref to point at it, if any.
+    dst: Option<String>,
+    force: bool,
+    /// Whether `FETCH_HEAD` marks it as the one for `merge` to use.
+    merge: bool,
+}
+
+/// `fetch [--prune] [--force] [--depth <n> | --deepen <n> | --shallow-since <date> |
+/// --unshallow] [<remote>] [<refspec>...]`: downloads the objects of the remote refs the
+/// refspecs pick that we don't have yet, updates the local refs they map to and records
+/// what was fetched in `FETCH_HEAD`. Returns false when an update was rejected.
+pub(crate) fn fetch_invoke(
+    remote: Option<&str>,
+    refspecs: &[String],
+    opts: &FetchOptions,
+) -> anyhow::Result<bool> {
+    let deepen = deepen_request(opts)?;
+    let config = Config::read()?;
+    let branch = refs::head_ref()?;
+    let branch_name = branch.as_deref().map(refspec::short_name);
+    let (name, url) = resolve_remote(&config, remote)?;
+
+    let from_command_line = !refspecs.is_empty();
+    let configured = config.get_all(&format!("remote.{name}.fetch"));
+    let sources: Vec<&str> = if from_command_line {
+        refspecs.iter().map(String::as_str).collect()
+    } else if configured.is_empty() {
+        // Nothing to map, so the remote's HEAD is fetched for merging
+        vec!["HEAD"]
+    } else {
+        configured
+    };
+    let specs = sources
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // With configured refspecs, only the upstream of the current branch is for merging
+    let merge_ref = branch_name
+        .filter(|b| config.get(&format!("branch.{b}.remote")) == Some(name.as_str()))
+        .and_then(|b| config.get(&format!("branch.{b}.merge")));
+
+    let transport = Transport::connect(&url).context("Connecting to the remote")?;
+    let prefixes: Vec<String> = specs.iter().flat_map(Refspec::prefixes).collect();
+    let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+    let remote_refs = transport.ls_refs(&prefixes)?;
+
+    let mut updates: Vec<Update> = Vec::new();
+    for spec in &specs {
+        let picked: Vec<&RemoteRef> = if spec.is_glob() {
+            remote_refs
+                .iter()
+                .filter(|r| spec.matches_src(&r.name))
+                .collect()
+        } else {
+            // The first of the names the source can stand for that the remote has
+            let found = refspec::expand_short_name(&spec.src)
+                .into_iter()
+                .find_map(|full| remote_refs.iter().find(|r| r.name == full));
+            match found {
+                Some(found) => vec![found],
+                // An empty remote has no HEAD, which is not worth failing over
+                None if !from_command_line && spec.src == "HEAD" => Vec::new(),
+                None => anyhow::bail!("couldn't find remote ref {}", spec.src),
+            }
+        };
+        for remote_ref in picked {
+            let dst = spec.dst_for(&remote_ref.name);
+            let duplicate = updates
+                .iter()
+                .any(|u| u.remote.name == remote_ref.name && u.dst == dst);
+            if duplicate {
+                continue;
+            }
+            let merge = if from_command_line || spec.src == "HEAD" && spec.dst.is_none() {
+                !spec.is_glob()
+            } else {
+                merge_ref == Some(remote_ref.name.as_str())
+            };
+            updates.push(Update {
+                remote: remote_ref.clone(),
+                dst,
+                force: spec.force || opts.force,
+                merge,
+            });
+        }
+    }
+
+    if let Some(branch) = &branch {
+        let moved = updates.iter().any(|u| {
+            u.dst.as_ref() == Some(branch)
+                && refs::resolve_ref(branch).ok().flatten().as_ref() != Some(&u.remote.hash)
+        });
+        anyhow::ensure!(
+            !moved,
+            "refusing to fetch into branch '{branch}' checked out at '{}'",
+            std::env::current_dir()?.display()
+        );
+    }
+
+    let mut wants: Vec<String> = Vec::new();
+    for update in &updates {
+        let hash = &update.remote.hash;
+        // What we have may still be missing history that deepening brings in
+        if !wants.contains(hash) && (deepen.is_some() || !objects::has_object(hash)?) {
+            wants.push(hash.clone());
+        }
+    }
+    if !wants.is_empty() {
+        match &transport {
+            Transport::Smart(connection) => fetch_pack(connection, &config, &name, wants, deepen)?,
+            Transport::Local(_) if deepen.is_some() => {
+                anyhow::bail!("shallow fetches from a local repository are not supported")
+            }
+            // Whatever the repository has is linked in, which is sure to cover the wants
+            Transport::Local(repo) => repo
+                .copy_objects(true)
+                .context("Copying the objects of the local repository")?,
+        }
+    }
+
+    // Shown without the parts that most URLs of the same repository differ in
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    let mut report = Report::new(url);
+    if opts.prune {
+        prune(&specs, &remote_refs, &mut report)?;
+    }
+    let mut ok = true;
+    let mut cache = CommitCache::default();
+    for update in &updates {
+        let remote_name = update.remote.name.as_str();
+        let from = refspec::short_name(remote_name);
+        let Some(dst) = &update.dst else {
+            let kind = if remote_name.starts_with("refs/tags/") {
+                "tag"
+            } else if remote_name.starts_with("refs/heads/") || remote_name == "HEAD" {
+                "branch"
+            } else {
+                ""
+            };
+            report.line('*', kind, from, "FETCH_HEAD", "", false);
+            continue;
+        };
+        let to = refspec::short_name(dst);
+        let new = &update.remote.hash;
+        let Some(old) = refs::resolve_ref(dst)? else {
+            let summary = if remote_name.starts_with("refs/tags/") {
+                "[new tag]"
+            } else if remote_name.starts_with("refs/heads/") {
+                "[new branch]"
+            } else {
+                "[new ref]"
+            };
+            refs::update_ref(dst, new)?;
+            report.line('*', summary, from, to, "", true);
+            continue;
+        };
+        if &old == new {
+            continue;
+        }
+
+        if dst.starts_with("refs/tags/") {
+            if update.force {
+                refs::update_ref(dst, new)?;
+                report.line('t', "[tag update]", from, to, "", true);
+            } else {
+                ok = false;
+                report.line(
+                    '!',
+                    "[rejected]",
+                    from,
+                    to,
+                    "  (would clobber existing tag)",
+                    true,
+                );
+            }
+            continue;
+        }
+        let fast_forward =
+            is_commit(&old)? && is_commit(new)? && base::is_ancestor(&mut cache, &old, new)?;
+        if fast_forward {
+            refs::update_ref(dst, new)?;
+            let range = format!("{}..{}", &old[..7], &new[..7]);
+            report.line(' ', &range, from, to, "", true);
+        } else if update.force {
+            refs::update_ref(dst, new)?;
+            let range = format!("{}...{}", &old[..7], &new[..7]);
+            report.line('+', &range, from, to, "  (forced update)", true);
+        } else {
+            ok = false;
+            report.line('!', "[rejected]", from, to, "  (non-fast-forward)", true);
+        }
+    }
+    report.print();
+
+    write_fetch_head(url, &updates)?;
+    Ok(ok)
+}
+
+/// The name and URL of `remote`, which defaults to the current branch's remote and then
+/// `origin`. A URL can stand in for a configured remote, and is then its own name.
+pub(crate) fn resolve_remote(
+    config: &Config,
+    remote: Option<&str>,
+) -> anyhow::Result<(String, String)> {
+    let name = match remote {
+        Some(name) => name.to_string(),
+        None => refs::head_ref()?
+            .and_then(|b| config.get(&format!("branch.{}.remote", refspec::short_name(&b))))
+            .unwrap_or("origin")
+            .to_string(),
+    };
+    let url = match config.get(&format!("remote.{name}.url")) {
+        Some(url) => url.to_string(),
+        None if name.contains(':') || name.contains('/') => name.clone(),
+        None => anyhow::bail!(
+            "'{name}' does not appear to be a git repository\n\
+             Could not read from remote repository."
+        ),
+    };
+    Ok((name, url))
+}
+
+/// How much history the options ask for, if they change it.
+fn deepen_request(opts: &FetchOptions) -> anyhow::Result<Option<Deepen>> {
+    if opts.unshallow {
+        anyhow::ensure!(
+            !shallow::commits()?.is_empty(),
+            "--unshallow on a complete repository does not make sense"
+        );
+        return Ok(Some(Deepen::Depth(INFINITE_DEPTH)));
+    }
+    if opts.depth == Some(0) || opts.deepen == Some(0) {
+        anyhow::bail!("depth 0 is not a positive number");
+    }
+    Ok(match (opts.depth, opts.deepen, &opts.shallow_since) {
+        (Some(depth), _, _) => Some(Deepen::Depth(depth)),
+        (None, Some(depth), _) => Some(Deepen::Relative(depth)),
+        (None, None, Some(date)) => Some(Deepen::Since(ident::parse_timestamp(date)?)),
+        (None, None, None) => None,
+    })
+}
+
+/// Fetches the objects of `wants` from `connection` into a new pack, leaving out what a
+/// partial clone from `remote` leaves out, and records where shallow history now ends.
+fn fetch_pack(
+    connection: &Connection,
+    config: &Config,
+    remote: &str,
+    wants: Vec<String>,
+    deepen: Option<Deepen>,
+) -> anyhow::Result<()> {
+    // A partial clone keeps leaving out what it left out when cloning
+    let promisor = config.get_bool(&format!("remote.{remote}.promisor")) == Some(true);
+    let filter = config
+        .get(&format!("remote.{remote}.partialclonefilter"))
+        .filter(|_| promisor);
+    let response = connection
+        .fetch(&FetchRequest {
+            wants,
+            haves: local_history()?,
+            shallow: shallow::commits()?,
+            deepen,
+            filter: filter.map(str::to_string),
+        })
+        .context("Fetching from git-upload-pack")?;
+    let checksum = indexer::index_pack(response.pack).context("Storing the pack")?;
+    shallow::update(&response.shallow, &response.unshallow)?;
+    if promisor {
+        promisor::mark_pack(&checksum)?;
+    }
+    Ok(())
+}
+
+/// Our commits, newest first, from the tips of every ref and HEAD.
+fn local_history() -> anyhow::Result<Vec<String>> {
+    let mut cache = CommitCache::default();
+    let mut queue: BinaryHeap<(i64, Reverse<usize>, String)> = BinaryHeap::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut counter = 0;
+    let tips = refs::list_refs("refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .chain(refs::head_commit()?);
+    for tip in tips {
+        // Tags may point at trees or blobs, which are not part of any history
+        let Ok(commit) = refs::peel_to_commit(&tip) else {
+            continue;
+        };
+        if seen.insert(commit.clone()) {
+            queue.push((cache.get(&commit)?.time(), Reverse(counter), commit));
+            counter += 1;
+        }
+    }
+
+    let mut haves = Vec::new();
+    while let Some((_, _, hash)) = queue.pop() {
+        if haves.len() == MAX_HAVES {
+            break;
+        }
+        for parent in cache.get(&hash)?.parents.clone() {
+            if seen.insert(parent.clone()) {
+                queue.push((cache.get(&parent)?.time(), Reverse(counter), parent));
+                counter += 1;
+            }
+        }
+        haves.push(hash);
+    }
+    Ok(haves)
+}
+
+fn is_commit(hash: &str) -> anyhow::Result<bool> {
+    Ok(Object::read(hash)?.kind == Kind::Commit)
+}
+
+/// Deletes the refs that glob refspecs map remote refs to when the remote ref is gone.
+fn prune(specs: &[Refspec], remote_refs: &[RemoteRef], report: &mut Report) -> anyhow::Result<()> {
+    for spec in specs.iter().filter(|s| s.is_glob()) {
+        let Some((prefix, _)) = spec.dst.as_deref().and_then(|dst| dst.split_once('*')) else {
+            continue;
+        };
+        for (name, _) in refs::list_refs(prefix)? {
+            let Some(src) = spec.src_for(&name) else {
+                continue;
+            };
+            if remote_refs.iter().any(|r| r.name == src) {
+                continue;
+            }
+            refs::delete_ref(&name)?;
+            report.line(
+                '-',
+                "[deleted]",
+                "(none)",
+                refspec::short_name(&name),
+                "",
+                false,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Lists `FETCH_HEAD` entries for merging first, as `merge FETCH_HEAD` uses the first one.
+fn write_fetch_head(url: &str, updates: &[Update]) -> anyhow::Result<()> {
+    let mut content = String::new();
+    for merge in [true, false] {
+        for update in updates.iter().filter(|u| u.merge == merge) {
+            let name = &update.remote.name;
+            let description = if name == "HEAD" {
+                url.to_string()
+            } else if let Some(branch) = name.strip_prefix("refs/heads/") {
+                format!("branch '{branch}' of {url}")
+            } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+                format!("tag '{tag}' of {url}")
+            } else {
+                format!("'{name}' of {url}")
+            };
+            let marker = if merge { "" } else { "not-for-merge" };
+            content.push_str(&format!(
+                "{}\t{marker}\t{description}\n",
+                update.remote.hash
+            ));
+        }
+    }
+    fs::write(Path::new(".git/FETCH_HEAD"), content).context("Writing .git/FETCH_HEAD")
+}
+
+/// The lines git prints about each ref, which line up once all of them are known.
+struct Report {
+    url: String,
+    /// Flag, summary, remote name, local name, reason.
+    lines: Vec<(char, String, String, String, &'static str)>,
+    width: usize,
+}
+
+impl Report {
+    fn new(url: &str) -> Report {
+        Report {
+            url: url.to_string(),
+            lines: Vec::new(),
+            width: 10,
+        }
+    }
+
+    /// Adds a line; `aligned` lines widen the remote name column, unless they are too long
+    /// to fit on the terminal anyway.
+    fn line(
+        &mut self,
+        flag: char,
+        summary: &str,
+        from: &str,
+        to: &str,
+        reason: &'static str,
+        aligned: bool,
+    ) {
+        if aligned && from != "HEAD" && 21 + from.len() + 4 + to.len() < 80 {
+            self.width = self.width.max(from.len());
+        }
+        self.lines.push((
+            flag,
+            summary.to_string(),
+            from.to_string(),
+            to.to_string(),
+            reason,
+        ));
+    }
+
+    fn print(&self) {
+        if self.lines.is_empty() {
+            return;
+        }
+        eprintln!("From {}", self.url);
+        for (flag, summary, from, to, reason) in &self.lines {
+            eprintln!(
+                " {flag} {summary:<17} {from:<width$} -> {to}{reason}",
+                width = self.width
+            );
+        }
+    }
+}
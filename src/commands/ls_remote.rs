@@ -0,0 +1,72 @@
+use crate::commands::fetch;
+use crate::config::Config;
+use crate::pathspec;
+use crate::protocol::Transport;
+use anyhow::Context;
+use std::io::Write;
+
+#[derive(Debug, Default)]
+pub(crate) struct LsRemoteOptions {
+    /// Only list branches, or branches and tags along with `tags`.
+    pub(crate) heads: bool,
+    /// Only list tags, or tags and branches along with `heads`.
+    pub(crate) tags: bool,
+    /// Show what symbolic refs such as `HEAD` point at.
+    pub(crate) symref: bool,
+}
+
+/// `ls-remote [--heads] [--tags] [--symref] [<repository> [<patterns>...]]`: lists the refs
+/// a remote has as `<hash>\t<ref>`, with annotated tags followed by what they point at as
+/// `<tag>^{}`. Patterns match the end of a ref name, as `master` matches
+/// `refs/heads/master`.
+pub(crate) fn ls_remote_invoke(
+    remote: Option<&str>,
+    patterns: &[String],
+    opts: &LsRemoteOptions,
+) -> anyhow::Result<()> {
+    let config = Config::read()?;
+    let (_, url) = fetch::resolve_remote(&config, remote)?;
+    if remote.is_none() {
+        eprintln!("From {url}");
+    }
+
+    let mut prefixes = Vec::new();
+    if opts.heads {
+        prefixes.push("refs/heads/");
+    }
+    if opts.tags {
+        prefixes.push("refs/tags/");
+    }
+    let transport = Transport::connect(&url).context("Connecting to the remote")?;
+    let remote_refs = transport.ls_refs(&prefixes)?;
+
+    // Like git, a pattern is matched against what follows any `/` in the name
+    let patterns: Vec<String> = patterns.iter().map(|p| format!("*/{p}")).collect();
+    let selected = |name: &str| {
+        patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|p| pathspec::matches(&format!("/{name}"), p))
+    };
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for remote_ref in &remote_refs {
+        let name = &remote_ref.name;
+        if selected(name) {
+            if let Some(target) = remote_ref.symref_target.as_ref().filter(|_| opts.symref) {
+                writeln!(stdout, "ref: {target}\t{name}")?;
+            }
+            writeln!(stdout, "{}\t{name}", remote_ref.hash)?;
+        }
+        let peeled_name = format!("{name}^{{}}");
+        if let Some(peeled) = remote_ref
+            .peeled
+            .as_ref()
+            .filter(|_| selected(&peeled_name))
+        {
+            writeln!(stdout, "{peeled}\t{peeled_name}")?;
+        }
+    }
+    Ok(())
+}
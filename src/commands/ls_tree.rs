@@ -4,14 +4,37 @@ use std::{
     ffi::CStr,
     io::{BufRead, Read, Write},
 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Listing {
+    /// `<mode> <kind> <hash> <name>` for every entry.
+    Long,
+    NameOnly,
+    /// Names with a `/` after subtrees, as `show` lists a tree.
+    NamesWithSlashes,
+}
+
 pub(crate) fn ls_tree_invoke(name_only: bool, tree_hash: &str) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let listing = if name_only {
+        Listing::NameOnly
+    } else {
+        Listing::Long
+    };
+    write_tree_listing(&mut stdout, tree_hash, listing)
+}
+
+pub(crate) fn write_tree_listing(
+    stdout: &mut impl Write,
+    tree_hash: &str,
+    listing: Listing,
+) -> anyhow::Result<()> {
     let mut obj = Object::read(tree_hash).context("parsing tree hash")?;
     match obj.kind {
         Kind::Tree => {
             let mut buf = Vec::new();
             let mut hashbuf = [0; 20];
-            let stdout = std::io::stdout();
-            let mut stdout = stdout.lock();
 
             loop {
                 buf.clear();
@@ -36,12 +59,15 @@ pub(crate) fn ls_tree_invoke(name_only: bool, tree_hash: &str) -> anyhow::Result
                     .next()
                     .ok_or_else(|| anyhow::anyhow!("tree entry has no filename"))?;
 
-                if name_only {
+                if listing != Listing::Long {
                     stdout.write_all(name).context("write tree entry name")?;
+                    if listing == Listing::NamesWithSlashes && mode == b"40000" {
+                        stdout.write_all(b"/").context("write tree entry name")?;
+                    }
                 } else {
                     let mode = std::str::from_utf8(mode).context("mode is valid utf-8")?;
 
-                    let hash = hex::encode(&hashbuf);
+                    let hash = hex::encode(hashbuf);
                     let obj = Object::read(&hash)
                         .with_context(|| format!("read object for tree entry {hash}"))?;
                     
@@ -49,7 +75,7 @@ pub(crate) fn ls_tree_invoke(name_only: bool, tree_hash: &str) -> anyhow::Result
                         .context("writing tree entry kind and hash")?;
                     stdout.write_all(name).context("write tree entry name")?;
                 }
-                writeln!(stdout, "").context("writing a newline to stdout")?;
+                writeln!(stdout).context("writing a newline to stdout")?;
 
                 buf.clear();
             }
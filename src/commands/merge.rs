@@ -0,0 +1,277 @@
+use crate::config::Config;
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{self, tree};
+use crate::ident;
+use crate::index::Index;
+use crate::merge;
+use crate::merge::base::{self, CommitCache};
+use crate::merge::file::ConflictStyle;
+use crate::merge::tree::{self as tree_merge, TreeMergeOptions};
+use crate::objects;
+use crate::refs;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+const MERGE_HEAD: &str = ".git/MERGE_HEAD";
+const MERGE_MSG: &str = ".git/MERGE_MSG";
+const MERGE_MODE: &str = ".git/MERGE_MODE";
+
+#[derive(Debug, Default)]
+pub(crate) struct MergeOptions {
+    /// Create a merge commit even when a fast-forward is possible.
+    pub(crate) no_ff: bool,
+    /// Refuse to do anything but a fast-forward.
+    pub(crate) ff_only: bool,
+    /// Stop before committing a clean merge, as if it had conflicts.
+    pub(crate) no_commit: bool,
+    pub(crate) message: Option<String>,
+}
+
+/// `merge [--no-ff | --ff-only] [--no-commit] [-m <msg>] <commit>`: fast-forwards to
+/// `name` when possible, otherwise merges it into HEAD and commits the result. Conflicts
+/// are left in the index and worktree for `merge --continue` or `merge --abort`.
+pub(crate) fn merge_invoke(name: &str, opts: &MergeOptions) -> anyhow::Result<()> {
+    let mut index = Index::read().context("Reading the index")?;
+    if index.entries.iter().any(|e| e.stage != 0) {
+        anyhow::bail!(
+            "Merging is not possible because you have unmerged files.\n\
+             Fix them up in the work tree, and then use 'add' to mark resolution and \
+             'merge --continue' to make a commit."
+        );
+    }
+    anyhow::ensure!(
+        !Path::new(MERGE_HEAD).exists(),
+        "You have not concluded your merge (MERGE_HEAD exists).\n\
+         Please, commit your changes before you merge."
+    );
+
+    let theirs = refs::peel_to_commit(&refs::resolve(name)?)
+        .with_context(|| format!("{name} - not something we can merge"))?;
+    let Some(head) = refs::head_commit()? else {
+        // Merging into an unborn branch just checks the other commit out
+        merge::checkout_commit(&mut index, &BTreeMap::new(), &theirs, "merge")?;
+        return refs::update_head(&theirs);
+    };
+
+    let mut cache = CommitCache::default();
+    if base::is_ancestor(&mut cache, &theirs, &head)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    let head_entries = diff::tree_entries(&cache.get(&head)?.tree.clone())?;
+    if !opts.no_ff && base::is_ancestor(&mut cache, &head, &theirs)? {
+        merge::checkout_commit(&mut index, &head_entries, &theirs, "merge")?;
+        println!("Updating {}..{}", &head[..7], &theirs[..7]);
+        println!("Fast-forward");
+        fs::write(".git/ORIG_HEAD", format!("{head}\n")).context("Writing ORIG_HEAD")?;
+        refs::update_head(&theirs)?;
+        return print_diffstat(&head, &theirs);
+    }
+    anyhow::ensure!(!opts.ff_only, "Not possible to fast-forward, aborting.");
+
+    let staged: Vec<String> = diff::diff_entries(&head_entries, &diff::index_entries(&index))
+        .iter()
+        .map(|c| c.path().to_string())
+        .collect();
+    anyhow::ensure!(
+        staged.is_empty(),
+        "Your local changes to the following files would be overwritten by merge:\n\t{}\n\
+         Please commit your changes or stash them before you merge.\nAborting",
+        staged.join("\n\t")
+    );
+
+    let config = Config::read()?;
+    let bases = base::merge_bases(&mut cache, &head, std::slice::from_ref(&theirs))?;
+    let result = tree_merge::merge_commits(
+        &mut cache,
+        &bases,
+        &objects::read_commit(&head)?.tree,
+        &objects::read_commit(&theirs)?.tree,
+        &TreeMergeOptions {
+            style: conflict_style(&config)?,
+            ours_label: "HEAD".to_string(),
+            theirs_label: name.to_string(),
+            base_label: tree_merge::ancestor_label(&bases),
+        },
+    )
+    .context("Merging trees")?;
+
+    merge::check_worktree(&index, &head_entries, &result.entries, "merge")?;
+    merge::checkout_result(&mut index, &head_entries, &result.entries)?;
+    index.write().context("Writing the merged index")?;
+    for (_, message) in &result.messages {
+        println!("{message}");
+    }
+    fs::write(".git/ORIG_HEAD", format!("{head}\n")).context("Writing ORIG_HEAD")?;
+
+    let message = match &opts.message {
+        Some(message) => format!("{}\n", message.trim_end()),
+        None => default_message(name)?,
+    };
+    if result.is_clean() && !opts.no_commit {
+        let commit = commit_merge(&config, &index, &[head.clone(), theirs], &message)?;
+        println!("Merge made by the 'ort' strategy.");
+        return print_diffstat(&head, &commit);
+    }
+
+    fs::write(MERGE_HEAD, format!("{theirs}\n")).context("Writing MERGE_HEAD")?;
+    fs::write(MERGE_MODE, if opts.no_ff { "no-ff" } else { "" }).context("Writing MERGE_MODE")?;
+    let mut merge_msg = message;
+    if !result.is_clean() {
+        merge_msg.push_str("\n# Conflicts:\n");
+        for path in result.conflicted_paths() {
+            merge_msg.push_str(&format!("#\t{path}\n"));
+        }
+    }
+    fs::write(MERGE_MSG, merge_msg).context("Writing MERGE_MSG")?;
+
+    if result.is_clean() {
+        println!("Automatic merge went well; stopped before committing as requested");
+        return Ok(());
+    }
+    anyhow::bail!("Automatic merge failed; fix conflicts and then commit the result.")
+}
+
+/// `merge --continue`: commits a merge whose conflicts have been resolved and added.
+pub(crate) fn merge_continue() -> anyhow::Result<()> {
+    let merge_head = fs::read_to_string(MERGE_HEAD)
+        .map_err(|_| anyhow::anyhow!("There is no merge in progress (MERGE_HEAD missing)."))?;
+    let index = Index::read().context("Reading the index")?;
+    anyhow::ensure!(
+        !index.entries.iter().any(|e| e.stage != 0),
+        "Committing is not possible because you have unmerged files."
+    );
+
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    let mut parents = vec![head];
+    parents.extend(merge_head.lines().map(str::to_string));
+
+    let message: String = fs::read_to_string(MERGE_MSG)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let message = format!("{}\n", message.trim());
+    anyhow::ensure!(
+        !message.trim().is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+
+    let commit = commit_merge(&Config::read()?, &index, &parents, &message)?;
+    let branch = match refs::head_ref()? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "detached HEAD".to_string(),
+    };
+    println!(
+        "[{branch} {}] {}",
+        &commit[..7],
+        message.lines().next().unwrap_or("")
+    );
+    Ok(())
+}
+
+/// `merge --abort`: puts the index and worktree back to HEAD and forgets the merge.
+pub(crate) fn merge_abort() -> anyhow::Result<()> {
+    anyhow::ensure!(
+        Path::new(MERGE_HEAD).exists(),
+        "There is no merge to abort (MERGE_HEAD missing)."
+    );
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    merge::reset_merge(&head)?;
+    cleanup_merge_state()
+}
+
+fn cleanup_merge_state() -> anyhow::Result<()> {
+    for path in [MERGE_HEAD, MERGE_MSG, MERGE_MODE] {
+        if Path::new(path).exists() {
+            fs::remove_file(path).with_context(|| format!("Removing {path}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the merged index as a tree and commits it with `parents`, moving HEAD along.
+fn commit_merge(
+    config: &Config,
+    index: &Index,
+    parents: &[String],
+    message: &str,
+) -> anyhow::Result<String> {
+    let tree = objects::write_tree_from_entries(&diff::index_entries(index))?;
+    let commit = objects::write_commit(
+        &hex::encode(tree),
+        parents,
+        &ident::author(config)?,
+        &ident::committer(config)?,
+        message,
+    )?;
+    refs::update_head(&commit)?;
+    cleanup_merge_state()?;
+    Ok(commit)
+}
+
+/// `merge.conflictStyle` from the config, `merge` when unset.
+pub(crate) fn conflict_style(config: &Config) -> anyhow::Result<ConflictStyle> {
+    config
+        .get("merge.conflictStyle")
+        .map_or(Ok(ConflictStyle::Merge), str::parse)
+}
+
+/// `Merge branch 'topic'` and the like, naming the current branch unless it is `main`
+/// or `master`. What was fetched is described as `fetch` recorded it in `FETCH_HEAD`.
+fn default_message(name: &str) -> anyhow::Result<String> {
+    let fetched = match name {
+        "FETCH_HEAD" => fs::read_to_string(".git/FETCH_HEAD").context("Reading FETCH_HEAD")?,
+        _ => String::new(),
+    };
+    let description = refs::fetch_head_line(&fetched).and_then(|line| line.split('\t').nth(2));
+    let kind = if refs::resolve_ref(&format!("refs/heads/{name}"))?.is_some() {
+        "branch"
+    } else if refs::resolve_ref(&format!("refs/remotes/{name}"))?.is_some() {
+        "remote-tracking branch"
+    } else if refs::resolve_ref(&format!("refs/tags/{name}"))?.is_some() {
+        "tag"
+    } else {
+        "commit"
+    };
+    let mut message = match description {
+        Some(description) => format!("Merge {description}"),
+        None => format!("Merge {kind} '{name}'"),
+    };
+    if let Some(branch) = refs::head_ref()? {
+        let branch = branch.trim_start_matches("refs/heads/");
+        if branch != "main" && branch != "master" {
+            message.push_str(&format!(" into {branch}"));
+        }
+    }
+    message.push('\n');
+    Ok(message)
+}
+
+/// The `--stat --summary` of what a merge or fast-forward brought in.
+fn print_diffstat(old: &str, new: &str) -> anyhow::Result<()> {
+    let old_tree = objects::read_commit(old)?.tree;
+    let new_tree = objects::read_commit(new)?.tree;
+    let changes = tree::diff_trees(Some(&old_tree), Some(&new_tree), true)?;
+    let changes = rename::detect(changes, Vec::new(), &RenameOptions::default())?;
+
+    let opts = PatchOptions::default();
+    let stats = changes
+        .iter()
+        .map(|c| patch::file_stat(c, &opts))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if !stats.is_empty() {
+        patch::write_stat(&mut stdout, &stats)?;
+    }
+    patch::write_summary(&mut stdout, &changes)?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::merge::base::{self, CommitCache};
+use crate::refs;
+use anyhow::Context;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// The merge bases of the first commit and all the others.
+    Default,
+    /// Whether the first commit is an ancestor of the second.
+    IsAncestor,
+    /// The common ancestors of all commits, for an octopus merge.
+    Octopus,
+    /// The commits that are not ancestors of any of the others.
+    Independent,
+    /// Where the second commit (HEAD by default) forked from the ref named first.
+    ForkPoint,
+}
+
+/// `merge-base [--all] [--is-ancestor | --octopus | --independent | --fork-point]
+/// <commit>...`. Returns
+/// false when there is no answer (no merge base, not an ancestor), which git reports
+/// through its exit status.
+pub(crate) fn merge_base_invoke(mode: Mode, all: bool, names: &[String]) -> anyhow::Result<bool> {
+    let mut cache = CommitCache::default();
+    let resolve = |name: &str| {
+        refs::resolve(name)
+            .and_then(|hash| refs::peel_to_commit(&hash))
+            .with_context(|| format!("Not a valid commit name {name}"))
+    };
+
+    let bases = match mode {
+        Mode::IsAncestor => {
+            anyhow::ensure!(names.len() == 2, "--is-ancestor takes exactly two commits");
+            let ancestor = resolve(&names[0])?;
+            let descendant = resolve(&names[1])?;
+            return base::is_ancestor(&mut cache, &ancestor, &descendant);
+        }
+        Mode::ForkPoint => {
+            anyhow::ensure!(
+                matches!(names.len(), 1 | 2),
+                "--fork-point takes a ref and at most one commit"
+            );
+            let commit = resolve(names.get(1).map_or("HEAD", String::as_str))?;
+            base::fork_point(&mut cache, &names[0], &commit)?
+                .into_iter()
+                .collect()
+        }
+        Mode::Octopus | Mode::Independent => {
+            let commits = names
+                .iter()
+                .map(|name| resolve(name))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            if mode == Mode::Octopus {
+                base::octopus_merge_bases(&mut cache, &commits)?
+            } else {
+                base::independent(&mut cache, commits)?
+            }
+        }
+        Mode::Default => {
+            anyhow::ensure!(names.len() >= 2, "merge-base needs at least two commits");
+            let one = resolve(&names[0])?;
+            let twos = names[1..]
+                .iter()
+                .map(|name| resolve(name))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            base::merge_bases(&mut cache, &one, &twos)?
+        }
+    };
+
+    let shown = if all || mode == Mode::Independent {
+        bases.len()
+    } else {
+        1
+    };
+    for hash in bases.iter().take(shown) {
+        println!("{hash}");
+    }
+    Ok(!bases.is_empty())
+}
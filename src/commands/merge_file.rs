@@ -0,0 +1,69 @@
+use crate::commands::merge::conflict_style;
+use crate::config::Config;
+use crate::diff::patch;
+use crate::merge::file::{self, ConflictStyle, Favor, Level, MergeFileOptions};
+use anyhow::Context;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// `merge-file [-p] <current> <base> <other>`: merges the changes from `base` to `other`
+/// into `current`, overwriting it unless `stdout` is set. Returns the number of conflicts.
+pub(crate) fn merge_file_invoke(
+    files: [&Path; 3],
+    labels: &[String],
+    style: Option<ConflictStyle>,
+    favor: Option<Favor>,
+    marker_size: usize,
+    stdout: bool,
+) -> anyhow::Result<usize> {
+    anyhow::ensure!(labels.len() <= 3, "too many labels on the command line");
+    let mut contents = Vec::with_capacity(3);
+    for path in files {
+        let data = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        anyhow::ensure!(
+            !patch::is_binary(&data),
+            "Cannot merge binary files: {}",
+            path.display()
+        );
+        contents.push(data);
+    }
+
+    let style = match style {
+        Some(style) => style,
+        None => conflict_style(&Config::read()?)?,
+    };
+    let label = |i: usize| {
+        Some(
+            labels
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| files[i].display().to_string()),
+        )
+    };
+    let (merged, conflicts) = file::merge(
+        &contents[1],
+        &contents[0],
+        &contents[2],
+        &MergeFileOptions {
+            style,
+            level: Level::ZealousAlnum,
+            favor,
+            marker_size,
+            ours_label: label(0),
+            base_label: label(1),
+            theirs_label: label(2),
+            ..Default::default()
+        },
+    );
+
+    if stdout {
+        std::io::stdout()
+            .lock()
+            .write_all(&merged)
+            .context("Writing the merge result")?;
+    } else {
+        fs::write(files[0], &merged).with_context(|| format!("Writing {}", files[0].display()))?;
+    }
+    Ok(conflicts)
+}
@@ -0,0 +1,362 @@
+use crate::commands::fetch;
+use crate::config::Config;
+use crate::merge::base::{self, CommitCache};
+use crate::objects::{self, Kind, Object};
+use crate::pack::writer;
+use crate::protocol::push::{PushConnection, RefCommand, ZERO_HASH};
+use crate::protocol::RemoteRef;
+use crate::refs;
+use crate::refspec::{self, Refspec};
+use anyhow::Context;
+
+#[derive(Debug, Default)]
+pub(crate) struct PushOptions {
+    /// Update remote refs even when that is not a fast-forward.
+    pub(crate) force: bool,
+    /// Each `--force-with-lease[=<ref>[:<expect>]]`, empty for the form without a value.
+    pub(crate) force_with_lease: Vec<String>,
+}
+
+/// A remote ref to set, and what became of it.
+struct Push {
+    /// The local ref or revision it is set from, or `None` to delete it.
+    src: Option<String>,
+    new: String,
+    dst: String,
+    old: Option<String>,
+    force: bool,
+    status: Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Status {
+    UpToDate,
+    New,
+    FastForward,
+    Forced,
+    Deleted,
+    /// Refused before sending anything.
+    Rejected(&'static str),
+    RemoteRejected(String),
+}
+
+/// `push [--force] [--force-with-lease[=<ref>[:<expect>]]] [<remote>] [<refspec>...]`:
+/// sends the objects the remote is missing and asks it to move its refs. Without refspecs,
+/// the current branch goes to the branch of the same name. Returns false when a ref was not
+/// updated.
+pub(crate) fn push_invoke(
+    remote: Option<&str>,
+    refspecs: &[String],
+    opts: &PushOptions,
+) -> anyhow::Result<bool> {
+    let config = Config::read()?;
+    let (name, url) = fetch::resolve_remote(&config, remote)?;
+    let specs = if refspecs.is_empty() {
+        let branch = refs::head_ref()?.context("You are not currently on a branch.")?;
+        vec![Refspec::parse(&format!("{branch}:{branch}"))?]
+    } else {
+        refspecs
+            .iter()
+            .map(|s| Refspec::parse(s))
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+
+    let connection = PushConnection::connect(&url).context("Connecting to the remote")?;
+    let remote_refs = connection.refs();
+    let mut pushes: Vec<Push> = Vec::new();
+    for spec in &specs {
+        for (src, new, dst) in expand_refspec(spec, remote_refs)? {
+            if pushes.iter().any(|p| p.dst == dst) {
+                anyhow::bail!("multiple updates for ref '{dst}' not allowed");
+            }
+            pushes.push(Push {
+                src,
+                new,
+                old: remote_refs
+                    .iter()
+                    .find(|r| r.name == dst)
+                    .map(|r| r.hash.clone()),
+                dst,
+                force: spec.force || opts.force,
+                status: Status::UpToDate,
+            });
+        }
+    }
+
+    let tracking = config.get_all(&format!("remote.{name}.fetch"));
+    let tracking = tracking
+        .into_iter()
+        .map(Refspec::parse)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut cache = CommitCache::default();
+    for push in &mut pushes {
+        push.status = check(push, opts, &tracking, &mut cache)?;
+        if push.status == Status::Deleted && connection.capability("delete-refs").is_none() {
+            push.status = Status::Rejected("remote does not support deleting refs");
+        }
+    }
+
+    let commands: Vec<RefCommand> = pushes
+        .iter()
+        .filter(|p| {
+            !matches!(
+                p.status,
+                Status::UpToDate | Status::Rejected(_) | Status::RemoteRejected(_)
+            )
+        })
+        .map(|p| RefCommand {
+            old: p.old.clone().unwrap_or_else(|| ZERO_HASH.to_string()),
+            new: p.new.clone(),
+            name: p.dst.clone(),
+        })
+        .collect();
+    if !commands.is_empty() {
+        // Deleting refs needs no objects, and then no pack at all
+        let pack = if commands.iter().any(|c| c.new != ZERO_HASH) {
+            let include: Vec<String> = commands
+                .iter()
+                .filter(|c| c.new != ZERO_HASH)
+                .map(|c| c.new.clone())
+                .collect();
+            let exclude: Vec<String> = remote_refs.iter().map(|r| r.hash.clone()).collect();
+            let objects = writer::list_objects(&include, &exclude)?;
+            let mut pack = Vec::new();
+            writer::write_pack(&objects, &mut pack).context("Writing the pack")?;
+            Some(pack)
+        } else {
+            None
+        };
+
+        let report = connection
+            .push(&commands, pack)
+            .context("Pushing to git-receive-pack")?;
+        if let Some(error) = &report.unpack_error {
+            eprintln!("error: remote unpack failed: {error}");
+        }
+        for push in pushes.iter_mut() {
+            if !commands.iter().any(|c| c.name == push.dst) {
+                continue;
+            }
+            match report.refs.iter().find(|(name, _)| *name == push.dst) {
+                Some((_, None)) => {
+                    update_tracking(&tracking, push)?;
+                }
+                Some((_, Some(reason))) => push.status = Status::RemoteRejected(reason.clone()),
+                None => {
+                    push.status =
+                        Status::RemoteRejected("remote failed to report status".to_string())
+                }
+            }
+        }
+    }
+
+    print_status(&url, &pushes);
+    let ok = pushes
+        .iter()
+        .all(|p| !matches!(p.status, Status::Rejected(_) | Status::RemoteRejected(_)));
+    if !ok {
+        eprintln!("error: failed to push some refs to '{url}'");
+    }
+    Ok(ok)
+}
+
+/// The `(src, new, dst)` of each ref `spec` pushes: the local ref or revision, the hash
+/// it stands for and the full name of the remote ref to set.
+fn expand_refspec(
+    spec: &Refspec,
+    remote_refs: &[RemoteRef],
+) -> anyhow::Result<Vec<(Option<String>, String, String)>> {
+    if spec.is_glob() {
+        let (prefix, _) = spec.src.split_once('*').expect("globs have a '*'");
+        let mut pushes = Vec::new();
+        for (name, hash) in refs::list_refs(prefix)? {
+            if let Some(dst) = spec.dst_for(&name) {
+                pushes.push((Some(name), hash, dst));
+            }
+        }
+        return Ok(pushes);
+    }
+
+    let on_remote = |name: &str| {
+        refspec::expand_short_name(name)
+            .into_iter()
+            .find(|full| remote_refs.iter().any(|r| r.name == *full))
+    };
+    if spec.src.is_empty() {
+        let dst = spec
+            .dst
+            .as_deref()
+            .expect("an empty source has a destination");
+        let dst = match dst.starts_with("refs/") {
+            true => dst.to_string(),
+            false => on_remote(dst)
+                .with_context(|| format!("unable to delete '{dst}': remote ref does not exist"))?,
+        };
+        return Ok(vec![(None, ZERO_HASH.to_string(), dst)]);
+    }
+
+    // A ref by its short or full name, or else any revision
+    let local = match spec.src.as_str() {
+        "HEAD" => refs::head_ref()?,
+        src => refspec::expand_short_name(src)
+            .into_iter()
+            .filter(|full| full.starts_with("refs/"))
+            .find(|full| refs::resolve_ref(full).ok().flatten().is_some()),
+    };
+    let new = match &local {
+        Some(local) => refs::resolve_ref(local)?.context("the ref has just been found")?,
+        None => refs::resolve(&spec.src)
+            .with_context(|| format!("src refspec {} does not match any", spec.src))?,
+    };
+    let dst = match (spec.dst.as_deref(), &local) {
+        (None, Some(local)) => local.clone(),
+        (Some(dst), _) if dst.starts_with("refs/") => dst.to_string(),
+        (Some(dst), _) if on_remote(dst).is_some() => on_remote(dst).expect("just checked"),
+        (Some(dst), Some(local)) if local.starts_with("refs/tags/") => format!("refs/tags/{dst}"),
+        (Some(dst), Some(local)) if local.starts_with("refs/heads/") => {
+            format!("refs/heads/{dst}")
+        }
+        (dst, _) => anyhow::bail!(
+            "The destination you provided is not a full refname (i.e.,\n\
+             starting with \"refs/\"): '{}'",
+            dst.unwrap_or(&spec.src)
+        ),
+    };
+    // HEAD is shown as such rather than as the branch it is on
+    let src = match local {
+        Some(local) if spec.src != "HEAD" => local,
+        _ => spec.src.clone(),
+    };
+    Ok(vec![(Some(src), new, dst)])
+}
+
+/// What to do with `push`, or why not to.
+fn check(
+    push: &Push,
+    opts: &PushOptions,
+    tracking: &[Refspec],
+    cache: &mut CommitCache,
+) -> anyhow::Result<Status> {
+    let mut force = push.force;
+    if let Some(expected) = lease(push, opts, tracking)? {
+        if expected != push.old {
+            return Ok(Status::Rejected("stale info"));
+        }
+        force = true;
+    }
+
+    let Some(old) = &push.old else {
+        return Ok(match push.src {
+            Some(_) => Status::New,
+            None => Status::Rejected("remote ref does not exist"),
+        });
+    };
+    if push.src.is_none() {
+        return Ok(Status::Deleted);
+    }
+    if *old == push.new {
+        return Ok(Status::UpToDate);
+    }
+    // We can't tell whether it would be a fast-forward without the remote's commit
+    let known = objects::has_object(old)?;
+    let fast_forward = known
+        && is_commit(old)?
+        && is_commit(&push.new)?
+        && base::is_ancestor(cache, old, &push.new)?;
+    let tag = push.dst.starts_with("refs/tags/");
+    Ok(if fast_forward && !tag {
+        Status::FastForward
+    } else if force {
+        Status::Forced
+    } else if tag {
+        Status::Rejected("already exists")
+    } else if !known {
+        Status::Rejected("fetch first")
+    } else {
+        Status::Rejected("non-fast-forward")
+    })
+}
+
+/// The value `--force-with-lease` expects the remote ref of `push` to have, if it covers
+/// that ref: the one given, or else that of our remote-tracking ref for it.
+fn lease(
+    push: &Push,
+    opts: &PushOptions,
+    tracking: &[Refspec],
+) -> anyhow::Result<Option<Option<String>>> {
+    for lease in &opts.force_with_lease {
+        let (name, expect) = match lease.split_once(':') {
+            Some((name, expect)) => (name, Some(expect)),
+            None => (lease.as_str(), None),
+        };
+        let covers = name.is_empty() || refspec::expand_short_name(name).contains(&push.dst);
+        if !covers {
+            continue;
+        }
+        let expected = match expect {
+            // An empty value expects the ref not to exist yet
+            Some("") => None,
+            Some(expect) => Some(refs::resolve(expect)?),
+            None => match tracking.iter().find_map(|spec| spec.dst_for(&push.dst)) {
+                Some(tracking) => refs::resolve_ref(&tracking)?,
+                None => None,
+            },
+        };
+        return Ok(Some(expected));
+    }
+    Ok(None)
+}
+
+fn is_commit(hash: &str) -> anyhow::Result<bool> {
+    Ok(Object::read(hash)?.kind == Kind::Commit)
+}
+
+/// Moves our remote-tracking ref for the remote ref `push` just updated along with it.
+fn update_tracking(tracking: &[Refspec], push: &Push) -> anyhow::Result<()> {
+    let Some(local) = tracking.iter().find_map(|spec| spec.dst_for(&push.dst)) else {
+        return Ok(());
+    };
+    match push.status {
+        Status::Deleted => refs::delete_ref(&local),
+        _ => refs::update_ref(&local, &push.new),
+    }
+}
+
+/// Prints a line for each ref that changed or failed to, successes first, as git does.
+fn print_status(url: &str, pushes: &[Push]) {
+    if pushes.iter().all(|p| p.status == Status::UpToDate) {
+        eprintln!("Everything up-to-date");
+        return;
+    }
+    eprintln!("To {url}");
+    let failed = |p: &&Push| matches!(p.status, Status::Rejected(_) | Status::RemoteRejected(_));
+    let succeeded = pushes.iter().filter(|p| !failed(p));
+    for push in succeeded.chain(pushes.iter().filter(failed)) {
+        let to = refspec::short_name(&push.dst);
+        let from = push.src.as_deref().map(refspec::short_name).unwrap_or("");
+        let line = |flag: char, summary: &str, reason: &str| {
+            eprintln!(" {flag} {summary:<17} {from} -> {to}{reason}");
+        };
+        let (old, new) = (push.old.as_deref().unwrap_or(ZERO_HASH), &push.new);
+        match &push.status {
+            Status::UpToDate => {}
+            Status::New if push.dst.starts_with("refs/tags/") => line('*', "[new tag]", ""),
+            Status::New if push.dst.starts_with("refs/heads/") => line('*', "[new branch]", ""),
+            Status::New => line('*', "[new reference]", ""),
+            Status::FastForward => line(' ', &format!("{}..{}", &old[..7], &new[..7]), ""),
+            Status::Forced => line(
+                '+',
+                &format!("{}...{}", &old[..7], &new[..7]),
+                " (forced update)",
+            ),
+            Status::Deleted => eprintln!(" - {:<17} {to}", "[deleted]"),
+            Status::Rejected(reason) if push.src.is_none() => {
+                eprintln!(" ! {:<17} {to} ({reason})", "[rejected]")
+            }
+            Status::Rejected(reason) => line('!', "[rejected]", &format!(" ({reason})")),
+            Status::RemoteRejected(reason) => {
+                line('!', "[remote rejected]", &format!(" ({reason})"))
+            }
+        }
+    }
+}
@@ -0,0 +1,817 @@
+use crate::config::Config;
+use crate::diff::{self, worktree};
+use crate::ident;
+use crate::index::Index;
+use crate::merge;
+use crate::merge::base::{self, CommitCache};
+use crate::merge::tree::Entries;
+use crate::objects;
+use crate::refs;
+use crate::sequencer::{self, Action};
+use anyhow::Context;
+use std::collections::HashSet;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process;
+
+const REBASE_DIR: &str = ".git/rebase-merge";
+const REBASE_HEAD: &str = ".git/REBASE_HEAD";
+const MERGE_MSG: &str = ".git/MERGE_MSG";
+const COMMIT_EDITMSG: &str = ".git/COMMIT_EDITMSG";
+
+#[derive(Debug, Default)]
+pub(crate) struct RebaseOptions {
+    /// Replay the commits on top of this commit instead of the upstream.
+    pub(crate) onto: Option<String>,
+    /// Let the user edit the todo list before starting.
+    pub(crate) interactive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Pick,
+    Reword,
+    Squash,
+    Fixup,
+    Drop,
+    Exec,
+}
+
+impl Command {
+    fn parse(word: &str) -> Option<Command> {
+        Some(match word {
+            "pick" | "p" => Command::Pick,
+            "reword" | "r" => Command::Reword,
+            "squash" | "s" => Command::Squash,
+            "fixup" | "f" => Command::Fixup,
+            "drop" | "d" => Command::Drop,
+            "exec" | "x" => Command::Exec,
+            _ => return None,
+        })
+    }
+
+    fn word(self) -> &'static str {
+        match self {
+            Command::Pick => "pick",
+            Command::Reword => "reword",
+            Command::Squash => "squash",
+            Command::Fixup => "fixup",
+            Command::Drop => "drop",
+            Command::Exec => "exec",
+        }
+    }
+
+    /// Whether the command melds its commit into the one before.
+    fn is_fixup(self) -> bool {
+        matches!(self, Command::Squash | Command::Fixup)
+    }
+}
+
+/// One line of the todo list: a commit to replay, or a shell command for `exec`.
+#[derive(Debug, Clone)]
+struct TodoItem {
+    command: Command,
+    arg: String,
+}
+
+impl TodoItem {
+    /// The line as stored in `git-rebase-todo` and `done`, with the full hash and subject.
+    fn line(&self, cache: &mut CommitCache) -> anyhow::Result<String> {
+        if self.command == Command::Exec {
+            return Ok(format!("exec {}", self.arg));
+        }
+        let subject = sequencer::subject(&cache.get(&self.arg)?.message).to_string();
+        Ok(format!("{} {} {subject}", self.command.word(), self.arg))
+    }
+}
+
+/// `rebase [-i] [--onto <newbase>] [<upstream> [<branch>]]`: replays the commits of the
+/// current branch that are not in `upstream` on top of it (or of `newbase`), then moves the
+/// branch to the result. Progress is kept in `.git/rebase-merge`, so that a commit that
+/// conflicts can be resolved and the rebase continued, skipped past or aborted.
+pub(crate) fn rebase_invoke(
+    upstream: Option<&str>,
+    branch: Option<&str>,
+    opts: &RebaseOptions,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !Path::new(REBASE_DIR).exists(),
+        "It seems that there is already a rebase-merge directory, and\n\
+         I wonder if you are in the middle of another rebase.  If that is the\n\
+         case, please try\n\
+         \trebase (--continue | --abort | --skip)\n\
+         If that is not the case, please\n\
+         \trm -fr \".git/rebase-merge\"\n\
+         and run me again.  I am stopping in case you still have something\n\
+         valuable there."
+    );
+    let current = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    check_clean(&current)?;
+    // The branch to rebase is only checked out when the commits are replayed
+    let (head, head_name) = match branch {
+        Some(name) => {
+            let branch = format!("refs/heads/{name}");
+            let head_name = refs::resolve_ref(&branch)?.map(|_| branch);
+            (resolve_commit(name)?, head_name)
+        }
+        None => (current.clone(), refs::head_ref()?),
+    };
+
+    let upstream_name = match upstream {
+        Some(name) => name.to_string(),
+        None => tracking_branch(head_name.as_deref())?,
+    };
+    let upstream = resolve_commit(&upstream_name)?;
+    let onto = match &opts.onto {
+        Some(name) => resolve_commit(name)?,
+        None => upstream.clone(),
+    };
+
+    let mut cache = CommitCache::default();
+    if !opts.interactive
+        && base::merge_bases(&mut cache, &onto, std::slice::from_ref(&head))? == [onto.clone()]
+        && base::merge_bases(&mut cache, &upstream, std::slice::from_ref(&head))? == [onto.clone()]
+    {
+        if let Some(branch) = branch {
+            switch_to(branch)?;
+        }
+        match &head_name {
+            Some(name) => println!(
+                "Current branch {} is up to date.",
+                name.trim_start_matches("refs/heads/")
+            ),
+            None => println!("HEAD is up to date."),
+        }
+        return Ok(());
+    }
+
+    // Merges are not replayed; their changes come along with the commits around them
+    let commits = base::commits_between(&mut cache, std::slice::from_ref(&upstream), &head)?;
+    let mut commits = base::sort_topologically(&mut cache, commits)?;
+    commits.reverse();
+    let applied = upstream_patch_ids(&mut cache, &head, &[&upstream, &onto])?;
+    let mut todo = Vec::new();
+    for commit in commits {
+        if cache.get(&commit)?.parents.len() > 1 {
+            continue;
+        }
+        if !applied.is_empty() && applied.contains(&patch_id(&mut cache, &commit)?) {
+            eprintln!(
+                "warning: skipped previously applied commit {}",
+                &commit[..7]
+            );
+            continue;
+        }
+        todo.push(TodoItem {
+            command: Command::Pick,
+            arg: commit,
+        });
+    }
+
+    fs::create_dir_all(REBASE_DIR).context("Creating .git/rebase-merge")?;
+    if opts.interactive {
+        todo = match edit_todo(&mut cache, &todo, &upstream, &head, &onto) {
+            Ok(todo) => todo,
+            Err(error) => {
+                fs::remove_dir_all(REBASE_DIR).context("Removing .git/rebase-merge")?;
+                return Err(error);
+            }
+        };
+        write_state("interactive", "")?;
+    }
+    // Leading picks that are already on top of onto need no replaying
+    let mut start = onto.clone();
+    let mut done = String::new();
+    while let Some(item) = todo.first() {
+        if item.command != Command::Pick || cache.get(&item.arg)?.parents.first() != Some(&start) {
+            break;
+        }
+        let item = todo.remove(0);
+        done.push_str(&format!("{}\n", item.line(&mut cache)?));
+        start = item.arg;
+    }
+
+    write_state(
+        "head-name",
+        &format!("{}\n", head_name.as_deref().unwrap_or("detached HEAD")),
+    )?;
+    write_state("onto", &format!("{onto}\n"))?;
+    write_state("orig-head", &format!("{head}\n"))?;
+    save_todo(&mut cache, &todo)?;
+    write_state("done", &done)?;
+    fs::write(".git/ORIG_HEAD", format!("{head}\n")).context("Writing ORIG_HEAD")?;
+
+    // The commits are replayed on a detached HEAD; the branch only moves once all are done
+    let mut index = Index::read().context("Reading the index")?;
+    let head_entries = diff::tree_entries(&cache.get(&current)?.tree.clone())?;
+    merge::checkout_commit(&mut index, &head_entries, &start, "checkout")?;
+    refs::update_ref("HEAD", &start)?;
+
+    run_todo(&mut cache, &Config::read()?)
+}
+
+/// The patch-ids of the commits on `sides` that `head` doesn't have, so that commits
+/// already applied upstream, as with a cherry-pick, aren't replayed again.
+fn upstream_patch_ids(
+    cache: &mut CommitCache,
+    head: &str,
+    sides: &[&String],
+) -> anyhow::Result<HashSet<[u8; 20]>> {
+    let mut ids = HashSet::new();
+    let mut seen = HashSet::new();
+    for side in sides {
+        for commit in base::commits_between(cache, &[head.to_string()], side)? {
+            if seen.insert(commit.clone()) && cache.get(&commit)?.parents.len() <= 1 {
+                ids.insert(patch_id(cache, &commit)?);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// The patch-id of what `commit` changed relative to its parent.
+fn patch_id(cache: &mut CommitCache, commit: &str) -> anyhow::Result<[u8; 20]> {
+    let parent = cache.get(commit)?.parents.first().cloned();
+    let parent_tree = match parent {
+        Some(parent) => Some(cache.get(&parent)?.tree.clone()),
+        None => None,
+    };
+    let tree = cache.get(commit)?.tree.clone();
+    let changes = diff::tree::diff_trees(parent_tree.as_deref(), Some(&tree), true)?;
+    diff::patch::patch_id(&changes)
+}
+
+/// `rebase --continue`: commits the resolved conflicts of the commit that stopped, then
+/// replays the rest of the todo list.
+pub(crate) fn rebase_continue() -> anyhow::Result<()> {
+    anyhow::ensure!(Path::new(REBASE_DIR).exists(), "No rebase in progress?");
+    let index = Index::read().context("Reading the index")?;
+    anyhow::ensure!(
+        !index.entries.iter().any(|e| e.stage != 0),
+        "You must edit all merge conflicts and then\n\
+         mark them as resolved using add"
+    );
+
+    let mut cache = CommitCache::default();
+    let config = Config::read()?;
+    if Path::new(REBASE_DIR).join("stopped-sha").exists() {
+        let item = read_todo("done")?
+            .pop()
+            .context("The done list of .git/rebase-merge is empty")?;
+        let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+        let tree = hex::encode(objects::write_tree_from_entries(&diff::index_entries(
+            &index,
+        ))?);
+        // A melded commit goes on with the combined message of its chain
+        let message = if item.command.is_fixup() {
+            read_state("message-squash")?
+        } else {
+            let message = fs::read_to_string(MERGE_MSG).or_else(|_| read_state("message"))?;
+            sequencer::cleanup_message(&message)
+        };
+        // Resolving the conflicts back to HEAD drops the commit, unless it is to be melded
+        if cache.get(&head)?.tree != tree || item.command.is_fixup() {
+            let next = read_todo("git-rebase-todo")?
+                .first()
+                .map(|item| item.command);
+            commit_item(&mut cache, &config, &item, &tree, &message, next, true)?;
+        }
+        remove_stop_state()?;
+    }
+    run_todo(&mut cache, &config)
+}
+
+/// `rebase --skip`: drops the commit that stopped and goes on with the rest of the list.
+pub(crate) fn rebase_skip() -> anyhow::Result<()> {
+    anyhow::ensure!(Path::new(REBASE_DIR).exists(), "No rebase in progress?");
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    merge::reset_merge(&head)?;
+    remove_stop_state()?;
+    run_todo(&mut CommitCache::default(), &Config::read()?)
+}
+
+/// `rebase --abort`: puts HEAD, the branch, the index and the worktree back to where they
+/// were before the rebase started.
+pub(crate) fn rebase_abort() -> anyhow::Result<()> {
+    anyhow::ensure!(Path::new(REBASE_DIR).exists(), "No rebase in progress?");
+    let head_name = read_state("head-name")?.trim_end().to_string();
+    let orig_head = read_state("orig-head")?.trim_end().to_string();
+    if head_name == "detached HEAD" {
+        refs::update_ref("HEAD", &orig_head)?;
+    } else {
+        refs::attach_head(&head_name)?;
+    }
+    merge::reset_merge(&orig_head)?;
+    remove_stop_state()?;
+    fs::remove_dir_all(REBASE_DIR).context("Removing .git/rebase-merge")
+}
+
+/// Works through `git-rebase-todo` one item at a time, moving each to `done` before it is
+/// carried out so that a stop leaves the rest for `--continue`. Once the list is empty the
+/// branch is moved to the new HEAD and the state is removed.
+fn run_todo(cache: &mut CommitCache, config: &Config) -> anyhow::Result<()> {
+    loop {
+        let mut todo = read_todo("git-rebase-todo")?;
+        if todo.is_empty() {
+            break;
+        }
+        let item = todo.remove(0);
+        let mut done = fs::read_to_string(Path::new(REBASE_DIR).join("done")).unwrap_or_default();
+        done.push_str(&format!("{}\n", item.line(cache)?));
+        write_state("done", &done)?;
+        save_todo(cache, &todo)?;
+        let msgnum = done.lines().count();
+        write_state("msgnum", &format!("{msgnum}\n"))?;
+        write_state("end", &format!("{}\n", msgnum + todo.len()))?;
+        // Progress overwrites itself on a terminal, and would only run into what follows
+        // anywhere else
+        if std::io::stderr().is_terminal() {
+            eprint!("Rebasing ({msgnum}/{})\r", msgnum + todo.len());
+        }
+
+        let next = todo.first().map(|item| item.command);
+        match item.command {
+            Command::Drop => {}
+            Command::Exec => {
+                eprintln!("Executing: {}", item.arg);
+                let status = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(&item.arg)
+                    .status()
+                    .with_context(|| format!("Running {}", item.arg))?;
+                anyhow::ensure!(
+                    status.success(),
+                    "execution failed: {}\n\
+                     You can fix the problem, and then run\n\n  rebase --continue\n",
+                    item.arg
+                );
+            }
+            _ => replay(cache, config, &item, next)?,
+        }
+    }
+
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    let head_name = read_state("head-name")?.trim_end().to_string();
+    if head_name != "detached HEAD" {
+        refs::update_ref(&head_name, &head)?;
+        refs::attach_head(&head_name)?;
+    }
+    fs::remove_dir_all(REBASE_DIR).context("Removing .git/rebase-merge")?;
+    eprintln!("Successfully rebased and updated {head_name}.");
+    Ok(())
+}
+
+/// Merges the changes of a `pick`, `reword`, `squash` or `fixup` item into HEAD and commits
+/// them. `next` is the command that follows, which tells whether a chain of fixups ends.
+fn replay(
+    cache: &mut CommitCache,
+    config: &Config,
+    item: &TodoItem,
+    next: Option<Command>,
+) -> anyhow::Result<()> {
+    let hash = item.arg.as_str();
+    let commit = cache.get(hash)?.clone();
+    let parent = sequencer::replayed_parent(hash, &commit, None)?;
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    let head_tree = cache.get(&head)?.tree.clone();
+
+    let message = if item.command.is_fixup() {
+        let done = read_todo("done")?;
+        anyhow::ensure!(
+            done.len() > 1,
+            "cannot '{}' without a previous commit",
+            item.command.word()
+        );
+        add_fixup(cache, &head, item)?
+    } else {
+        commit.message.clone()
+    };
+
+    let mut index = Index::read().context("Reading the index")?;
+    let result = sequencer::merge_changes(
+        cache,
+        config,
+        Action::Pick,
+        hash,
+        parent.as_deref(),
+        &diff::tree_entries(&head_tree)?,
+        &mut index,
+    )?;
+    if !result.is_clean() {
+        // As with git, merge messages are only worth showing when something went wrong
+        for (_, line) in &result.messages {
+            println!("{line}");
+        }
+        write_state("message", &format!("{message}\n"))?;
+        write_state("stopped-sha", &format!("{hash}\n"))?;
+        fs::write(REBASE_HEAD, format!("{hash}\n")).context("Writing REBASE_HEAD")?;
+        fs::write(
+            MERGE_MSG,
+            format!("{message}{}", sequencer::conflicts_footer(&result)),
+        )
+        .context("Writing MERGE_MSG")?;
+        let short = &hash[..7];
+        let subject = sequencer::subject(&commit.message);
+        anyhow::bail!(
+            "could not apply {short}... {subject}\n\
+             hint: Resolve all conflicts manually, mark them as resolved with\n\
+             hint: \"add/rm <conflicted_files>\", then run \"rebase --continue\".\n\
+             hint: You can instead skip this commit: run \"rebase --skip\".\n\
+             hint: To abort and get back to the state before \"rebase\", run \"rebase --abort\"."
+        );
+    }
+
+    let tree = hex::encode(objects::write_tree_from_entries(&diff::index_entries(
+        &index,
+    ))?);
+    if !item.command.is_fixup() {
+        // A commit whose parent is HEAD can be kept as it is
+        if commit.parents.first() == Some(&head) {
+            refs::update_ref("HEAD", hash)?;
+            if item.command == Command::Reword {
+                amend(config, &commit.message)?;
+            }
+            return Ok(());
+        }
+        let parent_tree = match &parent {
+            Some(parent) => Some(cache.get(parent)?.tree.clone()),
+            None => None,
+        };
+        if tree == head_tree && parent_tree.as_deref() != Some(commit.tree.as_str()) {
+            eprintln!(
+                "dropping {hash} {} -- patch contents already upstream",
+                sequencer::subject(&commit.message)
+            );
+            return Ok(());
+        }
+    }
+    commit_item(cache, config, item, &tree, &message, next, false)
+}
+
+/// Commits `tree` for `item`: a new commit on top of HEAD for `pick` and `reword`, an
+/// amended HEAD for `squash` and `fixup`, which opens the editor on the combined message
+/// once the last of a chain containing a `squash` is in. `resumed` is set when committing
+/// resolved conflicts, which like a reworded commit get a summary.
+fn commit_item(
+    cache: &mut CommitCache,
+    config: &Config,
+    item: &TodoItem,
+    tree: &str,
+    message: &str,
+    next: Option<Command>,
+    resumed: bool,
+) -> anyhow::Result<()> {
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    let committer = ident::committer(config)?;
+    if !item.command.is_fixup() {
+        let author = cache.get(&item.arg)?.author.clone();
+        let mut message = message.to_string();
+        if item.command == Command::Reword {
+            message = edit_message(config, &message)?;
+        }
+        let commit = objects::write_commit(
+            tree,
+            std::slice::from_ref(&head),
+            &author,
+            &committer,
+            &message,
+        )?;
+        refs::update_ref("HEAD", &commit)?;
+        if item.command == Command::Reword || resumed {
+            sequencer::print_summary(&commit, !resumed)?;
+        }
+        return Ok(());
+    }
+
+    let amended = cache.get(&head)?.clone();
+    // Committing resolved conflicts ends the chain; later fixups start a new one
+    let chain_ends = resumed || !next.is_some_and(Command::is_fixup);
+    let fixups = read_state("current-fixups").unwrap_or_default();
+    let squashed = fixups.lines().any(|line| line.starts_with("squash "));
+    let final_message = if chain_ends && squashed {
+        edit_message(config, message)?
+    } else {
+        sequencer::cleanup_message(message)
+    };
+    let commit = objects::write_commit(
+        tree,
+        &amended.parents,
+        &amended.author,
+        &committer,
+        &final_message,
+    )?;
+    refs::update_ref("HEAD", &commit)?;
+    if chain_ends {
+        for name in ["message-squash", "current-fixups"] {
+            let path = Path::new(REBASE_DIR).join(name);
+            if path.exists() {
+                fs::remove_file(&path).with_context(|| format!("Removing {}", path.display()))?;
+            }
+        }
+        if squashed || resumed {
+            sequencer::print_summary(&commit, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds the message of a `squash` or `fixup` item to the combined message of the chain,
+/// which starts with the message of HEAD, and returns the new combined message. Messages
+/// of fixups are commented out, so that only the squashed ones survive.
+fn add_fixup(cache: &mut CommitCache, head: &str, item: &TodoItem) -> anyhow::Result<String> {
+    let mut fixups = read_state("current-fixups").unwrap_or_default();
+    let mut combined = match read_state("message-squash") {
+        Ok(combined) if !fixups.is_empty() => combined,
+        _ => {
+            fixups.clear();
+            format!(
+                "# This is a combination of 2 commits.\n\
+                 # This is the 1st commit message:\n\n{}",
+                cache.get(head)?.message
+            )
+        }
+    };
+    if !fixups.is_empty() {
+        fixups.push('\n');
+    }
+    fixups.push_str(&format!("{} {}", item.command.word(), item.arg));
+    let count = fixups.lines().count() + 1;
+    if let Some((_, rest)) = combined.split_once('\n') {
+        combined = format!("# This is a combination of {count} commits.\n{rest}");
+    }
+    if !combined.ends_with('\n') {
+        combined.push('\n');
+    }
+
+    let message = &cache.get(&item.arg)?.message;
+    if item.command == Command::Squash {
+        combined.push_str(&format!(
+            "\n# This is the commit message #{count}:\n\n{message}"
+        ));
+    } else {
+        combined.push_str(&format!(
+            "\n# The commit message #{count} will be skipped:\n\n"
+        ));
+        for line in message.lines() {
+            if line.is_empty() {
+                combined.push_str("#\n");
+            } else {
+                combined.push_str(&format!("# {line}\n"));
+            }
+        }
+    }
+    write_state("message-squash", &combined)?;
+    write_state("current-fixups", &format!("{fixups}\n"))?;
+    Ok(combined)
+}
+
+/// Replaces HEAD by a commit with the same tree, parents and author but a message edited
+/// from `message`, and prints a summary of it.
+fn amend(config: &Config, message: &str) -> anyhow::Result<()> {
+    let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+    let commit = objects::read_commit(&head)?;
+    let message = edit_message(config, message)?;
+    let amended = objects::write_commit(
+        &commit.tree,
+        &commit.parents,
+        &commit.author,
+        &ident::committer(config)?,
+        &message,
+    )?;
+    refs::update_ref("HEAD", &amended)?;
+    sequencer::print_summary(&amended, true)
+}
+
+/// Lets the user edit a commit message in `.git/COMMIT_EDITMSG` and returns it cleaned up.
+fn edit_message(config: &Config, message: &str) -> anyhow::Result<String> {
+    let mut text = message.to_string();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(
+        "\n# Please enter the commit message for your changes. Lines starting\n\
+         # with '#' will be ignored, and an empty message aborts the commit.\n",
+    );
+    fs::write(COMMIT_EDITMSG, text).context("Writing COMMIT_EDITMSG")?;
+    run_editor(&editor(config, false), Path::new(COMMIT_EDITMSG))?;
+    let edited = fs::read_to_string(COMMIT_EDITMSG).context("Reading COMMIT_EDITMSG")?;
+    let message = sequencer::cleanup_message(&edited);
+    anyhow::ensure!(
+        !message.is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+    Ok(message)
+}
+
+/// Writes the todo list with abbreviated hashes and a help text for the user to edit, and
+/// reads back what they left.
+fn edit_todo(
+    cache: &mut CommitCache,
+    todo: &[TodoItem],
+    upstream: &str,
+    head: &str,
+    onto: &str,
+) -> anyhow::Result<Vec<TodoItem>> {
+    let mut text = String::new();
+    for item in todo {
+        let subject = sequencer::subject(&cache.get(&item.arg)?.message).to_string();
+        text.push_str(&format!(
+            "{} {} {subject}\n",
+            item.command.word(),
+            &item.arg[..7]
+        ));
+    }
+    let commands = if todo.len() == 1 {
+        "1 command".to_string()
+    } else {
+        format!("{} commands", todo.len())
+    };
+    text.push_str(&format!(
+        "\n# Rebase {}..{} onto {} ({commands})\n\
+         #\n\
+         # Commands:\n\
+         # p, pick <commit> = use commit\n\
+         # r, reword <commit> = use commit, but edit the commit message\n\
+         # s, squash <commit> = use commit, but meld into previous commit\n\
+         # f, fixup <commit> = like \"squash\" but keep only the previous\n\
+         #                    commit's log message\n\
+         # x, exec <command> = run command (the rest of the line) using shell\n\
+         # d, drop <commit> = remove commit\n\
+         #\n\
+         # These lines can be re-ordered; they are executed from top to bottom.\n\
+         #\n\
+         # If you remove a line here THAT COMMIT WILL BE LOST.\n\
+         #\n\
+         # However, if you remove everything, the rebase will be aborted.\n\
+         #\n",
+        &upstream[..7],
+        &head[..7],
+        &onto[..7]
+    ));
+    let path = Path::new(REBASE_DIR).join("git-rebase-todo");
+    fs::write(&path, text).context("Writing .git/rebase-merge/git-rebase-todo")?;
+    run_editor(&editor(&Config::read()?, true), &path)?;
+
+    let todo = read_todo("git-rebase-todo")?;
+    anyhow::ensure!(!todo.is_empty(), "nothing to do");
+    Ok(todo)
+}
+
+/// The editor to run: for the todo list `GIT_SEQUENCE_EDITOR` or `sequence.editor` first,
+/// then as for commit messages `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR` and `vi`.
+fn editor(config: &Config, sequence: bool) -> String {
+    let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let sequence_editor = if sequence {
+        env("GIT_SEQUENCE_EDITOR").or_else(|| config.get("sequence.editor").map(str::to_string))
+    } else {
+        None
+    };
+    sequence_editor
+        .or_else(|| env("GIT_EDITOR"))
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| env("VISUAL"))
+        .or_else(|| env("EDITOR"))
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Runs `editor` on `path` through the shell, as the editor setting may carry arguments.
+fn run_editor(editor: &str, path: &Path) -> anyhow::Result<()> {
+    if editor == ":" {
+        return Ok(());
+    }
+    let status = process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Running the editor {editor}"))?;
+    anyhow::ensure!(
+        status.success(),
+        "There was a problem with the editor '{editor}'."
+    );
+    Ok(())
+}
+
+/// Checks out `name` before rebasing it: a branch is switched to, anything else detached.
+fn switch_to(name: &str) -> anyhow::Result<()> {
+    let branch = format!("refs/heads/{name}");
+    let target = resolve_commit(name)?;
+    let mut index = Index::read().context("Reading the index")?;
+    let head_entries = match refs::head_commit()? {
+        Some(head) => diff::tree_entries(&objects::read_commit(&head)?.tree)?,
+        None => Entries::new(),
+    };
+    merge::checkout_commit(&mut index, &head_entries, &target, "checkout")?;
+    if refs::resolve_ref(&branch)?.is_some() {
+        refs::attach_head(&branch)
+    } else {
+        refs::update_ref("HEAD", &target)
+    }
+}
+
+/// Refuses to start with changes in the index or the worktree, which replaying commits
+/// would mix with theirs.
+fn check_clean(head: &str) -> anyhow::Result<()> {
+    let index = Index::read().context("Reading the index")?;
+    let staged = diff::index_entries(&index);
+    anyhow::ensure!(
+        worktree::worktree_entries(&index)? == staged,
+        "cannot rebase: You have unstaged changes.\n\
+         error: Please commit or stash them."
+    );
+    anyhow::ensure!(
+        diff::tree_entries(&objects::read_commit(head)?.tree)? == staged,
+        "cannot rebase: Your index contains uncommitted changes.\n\
+         error: Please commit or stash them."
+    );
+    Ok(())
+}
+
+/// The branch `branch.<name>.remote` and `branch.<name>.merge` say the current branch
+/// tracks, as the upstream when none is given.
+fn tracking_branch(head_name: Option<&str>) -> anyhow::Result<String> {
+    let config = Config::read()?;
+    let tracked = head_name
+        .and_then(|name| name.strip_prefix("refs/heads/"))
+        .and_then(|branch| {
+            let remote = config.get(&format!("branch.{branch}.remote"))?;
+            let merge = config.get(&format!("branch.{branch}.merge"))?;
+            Some(match remote {
+                "." => merge.to_string(),
+                remote => format!(
+                    "refs/remotes/{remote}/{}",
+                    merge.trim_start_matches("refs/heads/")
+                ),
+            })
+        });
+    tracked.context(
+        "There is no tracking information for the current branch.\n\
+         Please specify which branch you want to rebase against.",
+    )
+}
+
+fn resolve_commit(name: &str) -> anyhow::Result<String> {
+    refs::resolve(name)
+        .and_then(|hash| refs::peel_to_commit(&hash))
+        .with_context(|| format!("invalid upstream '{name}'"))
+}
+
+fn remove_stop_state() -> anyhow::Result<()> {
+    for path in [
+        Path::new(REBASE_DIR).join("message"),
+        Path::new(REBASE_DIR).join("stopped-sha"),
+        Path::new(REBASE_HEAD).to_path_buf(),
+        Path::new(MERGE_MSG).to_path_buf(),
+    ] {
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Removing {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_state(name: &str, contents: &str) -> anyhow::Result<()> {
+    fs::write(Path::new(REBASE_DIR).join(name), contents)
+        .with_context(|| format!("Writing .git/rebase-merge/{name}"))
+}
+
+fn read_state(name: &str) -> anyhow::Result<String> {
+    fs::read_to_string(Path::new(REBASE_DIR).join(name))
+        .with_context(|| format!("Reading .git/rebase-merge/{name}"))
+}
+
+fn save_todo(cache: &mut CommitCache, todo: &[TodoItem]) -> anyhow::Result<()> {
+    let mut contents = String::new();
+    for item in todo {
+        contents.push_str(&format!("{}\n", item.line(cache)?));
+    }
+    write_state("git-rebase-todo", &contents)
+}
+
+/// Parses a todo list from `.git/rebase-merge`, resolving the commit of each line (which
+/// may be any revision) to its full hash.
+fn read_todo(name: &str) -> anyhow::Result<Vec<TodoItem>> {
+    let mut todo = Vec::new();
+    for (number, line) in read_state(name)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let invalid = || format!("invalid line {}: {line}", number + 1);
+        let command = Command::parse(word).with_context(invalid)?;
+        let arg = if command == Command::Exec {
+            rest.trim().to_string()
+        } else {
+            let name = rest.split_whitespace().next().with_context(invalid)?;
+            refs::resolve(name)
+                .and_then(|hash| refs::peel_to_commit(&hash))
+                .with_context(invalid)?
+        };
+        anyhow::ensure!(!arg.is_empty(), "{}", invalid());
+        todo.push(TodoItem { command, arg });
+    }
+    Ok(todo)
+}
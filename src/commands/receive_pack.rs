@@ -0,0 +1,34 @@
+use crate::protocol::pkt_line::PktWriter;
+use crate::server::{self, receive_pack};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub(crate) struct ReceivePackOptions {
+    /// Answer one request and quit, as over HTTP, rather than holding a conversation.
+    pub(crate) stateless_rpc: bool,
+    /// Only send the advertisement that starts a conversation.
+    pub(crate) advertise_refs: bool,
+}
+
+/// `receive-pack [--stateless-rpc] [--advertise-refs] <directory>`: the server side of a
+/// push, on stdin and stdout. It sends the repository's refs, stores the pack the client
+/// sends, checks that the updated refs lead only to objects we have, runs the
+/// `pre-receive`, `update` and `post-receive` hooks, and reports on each ref.
+pub(crate) fn receive_pack_invoke(dir: &Path, opts: &ReceivePackOptions) -> anyhow::Result<()> {
+    let _link = server::enter_repository(dir)?;
+    let version = server::protocol_version(&std::env::var("GIT_PROTOCOL").unwrap_or_default());
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if opts.advertise_refs || !opts.stateless_rpc {
+        let mut writer = PktWriter::new(&mut out);
+        receive_pack::advertise(&mut writer, version)?;
+        writer.flush()?;
+    }
+    if !opts.advertise_refs {
+        receive_pack::serve(io::stdin().lock(), &mut out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
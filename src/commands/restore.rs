@@ -0,0 +1,138 @@
+use crate::commands::clone::checkout_empty::handle_file_creation;
+use crate::diff;
+use crate::index::{Index, IndexEntry};
+use crate::pathspec;
+use crate::promisor;
+use crate::refs;
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+pub(crate) struct RestoreOptions<'a> {
+    /// Tree-ish to take contents from; `None` means the index (or HEAD when only staging).
+    pub(crate) source: Option<&'a str>,
+    pub(crate) staged: bool,
+    pub(crate) worktree: bool,
+    /// In overlay mode paths missing from the source are left alone instead of being removed.
+    pub(crate) overlay: bool,
+}
+
+pub(crate) fn restore_invoke(
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+    pathspecs: &[String],
+) -> anyhow::Result<()> {
+    restore_paths(
+        &RestoreOptions {
+            source,
+            staged,
+            // Without either flag restore only touches the worktree
+            worktree: worktree || !staged,
+            overlay: false,
+        },
+        pathspecs,
+    )
+}
+
+pub(crate) fn restore_paths(opts: &RestoreOptions, pathspecs: &[String]) -> anyhow::Result<()> {
+    anyhow::ensure!(!pathspecs.is_empty(), "you must specify path(s) to restore");
+
+    let mut index = Index::read().context("Reading the index")?;
+
+    let source_rev = match opts.source {
+        Some(rev) => Some(rev),
+        None if opts.staged => Some("HEAD"),
+        None => None,
+    };
+    let source: BTreeMap<String, (u32, [u8; 20])> = match source_rev {
+        Some("HEAD") if refs::head_commit()?.is_none() => BTreeMap::new(),
+        Some(rev) => {
+            let tree = refs::peel_to_tree(&refs::resolve(rev)?)
+                .with_context(|| format!("could not resolve {rev}"))?;
+            diff::tree_entries(&tree)?
+        }
+        None => diff::index_entries(&index),
+    };
+
+    let mut tracked: BTreeSet<&str> = source.keys().map(String::as_str).collect();
+    if !opts.overlay {
+        tracked.extend(index.entries.iter().map(|e| e.path.as_str()));
+    }
+    let matched: Vec<String> = tracked
+        .into_iter()
+        .filter(|path| pathspec::matches_any(path, pathspecs))
+        .map(str::to_string)
+        .collect();
+
+    for spec in pathspecs {
+        anyhow::ensure!(
+            matched.iter().any(|path| pathspec::matches(path, spec)),
+            "pathspec '{spec}' did not match any file(s) known to git"
+        );
+    }
+
+    if opts.worktree {
+        promisor::prefetch(matched.iter().filter_map(|path| source.get(path).copied()))?;
+    }
+    for path in &matched {
+        if source_rev.is_none() && !source.contains_key(path) && index.is_unmerged(path) {
+            anyhow::bail!("path '{path}' is unmerged");
+        }
+
+        match source.get(path) {
+            Some(&(mode, hash)) => {
+                if opts.worktree {
+                    handle_file_creation(
+                        &format!("{mode:o}"),
+                        path,
+                        &hex::encode(hash),
+                        Path::new(""),
+                    )
+                    .with_context(|| format!("Restoring {path}"))?;
+                }
+                if opts.staged {
+                    let entry = if opts.worktree {
+                        IndexEntry::from_worktree(path, mode, hash)?
+                    } else {
+                        IndexEntry::new(path, mode, hash, 0)
+                    };
+                    index.add(entry);
+                }
+            }
+            None => {
+                if opts.worktree {
+                    remove_worktree_file(path)?;
+                }
+                if opts.staged {
+                    index.remove(path);
+                }
+            }
+        }
+    }
+
+    if opts.staged {
+        index.write().context("Writing the updated index")?;
+    }
+    Ok(())
+}
+
+/// Deletes a tracked file and any directories that become empty because of it.
+pub(crate) fn remove_worktree_file(path: &str) -> anyhow::Result<()> {
+    let path = Path::new(path);
+    // Nothing to do when the file is gone or a directory has taken its place
+    if fs::symlink_metadata(path).map_or(true, |m| m.is_dir()) {
+        return Ok(());
+    }
+    fs::remove_file(path).with_context(|| format!("Removing {}", path.display()))?;
+
+    let mut dir = path.parent();
+    while let Some(d) = dir.filter(|d| !d.as_os_str().is_empty()) {
+        if fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
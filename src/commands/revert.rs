@@ -0,0 +1,22 @@
+use crate::sequencer::{self, Action, ReplayOptions};
+
+/// `revert [-n] [-m <parent>] <commit>...`: commits the reverse of the changes each commit
+/// introduced, with a `Revert "<subject>"` message.
+pub(crate) fn revert_invoke(commits: &[String], opts: &ReplayOptions) -> anyhow::Result<()> {
+    sequencer::replay(Action::Revert, commits, opts)
+}
+
+/// `revert --continue`: commits the resolved conflicts and reverts the remaining commits.
+pub(crate) fn revert_continue() -> anyhow::Result<()> {
+    sequencer::continue_replay(Action::Revert)
+}
+
+/// `revert --skip`: drops the commit that conflicted and reverts the remaining ones.
+pub(crate) fn revert_skip() -> anyhow::Result<()> {
+    sequencer::skip_replay(Action::Revert)
+}
+
+/// `revert --abort`: goes back to the commit HEAD was on before reverting started.
+pub(crate) fn revert_abort() -> anyhow::Result<()> {
+    sequencer::abort_replay()
+}
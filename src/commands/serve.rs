@@ -0,0 +1,28 @@
+use crate::server::http;
+use anyhow::Context;
+use std::net::TcpListener;
+use std::path::Path;
+
+#[derive(Debug)]
+pub(crate) struct ServeOptions {
+    /// The address to listen on.
+    pub(crate) listen: String,
+    pub(crate) port: u16,
+    /// Accept pushes as well as fetches.
+    pub(crate) enable_receive_pack: bool,
+}
+
+/// `serve [--listen <address>] [--port <port>] [--enable-receive-pack] <base-path>`: serves
+/// the repositories under `<base-path>` over smart HTTP, so that
+/// `http://<address>:<port>/<path>` can be cloned and fetched from with protocol version
+/// 0, 1 or 2, and pushed to when that is enabled.
+pub(crate) fn serve_invoke(base: &Path, opts: &ServeOptions) -> anyhow::Result<()> {
+    anyhow::ensure!(base.is_dir(), "'{}' is not a directory", base.display());
+    let listener = TcpListener::bind((opts.listen.as_str(), opts.port))
+        .with_context(|| format!("Listening on {}:{}", opts.listen, opts.port))?;
+    let address = listener
+        .local_addr()
+        .context("Finding the address listened on")?;
+    eprintln!("Serving {} on http://{address}/", base.display());
+    http::run(listener, base.to_path_buf(), opts.enable_receive_pack)
+}
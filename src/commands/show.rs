@@ -0,0 +1,139 @@
+use crate::commands::cat_file;
+use crate::commands::diff::{self, OutputOptions};
+use crate::commands::ls_tree::{self, Listing};
+use crate::diff::patch::PatchOptions;
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::tree;
+use crate::ident;
+use crate::objects::{self, Kind, Object};
+use crate::promisor;
+use crate::refs;
+use crate::shallow;
+use anyhow::Context;
+use std::io::Write;
+
+/// `show [<object>...]`: commits with their header and patch, annotated tags followed by
+/// the object they point at, trees as a list of names and blobs as their raw content.
+pub(crate) fn show_invoke(
+    names: &[String],
+    no_patch: bool,
+    output: OutputOptions,
+    opts: &PatchOptions,
+) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+
+    let head = ["HEAD".to_string()];
+    let names = if names.is_empty() { &head[..] } else { names };
+
+    // Everything but blobs is separated from what was shown before by a blank line
+    let mut shown_one = false;
+    for name in names {
+        let mut hash = refs::resolve(name).with_context(|| format!("Resolving {name}"))?;
+        loop {
+            let obj = Object::read(&hash).with_context(|| format!("Reading object {hash}"))?;
+            match obj.kind {
+                Kind::Blob => cat_file::write_blob(obj, &mut stdout)?,
+                Kind::Tree => {
+                    if shown_one {
+                        writeln!(stdout)?;
+                    }
+                    writeln!(stdout, "tree {name}\n")?;
+                    ls_tree::write_tree_listing(&mut stdout, &hash, Listing::NamesWithSlashes)?;
+                    shown_one = true;
+                }
+                Kind::Commit => {
+                    if shown_one {
+                        writeln!(stdout)?;
+                    }
+                    write_commit(&mut stdout, &hash, no_patch, output, opts)
+                        .with_context(|| format!("Showing commit {hash}"))?;
+                    shown_one = true;
+                }
+                Kind::Tag => {
+                    if shown_one {
+                        writeln!(stdout)?;
+                    }
+                    let tag = objects::read_tag(&hash)?;
+                    writeln!(stdout, "tag {}", tag.name)?;
+                    if let Some(tagger) = &tag.tagger {
+                        write_ident(&mut stdout, "Tagger", tagger)?;
+                    }
+                    writeln!(stdout)?;
+                    write!(stdout, "{}", tag.message)?;
+                    shown_one = true;
+                    // Go on to show what the tag points at
+                    hash = tag.object;
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn write_commit(
+    out: &mut impl Write,
+    hash: &str,
+    no_patch: bool,
+    output: OutputOptions,
+    opts: &PatchOptions,
+) -> anyhow::Result<()> {
+    let commit = objects::read_commit(hash)?;
+    writeln!(out, "commit {hash}")?;
+    if commit.parents.len() > 1 {
+        let parents: Vec<&str> = commit.parents.iter().map(|p| &p[..7]).collect();
+        writeln!(out, "Merge: {}", parents.join(" "))?;
+    }
+    write_ident(out, "Author", &commit.author)?;
+    writeln!(out)?;
+    let message = commit.message.trim_start_matches('\n').trim_end();
+    for line in message.lines() {
+        writeln!(out, "    {line}")?;
+    }
+
+    if no_patch {
+        return Ok(());
+    }
+    let summary = output.stat || output.numstat;
+    if commit.parents.len() > 1 && !summary {
+        // Git shows merges as a combined diff, which is empty for merges that resolved
+        // without conflicts; combined diffs of conflicted merges are not supported
+        writeln!(out)?;
+        return Ok(());
+    }
+
+    // A commit at the shallow boundary is shown as if it were a root
+    let parent_tree = match commit.parents.first() {
+        Some(parent) if !shallow::is_shallow(hash)? => Some(objects::read_commit(parent)?.tree),
+        _ => None,
+    };
+    let changes = tree::diff_trees(parent_tree.as_deref(), Some(&commit.tree), true)?;
+    // A partial clone fetches the blobs of the whole commit at once
+    promisor::prefetch(
+        changes
+            .iter()
+            .flat_map(|c| [(c.old.mode, c.old.hash), (c.new.mode, c.new.hash)]),
+    )?;
+    let changes = rename::detect(changes, Vec::new(), &RenameOptions::default())?;
+    if changes.is_empty() {
+        return Ok(());
+    }
+    writeln!(out)?;
+    diff::write_changes(out, &changes, output, opts)
+}
+
+/// Writes an `author`/`tagger` line as `<label>: Name <email>` followed by its date in the
+/// timezone it was recorded in.
+fn write_ident(out: &mut impl Write, label: &str, ident: &str) -> anyhow::Result<()> {
+    let Some((who, date)) = ident::split(ident) else {
+        writeln!(out, "{label}: {ident}")?;
+        return Ok(());
+    };
+    writeln!(out, "{label}: {who}")?;
+    if let Some(date) = date {
+        writeln!(out, "Date:   {date}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use crate::commands::add::list_files;
+use crate::commands::clone::checkout_empty::handle_file_creation;
+use crate::commands::merge::conflict_style;
+use crate::commands::restore::{self, remove_worktree_file, RestoreOptions};
+use crate::config::Config;
+use crate::diff::{self, worktree};
+use crate::ident;
+use crate::index::{Index, IndexEntry};
+use crate::merge;
+use crate::merge::tree::{self as tree_merge, Entries, TreeMergeOptions};
+use crate::objects;
+use crate::refs::{self, ReflogEntry};
+use crate::sequencer;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+const STASH_REF: &str = "refs/stash";
+
+#[derive(Debug, Default)]
+pub(crate) struct StashOptions {
+    /// Stash untracked files too, and remove them from the worktree.
+    pub(crate) include_untracked: bool,
+    /// Leave the staged changes in the index and worktree.
+    pub(crate) keep_index: bool,
+    pub(crate) message: Option<String>,
+}
+
+/// `stash [push] [-u] [-k] [-m <message>]`: records the index and the worktree as a stash
+/// commit on `refs/stash`, then resets both to HEAD.
+///
+/// The stash commit `W` holds the worktree of the tracked files and has HEAD, a commit `I`
+/// of the index and, with `-u`, a root commit `U` of the untracked files as its parents.
+pub(crate) fn stash_push(opts: &StashOptions) -> anyhow::Result<()> {
+    let head = refs::head_commit()?.context("You do not have the initial commit yet")?;
+    let mut index = Index::read().context("Reading the index")?;
+    anyhow::ensure!(
+        !index.entries.iter().any(|e| e.stage != 0),
+        "could not save index tree"
+    );
+
+    let head_commit = objects::read_commit(&head)?;
+    let head_entries = diff::tree_entries(&head_commit.tree)?;
+    let staged = diff::index_entries(&index);
+    let current = worktree::worktree_entries(&index)?;
+    let untracked = if opts.include_untracked {
+        untracked_files(&index)?
+    } else {
+        Vec::new()
+    };
+    if staged == head_entries && current == staged && untracked.is_empty() {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let branch = match refs::head_ref()? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "(no branch)".to_string(),
+    };
+    let on = format!(
+        "{branch}: {} {}",
+        &head[..7],
+        sequencer::subject(&head_commit.message)
+    );
+    let config = Config::read()?;
+    let author = ident::author(&config)?;
+    let committer = ident::committer(&config)?;
+    let commit = |tree: [u8; 20], parents: &[String], message: &str| {
+        objects::write_commit(&hex::encode(tree), parents, &author, &committer, message)
+    };
+
+    let index_commit = commit(
+        objects::write_tree_from_entries(&staged)?,
+        std::slice::from_ref(&head),
+        &format!("index on {on}\n"),
+    )?;
+    let mut parents = vec![head.clone(), index_commit];
+    if !untracked.is_empty() {
+        let mut entries = Entries::new();
+        for path in &untracked {
+            let meta = fs::symlink_metadata(path).with_context(|| format!("stat {path}"))?;
+            let data = worktree::read_worktree_file(path)?;
+            let hash = objects::write_blob(&data).with_context(|| format!("Stashing {path}"))?;
+            entries.insert(path.clone(), (worktree::worktree_mode(&meta), hash));
+        }
+        parents.push(commit(
+            objects::write_tree_from_entries(&entries)?,
+            &[],
+            &format!("untracked files on {on}\n"),
+        )?);
+    }
+
+    // Tracked files as they are in the worktree; deleted ones are left out
+    let mut worktree_entries = Entries::new();
+    for (path, &(mode, hash)) in &current {
+        if staged.get(path) != Some(&(mode, hash)) {
+            let data = worktree::read_worktree_file(path)?;
+            objects::write_blob(&data).with_context(|| format!("Stashing {path}"))?;
+        }
+        worktree_entries.insert(path.clone(), (mode, hash));
+    }
+    let subject = match &opts.message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {on}"),
+    };
+    let stash = commit(
+        objects::write_tree_from_entries(&worktree_entries)?,
+        &parents,
+        &subject,
+    )?;
+
+    let mut log = refs::read_reflog(STASH_REF)?;
+    log.push(ReflogEntry {
+        old: refs::resolve_ref(STASH_REF)?.unwrap_or_else(|| "0".repeat(40)),
+        new: stash.clone(),
+        ident: committer.clone(),
+        message: subject.clone(),
+    });
+    refs::update_ref(STASH_REF, &stash)?;
+    refs::write_reflog(STASH_REF, &log)?;
+    println!("Saved working directory and index state {subject}");
+
+    // Back to HEAD, or with --keep-index to the stashed index
+    let target = if opts.keep_index { &parents[1] } else { &head };
+    let target_entries = diff::tree_entries(&objects::read_commit(target)?.tree)?;
+    let mut paths: BTreeSet<String> = staged
+        .iter()
+        .filter(|(path, entry)| current.get(*path) != Some(entry))
+        .map(|(path, _)| path.clone())
+        .collect();
+    paths.extend(
+        target_entries
+            .iter()
+            .chain(&staged)
+            .filter(|(path, entry)| {
+                staged.get(*path) != Some(entry) || target_entries.get(*path) != Some(entry)
+            })
+            .map(|(path, _)| path.clone()),
+    );
+    if !paths.is_empty() {
+        restore::restore_paths(
+            &RestoreOptions {
+                source: Some(target),
+                staged: true,
+                worktree: true,
+                overlay: false,
+            },
+            &paths.into_iter().collect::<Vec<_>>(),
+        )?;
+        index = Index::read().context("Reading the index")?;
+    }
+    for path in &untracked {
+        remove_worktree_file(path)?;
+    }
+    index.write().context("Writing the index")
+}
+
+/// `stash list`: one line per stash, newest first.
+pub(crate) fn stash_list() -> anyhow::Result<()> {
+    for (n, entry) in refs::read_reflog(STASH_REF)?.iter().rev().enumerate() {
+        println!("stash@{{{n}}}: {}", entry.message);
+    }
+    Ok(())
+}
+
+/// `stash apply [<stash>]`: merges the changes recorded in a stash into the worktree,
+/// against the commit the stash was made on. Files the stash added are staged, other
+/// changes are left unstaged; untracked files are put back as they were.
+pub(crate) fn stash_apply(name: Option<&str>) -> anyhow::Result<()> {
+    let (_, stash) = resolve_stash(name)?;
+    anyhow::ensure!(
+        apply(&stash)?,
+        "Conflicts while applying the stash; resolve them and add the files."
+    );
+    Ok(())
+}
+
+/// `stash pop [<stash>]`: applies a stash and drops it, unless applying it conflicted.
+pub(crate) fn stash_pop(name: Option<&str>) -> anyhow::Result<()> {
+    let (label, stash) = resolve_stash(name)?;
+    anyhow::ensure!(
+        apply(&stash)?,
+        "The stash entry is kept in case you need it again."
+    );
+    drop_stash(&label, name)
+}
+
+/// `stash drop [<stash>]`: removes a stash from the list.
+pub(crate) fn stash_drop(name: Option<&str>) -> anyhow::Result<()> {
+    let (label, _) = resolve_stash(name)?;
+    drop_stash(&label, name)
+}
+
+/// Applies `stash` to the index and worktree; `false` when its changes conflict.
+fn apply(stash: &str) -> anyhow::Result<bool> {
+    let commit = objects::read_commit(stash)?;
+    let (Some(base), Some(_)) = (commit.parents.first(), commit.parents.get(1)) else {
+        anyhow::bail!("'{}' is not a stash-like commit", &stash[..7]);
+    };
+    let mut index = Index::read().context("Reading the index")?;
+    anyhow::ensure!(
+        !index.entries.iter().any(|e| e.stage != 0),
+        "Cannot apply a stash in the middle of a merge"
+    );
+
+    if let Some(untracked) = commit.parents.get(2) {
+        let entries = diff::tree_entries(&objects::read_commit(untracked)?.tree)?;
+        for path in entries.keys() {
+            anyhow::ensure!(
+                fs::symlink_metadata(path).is_err(),
+                "{path} already exists, no checkout\n\
+                 error: could not restore untracked files from stash"
+            );
+        }
+        for (path, (mode, hash)) in &entries {
+            handle_file_creation(
+                &format!("{mode:o}"),
+                path,
+                &hex::encode(hash),
+                Path::new(""),
+            )
+            .with_context(|| format!("Restoring {path}"))?;
+        }
+    }
+
+    let ours = diff::index_entries(&index);
+    let base_entries = diff::tree_entries(&objects::read_commit(base)?.tree)?;
+    let result = tree_merge::merge_trees(
+        &base_entries,
+        &ours,
+        &diff::tree_entries(&commit.tree)?,
+        &TreeMergeOptions {
+            style: conflict_style(&Config::read()?)?,
+            ours_label: "Updated upstream".to_string(),
+            theirs_label: "Stashed changes".to_string(),
+            base_label: "Stash base".to_string(),
+        },
+    )
+    .context("Merging trees")?;
+    merge::check_worktree(&index, &ours, &result.entries, "merge")?;
+    merge::checkout_result(&mut index, &ours, &result.entries)?;
+    for (_, message) in &result.messages {
+        println!("{message}");
+    }
+    if !result.is_clean() {
+        index.write().context("Writing the index")?;
+        return Ok(false);
+    }
+
+    // Only files new in the stash stay staged; everything else goes back to the old index
+    for (path, &(mode, hash)) in &ours {
+        let merged = index
+            .entries
+            .iter()
+            .find(|e| e.path == *path)
+            .map(|e| (e.mode, e.hash));
+        if merged != Some((mode, hash)) {
+            index.add(IndexEntry::new(path, mode, hash, 0));
+        }
+    }
+    index.write().context("Writing the index")?;
+    Ok(true)
+}
+
+/// Removes stash `label` from the reflog of `refs/stash`, pointing the ref at the newest
+/// remaining stash, or deleting it when none is left.
+fn drop_stash(label: &str, name: Option<&str>) -> anyhow::Result<()> {
+    let mut log = refs::read_reflog(STASH_REF)?;
+    let n = stash_number(name)?;
+    let removed = log.remove(log.len() - 1 - n);
+    // The entry after the dropped one now continues from the one before it
+    let newer = log.len() - n;
+    if newer < log.len() {
+        log[newer].old = match newer {
+            0 => "0".repeat(40),
+            _ => log[newer - 1].new.clone(),
+        };
+    }
+    match log.last() {
+        Some(last) => {
+            refs::update_ref(STASH_REF, &last.new)?;
+            refs::write_reflog(STASH_REF, &log)?;
+        }
+        None => refs::delete_ref(STASH_REF)?,
+    }
+    println!("Dropped {label} ({})", removed.new);
+    Ok(())
+}
+
+/// The name to report and the commit of `name` (`stash@{<n>}` or just `<n>`), which
+/// defaults to the latest stash.
+fn resolve_stash(name: Option<&str>) -> anyhow::Result<(String, String)> {
+    let log = refs::read_reflog(STASH_REF)?;
+    anyhow::ensure!(!log.is_empty(), "No stash entries found.");
+    let n = stash_number(name)?;
+    let label = match name {
+        Some(name) if name.starts_with("stash@{") => name.to_string(),
+        _ => format!("refs/stash@{{{n}}}"),
+    };
+    anyhow::ensure!(n < log.len(), "{label} is not a valid reference");
+    Ok((label, log[log.len() - 1 - n].new.clone()))
+}
+
+fn stash_number(name: Option<&str>) -> anyhow::Result<usize> {
+    let Some(name) = name else {
+        return Ok(0);
+    };
+    let n = name
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(name);
+    n.parse()
+        .with_context(|| format!("{name} is not a valid reference"))
+}
+
+/// Worktree files that are not in the index.
+fn untracked_files(index: &Index) -> anyhow::Result<Vec<String>> {
+    let mut files = BTreeSet::new();
+    list_files(Path::new(""), &mut files)?;
+    Ok(files
+        .into_iter()
+        .filter(|path| !index.entries.iter().any(|e| e.path == *path))
+        .collect())
+}
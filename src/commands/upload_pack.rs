@@ -0,0 +1,34 @@
+use crate::protocol::pkt_line::PktWriter;
+use crate::server::{self, upload_pack};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub(crate) struct UploadPackOptions {
+    /// Answer one request and quit, as over HTTP, rather than holding a conversation.
+    pub(crate) stateless_rpc: bool,
+    /// Only send the advertisement that starts a conversation.
+    pub(crate) advertise_refs: bool,
+}
+
+/// `upload-pack [--stateless-rpc] [--advertise-refs] <directory>`: the server side of a
+/// fetch, speaking the protocol version `GIT_PROTOCOL` asks for on stdin and stdout. It
+/// sends the repository's refs, finds out what the client already has and sends a pack
+/// of what it is missing.
+pub(crate) fn upload_pack_invoke(dir: &Path, opts: &UploadPackOptions) -> anyhow::Result<()> {
+    let _link = server::enter_repository(dir)?;
+    let version = server::protocol_version(&std::env::var("GIT_PROTOCOL").unwrap_or_default());
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if opts.advertise_refs || !opts.stateless_rpc {
+        let mut writer = PktWriter::new(&mut out);
+        upload_pack::advertise(&mut writer, version)?;
+        writer.flush()?;
+    }
+    if !opts.advertise_refs {
+        upload_pack::serve(io::stdin().lock(), &mut out, version, opts.stateless_rpc)?;
+    }
+    out.flush()?;
+    Ok(())
+}
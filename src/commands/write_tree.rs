@@ -2,13 +2,13 @@ use crate::objects::{Kind, Object};
 use anyhow::Context;
 use std::fs;
 use std::io::Cursor;
-use std::os::unix::fs::{ PermissionsExt};
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 fn write_tree_for(path: &Path) -> anyhow::Result<Option<[u8; 20]>> {
-    let mut dir = fs::read_dir(path).with_context(|| format!("Open dir {}", path.display()))?;
+    let dir = fs::read_dir(path).with_context(|| format!("Open dir {}", path.display()))?;
     let mut entries = Vec::new();
-    while let Some(entry) = dir.next() {
+    for entry in dir {
         let entry = entry.with_context(|| format!("Bad dir entry in {}", path.display()))?;
         entries.push(entry);
     }
@@ -48,25 +48,10 @@ fn write_tree_for(path: &Path) -> anyhow::Result<Option<[u8; 20]>> {
             };
             hash
         } else {
-            let tmp = "temporary";
-            let writer = std::fs::File::create(tmp).context("construct temp file or blob")?;
-
-            let hash = Object::blob_from_file(&path)
+            Object::blob_from_file(&path)
                 .context(" open blob input file ")?
-                .write(writer)
-                .context("stream file into blob")?;
-
-            let hex_hash = hex::encode(hash);
-
-            fs::create_dir_all(format!(".git/objects/{}/", &hex_hash[..2]))
-                .context("create subdir of git objects")?;
-            fs::rename(
-                tmp,
-                format!(".git/objects/{}/{}", &hex_hash[..2], &hex_hash[2..]),
-            )
-            .context("renaming temp file to actual hashed name")?;
-
-            hash
+                .write_to_objects()
+                .context("stream file into blob")?
         };
         tree_object.extend(mode.as_bytes());
         tree_object.push(b' ');
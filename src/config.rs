@@ -0,0 +1,261 @@
+use anyhow::Context;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings from `~/.gitconfig` and `.git/config`, in the order they were read so that
+/// later (more local) values win.
+#[derive(Debug, Default)]
+pub(crate) struct Config {
+    /// Keys are `section.name` or `section.subsection.name`, with the section and name
+    /// lowercased as git compares them case-insensitively.
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    pub(crate) fn read() -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        if let Some(home) = std::env::var_os("HOME") {
+            config.read_file(&PathBuf::from(home).join(".gitconfig"))?;
+        }
+        config.read_file(Path::new(".git/config"))?;
+        Ok(config)
+    }
+
+    /// Settings from a single file in the config format, such as `.git/sequencer/opts`.
+    pub(crate) fn read_from(path: &Path) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        config.read_file(path)?;
+        Ok(config)
+    }
+
+    fn read_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let Ok(text) = fs::read_to_string(path) else {
+            return Ok(());
+        };
+        self.parse(&text)
+            .with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    fn parse(&mut self, text: &str) -> anyhow::Result<()> {
+        let mut section = String::new();
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = parse_header(line)?;
+                continue;
+            }
+
+            let (name, raw) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim_start().to_string())),
+                // A bare key is a boolean set to true
+                None => (line, None),
+            };
+            let key = format!("{section}.{}", name.to_ascii_lowercase());
+            let value = match raw {
+                None => "true".to_string(),
+                Some(mut raw) => {
+                    // A trailing backslash continues the value on the next line
+                    while raw.ends_with('\\') && !raw.ends_with("\\\\") {
+                        raw.pop();
+                        raw.push_str(lines.next().unwrap_or(""));
+                    }
+                    parse_value(&raw)
+                }
+            };
+            self.entries.push((key, value));
+        }
+        Ok(())
+    }
+
+    /// The last value set for `key`, e.g. `user.name` or `remote.origin.url`.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The boolean `key` is set to, read as git reads one. `None` when it is unset or
+    /// not a boolean.
+    pub(crate) fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).and_then(parse_bool)
+    }
+
+    /// Every value set for a multi-valued `key` such as `remote.origin.fetch`, in order.
+    pub(crate) fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// The subsections of `section` that set `name`, such as the remotes with a `url`, in
+    /// the order they first appear.
+    pub(crate) fn subsections(&self, section: &str, name: &str) -> Vec<&str> {
+        let prefix = format!("{}.", section.to_ascii_lowercase());
+        let suffix = format!(".{}", name.to_ascii_lowercase());
+        let mut found: Vec<&str> = Vec::new();
+        for (key, _) in &self.entries {
+            let Some(sub) = key
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(&suffix))
+            else {
+                continue;
+            };
+            if !found.contains(&sub) {
+                found.push(sub);
+            }
+        }
+        found
+    }
+}
+
+/// A boolean value: `true`, `yes`, `on` or a number other than 0 for true, and `false`,
+/// `no`, `off`, `0` or nothing for false, in any case.
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        number => number.parse::<i64>().ok().map(|n| n != 0),
+    }
+}
+
+/// Adds `key = value` to the config file at `path` after the last line of the key's
+/// section, or in a new section at the end. Values already set for the key are kept, as
+/// with `git config --add`.
+pub(crate) fn add_value(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    let text = fs::read_to_string(path).unwrap_or_default();
+    let (section, name) = key
+        .rsplit_once('.')
+        .with_context(|| format!("key does not contain a section: {key}"))?;
+    let wanted = match section.split_once('.') {
+        Some((section, sub)) => format!("{}.{sub}", section.to_ascii_lowercase()),
+        None => section.to_ascii_lowercase(),
+    };
+
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut current = String::new();
+    let mut insert_at = None;
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            current = parse_header(trimmed)?;
+        }
+        if current == wanted && !trimmed.is_empty() {
+            insert_at = Some(i + 1);
+        }
+    }
+
+    let entry = format!("\t{name} = {}", quote_value(value));
+    match insert_at {
+        Some(i) => lines.insert(i, entry),
+        None => {
+            let header = match section.split_once('.') {
+                Some((section, sub)) => {
+                    let sub = sub.replace('\\', "\\\\").replace('"', "\\\"");
+                    format!("[{section} \"{sub}\"]")
+                }
+                None => format!("[{section}]"),
+            };
+            lines.push(header);
+            lines.push(entry);
+        }
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    fs::write(path, text).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// The section a `[section "subsection"]` header line starts, as keys spell it.
+fn parse_header(line: &str) -> anyhow::Result<String> {
+    let (header, _) = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .with_context(|| format!("bad section header '{line}'"))?;
+    Ok(match header.split_once(char::is_whitespace) {
+        // [section "subsection"] keeps the subsection's case
+        Some((name, sub)) => {
+            let sub = sub.trim().trim_matches('"').replace("\\\"", "\"");
+            format!("{}.{sub}", name.to_ascii_lowercase())
+        }
+        // The deprecated [section.subsection] form lowercases everything
+        None => header.to_ascii_lowercase(),
+    })
+}
+
+/// Writes a value so that `parse_value` reads it back unchanged.
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let needs_quotes = escaped.contains(['#', ';'])
+        || escaped.starts_with(char::is_whitespace)
+        || escaped.ends_with(char::is_whitespace);
+    match needs_quotes {
+        true => format!("\"{escaped}\""),
+        false => escaped,
+    }
+}
+
+/// Lowercases the section and the name of a key but not its subsection.
+fn normalize_key(key: &str) -> String {
+    let (Some((section, rest)), Some((_, name))) = (key.split_once('.'), key.rsplit_once('.'))
+    else {
+        return key.to_ascii_lowercase();
+    };
+    if rest.len() == name.len() {
+        return key.to_ascii_lowercase();
+    }
+    let sub = &rest[..rest.len() - name.len() - 1];
+    format!(
+        "{}.{sub}.{}",
+        section.to_ascii_lowercase(),
+        name.to_ascii_lowercase()
+    )
+}
+
+/// Strips quotes and inline comments from a value and resolves its escapes.
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = raw.chars();
+    // Whitespace is only kept once something follows it
+    let mut pending_space = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => break,
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(other) => value.push(other),
+                    None => {}
+                }
+            }
+            c if c.is_whitespace() && !quoted => pending_space.push(c),
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+    value
+}
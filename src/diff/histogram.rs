@@ -0,0 +1,171 @@
+//! Histogram diff, following git's xhistogram: the region is split around the longest
+//! common stretch built from the least frequent lines, and both halves are diffed again.
+//! Regions whose common lines are all very frequent are handed to Myers instead.
+
+use crate::diff::myers;
+use std::collections::HashMap;
+
+/// Lines occurring more often than this on the old side are not used to split on.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+pub(crate) fn diff(a: &[u32], b: &[u32], a_changed: &mut [bool], b_changed: &mut [bool]) {
+    histogram(a, b, 0..a.len(), 0..b.len(), a_changed, b_changed);
+}
+
+/// A common stretch `a[begin1..=end1] == b[begin2..=end2]`.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    begin1: usize,
+    end1: usize,
+    begin2: usize,
+    end2: usize,
+}
+
+enum Lcs {
+    Found(Region),
+    NothingInCommon,
+    TooCommon,
+}
+
+fn histogram(
+    a: &[u32],
+    b: &[u32],
+    mut a_range: std::ops::Range<usize>,
+    mut b_range: std::ops::Range<usize>,
+    a_changed: &mut [bool],
+    b_changed: &mut [bool],
+) {
+    loop {
+        if a_range.is_empty() || b_range.is_empty() {
+            a_changed[a_range].fill(true);
+            b_changed[b_range].fill(true);
+            return;
+        }
+
+        match find_lcs(a, b, a_range.clone(), b_range.clone()) {
+            Lcs::TooCommon => {
+                let (a_start, b_start) = (a_range.start, b_range.start);
+                myers::diff(
+                    &a[a_range.clone()],
+                    &b[b_range.clone()],
+                    &mut a_changed[a_start..a_range.end],
+                    &mut b_changed[b_start..b_range.end],
+                );
+                return;
+            }
+            Lcs::NothingInCommon => {
+                a_changed[a_range].fill(true);
+                b_changed[b_range].fill(true);
+                return;
+            }
+            Lcs::Found(lcs) => {
+                histogram(
+                    a,
+                    b,
+                    a_range.start..lcs.begin1,
+                    b_range.start..lcs.begin2,
+                    a_changed,
+                    b_changed,
+                );
+                a_range = lcs.end1 + 1..a_range.end;
+                b_range = lcs.end2 + 1..b_range.end;
+            }
+        }
+    }
+}
+
+fn find_lcs(
+    a: &[u32],
+    b: &[u32],
+    a_range: std::ops::Range<usize>,
+    b_range: std::ops::Range<usize>,
+) -> Lcs {
+    // For every distinct line: its first position in the region and how often it occurs.
+    // next[i] links each occurrence to the following one.
+    let mut records: HashMap<u32, (usize, usize)> = HashMap::new();
+    let mut next: Vec<Option<usize>> = vec![None; a_range.len()];
+    for ptr in a_range.clone().rev() {
+        match records.get_mut(&a[ptr]) {
+            Some(rec) => {
+                next[ptr - a_range.start] = Some(rec.0);
+                rec.0 = ptr;
+                rec.1 += 1;
+            }
+            None => {
+                records.insert(a[ptr], (ptr, 1));
+            }
+        }
+    }
+    let count_of = |line: u32| records[&line].1;
+    let (end1, end2) = (a_range.end - 1, b_range.end - 1);
+
+    let mut lcs: Option<Region> = None;
+    let mut cnt = MAX_CHAIN_LENGTH + 1;
+    let mut has_common = false;
+
+    let mut b_ptr = b_range.start;
+    while b_ptr <= end2 {
+        let mut b_next = b_ptr + 1;
+        if let Some(&(first, rec_cnt)) = records.get(&b[b_ptr]) {
+            has_common = true;
+            if rec_cnt <= cnt {
+                let mut as_ = first;
+                loop {
+                    let np = next[as_ - a_range.start];
+                    let mut bs = b_ptr;
+                    let (mut ae, mut be) = (as_, bs);
+                    let mut rc = rec_cnt;
+
+                    while a_range.start < as_ && b_range.start < bs && a[as_ - 1] == b[bs - 1] {
+                        as_ -= 1;
+                        bs -= 1;
+                        if rc > 1 {
+                            rc = rc.min(count_of(a[as_]));
+                        }
+                    }
+                    while ae < end1 && be < end2 && a[ae + 1] == b[be + 1] {
+                        ae += 1;
+                        be += 1;
+                        if rc > 1 {
+                            rc = rc.min(count_of(a[ae]));
+                        }
+                    }
+
+                    if b_next <= be {
+                        b_next = be + 1;
+                    }
+                    let best_len = lcs.map_or(0, |l| l.end1 - l.begin1);
+                    if best_len < ae - as_ || rc < cnt {
+                        lcs = Some(Region {
+                            begin1: as_,
+                            end1: ae,
+                            begin2: bs,
+                            end2: be,
+                        });
+                        cnt = rc;
+                    }
+
+                    // Continue with the next occurrence past the stretch just found
+                    let mut np = np;
+                    while let Some(p) = np {
+                        if p > ae {
+                            break;
+                        }
+                        np = next[p - a_range.start];
+                    }
+                    match np {
+                        Some(p) => as_ = p,
+                        None => break,
+                    }
+                }
+            }
+        }
+        b_ptr = b_next;
+    }
+
+    match lcs {
+        _ if has_common && cnt > MAX_CHAIN_LENGTH => Lcs::TooCommon,
+        Some(region) => Lcs::Found(region),
+        None => Lcs::NothingInCommon,
+    }
+}
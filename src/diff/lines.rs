@@ -0,0 +1,453 @@
+use crate::diff::{histogram, myers, patience};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Algorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "myers" | "default" => Ok(Algorithm::Myers),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Ok(Algorithm::Histogram),
+            _ => anyhow::bail!("unknown diff algorithm '{s}'"),
+        }
+    }
+}
+
+/// Splits content into lines, each keeping its trailing newline (the last may lack one).
+pub(crate) fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|b| *b == b'\n').collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+/// Diffs two lists of lines, returning one operation per line in patch order: within each
+/// changed stretch all deletions come before the insertions.
+pub(crate) fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> Vec<Op> {
+    diff_lines_with(old, new, algorithm, true)
+}
+
+/// [`diff_lines`] without the indent heuristic, which git only applies to diffs it shows
+/// and not to the diffs behind a merge.
+pub(crate) fn diff_lines_for_merge(old: &[&[u8]], new: &[&[u8]], algorithm: Algorithm) -> Vec<Op> {
+    diff_lines_with(old, new, algorithm, false)
+}
+
+fn diff_lines_with(
+    old: &[&[u8]],
+    new: &[&[u8]],
+    algorithm: Algorithm,
+    indent_heuristic: bool,
+) -> Vec<Op> {
+    // Compare small integers instead of byte strings
+    let mut ids: HashMap<&[u8], u32> = HashMap::new();
+    let mut a = Vec::with_capacity(old.len());
+    let mut b = Vec::with_capacity(new.len());
+    for (lines, out) in [(old, &mut a), (new, &mut b)] {
+        for line in lines {
+            let next = ids.len() as u32;
+            out.push(*ids.entry(line).or_insert(next));
+        }
+    }
+
+    // Mark changed lines on both sides, then slide ambiguous groups of changes into the
+    // position git would show them in
+    let mut a_changed = vec![false; a.len()];
+    let mut b_changed = vec![false; b.len()];
+    match algorithm {
+        Algorithm::Myers => myers::diff(&a, &b, &mut a_changed, &mut b_changed),
+        Algorithm::Patience => patience::diff(&a, &b, &mut a_changed, &mut b_changed),
+        Algorithm::Histogram => histogram::diff(&a, &b, &mut a_changed, &mut b_changed),
+    }
+    compact(&a, old, &mut a_changed, &b_changed, indent_heuristic);
+    compact(&b, new, &mut b_changed, &a_changed, indent_heuristic);
+
+    let mut ops = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && a_changed[i] {
+            ops.push(Op::Delete { old: i });
+            i += 1;
+        } else if j < b.len() && b_changed[j] {
+            ops.push(Op::Insert { new: j });
+            j += 1;
+        } else {
+            ops.push(Op::Equal { old: i, new: j });
+            i += 1;
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// A changed region: `old_len` lines at `old_start` were replaced by `new_len` lines at
+/// `new_start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Edit {
+    pub(crate) old_start: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_len: usize,
+}
+
+/// Groups the operations of [`diff_lines`] into maximal changed regions.
+pub(crate) fn edits(ops: &[Op]) -> Vec<Edit> {
+    let mut edits: Vec<Edit> = Vec::new();
+    let (mut old, mut new) = (0, 0);
+    let mut in_edit = false;
+    for op in ops {
+        match *op {
+            Op::Equal { .. } => {
+                in_edit = false;
+                old += 1;
+                new += 1;
+                continue;
+            }
+            _ if !in_edit => {
+                edits.push(Edit {
+                    old_start: old,
+                    old_len: 0,
+                    new_start: new,
+                    new_len: 0,
+                });
+                in_edit = true;
+            }
+            _ => {}
+        }
+        let edit = edits.last_mut().expect("an edit was just started");
+        if let Op::Delete { .. } = op {
+            edit.old_len += 1;
+            old += 1;
+        } else {
+            edit.new_len += 1;
+            new += 1;
+        }
+    }
+    edits
+}
+
+/// A run of changed lines `start..end`; empty groups sit between two unchanged lines.
+#[derive(Debug, Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Group {
+        let end = changed.iter().take_while(|c| **c).count();
+        Group { start: 0, end }
+    }
+
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_up(&mut self, lines: &[u32], changed: &mut [bool]) -> bool {
+        if self.start == 0 || lines[self.start - 1] != lines[self.end - 1] {
+            return false;
+        }
+        self.start -= 1;
+        self.end -= 1;
+        changed[self.start] = true;
+        changed[self.end] = false;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, lines: &[u32], changed: &mut [bool]) -> bool {
+        if self.end == lines.len() || lines[self.start] != lines[self.end] {
+            return false;
+        }
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+        true
+    }
+}
+
+/// Port of xdiff's `xdl_change_compact`: every group of changes is slid as far down as it
+/// goes, then back up to line up with a change on the other side if it can, or otherwise
+/// to the position the indent heuristic scores best when that is enabled.
+fn compact(
+    lines: &[u32],
+    text: &[&[u8]],
+    changed: &mut [bool],
+    other_changed: &[bool],
+    indent_heuristic: bool,
+) {
+    const MAX_SLIDING: usize = 100;
+
+    let mut g = Group::first(changed);
+    let mut go = Group::first(other_changed);
+    loop {
+        if g.end != g.start {
+            let mut group_size;
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                group_size = g.end - g.start;
+                end_matching_other = None;
+
+                while g.slide_up(lines, changed) {
+                    go.previous(other_changed);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+
+                while g.slide_down(lines, changed) {
+                    go.next(other_changed);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+                if group_size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // The group cannot move
+            } else if end_matching_other.is_some() {
+                while go.end == go.start {
+                    g.slide_up(lines, changed);
+                    go.previous(other_changed);
+                }
+            } else if indent_heuristic {
+                let mut shift = earliest_end
+                    .max(g.end.saturating_sub(group_size + 1))
+                    .max(g.end.saturating_sub(MAX_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                while shift <= g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&measure_split(text, shift));
+                    score.add(&measure_split(text, shift - group_size));
+                    if best.map_or(true, |(_, b)| score.cmp(&b) <= 0) {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                if let Some((best_shift, _)) = best {
+                    while g.end > best_shift {
+                        g.slide_up(lines, changed);
+                        go.previous(other_changed);
+                    }
+                }
+            }
+        }
+
+        if !g.next(changed) {
+            break;
+        }
+        go.next(other_changed);
+    }
+}
+
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+
+/// Indentation width of a line with tabs expanded, or -1 for a blank line.
+fn indent_of(line: &[u8]) -> i32 {
+    let mut indent = 0;
+    for &c in line {
+        if !c.is_ascii_whitespace() {
+            return indent;
+        }
+        if c == b' ' {
+            indent += 1;
+        } else if c == b'\t' {
+            indent += 8 - indent % 8;
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// What surrounds a candidate split point between `split - 1` and `split`.
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: i32,
+    pre_blank: i32,
+    pre_indent: i32,
+    post_blank: i32,
+    post_indent: i32,
+}
+
+fn measure_split(text: &[&[u8]], split: usize) -> SplitMeasurement {
+    let mut m = SplitMeasurement {
+        end_of_file: split >= text.len(),
+        indent: text.get(split).map_or(-1, |l| indent_of(l)),
+        pre_blank: 0,
+        pre_indent: -1,
+        post_blank: 0,
+        post_indent: -1,
+    };
+    for line in text[..split.min(text.len())].iter().rev() {
+        m.pre_indent = indent_of(line);
+        if m.pre_indent != -1 {
+            break;
+        }
+        m.pre_blank += 1;
+        if m.pre_blank == MAX_BLANKS {
+            m.pre_indent = 0;
+            break;
+        }
+    }
+    for line in text.iter().skip(split + 1) {
+        m.post_indent = indent_of(line);
+        if m.post_indent != -1 {
+            break;
+        }
+        m.post_blank += 1;
+        if m.post_blank == MAX_BLANKS {
+            m.post_indent = 0;
+            break;
+        }
+    }
+    m
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if m.end_of_file {
+            self.penalty += 21;
+        }
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += -30 * total_blank;
+        self.penalty += 6 * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            // No adjustment needed
+        } else if indent > m.pre_indent {
+            self.penalty += if any_blanks { 10 } else { -4 };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += if any_blanks { 17 } else { 24 };
+        } else {
+            self.penalty += if any_blanks { 17 } else { 23 };
+        }
+    }
+
+    fn cmp(&self, other: &SplitScore) -> i32 {
+        let indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+        60 * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// A group of changes together with up to `context` unchanged lines around it.
+#[derive(Debug)]
+pub(crate) struct Hunk<'a> {
+    pub(crate) old_start: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_len: usize,
+    pub(crate) ops: &'a [Op],
+}
+
+/// Groups `ops` into hunks, merging changes separated by at most `2 * context` lines.
+pub(crate) fn hunks(ops: &[Op], context: usize) -> Vec<Hunk<'_>> {
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal { .. }))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut hunks = Vec::new();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(context);
+        let mut last = changed[k];
+        while k + 1 < changed.len() && changed[k + 1] - last <= 2 * context + 1 {
+            k += 1;
+            last = changed[k];
+        }
+        let end = (last + context + 1).min(ops.len());
+        k += 1;
+
+        let slice = &ops[start..end];
+        // Lines before the hunk on each side tell us where it starts
+        let (old_start, new_start) = ops[..start].iter().fold((0, 0), |(o, n), op| match op {
+            Op::Equal { .. } => (o + 1, n + 1),
+            Op::Delete { .. } => (o + 1, n),
+            Op::Insert { .. } => (o, n + 1),
+        });
+        let old_len = slice
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert { .. }))
+            .count();
+        let new_len = slice
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete { .. }))
+            .count();
+        hunks.push(Hunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+            ops: slice,
+        });
+    }
+    hunks
+}
@@ -0,0 +1,202 @@
+pub(crate) mod histogram;
+pub(crate) mod lines;
+pub(crate) mod myers;
+pub(crate) mod patch;
+pub(crate) mod patience;
+pub(crate) mod rename;
+pub(crate) mod tree;
+pub(crate) mod worktree;
+
+use crate::index::{self, Index};
+use crate::objects;
+use std::collections::BTreeMap;
+
+/// The all-zero hash git prints for the missing side of an added or deleted entry.
+pub(crate) const NULL_HASH: [u8; 20] = [0; 20];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    Added,
+    Deleted,
+    Modified,
+    TypeChanged,
+    /// A rename with the similarity of the two sides as a percentage.
+    Renamed(u32),
+    Copied(u32),
+}
+
+impl Status {
+    pub(crate) fn letter(&self) -> char {
+        match self {
+            Status::Added => 'A',
+            Status::Deleted => 'D',
+            Status::Modified => 'M',
+            Status::TypeChanged => 'T',
+            Status::Renamed(_) => 'R',
+            Status::Copied(_) => 'C',
+        }
+    }
+
+    /// The status as git prints it, with the similarity of renames and copies (`R086`).
+    pub(crate) fn code(&self) -> String {
+        match self {
+            Status::Renamed(score) | Status::Copied(score) => {
+                format!("{}{score:03}", self.letter())
+            }
+            _ => self.letter().to_string(),
+        }
+    }
+}
+
+/// One side of a change; `mode` is 0 and `hash` is [`NULL_HASH`] when the path is absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Side {
+    pub(crate) path: String,
+    pub(crate) mode: u32,
+    pub(crate) hash: [u8; 20],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Change {
+    pub(crate) status: Status,
+    pub(crate) old: Side,
+    pub(crate) new: Side,
+}
+
+impl Change {
+    pub(crate) fn added(path: &str, mode: u32, hash: [u8; 20]) -> Self {
+        Change {
+            status: Status::Added,
+            old: Side {
+                path: path.to_string(),
+                mode: 0,
+                hash: NULL_HASH,
+            },
+            new: Side {
+                path: path.to_string(),
+                mode,
+                hash,
+            },
+        }
+    }
+
+    pub(crate) fn deleted(path: &str, mode: u32, hash: [u8; 20]) -> Self {
+        Change {
+            status: Status::Deleted,
+            old: Side {
+                path: path.to_string(),
+                mode,
+                hash,
+            },
+            new: Side {
+                path: path.to_string(),
+                mode: 0,
+                hash: NULL_HASH,
+            },
+        }
+    }
+
+    /// A change to an entry present on both sides; the status is `T` when the kind of
+    /// entry (regular file, symlink, submodule) differs and `M` otherwise.
+    pub(crate) fn modified(path: &str, old: (u32, [u8; 20]), new: (u32, [u8; 20])) -> Self {
+        let status = if old.0 & 0o170000 == new.0 & 0o170000 {
+            Status::Modified
+        } else {
+            Status::TypeChanged
+        };
+        Change {
+            status,
+            old: Side {
+                path: path.to_string(),
+                mode: old.0,
+                hash: old.1,
+            },
+            new: Side {
+                path: path.to_string(),
+                mode: new.0,
+                hash: new.1,
+            },
+        }
+    }
+
+    /// The path a change is reported under.
+    pub(crate) fn path(&self) -> &str {
+        &self.new.path
+    }
+
+    /// The path, or for renames and copies the source and destination separated by a tab.
+    pub(crate) fn paths(&self) -> String {
+        match self.status {
+            Status::Renamed(_) | Status::Copied(_) => {
+                format!("{}\t{}", self.old.path, self.new.path)
+            }
+            _ => self.path().to_string(),
+        }
+    }
+
+    /// Formats the change like `git diff --raw` does, with full hashes.
+    pub(crate) fn raw(&self) -> String {
+        format!(
+            ":{:06o} {:06o} {} {} {}\t{}",
+            self.old.mode,
+            self.new.mode,
+            hex::encode(self.old.hash),
+            hex::encode(self.new.hash),
+            self.status.code(),
+            self.paths()
+        )
+    }
+}
+
+/// Compares two flat path -> (mode, hash) listings, such as a flattened tree and the index.
+pub(crate) fn diff_entries(
+    old: &BTreeMap<String, (u32, [u8; 20])>,
+    new: &BTreeMap<String, (u32, [u8; 20])>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut old_iter = old.iter().peekable();
+    let mut new_iter = new.iter().peekable();
+    loop {
+        match (old_iter.peek(), new_iter.peek()) {
+            (Some((op, &o)), Some((np, &n))) if op == np => {
+                if o != n {
+                    changes.push(Change::modified(op, o, n));
+                }
+                old_iter.next();
+                new_iter.next();
+            }
+            (Some((op, &(mode, hash))), Some((np, _))) if op < np => {
+                changes.push(Change::deleted(op, mode, hash));
+                old_iter.next();
+            }
+            (Some((op, &(mode, hash))), None) => {
+                changes.push(Change::deleted(op, mode, hash));
+                old_iter.next();
+            }
+            (_, Some((np, &(mode, hash)))) => {
+                changes.push(Change::added(np, mode, hash));
+                new_iter.next();
+            }
+            (None, None) => break,
+        }
+    }
+    changes
+}
+
+/// Flattens a tree into path -> (mode, hash) for comparing with the index or the worktree.
+pub(crate) fn tree_entries(tree_hash: &str) -> anyhow::Result<BTreeMap<String, (u32, [u8; 20])>> {
+    objects::flatten_tree(tree_hash)?
+        .into_iter()
+        .map(|(path, entry)| Ok((path, (index::parse_mode(&entry.mode)?, entry.hash))))
+        .collect()
+}
+
+/// The merged (stage 0) entries of the index as path -> (mode, hash).
+pub(crate) fn index_entries(index: &Index) -> BTreeMap<String, (u32, [u8; 20])> {
+    index
+        .entries
+        .iter()
+        .filter(|e| e.stage == 0)
+        .map(|e| (e.path.clone(), (e.mode, e.hash)))
+        .collect()
+}
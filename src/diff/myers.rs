@@ -0,0 +1,484 @@
+//! Myers' O(ND) diff as implemented by git's xdiff: common ends are trimmed, lines with
+//! no counterpart are discarded up front, and the rest is split recursively around the
+//! middle snake in linear space, with xdiff's cost heuristics for very different inputs.
+
+use std::collections::HashMap;
+
+const MAX_COST_MIN: isize = 256;
+const HEUR_MIN_COST: isize = 256;
+const SNAKE_CNT: isize = 20;
+const K_HEUR: isize = 4;
+const MAX_EQLIMIT: usize = 1024;
+const SIMSCAN_WINDOW: isize = 100;
+const KPDIS_RUN: isize = 4;
+
+/// Marks the lines of `a` and `b` that are not part of the common subsequence.
+pub(crate) fn diff(a: &[u32], b: &[u32], a_changed: &mut [bool], b_changed: &mut [bool]) {
+    let shortest = a.len().min(b.len());
+    let start = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let tail = a[start..]
+        .iter()
+        .rev()
+        .zip(b[start..].iter().rev())
+        .take(shortest - start)
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_end, b_end) = (a.len() - tail, b.len() - tail);
+
+    let mut a_counts: HashMap<u32, usize> = HashMap::new();
+    let mut b_counts: HashMap<u32, usize> = HashMap::new();
+    for line in a {
+        *a_counts.entry(*line).or_default() += 1;
+    }
+    for line in b {
+        *b_counts.entry(*line).or_default() += 1;
+    }
+
+    let (a_lines, a_index) = cleanup(a, start, a_end, &b_counts, a_changed);
+    let (b_lines, b_index) = cleanup(b, start, b_end, &a_counts, b_changed);
+
+    let ndiags = a_lines.len() + b_lines.len() + 3;
+    let mut ctx = Context {
+        a: &a_lines,
+        b: &b_lines,
+        kvdf: vec![0; ndiags + 1],
+        kvdb: vec![0; ndiags + 1],
+        koff: b_lines.len() as isize + 1,
+        mxcost: bogosqrt(ndiags).max(MAX_COST_MIN as usize) as isize,
+    };
+    let mut a_marks = vec![false; a_lines.len()];
+    let mut b_marks = vec![false; b_lines.len()];
+    ctx.compare(
+        0,
+        a_lines.len() as isize,
+        0,
+        b_lines.len() as isize,
+        false,
+        &mut a_marks,
+        &mut b_marks,
+    );
+
+    for (i, changed) in a_marks.into_iter().enumerate() {
+        if changed {
+            a_changed[a_index[i]] = true;
+        }
+    }
+    for (i, changed) in b_marks.into_iter().enumerate() {
+        if changed {
+            b_changed[b_index[i]] = true;
+        }
+    }
+}
+
+fn bogosqrt(mut n: usize) -> usize {
+    let mut i = 1;
+    while n > 0 {
+        i <<= 1;
+        n >>= 2;
+    }
+    i
+}
+
+/// Drops lines of `lines[start..end]` that cannot take part in the common subsequence,
+/// marking them changed, and returns the remaining lines with their original positions.
+fn cleanup(
+    lines: &[u32],
+    start: usize,
+    end: usize,
+    other_counts: &HashMap<u32, usize>,
+    changed: &mut [bool],
+) -> (Vec<u32>, Vec<usize>) {
+    let mlim = bogosqrt(lines.len()).min(MAX_EQLIMIT);
+    // 0: no match on the other side, 1: some matches, 2: very many matches
+    let mut dis = vec![0u8; lines.len()];
+    for i in start..end {
+        let nm = other_counts.get(&lines[i]).copied().unwrap_or(0);
+        dis[i] = if nm == 0 {
+            0
+        } else if nm >= mlim {
+            2
+        } else {
+            1
+        };
+    }
+
+    let mut kept = Vec::new();
+    let mut index = Vec::new();
+    for i in start..end {
+        if dis[i] == 1
+            || (dis[i] == 2 && !clean_mmatch(&dis, i as isize, start as isize, end as isize - 1))
+        {
+            kept.push(lines[i]);
+            index.push(i);
+        } else {
+            changed[i] = true;
+        }
+    }
+    (kept, index)
+}
+
+/// Whether a line with many matches sits in a run that is mostly unmatched lines, in which
+/// case it is discarded too.
+fn clean_mmatch(dis: &[u8], i: isize, mut s: isize, mut e: isize) -> bool {
+    if i - s > SIMSCAN_WINDOW {
+        s = i - SIMSCAN_WINDOW;
+    }
+    if e - i > SIMSCAN_WINDOW {
+        e = i + SIMSCAN_WINDOW;
+    }
+
+    let (mut rdis0, mut rpdis0) = (0, 1);
+    let mut r = 1;
+    while i - r >= s {
+        match dis[(i - r) as usize] {
+            0 => rdis0 += 1,
+            2 => rpdis0 += 1,
+            _ => break,
+        }
+        r += 1;
+    }
+    if rdis0 == 0 {
+        return false;
+    }
+
+    let (mut rdis1, mut rpdis1) = (0, 1);
+    r = 1;
+    while i + r <= e {
+        match dis[(i + r) as usize] {
+            0 => rdis1 += 1,
+            2 => rpdis1 += 1,
+            _ => break,
+        }
+        r += 1;
+    }
+    if rdis1 == 0 {
+        return false;
+    }
+    rdis1 += rdis0;
+    rpdis1 += rpdis0;
+    rpdis1 * KPDIS_RUN < rpdis1 + rdis1
+}
+
+struct Split {
+    i1: isize,
+    i2: isize,
+    min_lo: bool,
+    min_hi: bool,
+}
+
+struct Context<'a> {
+    a: &'a [u32],
+    b: &'a [u32],
+    /// Furthest reaching forward and backward paths, indexed by diagonal + `koff`.
+    kvdf: Vec<isize>,
+    kvdb: Vec<isize>,
+    koff: isize,
+    mxcost: isize,
+}
+
+impl Context<'_> {
+    #[allow(clippy::too_many_arguments)]
+    fn compare(
+        &mut self,
+        mut off1: isize,
+        mut lim1: isize,
+        mut off2: isize,
+        mut lim2: isize,
+        need_min: bool,
+        a_marks: &mut [bool],
+        b_marks: &mut [bool],
+    ) {
+        while off1 < lim1 && off2 < lim2 && self.a[off1 as usize] == self.b[off2 as usize] {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.a[lim1 as usize - 1] == self.b[lim2 as usize - 1] {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            b_marks[off2 as usize..lim2 as usize].fill(true);
+        } else if off2 == lim2 {
+            a_marks[off1 as usize..lim1 as usize].fill(true);
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, need_min);
+            self.compare(
+                off1,
+                split.i1,
+                off2,
+                split.i2,
+                split.min_lo,
+                a_marks,
+                b_marks,
+            );
+            self.compare(
+                split.i1,
+                lim1,
+                split.i2,
+                lim2,
+                split.min_hi,
+                a_marks,
+                b_marks,
+            );
+        }
+    }
+
+    fn kf(&self, d: isize) -> isize {
+        self.kvdf[(d + self.koff) as usize]
+    }
+
+    fn set_kf(&mut self, d: isize, v: isize) {
+        self.kvdf[(d + self.koff) as usize] = v;
+    }
+
+    fn kb(&self, d: isize) -> isize {
+        self.kvdb[(d + self.koff) as usize]
+    }
+
+    fn set_kb(&mut self, d: isize, v: isize) {
+        self.kvdb[(d + self.koff) as usize] = v;
+    }
+
+    /// Finds where to split the box (off1, off2)..(lim1, lim2): the middle snake, or a
+    /// promising point once the edit cost gets too high.
+    fn split(
+        &mut self,
+        off1: isize,
+        lim1: isize,
+        off2: isize,
+        lim2: isize,
+        need_min: bool,
+    ) -> Split {
+        let (a, b) = (self.a, self.b);
+        let dmin = off1 - lim2;
+        let dmax = lim1 - off2;
+        let fmid = off1 - off2;
+        let bmid = lim1 - lim2;
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+
+        self.set_kf(fmid, off1);
+        self.set_kb(bmid, lim1);
+
+        let mut ec = 1;
+        loop {
+            let mut got_snake = false;
+
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_kf(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_kf(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.kf(d - 1) >= self.kf(d + 1) {
+                    self.kf(d - 1) + 1
+                } else {
+                    self.kf(d + 1)
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && a[i1 as usize] == b[i2 as usize] {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - prev1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_kf(d, i1);
+                if odd && bmin <= d && d <= bmax && self.kb(d) <= i1 {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_kb(bmin - 1, isize::MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_kb(bmax + 1, isize::MAX);
+            } else {
+                bmax -= 1;
+            }
+
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.kb(d - 1) < self.kb(d + 1) {
+                    self.kb(d - 1)
+                } else {
+                    self.kb(d + 1) - 1
+                };
+                let prev1 = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && a[i1 as usize - 1] == b[i2 as usize - 1] {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if prev1 - i1 > SNAKE_CNT {
+                    got_snake = true;
+                }
+                self.set_kb(d, i1);
+                if !odd && fmin <= d && d <= fmax && i1 <= self.kf(d) {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: true,
+                    };
+                }
+                d -= 2;
+            }
+
+            if need_min {
+                ec += 1;
+                continue;
+            }
+
+            // Past the heuristic threshold, settle for a diagonal that has made good progress
+            // and ends in a long enough snake
+            if got_snake && ec > HEUR_MIN_COST {
+                let mut best = 0;
+                let mut found = None;
+                let mut d = fmax;
+                while d >= fmin {
+                    let dd = (d - fmid).abs();
+                    let i1 = self.kf(d);
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 + SNAKE_CNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_CNT <= i2
+                        && i2 < lim2
+                    {
+                        let mut k = 1;
+                        while a[(i1 - k) as usize] == b[(i2 - k) as usize] {
+                            if k == SNAKE_CNT {
+                                best = v;
+                                found = Some((i1, i2));
+                                break;
+                            }
+                            k += 1;
+                        }
+                    }
+                    d -= 2;
+                }
+                if let Some((i1, i2)) = found {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: true,
+                        min_hi: false,
+                    };
+                }
+
+                best = 0;
+                let mut d = bmax;
+                while d >= bmin {
+                    let dd = (d - bmid).abs();
+                    let i1 = self.kb(d);
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - dd;
+                    if v > K_HEUR * ec
+                        && v > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_CNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_CNT
+                    {
+                        let mut k = 0;
+                        while a[(i1 + k) as usize] == b[(i2 + k) as usize] {
+                            if k == SNAKE_CNT - 1 {
+                                best = v;
+                                found = Some((i1, i2));
+                                break;
+                            }
+                            k += 1;
+                        }
+                    }
+                    d -= 2;
+                }
+                if let Some((i1, i2)) = found {
+                    return Split {
+                        i1,
+                        i2,
+                        min_lo: false,
+                        min_hi: true,
+                    };
+                }
+            }
+
+            // Enough is enough: take the furthest reaching path found so far
+            if ec >= self.mxcost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.kf(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if fbest < i1 + i2 {
+                        fbest = i1 + i2;
+                        fbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = off1.max(self.kb(d));
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < bbest {
+                        bbest = i1 + i2;
+                        bbest1 = i1;
+                    }
+                    d -= 2;
+                }
+
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    Split {
+                        i1: fbest1,
+                        i2: fbest - fbest1,
+                        min_lo: true,
+                        min_hi: false,
+                    }
+                } else {
+                    Split {
+                        i1: bbest1,
+                        i2: bbest - bbest1,
+                        min_lo: false,
+                        min_hi: true,
+                    }
+                };
+            }
+            ec += 1;
+        }
+    }
+}
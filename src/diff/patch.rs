@@ -0,0 +1,478 @@
+use crate::diff::lines::{self, Algorithm, Op};
+use crate::diff::{worktree, Change, Side, Status};
+use crate::objects::Object;
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PatchOptions {
+    /// Number of unchanged lines shown around each change (`-U<n>`).
+    pub(crate) context: usize,
+    pub(crate) algorithm: Algorithm,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        PatchOptions {
+            context: 3,
+            algorithm: Algorithm::Myers,
+        }
+    }
+}
+
+/// Loads the content of one side of a change: from the object store when the blob is there,
+/// otherwise from the worktree file it was hashed from.
+pub(crate) fn load_side(side: &Side) -> anyhow::Result<Vec<u8>> {
+    if side.mode == 0 {
+        return Ok(Vec::new());
+    }
+    if side.mode == 0o160000 {
+        return Ok(format!("Subproject commit {}\n", hex::encode(side.hash)).into_bytes());
+    }
+    match Object::read(&hex::encode(side.hash)) {
+        Ok(obj) => obj.into_bytes(),
+        Err(_) => worktree::read_worktree_file(&side.path),
+    }
+}
+
+/// Git treats content with a NUL byte near the start as binary.
+pub(crate) fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(8000)].contains(&0)
+}
+
+pub(crate) fn abbrev(hash: &[u8; 20]) -> String {
+    hex::encode(hash)[..7].to_string()
+}
+
+/// Writes a `diff --git` patch for a single change.
+pub(crate) fn write_patch(
+    out: &mut impl Write,
+    change: &Change,
+    opts: &PatchOptions,
+) -> anyhow::Result<()> {
+    if change.status == Status::TypeChanged {
+        // A file turned into a symlink (or similar) is shown as a deletion plus an addition
+        let deleted = Change::deleted(&change.old.path, change.old.mode, change.old.hash);
+        let added = Change::added(&change.new.path, change.new.mode, change.new.hash);
+        write_patch(out, &deleted, opts)?;
+        return write_patch(out, &added, opts);
+    }
+
+    let (old, new) = (&change.old, &change.new);
+    writeln!(out, "diff --git a/{} b/{}", old.path, new.path)?;
+    match change.status {
+        Status::Added => writeln!(out, "new file mode {:06o}", new.mode)?,
+        Status::Deleted => writeln!(out, "deleted file mode {:06o}", old.mode)?,
+        _ if old.mode != new.mode => {
+            writeln!(out, "old mode {:06o}", old.mode)?;
+            writeln!(out, "new mode {:06o}", new.mode)?;
+        }
+        _ => {}
+    }
+    if let Status::Renamed(score) | Status::Copied(score) = change.status {
+        let verb = if change.status.letter() == 'R' {
+            "rename"
+        } else {
+            "copy"
+        };
+        writeln!(out, "similarity index {score}%")?;
+        writeln!(out, "{verb} from {}", old.path)?;
+        writeln!(out, "{verb} to {}", new.path)?;
+    }
+    if old.hash == new.hash {
+        return Ok(());
+    }
+    write!(out, "index {}..{}", abbrev(&old.hash), abbrev(&new.hash))?;
+    if old.mode == new.mode {
+        write!(out, " {:06o}", new.mode)?;
+    }
+    writeln!(out)?;
+
+    let old_data = load_side(old).with_context(|| format!("Loading old {}", old.path))?;
+    let new_data = load_side(new).with_context(|| format!("Loading new {}", new.path))?;
+    let old_name = if old.mode == 0 {
+        "/dev/null".to_string()
+    } else {
+        format!("a/{}", old.path)
+    };
+    let new_name = if new.mode == 0 {
+        "/dev/null".to_string()
+    } else {
+        format!("b/{}", new.path)
+    };
+
+    if is_binary(&old_data) || is_binary(&new_data) {
+        writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+        return Ok(());
+    }
+
+    let old_lines = lines::split_lines(&old_data);
+    let new_lines = lines::split_lines(&new_data);
+    let ops = lines::diff_lines(&old_lines, &new_lines, opts.algorithm);
+    let hunks = lines::hunks(&ops, opts.context);
+    if hunks.is_empty() {
+        return Ok(());
+    }
+
+    writeln!(out, "--- {old_name}")?;
+    writeln!(out, "+++ {new_name}")?;
+    for hunk in hunks {
+        write!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(hunk.old_start, hunk.old_len),
+            hunk_range(hunk.new_start, hunk.new_len)
+        )?;
+        if let Some(func) = function_context(&old_lines[..hunk.old_start]) {
+            write!(out, " ")?;
+            out.write_all(func)?;
+        }
+        writeln!(out)?;
+
+        for op in hunk.ops {
+            let (prefix, line) = match *op {
+                Op::Equal { old, .. } => (b' ', old_lines[old]),
+                Op::Delete { old } => (b'-', old_lines[old]),
+                Op::Insert { new } => (b'+', new_lines[new]),
+            };
+            out.write_all(&[prefix])?;
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                writeln!(out)?;
+                writeln!(out, "\\ No newline at end of file")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Identifies what `changes` do, as `git patch-id` does: the same edits made to other
+/// versions of the files get the same id, since line numbers and whitespace are left
+/// out.
+pub(crate) fn patch_id(changes: &[Change]) -> anyhow::Result<[u8; 20]> {
+    let mut hasher = Sha1::new();
+    for change in changes {
+        let (old, new) = (&change.old, &change.new);
+        hasher.update(format!("diff --git a/{} b/{}\n", old.path, new.path));
+        if old.mode != new.mode {
+            hasher.update(format!("{:06o} {:06o}\n", old.mode, new.mode));
+        }
+        let old_data = load_side(old).with_context(|| format!("Loading old {}", old.path))?;
+        let new_data = load_side(new).with_context(|| format!("Loading new {}", new.path))?;
+        if is_binary(&old_data) || is_binary(&new_data) {
+            hasher.update(old.hash);
+            hasher.update(new.hash);
+            continue;
+        }
+
+        let old_lines = lines::split_lines(&old_data);
+        let new_lines = lines::split_lines(&new_data);
+        let ops = lines::diff_lines(&old_lines, &new_lines, Algorithm::Myers);
+        for hunk in lines::hunks(&ops, 3) {
+            for op in hunk.ops {
+                let (prefix, line) = match *op {
+                    Op::Equal { old, .. } => (b' ', old_lines[old]),
+                    Op::Delete { old } => (b'-', old_lines[old]),
+                    Op::Insert { new } => (b'+', new_lines[new]),
+                };
+                hasher.update([prefix]);
+                let text: Vec<u8> = line
+                    .iter()
+                    .copied()
+                    .filter(|c| !c.is_ascii_whitespace())
+                    .collect();
+                hasher.update(text);
+            }
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// The nearest preceding line that looks like a function header, i.e. starts with a
+/// letter, `_` or `$`, trimmed to the 80 bytes git shows after the hunk range.
+fn function_context<'a>(before: &[&'a [u8]]) -> Option<&'a [u8]> {
+    let line = before.iter().rev().find(|line| {
+        line.first()
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_' || *c == b'$')
+    })?;
+    let line = &line[..line.len().min(80)];
+    let end = line
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..end])
+}
+
+/// Per-file line counts used by `--stat` and `--numstat`.
+#[derive(Debug, Clone)]
+pub(crate) struct FileStat {
+    pub(crate) path: String,
+    /// `None` for binary files, which git reports by size instead.
+    pub(crate) lines: Option<(usize, usize)>,
+    pub(crate) sizes: (usize, usize),
+}
+
+pub(crate) fn file_stat(change: &Change, opts: &PatchOptions) -> anyhow::Result<FileStat> {
+    let old_data = load_side(&change.old)?;
+    let new_data = load_side(&change.new)?;
+    let lines = if change.old.hash == change.new.hash {
+        Some((0, 0))
+    } else if is_binary(&old_data) || is_binary(&new_data) {
+        None
+    } else {
+        let old_lines = lines::split_lines(&old_data);
+        let new_lines = lines::split_lines(&new_data);
+        let ops = lines::diff_lines(&old_lines, &new_lines, opts.algorithm);
+        Some(ops.iter().fold((0, 0), |(a, d), op| match op {
+            Op::Insert { .. } => (a + 1, d),
+            Op::Delete { .. } => (a, d + 1),
+            Op::Equal { .. } => (a, d),
+        }))
+    };
+    let path = match change.status {
+        Status::Renamed(_) | Status::Copied(_) => rename_name(&change.old.path, &change.new.path),
+        _ => change.path().to_string(),
+    };
+    Ok(FileStat {
+        path,
+        lines,
+        sizes: (old_data.len(), new_data.len()),
+    })
+}
+
+/// Shows a rename compactly by factoring out the directories both paths share, as in
+/// `src/{old => new}/main.rs`.
+fn rename_name(old: &str, new: &str) -> String {
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // Walk back from the ends; with a common prefix the walk may reach its final slash
+    let mut suffix = 0;
+    let floor = prefix.saturating_sub(1);
+    let (mut i, mut j) = (a.len(), b.len());
+    while i >= floor && j >= floor {
+        let (x, y) = (a.get(i), b.get(j));
+        if x != y {
+            break;
+        }
+        if x == Some(&b'/') {
+            suffix = a.len() - i;
+        }
+        if i == 0 || j == 0 {
+            break;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let old_mid = &old[prefix..prefix + a_mid];
+    let new_mid = &new[prefix..prefix + b_mid];
+    if prefix + suffix == 0 {
+        return format!("{old_mid} => {new_mid}");
+    }
+    format!(
+        "{}{{{old_mid} => {new_mid}}}{}",
+        &old[..prefix],
+        &old[old.len() - suffix..]
+    )
+}
+
+pub(crate) fn write_numstat(out: &mut impl Write, stats: &[FileStat]) -> anyhow::Result<()> {
+    for stat in stats {
+        match stat.lines {
+            Some((added, deleted)) => writeln!(out, "{added}\t{deleted}\t{}", stat.path)?,
+            None => writeln!(out, "-\t-\t{}", stat.path)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the `--stat` histogram, laid out for an 80 column terminal like git does.
+pub(crate) fn write_stat(out: &mut impl Write, stats: &[FileStat]) -> anyhow::Result<()> {
+    const WIDTH: usize = 80;
+
+    let max_change = stats
+        .iter()
+        .filter_map(|s| s.lines.map(|(a, d)| a + d))
+        .max()
+        .unwrap_or(0);
+    let max_len = stats
+        .iter()
+        .map(|s| s.path.chars().count())
+        .max()
+        .unwrap_or(0);
+    let has_binary = stats.iter().any(|s| s.lines.is_none());
+
+    let mut number_width = max_change.to_string().len();
+    if has_binary {
+        number_width = number_width.max(3);
+    }
+    // Room for the "XXX -> YYY bytes" part of binary lines
+    let bin_width = stats
+        .iter()
+        .filter(|s| s.lines.is_none())
+        .map(|s| format!("Bin {} -> {} bytes", s.sizes.0, s.sizes.1).len())
+        .max()
+        .unwrap_or(0);
+    let mut name_width = max_len;
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    if name_width + number_width + 6 + graph_width > WIDTH {
+        if graph_width + number_width + 6 > WIDTH * 3 / 8 {
+            graph_width = (WIDTH * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > WIDTH - number_width - 6 - graph_width {
+            name_width = WIDTH - number_width - 6 - graph_width;
+        } else {
+            graph_width = WIDTH - number_width - 6 - name_width;
+        }
+    }
+
+    for stat in stats {
+        let count = stat.path.chars().count();
+        let name = if count > name_width {
+            let skip = count - name_width + 3;
+            let tail: String = stat.path.chars().skip(skip).collect();
+            // Prefer cutting at a directory boundary
+            match tail.find('/') {
+                Some(i) => format!(".../{}", &tail[i + 1..]),
+                None => format!("...{tail}"),
+            }
+        } else {
+            stat.path.clone()
+        };
+
+        let Some((added, deleted)) = stat.lines else {
+            writeln!(
+                out,
+                " {name:<name_width$} | {:>number_width$} {} -> {} bytes",
+                "Bin", stat.sizes.0, stat.sizes.1
+            )?;
+            continue;
+        };
+
+        write!(
+            out,
+            " {name:<name_width$} | {:>number_width$}",
+            added + deleted
+        )?;
+        let (mut plus, mut minus) = (added, deleted);
+        if graph_width < max_change {
+            let total = scale_linear(added + deleted, graph_width, max_change);
+            let total = if total < 2 && added > 0 && deleted > 0 {
+                2
+            } else {
+                total
+            };
+            if added < deleted {
+                plus = scale_linear(added, graph_width, max_change);
+                minus = total - plus;
+            } else {
+                minus = scale_linear(deleted, graph_width, max_change);
+                plus = total - minus;
+            }
+        }
+        if added + deleted > 0 {
+            write!(out, " {}{}", "+".repeat(plus), "-".repeat(minus))?;
+        }
+        writeln!(out)?;
+    }
+
+    write_shortstat(out, stats)
+}
+
+/// Writes the `N files changed, N insertions(+), N deletions(-)` line that ends `--stat`,
+/// and makes up `--shortstat`. Binary files count as changed but add no lines.
+pub(crate) fn write_shortstat(out: &mut impl Write, stats: &[FileStat]) -> anyhow::Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let (insertions, deletions) = stats
+        .iter()
+        .filter_map(|s| s.lines)
+        .fold((0, 0), |(a, d), (added, deleted)| (a + added, d + deleted));
+    write!(
+        out,
+        " {} file{} changed",
+        stats.len(),
+        if stats.len() == 1 { "" } else { "s" }
+    )?;
+    if insertions > 0 || deletions == 0 {
+        write!(
+            out,
+            ", {insertions} insertion{}(+)",
+            if insertions == 1 { "" } else { "s" }
+        )?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(
+            out,
+            ", {deletions} deletion{}(-)",
+            if deletions == 1 { "" } else { "s" }
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Writes the `--summary` lines for created, deleted, renamed and copied files and for
+/// mode changes.
+pub(crate) fn write_summary(out: &mut impl Write, changes: &[Change]) -> anyhow::Result<()> {
+    for change in changes {
+        let (old, new) = (&change.old, &change.new);
+        match change.status {
+            Status::Added => writeln!(out, " create mode {:06o} {}", new.mode, new.path)?,
+            Status::Deleted => writeln!(out, " delete mode {:06o} {}", old.mode, old.path)?,
+            Status::Renamed(score) | Status::Copied(score) => {
+                let kind = if let Status::Renamed(_) = change.status {
+                    "rename"
+                } else {
+                    "copy"
+                };
+                let name = rename_name(&old.path, &new.path);
+                writeln!(out, " {kind} {name} ({score}%)")?;
+                if old.mode != new.mode {
+                    writeln!(out, " mode change {:06o} => {:06o}", old.mode, new.mode)?;
+                }
+            }
+            _ if old.mode != new.mode => writeln!(
+                out,
+                " mode change {:06o} => {:06o} {}",
+                old.mode, new.mode, new.path
+            )?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn scale_linear(it: usize, width: usize, max_change: usize) -> usize {
+    if it == 0 {
+        0
+    } else {
+        1 + it * (width - 1) / max_change
+    }
+}
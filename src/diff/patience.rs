@@ -0,0 +1,145 @@
+//! Patience diff, following git's xpatience: lines that occur exactly once on both sides
+//! anchor the diff, the longest run of anchors in the same order is kept, and the stretches
+//! between anchors are diffed recursively, falling back to Myers where nothing is unique.
+
+use crate::diff::myers;
+use std::collections::HashMap;
+
+pub(crate) fn diff(a: &[u32], b: &[u32], a_changed: &mut [bool], b_changed: &mut [bool]) {
+    patience(a, b, 0..a.len(), 0..b.len(), a_changed, b_changed);
+}
+
+fn patience(
+    a: &[u32],
+    b: &[u32],
+    a_range: std::ops::Range<usize>,
+    b_range: std::ops::Range<usize>,
+    a_changed: &mut [bool],
+    b_changed: &mut [bool],
+) {
+    if a_range.is_empty() || b_range.is_empty() {
+        a_changed[a_range].fill(true);
+        b_changed[b_range].fill(true);
+        return;
+    }
+
+    // For each distinct line of `a`, in order of first appearance: its position in `a` and,
+    // if it is unique on both sides, its position in `b`
+    let mut order: Vec<u32> = Vec::new();
+    let mut entries: HashMap<u32, (usize, Occurrence)> = HashMap::new();
+    for i in a_range.clone() {
+        match entries.get_mut(&a[i]) {
+            Some(entry) => entry.1 = Occurrence::NonUnique,
+            None => {
+                entries.insert(a[i], (i, Occurrence::None));
+                order.push(a[i]);
+            }
+        }
+    }
+    let mut has_matches = false;
+    for j in b_range.clone() {
+        if let Some(entry) = entries.get_mut(&b[j]) {
+            has_matches = true;
+            entry.1 = match entry.1 {
+                Occurrence::None => Occurrence::Unique(j),
+                _ => Occurrence::NonUnique,
+            };
+        }
+    }
+
+    if !has_matches {
+        a_changed[a_range].fill(true);
+        b_changed[b_range].fill(true);
+        return;
+    }
+
+    let unique: Vec<(usize, usize)> = order
+        .iter()
+        .filter_map(|line| match entries[line] {
+            (i, Occurrence::Unique(j)) => Some((i, j)),
+            _ => None,
+        })
+        .collect();
+    let anchors = longest_increasing(&unique);
+
+    if anchors.is_empty() {
+        let (a_start, b_start) = (a_range.start, b_range.start);
+        myers::diff(
+            &a[a_range.clone()],
+            &b[b_range.clone()],
+            &mut a_changed[a_start..a_range.end],
+            &mut b_changed[b_start..b_range.end],
+        );
+        return;
+    }
+
+    let (mut line1, mut line2) = (a_range.start, b_range.start);
+    let mut k = 0;
+    loop {
+        // Grow the common stretch backwards from the next anchor and forwards from here
+        let (mut next1, mut next2) = match anchors.get(k) {
+            Some(&(x, y)) => (x, y),
+            None => (a_range.end, b_range.end),
+        };
+        if k < anchors.len() {
+            while next1 > line1 && next2 > line2 && a[next1 - 1] == b[next2 - 1] {
+                next1 -= 1;
+                next2 -= 1;
+            }
+        }
+        while line1 < next1 && line2 < next2 && a[line1] == b[line2] {
+            line1 += 1;
+            line2 += 1;
+        }
+
+        if next1 > line1 || next2 > line2 {
+            patience(a, b, line1..next1, line2..next2, a_changed, b_changed);
+        }
+
+        if k >= anchors.len() {
+            return;
+        }
+        // Skip over anchors that directly follow each other
+        while k + 1 < anchors.len()
+            && anchors[k + 1].0 == anchors[k].0 + 1
+            && anchors[k + 1].1 == anchors[k].1 + 1
+        {
+            k += 1;
+        }
+        line1 = anchors[k].0 + 1;
+        line2 = anchors[k].1 + 1;
+        k += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Occurrence {
+    None,
+    Unique(usize),
+    NonUnique,
+}
+
+/// Longest subsequence of `pairs` (ordered by their first element) whose second elements
+/// increase, found with patience sorting.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut piles: Vec<usize> = Vec::new();
+    let mut back: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (i, &(_, y)) in pairs.iter().enumerate() {
+        let pile = piles.partition_point(|&p| pairs[p].1 < y);
+        back[i] = pile.checked_sub(1).map(|p| piles[p]);
+        if pile == piles.len() {
+            piles.push(i);
+        } else {
+            piles[pile] = i;
+        }
+    }
+
+    let mut chain = Vec::new();
+    let mut cur = piles.last().copied();
+    while let Some(i) = cur {
+        chain.push(pairs[i]);
+        cur = back[i];
+    }
+    chain.reverse();
+    chain
+}
@@ -0,0 +1,373 @@
+use crate::diff::patch::{self, load_side};
+use crate::diff::{Change, Side, Status};
+use crate::pathspec;
+use anyhow::Context;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+
+/// Similarity scores are fractions of this value, as in git.
+const MAX_SCORE: u64 = 60000;
+/// Pairs that are less than 50% similar are not considered renames by default.
+const DEFAULT_SCORE: u64 = MAX_SCORE / 2;
+/// Inexact detection compares every source with every destination, so it is skipped when
+/// both lists are longer than this (git's `diff.renameLimit`).
+const RENAME_LIMIT: usize = 1000;
+/// How many of the best matching sources are remembered for each destination.
+const CANDIDATES_PER_DST: usize = 4;
+/// Chunk hashes are reduced modulo this prime.
+const HASHBASE: u32 = 107927;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RenameOptions {
+    /// Also look for copies of files that were modified in the same change (`-C`).
+    pub(crate) copies: bool,
+    /// Consider every unmodified file as a copy source as well (`--find-copies-harder`).
+    pub(crate) copies_harder: bool,
+    /// Minimum similarity out of [`MAX_SCORE`] (`-M<n>`).
+    pub(crate) min_score: u64,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        RenameOptions {
+            copies: false,
+            copies_harder: false,
+            min_score: DEFAULT_SCORE,
+        }
+    }
+}
+
+/// Parses the `<n>` of `-M<n>` the way git does: digits are the fractional part of a
+/// number (`-M5` and `-M.5` are 50%, `-M90` is 90%) unless followed by `%`.
+/// An empty value selects the default of 50%.
+pub(crate) fn parse_score(arg: &str) -> anyhow::Result<u64> {
+    if arg.is_empty() {
+        return Ok(DEFAULT_SCORE);
+    }
+    let (mut num, mut scale, mut dot) = (0u64, 1u64, false);
+    let mut chars = arg.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' if !dot => {
+                scale = 1;
+                dot = true;
+            }
+            '%' if chars.peek().is_none() => {
+                scale = if dot { scale * 100 } else { 100 };
+            }
+            '0'..='9' => {
+                if scale < 100000 {
+                    scale *= 10;
+                    num = num * 10 + u64::from(c as u8 - b'0');
+                }
+            }
+            _ => anyhow::bail!("invalid similarity score '{arg}'"),
+        }
+    }
+    Ok(if num >= scale {
+        MAX_SCORE
+    } else {
+        MAX_SCORE * num / scale
+    })
+}
+
+/// The entries of `old` that no change touches, offered as copy sources by
+/// `--find-copies-harder`.
+pub(crate) fn unchanged_sides(
+    old: &BTreeMap<String, (u32, [u8; 20])>,
+    changes: &[Change],
+    pathspecs: &[String],
+) -> Vec<Side> {
+    old.iter()
+        .filter(|(path, _)| {
+            pathspec::matches_any(path, pathspecs) && !changes.iter().any(|c| c.old.path == **path)
+        })
+        .map(|(path, &(mode, hash))| Side {
+            path: path.clone(),
+            mode,
+            hash,
+        })
+        .collect()
+}
+
+/// A file the content of a new file may have come from.
+struct Source {
+    side: Side,
+    /// Deleted sources turn into renames, surviving ones can only be copied.
+    deleted: bool,
+    /// Number of pairs using this source, counting a surviving source as using itself.
+    used: usize,
+}
+
+/// Per-file content loaded lazily for similarity estimation.
+struct Content {
+    size: u64,
+    spans: Vec<(u32, u64)>,
+}
+
+/// Pairs up added files with deleted (and, for copies, modified or unchanged) files that
+/// have similar content. Matched pairs replace the added entry in `changes`; deleted files
+/// that were renamed away are dropped. Identical content is matched first, then files are
+/// compared by hashing them in line-sized chunks and counting the bytes they share.
+pub(crate) fn detect(
+    changes: Vec<Change>,
+    unchanged: Vec<Side>,
+    opts: &RenameOptions,
+) -> anyhow::Result<Vec<Change>> {
+    let copies = opts.copies || opts.copies_harder;
+
+    let mut sources = Vec::new();
+    let mut dsts = Vec::new();
+    for (i, change) in changes.iter().enumerate() {
+        match change.status {
+            Status::Added => dsts.push(i),
+            Status::Deleted => sources.push(Source {
+                side: change.old.clone(),
+                deleted: true,
+                used: 0,
+            }),
+            _ if copies => sources.push(Source {
+                side: change.old.clone(),
+                deleted: false,
+                used: 1,
+            }),
+            _ => {}
+        }
+    }
+    if opts.copies_harder {
+        sources.extend(unchanged.into_iter().map(|side| Source {
+            side,
+            deleted: false,
+            used: 1,
+        }));
+    }
+    if dsts.is_empty() || sources.is_empty() {
+        return Ok(changes);
+    }
+    sources.sort_by(|a, b| a.side.path.cmp(&b.side.path));
+
+    // dst index into `changes` -> (source index, score)
+    let mut matched: HashMap<usize, (usize, u64)> = HashMap::new();
+
+    for &dst in &dsts {
+        let target = &changes[dst].new;
+        let mut best: Option<(usize, u32)> = None;
+        for (s, source) in sources.iter().enumerate() {
+            if source.side.hash != target.hash
+                || source.side.mode & 0o170000 != target.mode & 0o170000
+            {
+                continue;
+            }
+            if source.used > 0 && !copies {
+                continue;
+            }
+            // Prefer sources that are still unused and share the file name
+            let score = u32::from(source.used == 0) + u32::from(same_basename(source, target));
+            if best.map_or(true, |(_, b)| score > b) {
+                best = Some((s, score));
+                if score == 2 {
+                    break;
+                }
+            }
+        }
+        if let Some((s, _)) = best {
+            sources[s].used += 1;
+            matched.insert(dst, (s, MAX_SCORE));
+        }
+    }
+
+    let remaining: Vec<usize> = dsts
+        .iter()
+        .copied()
+        .filter(|d| !matched.contains_key(d))
+        .collect();
+    let usable: Vec<usize> = (0..sources.len())
+        .filter(|&s| copies || sources[s].used == 0)
+        .collect();
+    if !remaining.is_empty()
+        && !usable.is_empty()
+        && remaining.len() * usable.len() <= RENAME_LIMIT * RENAME_LIMIT
+    {
+        let mut cache: HashMap<[u8; 20], Content> = HashMap::new();
+        // (score, name score, dst, src); unused slots have no dst
+        let mut candidates: Vec<Option<(u64, bool, usize, usize)>> = Vec::new();
+        for &dst in &remaining {
+            let target = &changes[dst].new;
+            let mut best = [None; CANDIDATES_PER_DST];
+            for &s in &usable {
+                let source = &sources[s];
+                let score = estimate_similarity(&source.side, target, opts.min_score, &mut cache)
+                    .with_context(|| {
+                    format!("Comparing {} with {}", source.side.path, target.path)
+                })?;
+                let candidate = Some((score, same_basename(source, target), dst, s));
+                // Replace the worst remembered candidate if this one beats it
+                let mut worst = 0;
+                for i in 1..CANDIDATES_PER_DST {
+                    if compare_candidates(&best[i], &best[worst]).is_gt() {
+                        worst = i;
+                    }
+                }
+                if compare_candidates(&best[worst], &candidate).is_gt() {
+                    best[worst] = candidate;
+                }
+            }
+            candidates.extend(best);
+        }
+        candidates.sort_by(compare_candidates);
+
+        // Renames claim unused sources first, then copies may reuse any source
+        for allow_reuse in [false, true] {
+            if allow_reuse && !copies {
+                break;
+            }
+            for &(score, _, dst, s) in candidates.iter().flatten() {
+                if score < opts.min_score {
+                    break;
+                }
+                if matched.contains_key(&dst) || (!allow_reuse && sources[s].used > 0) {
+                    continue;
+                }
+                sources[s].used += 1;
+                matched.insert(dst, (s, score));
+            }
+        }
+    }
+
+    if matched.is_empty() {
+        return Ok(changes);
+    }
+
+    let renamed_away: Vec<String> = sources
+        .iter()
+        .filter(|s| s.deleted && s.used > 0)
+        .map(|s| s.side.path.clone())
+        .collect();
+
+    let mut result = Vec::with_capacity(changes.len());
+    for (i, change) in changes.into_iter().enumerate() {
+        if let Some(&(s, score)) = matched.get(&i) {
+            // Every pair but the last one using a deleted source is a copy
+            let source = &mut sources[s];
+            source.used -= 1;
+            let similarity = (score * 100 / MAX_SCORE) as u32;
+            let status = if source.used > 0 {
+                Status::Copied(similarity)
+            } else {
+                Status::Renamed(similarity)
+            };
+            result.push(Change {
+                status,
+                old: source.side.clone(),
+                new: change.new,
+            });
+        } else if change.status != Status::Deleted || !renamed_away.contains(&change.old.path) {
+            result.push(change);
+        }
+    }
+    Ok(result)
+}
+
+fn same_basename(source: &Source, target: &Side) -> bool {
+    let base = |p: &str| p.rsplit('/').next().unwrap_or(p).to_string();
+    base(&source.side.path) == base(&target.path)
+}
+
+/// Orders candidates best first, with empty slots last.
+fn compare_candidates(
+    a: &Option<(u64, bool, usize, usize)>,
+    b: &Option<(u64, bool, usize, usize)>,
+) -> std::cmp::Ordering {
+    match (a, b) {
+        (None, None) => std::cmp::Ordering::Equal,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (Some(a), Some(b)) => b.0.cmp(&a.0).then(b.1.cmp(&a.1)),
+    }
+}
+
+/// How much of the larger file is made of chunks also found in the other, out of
+/// [`MAX_SCORE`]. Only regular files are compared, and pairs whose sizes differ too much
+/// to reach `min_score` are rejected without reading them.
+fn estimate_similarity(
+    src: &Side,
+    dst: &Side,
+    min_score: u64,
+    cache: &mut HashMap<[u8; 20], Content>,
+) -> anyhow::Result<u64> {
+    if src.mode & 0o170000 != 0o100000 || dst.mode & 0o170000 != 0o100000 {
+        return Ok(0);
+    }
+    let src_size = content(src, cache)?.size;
+    let dst_size = content(dst, cache)?.size;
+    let max_size = src_size.max(dst_size);
+    let delta_size = max_size - src_size.min(dst_size);
+    if max_size * (MAX_SCORE - min_score) < delta_size * MAX_SCORE || max_size == 0 {
+        return Ok(0);
+    }
+
+    let src_spans = &cache[&src.hash].spans;
+    let dst_spans = &cache[&dst.hash].spans;
+    let mut copied = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < src_spans.len() && j < dst_spans.len() {
+        match src_spans[i].0.cmp(&dst_spans[j].0) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                copied += src_spans[i].1.min(dst_spans[j].1);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    Ok(copied * MAX_SCORE / max_size)
+}
+
+fn content<'a>(
+    side: &Side,
+    cache: &'a mut HashMap<[u8; 20], Content>,
+) -> anyhow::Result<&'a Content> {
+    Ok(match cache.entry(side.hash) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let data = load_side(side)?;
+            entry.insert(Content {
+                size: data.len() as u64,
+                spans: hash_chunks(&data),
+            })
+        }
+    })
+}
+
+/// Splits content into chunks ending at a newline or after 64 bytes and returns, sorted by
+/// chunk hash, how many bytes fall into chunks with each hash. CRs before LFs are ignored
+/// in text so that line ending changes do not hide a rename.
+fn hash_chunks(data: &[u8]) -> Vec<(u32, u64)> {
+    let text = !patch::is_binary(data);
+    let mut counts: HashMap<u32, u64> = HashMap::new();
+    let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0u64);
+    for (i, &c) in data.iter().enumerate() {
+        if text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old1 >> 25);
+        accum1 = accum1.wrapping_add(u32::from(c));
+        n += 1;
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x9e3779b1)) % HASHBASE;
+        *counts.entry(hash).or_default() += n;
+        (accum1, accum2, n) = (0, 0, 0);
+    }
+    if n > 0 {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x9e3779b1)) % HASHBASE;
+        *counts.entry(hash).or_default() += n;
+    }
+    let mut spans: Vec<_> = counts.into_iter().collect();
+    spans.sort_unstable();
+    spans
+}
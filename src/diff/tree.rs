@@ -0,0 +1,138 @@
+use crate::diff::Change;
+use crate::index;
+use crate::objects::{self, TreeEntry};
+use anyhow::Context;
+use std::cmp::Ordering;
+
+/// Compares two trees (either may be absent, e.g. for a root commit) and returns the changed
+/// entries in tree order. Subtrees with identical hashes are skipped without being read.
+/// Without `recursive`, differing subtrees are reported as single entries.
+pub(crate) fn diff_trees(
+    old: Option<&str>,
+    new: Option<&str>,
+    recursive: bool,
+) -> anyhow::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    diff_trees_into(old, new, "", recursive, &mut changes)?;
+    Ok(changes)
+}
+
+fn diff_trees_into(
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    recursive: bool,
+    changes: &mut Vec<Change>,
+) -> anyhow::Result<()> {
+    let old = match old {
+        Some(hash) => objects::read_tree(hash)?,
+        None => Vec::new(),
+    };
+    let new = match new {
+        Some(hash) => objects::read_tree(hash)?,
+        None => Vec::new(),
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        let order = match (old.get(i), new.get(j)) {
+            (Some(a), Some(b)) => tree_order(a, b),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        match order {
+            Ordering::Less => {
+                removed(&old[i], prefix, recursive, changes)?;
+                i += 1;
+            }
+            Ordering::Greater => {
+                added(&new[j], prefix, recursive, changes)?;
+                j += 1;
+            }
+            Ordering::Equal => {
+                let (a, b) = (&old[i], &new[j]);
+                i += 1;
+                j += 1;
+                if a.hash == b.hash && a.mode == b.mode {
+                    continue;
+                }
+                let path = format!("{prefix}{}", a.name);
+                if a.is_tree() && recursive {
+                    diff_trees_into(
+                        Some(&hex::encode(a.hash)),
+                        Some(&hex::encode(b.hash)),
+                        &format!("{path}/"),
+                        recursive,
+                        changes,
+                    )
+                    .with_context(|| format!("Comparing sub-tree {path}"))?;
+                } else {
+                    changes.push(Change::modified(
+                        &path,
+                        (index::parse_mode(&a.mode)?, a.hash),
+                        (index::parse_mode(&b.mode)?, b.hash),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn added(
+    entry: &TreeEntry,
+    prefix: &str,
+    recursive: bool,
+    changes: &mut Vec<Change>,
+) -> anyhow::Result<()> {
+    let path = format!("{prefix}{}", entry.name);
+    if entry.is_tree() && recursive {
+        diff_trees_into(
+            None,
+            Some(&hex::encode(entry.hash)),
+            &format!("{path}/"),
+            recursive,
+            changes,
+        )
+    } else {
+        changes.push(Change::added(
+            &path,
+            index::parse_mode(&entry.mode)?,
+            entry.hash,
+        ));
+        Ok(())
+    }
+}
+
+fn removed(
+    entry: &TreeEntry,
+    prefix: &str,
+    recursive: bool,
+    changes: &mut Vec<Change>,
+) -> anyhow::Result<()> {
+    let path = format!("{prefix}{}", entry.name);
+    if entry.is_tree() && recursive {
+        diff_trees_into(
+            Some(&hex::encode(entry.hash)),
+            None,
+            &format!("{path}/"),
+            recursive,
+            changes,
+        )
+    } else {
+        changes.push(Change::deleted(
+            &path,
+            index::parse_mode(&entry.mode)?,
+            entry.hash,
+        ));
+        Ok(())
+    }
+}
+
+/// Git orders tree entries by name, comparing directories as if they had a trailing `/`.
+/// A file and a directory with the same name are therefore distinct entries.
+fn tree_order(a: &TreeEntry, b: &TreeEntry) -> Ordering {
+    let a_name = a.name.as_bytes().iter().chain(a.is_tree().then_some(&b'/'));
+    let b_name = b.name.as_bytes().iter().chain(b.is_tree().then_some(&b'/'));
+    a_name.cmp(b_name)
+}
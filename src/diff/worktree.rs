@@ -0,0 +1,86 @@
+use crate::index::Index;
+use crate::objects::{Kind, Object};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+/// The mode git would record for a worktree file.
+pub(crate) fn worktree_mode(meta: &fs::Metadata) -> u32 {
+    if meta.file_type().is_symlink() {
+        0o120000
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// Reads a worktree file the way it would be stored as a blob: symlinks become their target.
+pub(crate) fn read_worktree_file(path: &str) -> anyhow::Result<Vec<u8>> {
+    let meta = fs::symlink_metadata(path).with_context(|| format!("stat {path}"))?;
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path).with_context(|| format!("readlink {path}"))?;
+        Ok(target.into_os_string().into_encoded_bytes())
+    } else {
+        fs::read(path).with_context(|| format!("Reading {path}"))
+    }
+}
+
+/// Computes the blob hash of a worktree file without writing it to the object store.
+pub(crate) fn hash_worktree_file(path: &str) -> anyhow::Result<[u8; 20]> {
+    let data = read_worktree_file(path)?;
+    Object {
+        kind: Kind::Blob,
+        expected_size: data.len() as u64,
+        reader: Cursor::new(data),
+    }
+    .write(std::io::sink())
+    .with_context(|| format!("Hashing {path}"))
+}
+
+/// The current (mode, hash) of every tracked file still present in the worktree. Files whose
+/// stat data matches the index reuse the indexed hash instead of being read again.
+pub(crate) fn worktree_entries(index: &Index) -> anyhow::Result<BTreeMap<String, (u32, [u8; 20])>> {
+    let index_mtime = fs::metadata(".git/index")
+        .map(|m| (m.mtime(), m.mtime_nsec()))
+        .unwrap_or((0, 0));
+
+    let mut entries = BTreeMap::new();
+    for entry in &index.entries {
+        if entries.contains_key(&entry.path) {
+            continue;
+        }
+        if entry.mode == 0o160000 {
+            // Submodules are not looked into
+            entries.insert(entry.path.clone(), (entry.mode, entry.hash));
+            continue;
+        }
+        let Ok(meta) = fs::symlink_metadata(&entry.path) else {
+            continue;
+        };
+        if meta.is_dir() {
+            continue;
+        }
+        let mode = worktree_mode(&meta);
+
+        let mtime = (meta.mtime() as u32, meta.mtime_nsec() as u32);
+        // A file modified in the same instant the index was written may have changed
+        // without its stat data changing, so it has to be hashed
+        let racy = (i64::from(mtime.0), i64::from(mtime.1)) >= index_mtime;
+        let clean = entry.stage == 0
+            && !racy
+            && entry.mtime == mtime
+            && entry.size == meta.size() as u32
+            && entry.ino == meta.ino() as u32
+            && entry.mode == mode;
+        let hash = if clean {
+            entry.hash
+        } else {
+            hash_worktree_file(&entry.path)?
+        };
+        entries.insert(entry.path.clone(), (mode, hash));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,122 @@
+use crate::config::Config;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Offset};
+
+/// `Name <email> <timestamp> <tz>` for the author of a new commit, taken from
+/// `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` and `GIT_AUTHOR_DATE` or the `user.*` settings.
+pub(crate) fn author(config: &Config) -> anyhow::Result<String> {
+    ident(config, "AUTHOR")
+}
+
+pub(crate) fn committer(config: &Config) -> anyhow::Result<String> {
+    ident(config, "COMMITTER")
+}
+
+fn ident(config: &Config, role: &str) -> anyhow::Result<String> {
+    let name = std::env::var(format!("GIT_{role}_NAME"))
+        .ok()
+        .or_else(|| config.get("user.name").map(str::to_string));
+    let email = std::env::var(format!("GIT_{role}_EMAIL"))
+        .ok()
+        .or_else(|| config.get("user.email").map(str::to_string))
+        .or_else(|| std::env::var("EMAIL").ok());
+    let (Some(name), Some(email)) = (name, email) else {
+        anyhow::bail!(
+            "{} identity unknown: set user.name and user.email in .git/config",
+            if role == "AUTHOR" {
+                "Author"
+            } else {
+                "Committer"
+            }
+        );
+    };
+
+    let date = match std::env::var(format!("GIT_{role}_DATE")) {
+        Ok(date) => parse_date(&date)?,
+        Err(_) => {
+            let now = Local::now();
+            format!("{} {}", now.timestamp(), format_offset(now.offset().fix()))
+        }
+    };
+    Ok(format!("{name} <{email}> {date}"))
+}
+
+/// Accepts the date formats git documents for `GIT_*_DATE`: the internal
+/// `<timestamp> <tz>` (optionally prefixed with `@`), RFC 2822 and ISO 8601.
+fn parse_date(date: &str) -> anyhow::Result<String> {
+    let date = date.trim();
+    if let Some((timestamp, tz)) = date.trim_start_matches('@').split_once(' ') {
+        if timestamp.parse::<i64>().is_ok()
+            && tz.len() == 5
+            && tz.starts_with(['+', '-'])
+            && tz[1..].bytes().all(|b| b.is_ascii_digit())
+        {
+            return Ok(format!("{timestamp} {tz}"));
+        }
+    }
+
+    let parsed = DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z"))
+        .or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z"))
+        .map_err(|_| anyhow::anyhow!("invalid date format: {date}"))?;
+    Ok(format!(
+        "{} {}",
+        parsed.timestamp(),
+        format_offset(*parsed.offset())
+    ))
+}
+
+/// The Unix time of `date`, given as a timestamp, a `YYYY-MM-DD` day (midnight UTC) or one
+/// of the formats [`parse_date`] accepts.
+pub(crate) fn parse_timestamp(date: &str) -> anyhow::Result<i64> {
+    let date = date.trim();
+    if let Ok(timestamp) = date.trim_start_matches('@').parse::<i64>() {
+        return Ok(timestamp);
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        let midnight = day.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+        return Ok(midnight.and_utc().timestamp());
+    }
+    let parsed = parse_date(date)?;
+    let timestamp = parsed.split(' ').next().unwrap_or_default();
+    Ok(timestamp.parse()?)
+}
+
+fn format_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Splits a `Name <email> <timestamp> <tz>` ident into the `Name <email>` part and its
+/// date, formatted in the timezone it was recorded in as git's default date format does.
+pub(crate) fn split(ident: &str) -> Option<(&str, Option<String>)> {
+    let mut parts = ident.rsplitn(3, ' ');
+    let (tz, timestamp, who) = (parts.next()?, parts.next()?, parts.next()?);
+    let date = timestamp
+        .parse::<i64>()
+        .ok()
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .zip(parse_offset(tz))
+        .map(|(date, offset)| {
+            date.with_timezone(&offset)
+                .format("%a %b %-d %H:%M:%S %Y %z")
+                .to_string()
+        });
+    Some((who, date))
+}
+
+/// Parses a `+hhmm`/`-hhmm` timezone as stored in commits and tags.
+fn parse_offset(tz: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match tz.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 {
+        return None;
+    }
+    let value: i32 = digits.parse().ok()?;
+    FixedOffset::east_opt(sign * ((value / 100) * 3600 + (value % 100) * 60))
+}
@@ -0,0 +1,195 @@
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const INDEX_PATH: &str = ".git/index";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) ctime: (u32, u32),
+    pub(crate) mtime: (u32, u32),
+    pub(crate) dev: u32,
+    pub(crate) ino: u32,
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) size: u32,
+    pub(crate) hash: [u8; 20],
+    pub(crate) stage: u8,
+    pub(crate) path: String,
+}
+
+impl IndexEntry {
+    /// An entry with no stat information, which git treats as needing a refresh.
+    pub(crate) fn new(path: &str, mode: u32, hash: [u8; 20], stage: u8) -> Self {
+        IndexEntry {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            stage,
+            path: path.to_string(),
+        }
+    }
+
+    /// An entry whose stat information is taken from the file currently in the worktree.
+    pub(crate) fn from_worktree(path: &str, mode: u32, hash: [u8; 20]) -> anyhow::Result<Self> {
+        let meta = fs::symlink_metadata(path).with_context(|| format!("stat {path}"))?;
+        Ok(IndexEntry {
+            ctime: (meta.ctime() as u32, meta.ctime_nsec() as u32),
+            mtime: (meta.mtime() as u32, meta.mtime_nsec() as u32),
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            mode,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+            hash,
+            stage: 0,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Converts a tree entry mode such as `100644` into the numeric mode stored in the index.
+pub(crate) fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    u32::from_str_radix(mode, 8).with_context(|| format!("Invalid file mode {mode}"))
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Index {
+    pub(crate) entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    /// Reads `.git/index`, treating a missing file as an empty index.
+    pub(crate) fn read() -> anyhow::Result<Index> {
+        if !Path::new(INDEX_PATH).exists() {
+            return Ok(Index::default());
+        }
+        let data = fs::read(INDEX_PATH).context("Reading .git/index")?;
+        Index::parse(&data)
+    }
+
+    fn parse(data: &[u8]) -> anyhow::Result<Index> {
+        anyhow::ensure!(
+            data.len() >= 32 && &data[..4] == b"DIRC",
+            "index file is corrupt: bad signature"
+        );
+        let (body, checksum) = data.split_at(data.len() - 20);
+        anyhow::ensure!(
+            Sha1::digest(body).as_slice() == checksum,
+            "index file is corrupt: checksum mismatch"
+        );
+
+        let version = be32(&data[4..]);
+        anyhow::ensure!(
+            version == 2 || version == 3,
+            "index version {version} is not supported"
+        );
+        let count = be32(&data[8..]);
+
+        let mut pos = 12;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            anyhow::ensure!(pos + 62 <= body.len(), "index file is truncated");
+            let e = &body[pos..];
+            let flags = u16::from_be_bytes([e[60], e[61]]);
+            let mut name_start = 62;
+            if flags & 0x4000 != 0 {
+                // Extended flags (intent-to-add, skip-worktree) are dropped when we rewrite
+                name_start += 2;
+            }
+            let name_len = e[name_start..]
+                .iter()
+                .position(|b| *b == 0)
+                .context("index entry name is not terminated")?;
+            let path = std::str::from_utf8(&e[name_start..name_start + name_len])
+                .context("index entry path is not valid UTF-8")?
+                .to_string();
+
+            entries.push(IndexEntry {
+                ctime: (be32(&e[0..]), be32(&e[4..])),
+                mtime: (be32(&e[8..]), be32(&e[12..])),
+                dev: be32(&e[16..]),
+                ino: be32(&e[20..]),
+                mode: be32(&e[24..]),
+                uid: be32(&e[28..]),
+                gid: be32(&e[32..]),
+                size: be32(&e[36..]),
+                hash: e[40..60].try_into().expect("slice is 20 bytes"),
+                stage: ((flags >> 12) & 0x3) as u8,
+                path,
+            });
+
+            // Entries are padded with 1-8 NULs to a multiple of eight bytes
+            let entry_len = name_start + name_len;
+            pos += (entry_len + 8) & !7;
+        }
+        Ok(Index { entries })
+    }
+
+    pub(crate) fn write(&self) -> anyhow::Result<()> {
+        let mut out = Vec::new();
+        out.extend(b"DIRC");
+        out.extend(2u32.to_be_bytes());
+        out.extend((self.entries.len() as u32).to_be_bytes());
+
+        for e in &self.entries {
+            let start = out.len();
+            for field in [
+                e.ctime.0, e.ctime.1, e.mtime.0, e.mtime.1, e.dev, e.ino, e.mode, e.uid, e.gid,
+                e.size,
+            ] {
+                out.extend(field.to_be_bytes());
+            }
+            out.extend(e.hash);
+            let name_len = e.path.len().min(0xfff) as u16;
+            out.extend(((u16::from(e.stage) << 12) | name_len).to_be_bytes());
+            out.extend(e.path.as_bytes());
+            let entry_len = out.len() - start;
+            out.resize(start + ((entry_len + 8) & !7), 0);
+        }
+
+        let checksum = Sha1::digest(&out);
+        out.extend(checksum);
+
+        let tmp = ".git/index.lock";
+        fs::write(tmp, &out).context("Writing .git/index.lock")?;
+        fs::rename(tmp, INDEX_PATH).context("Replacing .git/index")?;
+        Ok(())
+    }
+
+    pub(crate) fn is_unmerged(&self, path: &str) -> bool {
+        self.entries.iter().any(|e| e.path == path && e.stage != 0)
+    }
+
+    /// Inserts an entry, replacing every existing entry (at any stage) for the same path.
+    pub(crate) fn add(&mut self, entry: IndexEntry) {
+        self.remove(&entry.path);
+        self.insert_sorted(entry);
+    }
+
+    /// Inserts an entry next to any other stages of the same path.
+    pub(crate) fn insert_sorted(&mut self, entry: IndexEntry) {
+        let pos = self.entries.partition_point(|e| {
+            (e.path.as_bytes(), e.stage) < (entry.path.as_bytes(), entry.stage)
+        });
+        self.entries.insert(pos, entry);
+    }
+
+    pub(crate) fn remove(&mut self, path: &str) {
+        self.entries.retain(|e| e.path != path);
+    }
+}
+
+fn be32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
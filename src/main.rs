@@ -1,10 +1,24 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use std::path::Path;
+use std::ffi::OsString;
 use std::path::PathBuf;
 
 pub(crate) mod commands;
+pub(crate) mod config;
+pub(crate) mod diff;
+pub(crate) mod ident;
+pub(crate) mod index;
+pub(crate) mod merge;
 pub(crate) mod objects;
+pub(crate) mod pack;
+pub(crate) mod pathspec;
+pub(crate) mod promisor;
+pub(crate) mod protocol;
+pub(crate) mod refs;
+pub(crate) mod refspec;
+pub(crate) mod sequencer;
+pub(crate) mod server;
+pub(crate) mod shallow;
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -13,6 +27,95 @@ struct Args {
     command: Command,
 }
 
+/// Rename and copy detection flags shared by `diff` and `diff-tree`.
+#[derive(Debug, clap::Args)]
+struct RenameArgs {
+    /// Detect renames, optionally with a minimum similarity such as `-M90%`
+    #[clap(
+        short = 'M',
+        long = "find-renames",
+        value_name = "n",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    find_renames: Option<String>,
+
+    /// Detect copies of files modified in the same change as well as renames
+    #[clap(
+        short = 'C',
+        long = "find-copies",
+        value_name = "n",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    find_copies: Option<String>,
+
+    /// Consider unmodified files as copy sources too
+    #[clap(long)]
+    find_copies_harder: bool,
+}
+
+impl RenameArgs {
+    /// `None` when renames are not to be detected at all.
+    fn options(&self, enabled_by_default: bool) -> Result<Option<diff::rename::RenameOptions>> {
+        let copies = self.find_copies.is_some() || self.find_copies_harder;
+        if !copies && self.find_renames.is_none() && !enabled_by_default {
+            return Ok(None);
+        }
+        let score = self
+            .find_copies
+            .as_deref()
+            .or(self.find_renames.as_deref())
+            .unwrap_or("");
+        Ok(Some(diff::rename::RenameOptions {
+            copies,
+            copies_harder: self.find_copies_harder,
+            min_score: diff::rename::parse_score(score)?,
+        }))
+    }
+}
+
+/// Options of `stash push`, which are also accepted by a bare `stash`.
+#[derive(Debug, clap::Args)]
+struct StashPushArgs {
+    /// Stash untracked files too, and remove them from the worktree
+    #[clap(short = 'u', long)]
+    include_untracked: bool,
+
+    /// Leave the staged changes in the index and worktree
+    #[clap(short = 'k', long)]
+    keep_index: bool,
+
+    #[clap(short = 'm', long)]
+    message: Option<String>,
+}
+
+impl StashPushArgs {
+    fn options(self) -> commands::stash::StashOptions {
+        commands::stash::StashOptions {
+            include_untracked: self.include_untracked,
+            keep_index: self.keep_index,
+            message: self.message,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum StashCommand {
+    /// Save the local changes and go back to HEAD
+    Push(StashPushArgs),
+    /// List the stashes, newest first
+    List,
+    /// Apply a stash (default the latest) to the worktree
+    Apply { stash: Option<String> },
+    /// Apply a stash and remove it from the list
+    Pop { stash: Option<String> },
+    /// Remove a stash from the list
+    Drop { stash: Option<String> },
+}
+
 /// Doc comment
 #[derive(Debug, Subcommand)]
 
@@ -49,14 +152,502 @@ enum Command {
     },
 
     Clone {
+        /// Check out this branch, or detach at this tag, instead of the remote's HEAD
+        #[clap(short = 'b', long)]
+        branch: Option<String>,
+
+        /// Only fetch the branch that is checked out
+        #[clap(long)]
+        single_branch: bool,
+
+        #[clap(short = 'n', long)]
+        no_checkout: bool,
+
+        /// Make a repository without a worktree
+        #[clap(long)]
+        bare: bool,
+
+        /// Make a bare repository with every remote ref copied as is
+        #[clap(long)]
+        mirror: bool,
+
+        /// Only fetch this many commits of history
+        #[clap(long, conflicts_with = "shallow_since")]
+        depth: Option<u32>,
+
+        /// Only fetch the history after this date
+        #[clap(long, value_name = "date")]
+        shallow_since: Option<String>,
+
+        /// Leave out objects (`blob:none`, `blob:limit=<n>`, `tree:0`) until they are needed
+        #[clap(long, value_name = "filter-spec")]
+        filter: Option<String>,
+
+        /// Hard-link the objects of a repository given as a `file://` URL, as for a path
+        #[clap(short = 'l', long)]
+        local: bool,
+
+        /// Copy the objects of a local repository instead of hard-linking them
+        #[clap(long)]
+        no_hardlinks: bool,
+
+        /// Use the objects of a local repository through alternates instead of copying them
+        #[clap(short = 's', long)]
+        shared: bool,
+
         url: String,
 
-        dir_path: Option<String>,
+        dir_path: Option<PathBuf>,
+    },
+
+    Fetch {
+        /// Delete remote-tracking branches that no longer exist on the remote
+        #[clap(short = 'p', long)]
+        prune: bool,
+
+        /// Update refs even when that is not a fast-forward
+        #[clap(short = 'f', long)]
+        force: bool,
+
+        /// Cut history off this many commits from each fetched tip
+        #[clap(long, conflicts_with_all = ["deepen", "shallow_since", "unshallow"])]
+        depth: Option<u32>,
+
+        /// Fetch this many more commits of history past where it is cut off
+        #[clap(long, conflicts_with_all = ["shallow_since", "unshallow"])]
+        deepen: Option<u32>,
+
+        /// Cut history off at this date
+        #[clap(long, value_name = "date", conflicts_with = "unshallow")]
+        shallow_since: Option<String>,
+
+        /// Fetch the rest of the history of a shallow repository
+        #[clap(long)]
+        unshallow: bool,
+
+        /// A configured remote or a URL; defaults to the current branch's remote or origin
+        remote: Option<String>,
+
+        refspecs: Vec<String>,
+    },
+
+    LsRemote {
+        /// Only list branches
+        #[clap(long)]
+        heads: bool,
+
+        /// Only list tags
+        #[clap(short = 't', long)]
+        tags: bool,
+
+        /// Show what symbolic refs point at
+        #[clap(long)]
+        symref: bool,
+
+        /// A configured remote or a URL; defaults to the current branch's remote or origin
+        remote: Option<String>,
+
+        /// Only list refs whose names end with one of these
+        patterns: Vec<String>,
+    },
+
+    Push {
+        /// Update remote refs even when that is not a fast-forward
+        #[clap(short = 'f', long)]
+        force: bool,
+
+        /// Force only if the remote ref is where we expect it: where its remote-tracking
+        /// ref is, or `<expect>`
+        #[clap(
+            long,
+            value_name = "ref[:expect]",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        force_with_lease: Vec<String>,
+
+        /// A configured remote or a URL; defaults to the current branch's remote or origin
+        remote: Option<String>,
+
+        refspecs: Vec<String>,
+    },
+
+    Daemon {
+        /// The address to listen on
+        #[clap(long, default_value = "127.0.0.1")]
+        listen: String,
+
+        #[clap(long, default_value_t = 9418)]
+        port: u16,
+
+        /// Serve every repository, not only those with a git-daemon-export-ok file
+        #[clap(long)]
+        export_all: bool,
+
+        base_path: PathBuf,
+    },
+
+    ReceivePack {
+        /// Answer one request and quit, as behind an HTTP server
+        #[clap(long)]
+        stateless_rpc: bool,
+
+        /// Only send the refs, with what the server can do
+        #[clap(long, alias = "http-backend-info-refs")]
+        advertise_refs: bool,
+
+        directory: PathBuf,
+    },
+
+    Serve {
+        /// The address to listen on
+        #[clap(long, default_value = "127.0.0.1")]
+        listen: String,
+
+        #[clap(long, default_value_t = 8080)]
+        port: u16,
+
+        /// Accept pushes as well
+        #[clap(long)]
+        enable_receive_pack: bool,
+
+        base_path: PathBuf,
+    },
+
+    UploadPack {
+        /// Answer one request and quit, as behind an HTTP server
+        #[clap(long)]
+        stateless_rpc: bool,
+
+        /// Only send the refs, with what the server can do
+        #[clap(long, alias = "http-backend-info-refs")]
+        advertise_refs: bool,
+
+        directory: PathBuf,
+    },
+
+    Restore {
+        #[clap(short = 's', long)]
+        source: Option<String>,
+
+        #[clap(short = 'S', long)]
+        staged: bool,
+
+        #[clap(short = 'W', long)]
+        worktree: bool,
+
+        pathspec: Vec<String>,
+    },
+
+    Checkout {
+        tree_ish: Option<String>,
+
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    DiffTree {
+        #[clap(short = 'r')]
+        recursive: bool,
+
+        #[clap(long)]
+        root: bool,
+
+        #[clap(long, conflicts_with_all = ["name_status", "raw"])]
+        name_only: bool,
+
+        #[clap(long, conflicts_with = "raw")]
+        name_status: bool,
+
+        #[clap(long)]
+        raw: bool,
+
+        #[command(flatten)]
+        renames: RenameArgs,
+
+        tree_ish: String,
+
+        other_tree_ish: Option<String>,
+
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    Diff {
+        #[clap(long, alias = "staged")]
+        cached: bool,
+
+        #[clap(short = 'U', long = "unified", default_value_t = 3)]
+        context: usize,
+
+        #[clap(long, conflicts_with_all = ["histogram", "diff_algorithm"])]
+        patience: bool,
+
+        #[clap(long, conflicts_with = "diff_algorithm")]
+        histogram: bool,
+
+        #[clap(long)]
+        diff_algorithm: Option<commands::diff::Algorithm>,
+
+        #[clap(long)]
+        stat: bool,
+
+        #[clap(long)]
+        numstat: bool,
+
+        #[clap(long, conflicts_with = "name_status")]
+        name_only: bool,
+
+        #[clap(long)]
+        name_status: bool,
+
+        #[command(flatten)]
+        renames: RenameArgs,
+
+        /// Turn off rename detection
+        #[clap(long, conflicts_with_all = ["find_renames", "find_copies", "find_copies_harder"])]
+        no_renames: bool,
+
+        revs: Vec<String>,
+
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+
+    Show {
+        #[clap(
+            short = 's',
+            long,
+            conflicts_with_all = ["stat", "numstat", "name_only", "name_status"]
+        )]
+        no_patch: bool,
+
+        #[clap(short = 'U', long = "unified", default_value_t = 3)]
+        context: usize,
+
+        #[clap(long)]
+        stat: bool,
+
+        #[clap(long)]
+        numstat: bool,
+
+        #[clap(long, conflicts_with = "name_status")]
+        name_only: bool,
+
+        #[clap(long)]
+        name_status: bool,
+
+        objects: Vec<String>,
     },
+
+    Add {
+        pathspec: Vec<String>,
+    },
+
+    Merge {
+        /// Create a merge commit even when the merge could be a fast-forward
+        #[clap(long, conflicts_with = "ff_only")]
+        no_ff: bool,
+
+        #[clap(long)]
+        ff_only: bool,
+
+        /// Stop before committing a clean merge
+        #[clap(long)]
+        no_commit: bool,
+
+        #[clap(short = 'm')]
+        message: Option<String>,
+
+        /// Abandon a conflicted merge and go back to HEAD
+        #[clap(long, conflicts_with_all = ["continue_", "commit"])]
+        abort: bool,
+
+        /// Commit a merge once its conflicts are resolved
+        #[clap(long = "continue", conflicts_with = "commit")]
+        continue_: bool,
+
+        #[clap(required_unless_present_any = ["abort", "continue_"])]
+        commit: Option<String>,
+    },
+
+    MergeBase {
+        /// Print every merge base instead of just one
+        #[clap(short = 'a', long)]
+        all: bool,
+
+        /// Exit with status 0 if the first commit is an ancestor of the second, 1 if not
+        #[clap(long, conflicts_with_all = ["all", "octopus", "independent", "fork_point"])]
+        is_ancestor: bool,
+
+        /// Find the common ancestors of all commits, for an octopus merge
+        #[clap(long, conflicts_with_all = ["independent", "fork_point"])]
+        octopus: bool,
+
+        /// List the commits that cannot be reached from any of the others
+        #[clap(long, conflicts_with_all = ["all", "fork_point"])]
+        independent: bool,
+
+        /// Find where a commit (HEAD by default) forked from a ref, using the ref's reflog
+        #[clap(long)]
+        fork_point: bool,
+
+        #[clap(required = true)]
+        commits: Vec<String>,
+    },
+
+    MergeFile {
+        /// Labels for the conflict markers, in place of the file names
+        #[clap(short = 'L', action = clap::ArgAction::Append)]
+        labels: Vec<String>,
+
+        /// Print the result instead of overwriting the current file
+        #[clap(short = 'p', long)]
+        stdout: bool,
+
+        #[clap(long, conflicts_with = "zdiff3")]
+        diff3: bool,
+
+        #[clap(long)]
+        zdiff3: bool,
+
+        #[clap(long, conflicts_with_all = ["theirs", "union"])]
+        ours: bool,
+
+        #[clap(long, conflicts_with = "union")]
+        theirs: bool,
+
+        #[clap(long)]
+        union: bool,
+
+        #[clap(long, default_value_t = 7)]
+        marker_size: usize,
+
+        current: PathBuf,
+
+        base: PathBuf,
+
+        other: PathBuf,
+    },
+
+    CherryPick {
+        /// Apply the changes to the index and worktree without committing them
+        #[clap(short = 'n', long)]
+        no_commit: bool,
+
+        /// Append "(cherry picked from commit ...)" to the commit message
+        #[clap(short = 'x')]
+        record_origin: bool,
+
+        /// For merge commits, the parent (from 1) to take the changes relative to
+        #[clap(short = 'm', long = "mainline", value_name = "parent-number")]
+        mainline: Option<usize>,
+
+        /// Commit the resolved conflicts and pick the remaining commits
+        #[clap(long = "continue", conflicts_with_all = ["skip", "abort", "commits"])]
+        continue_: bool,
+
+        /// Drop the commit that conflicted and pick the remaining ones
+        #[clap(long, conflicts_with_all = ["abort", "commits"])]
+        skip: bool,
+
+        /// Go back to where HEAD was before picking started
+        #[clap(long, conflicts_with = "commits")]
+        abort: bool,
+
+        #[clap(required_unless_present_any = ["continue_", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+
+    Revert {
+        /// Apply the reverse changes to the index and worktree without committing them
+        #[clap(short = 'n', long)]
+        no_commit: bool,
+
+        /// For merge commits, the parent (from 1) to revert the changes relative to
+        #[clap(short = 'm', long = "mainline", value_name = "parent-number")]
+        mainline: Option<usize>,
+
+        /// Commit the resolved conflicts and revert the remaining commits
+        #[clap(long = "continue", conflicts_with_all = ["skip", "abort", "commits"])]
+        continue_: bool,
+
+        /// Drop the commit that conflicted and revert the remaining ones
+        #[clap(long, conflicts_with_all = ["abort", "commits"])]
+        skip: bool,
+
+        /// Go back to where HEAD was before reverting started
+        #[clap(long, conflicts_with = "commits")]
+        abort: bool,
+
+        #[clap(required_unless_present_any = ["continue_", "skip", "abort"])]
+        commits: Vec<String>,
+    },
+
+    Rebase {
+        /// Replay the commits on top of this commit rather than the upstream
+        #[clap(long, value_name = "newbase")]
+        onto: Option<String>,
+
+        /// Edit the todo list before replaying, with $GIT_SEQUENCE_EDITOR if set
+        #[clap(short = 'i', long)]
+        interactive: bool,
+
+        /// Commit the resolved conflicts and replay the remaining commits
+        #[clap(long = "continue", conflicts_with_all = ["skip", "abort", "upstream"])]
+        continue_: bool,
+
+        /// Drop the commit that conflicted and replay the remaining ones
+        #[clap(long, conflicts_with_all = ["abort", "upstream"])]
+        skip: bool,
+
+        /// Go back to the branch as it was before the rebase started
+        #[clap(long, conflicts_with = "upstream")]
+        abort: bool,
+
+        /// The commits of the branch not in this one are replayed; defaults to its upstream
+        upstream: Option<String>,
+
+        /// Check out this branch first
+        branch: Option<String>,
+    },
+
+    #[command(args_conflicts_with_subcommands = true)]
+    Stash {
+        #[command(subcommand)]
+        command: Option<StashCommand>,
+
+        #[clap(flatten)]
+        push: StashPushArgs,
+    },
+}
+
+/// Git spells similarity thresholds attached to the flag (`-M90%`, `-C5`), while clap only
+/// takes optional values after an `=`, so the attached form is rewritten before parsing.
+fn attach_score_values(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut rewritten = Vec::new();
+    let mut after_separator = false;
+    for arg in args {
+        let text = arg.to_string_lossy();
+        after_separator |= text == "--";
+        let attached = !after_separator
+            && text.len() > 2
+            && (text.starts_with("-M") || text.starts_with("-C"))
+            && text[2..]
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '.' || c == '%');
+        if attached {
+            rewritten.push(format!("{}={}", &text[..2], &text[2..]).into());
+        } else {
+            rewritten.push(arg);
+        }
+    }
+    rewritten
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let args = Args::parse_from(attach_score_values(std::env::args_os()));
 
     match args.command {
         Command::Init => {
@@ -88,12 +679,431 @@ fn main() -> Result<()> {
             commands::commit_tree::commit_tree_invoke(&tree_hash, parent.as_deref(), &message)?;
         }
 
-        Command::Clone { url, dir_path } => {
+        Command::Clone {
+            branch,
+            single_branch,
+            no_checkout,
+            bare,
+            mirror,
+            depth,
+            shallow_since,
+            filter,
+            local,
+            no_hardlinks,
+            shared,
+            url,
+            dir_path,
+        } => {
             commands::clone::clone::clone_invoke(
                 &url,
-                Path::new(dir_path.as_deref().unwrap_or(".")),
+                dir_path.as_deref(),
+                &commands::clone::clone::CloneOptions {
+                    branch,
+                    single_branch,
+                    no_checkout,
+                    bare,
+                    mirror,
+                    depth,
+                    shallow_since,
+                    filter,
+                    local,
+                    no_hardlinks,
+                    shared,
+                },
             )?;
         }
+
+        Command::Fetch {
+            prune,
+            force,
+            depth,
+            deepen,
+            shallow_since,
+            unshallow,
+            remote,
+            refspecs,
+        } => {
+            let opts = commands::fetch::FetchOptions {
+                prune,
+                force,
+                depth,
+                deepen,
+                shallow_since,
+                unshallow,
+            };
+            if !commands::fetch::fetch_invoke(remote.as_deref(), &refspecs, &opts)? {
+                std::process::exit(1);
+            }
+        }
+
+        Command::LsRemote {
+            heads,
+            tags,
+            symref,
+            remote,
+            patterns,
+        } => {
+            let opts = commands::ls_remote::LsRemoteOptions {
+                heads,
+                tags,
+                symref,
+            };
+            commands::ls_remote::ls_remote_invoke(remote.as_deref(), &patterns, &opts)?;
+        }
+
+        Command::Push {
+            force,
+            force_with_lease,
+            remote,
+            refspecs,
+        } => {
+            let opts = commands::push::PushOptions {
+                force,
+                force_with_lease,
+            };
+            if !commands::push::push_invoke(remote.as_deref(), &refspecs, &opts)? {
+                std::process::exit(1);
+            }
+        }
+
+        Command::Daemon {
+            listen,
+            port,
+            export_all,
+            base_path,
+        } => {
+            let opts = commands::daemon::DaemonOptions {
+                listen,
+                port,
+                export_all,
+            };
+            commands::daemon::daemon_invoke(&base_path, &opts)?;
+        }
+
+        Command::ReceivePack {
+            stateless_rpc,
+            advertise_refs,
+            directory,
+        } => {
+            let opts = commands::receive_pack::ReceivePackOptions {
+                stateless_rpc,
+                advertise_refs,
+            };
+            commands::receive_pack::receive_pack_invoke(&directory, &opts)?;
+        }
+
+        Command::Serve {
+            listen,
+            port,
+            enable_receive_pack,
+            base_path,
+        } => {
+            let opts = commands::serve::ServeOptions {
+                listen,
+                port,
+                enable_receive_pack,
+            };
+            commands::serve::serve_invoke(&base_path, &opts)?;
+        }
+
+        Command::UploadPack {
+            stateless_rpc,
+            advertise_refs,
+            directory,
+        } => {
+            let opts = commands::upload_pack::UploadPackOptions {
+                stateless_rpc,
+                advertise_refs,
+            };
+            commands::upload_pack::upload_pack_invoke(&directory, &opts)?;
+        }
+
+        Command::Restore {
+            source,
+            staged,
+            worktree,
+            pathspec,
+        } => {
+            commands::restore::restore_invoke(source.as_deref(), staged, worktree, &pathspec)?;
+        }
+
+        Command::Checkout { tree_ish, paths } => {
+            commands::checkout::checkout_invoke(tree_ish.as_deref(), &paths)?;
+        }
+
+        Command::DiffTree {
+            recursive,
+            root,
+            name_only,
+            name_status,
+            raw: _,
+            renames,
+            tree_ish,
+            other_tree_ish,
+            paths,
+        } => {
+            let format = if name_only {
+                commands::diff_tree::Format::NameOnly
+            } else if name_status {
+                commands::diff_tree::Format::NameStatus
+            } else {
+                commands::diff_tree::Format::Raw
+            };
+            commands::diff_tree::diff_tree_invoke(
+                recursive,
+                root,
+                format,
+                &tree_ish,
+                other_tree_ish.as_deref(),
+                &paths,
+                renames.options(false)?.as_ref(),
+            )?;
+        }
+
+        Command::Diff {
+            cached,
+            context,
+            patience,
+            histogram,
+            diff_algorithm,
+            stat,
+            numstat,
+            name_only,
+            name_status,
+            renames,
+            no_renames,
+            revs,
+            paths,
+        } => {
+            let algorithm = if patience {
+                commands::diff::Algorithm::Patience
+            } else if histogram {
+                commands::diff::Algorithm::Histogram
+            } else {
+                diff_algorithm.unwrap_or_default()
+            };
+            commands::diff::diff_invoke(
+                cached,
+                &revs,
+                &paths,
+                commands::diff::OutputOptions {
+                    stat,
+                    numstat,
+                    name_only,
+                    name_status,
+                },
+                &diff::patch::PatchOptions { context, algorithm },
+                renames.options(!no_renames)?.as_ref(),
+            )?;
+        }
+
+        Command::Show {
+            no_patch,
+            context,
+            stat,
+            numstat,
+            name_only,
+            name_status,
+            objects,
+        } => {
+            commands::show::show_invoke(
+                &objects,
+                no_patch,
+                commands::diff::OutputOptions {
+                    stat,
+                    numstat,
+                    name_only,
+                    name_status,
+                },
+                &diff::patch::PatchOptions {
+                    context,
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        Command::Add { pathspec } => {
+            commands::add::add_invoke(&pathspec)?;
+        }
+
+        Command::Merge {
+            no_ff,
+            ff_only,
+            no_commit,
+            message,
+            abort,
+            continue_,
+            commit,
+        } => {
+            if abort {
+                commands::merge::merge_abort()?;
+            } else if continue_ {
+                commands::merge::merge_continue()?;
+            } else if let Some(commit) = commit {
+                commands::merge::merge_invoke(
+                    &commit,
+                    &commands::merge::MergeOptions {
+                        no_ff,
+                        ff_only,
+                        no_commit,
+                        message,
+                    },
+                )?;
+            }
+        }
+
+        Command::MergeBase {
+            all,
+            is_ancestor,
+            octopus,
+            independent,
+            fork_point,
+            commits,
+        } => {
+            let mode = if is_ancestor {
+                commands::merge_base::Mode::IsAncestor
+            } else if octopus {
+                commands::merge_base::Mode::Octopus
+            } else if independent {
+                commands::merge_base::Mode::Independent
+            } else if fork_point {
+                commands::merge_base::Mode::ForkPoint
+            } else {
+                commands::merge_base::Mode::Default
+            };
+            if !commands::merge_base::merge_base_invoke(mode, all, &commits)? {
+                std::process::exit(1);
+            }
+        }
+
+        Command::MergeFile {
+            labels,
+            stdout,
+            diff3,
+            zdiff3,
+            ours,
+            theirs,
+            union,
+            marker_size,
+            current,
+            base,
+            other,
+        } => {
+            let style = if diff3 {
+                Some(merge::file::ConflictStyle::Diff3)
+            } else if zdiff3 {
+                Some(merge::file::ConflictStyle::ZealousDiff3)
+            } else {
+                None
+            };
+            let favor = if ours {
+                Some(merge::file::Favor::Ours)
+            } else if theirs {
+                Some(merge::file::Favor::Theirs)
+            } else if union {
+                Some(merge::file::Favor::Union)
+            } else {
+                None
+            };
+            let conflicts = commands::merge_file::merge_file_invoke(
+                [&current, &base, &other],
+                &labels,
+                style,
+                favor,
+                marker_size,
+                stdout,
+            )?;
+            // Like git, the exit status is the number of conflicts
+            if conflicts > 0 {
+                std::process::exit(conflicts.min(127) as i32);
+            }
+        }
+
+        Command::CherryPick {
+            no_commit,
+            record_origin,
+            mainline,
+            continue_,
+            skip,
+            abort,
+            commits,
+        } => {
+            if continue_ {
+                commands::cherry_pick::cherry_pick_continue()?;
+            } else if skip {
+                commands::cherry_pick::cherry_pick_skip()?;
+            } else if abort {
+                commands::cherry_pick::cherry_pick_abort()?;
+            } else {
+                commands::cherry_pick::cherry_pick_invoke(
+                    &commits,
+                    &sequencer::ReplayOptions {
+                        no_commit,
+                        record_origin,
+                        mainline,
+                    },
+                )?;
+            }
+        }
+
+        Command::Revert {
+            no_commit,
+            mainline,
+            continue_,
+            skip,
+            abort,
+            commits,
+        } => {
+            if continue_ {
+                commands::revert::revert_continue()?;
+            } else if skip {
+                commands::revert::revert_skip()?;
+            } else if abort {
+                commands::revert::revert_abort()?;
+            } else {
+                commands::revert::revert_invoke(
+                    &commits,
+                    &sequencer::ReplayOptions {
+                        no_commit,
+                        mainline,
+                        ..Default::default()
+                    },
+                )?;
+            }
+        }
+
+        Command::Rebase {
+            onto,
+            interactive,
+            continue_,
+            skip,
+            abort,
+            upstream,
+            branch,
+        } => {
+            if continue_ {
+                commands::rebase::rebase_continue()?;
+            } else if skip {
+                commands::rebase::rebase_skip()?;
+            } else if abort {
+                commands::rebase::rebase_abort()?;
+            } else {
+                commands::rebase::rebase_invoke(
+                    upstream.as_deref(),
+                    branch.as_deref(),
+                    &commands::rebase::RebaseOptions { onto, interactive },
+                )?;
+            }
+        }
+
+        Command::Stash { command, push } => match command {
+            None => commands::stash::stash_push(&push.options())?,
+            Some(StashCommand::Push(push)) => commands::stash::stash_push(&push.options())?,
+            Some(StashCommand::List) => commands::stash::stash_list()?,
+            Some(StashCommand::Apply { stash }) => commands::stash::stash_apply(stash.as_deref())?,
+            Some(StashCommand::Pop { stash }) => commands::stash::stash_pop(stash.as_deref())?,
+            Some(StashCommand::Drop { stash }) => commands::stash::stash_drop(stash.as_deref())?,
+        },
     }
     Ok(())
 }
@@ -0,0 +1,337 @@
+use crate::objects::{self, Commit};
+use crate::refs;
+use crate::shallow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Commits read during a history walk, so that each is parsed only once. Commits at the
+/// shallow boundary have no parents here, as their parents are not in the repository.
+#[derive(Default)]
+pub(crate) struct CommitCache {
+    commits: HashMap<String, Commit>,
+    generations: HashMap<String, u32>,
+}
+
+impl CommitCache {
+    pub(crate) fn get(&mut self, hash: &str) -> anyhow::Result<&Commit> {
+        if !self.commits.contains_key(hash) {
+            let mut commit = objects::read_commit(hash)?;
+            if shallow::is_shallow(hash)? {
+                commit.parents.clear();
+            }
+            self.commits.insert(hash.to_string(), commit);
+        }
+        Ok(&self.commits[hash])
+    }
+
+    /// The generation number of a commit: 1 for root commits, otherwise one more than
+    /// its highest parent. A commit can only reach commits of a lower generation, which
+    /// lets walks stop early. Every commit walked through on the way is remembered, so
+    /// each is only visited once however many queries the cache answers.
+    pub(crate) fn generation(&mut self, hash: &str) -> anyhow::Result<u32> {
+        // Iterative, as history can be far deeper than the stack: a commit is looked at
+        // again once everything above its parents is known
+        let mut stack = vec![(hash.to_string(), false)];
+        while let Some((top, parents_known)) = stack.pop() {
+            if self.generations.contains_key(&top) {
+                continue;
+            }
+            let parents = self.get(&top)?.parents.clone();
+            if parents_known {
+                let generation = 1 + parents
+                    .iter()
+                    .map(|p| self.generations[p])
+                    .max()
+                    .unwrap_or(0);
+                self.generations.insert(top, generation);
+                continue;
+            }
+            stack.push((top, true));
+            stack.extend(
+                parents
+                    .into_iter()
+                    .filter(|p| !self.generations.contains_key(p))
+                    .map(|p| (p, false)),
+            );
+        }
+        Ok(self.generations[hash])
+    }
+}
+
+/// The best common ancestors of `one` and each of `twos`: common ancestors that are not
+/// themselves ancestors of another common ancestor. Usually there is exactly one. With
+/// several `twos` this is the merge base of `one` and a hypothetical merge of all `twos`.
+pub(crate) fn merge_bases(
+    cache: &mut CommitCache,
+    one: &str,
+    twos: &[String],
+) -> anyhow::Result<Vec<String>> {
+    if twos.iter().any(|two| two == one) {
+        return Ok(vec![one.to_string()]);
+    }
+    let candidates = paint_down_to_common(cache, one, twos, 0)?;
+    independent(cache, candidates)
+}
+
+/// The merge bases of all `commits` at once, as needed for an octopus merge: the common
+/// ancestors of the first two are combined with the third, and so on, keeping only those
+/// that are not ancestors of one another each time.
+pub(crate) fn octopus_merge_bases(
+    cache: &mut CommitCache,
+    commits: &[String],
+) -> anyhow::Result<Vec<String>> {
+    let Some((first, rest)) = commits.split_first() else {
+        return Ok(Vec::new());
+    };
+    let mut bases = vec![first.clone()];
+    for commit in rest {
+        let mut next: Vec<String> = Vec::new();
+        for base in &bases {
+            for found in merge_bases(cache, commit, std::slice::from_ref(base))? {
+                if !next.contains(&found) {
+                    next.push(found);
+                }
+            }
+        }
+        bases = independent(cache, next)?;
+    }
+    Ok(bases)
+}
+
+/// Whether `ancestor` can be reached from `descendant` by following parents.
+pub(crate) fn is_ancestor(
+    cache: &mut CommitCache,
+    ancestor: &str,
+    descendant: &str,
+) -> anyhow::Result<bool> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+    let generation = cache.generation(ancestor)?;
+    if generation > cache.generation(descendant)? {
+        return Ok(false);
+    }
+    let bases = paint_down_to_common(cache, ancestor, &[descendant.to_string()], generation)?;
+    Ok(bases.iter().any(|b| b == ancestor))
+}
+
+/// The commits reachable from `include` but not from `exclude`, newest first, as
+/// `rev-list <exclude>..<include>` lists them.
+pub(crate) fn commits_between(
+    cache: &mut CommitCache,
+    exclude: &[String],
+    include: &str,
+) -> anyhow::Result<Vec<String>> {
+    let mut hidden: HashSet<String> = HashSet::new();
+    let mut stack = exclude.to_vec();
+    while let Some(hash) = stack.pop() {
+        if hidden.insert(hash.clone()) {
+            stack.extend(cache.get(&hash)?.parents.iter().cloned());
+        }
+    }
+
+    // Among equal dates, the commit queued first
+    let mut queue: BinaryHeap<(i64, Reverse<usize>, String)> = BinaryHeap::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut counter = 0;
+    let mut result = Vec::new();
+    if !hidden.contains(include) {
+        seen.insert(include.to_string());
+        queue.push((
+            cache.get(include)?.time(),
+            Reverse(counter),
+            include.to_string(),
+        ));
+    }
+    while let Some((_, _, hash)) = queue.pop() {
+        for parent in cache.get(&hash)?.parents.clone() {
+            if !hidden.contains(&parent) && seen.insert(parent.clone()) {
+                counter += 1;
+                queue.push((cache.get(&parent)?.time(), Reverse(counter), parent));
+            }
+        }
+        result.push(hash);
+    }
+    Ok(result)
+}
+
+/// Reorders `commits` (newest first, as from [`commits_between`]) so that no commit comes
+/// before all of its children, keeping each line of history together like `--topo-order`.
+pub(crate) fn sort_topologically(
+    cache: &mut CommitCache,
+    commits: Vec<String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut children: HashMap<String, usize> =
+        commits.iter().map(|hash| (hash.clone(), 0)).collect();
+    for hash in &commits {
+        for parent in cache.get(hash)?.parents.clone() {
+            if let Some(count) = children.get_mut(&parent) {
+                *count += 1;
+            }
+        }
+    }
+
+    // A stack, so that the parents of the commit just shown come next
+    let mut stack: Vec<String> = commits
+        .iter()
+        .rev()
+        .filter(|hash| children[*hash] == 0)
+        .cloned()
+        .collect();
+    let mut result = Vec::with_capacity(commits.len());
+    while let Some(hash) = stack.pop() {
+        for parent in cache.get(&hash)?.parents.clone() {
+            if let Some(count) = children.get_mut(&parent) {
+                *count -= 1;
+                if *count == 0 {
+                    stack.push(parent);
+                }
+            }
+        }
+        result.push(hash);
+    }
+    Ok(result)
+}
+
+/// Where `commit` forked from the history of `refname`: the merge base of `commit` and
+/// every value the ref's reflog says it has had, provided that base is itself one of
+/// those values. Unlike a plain merge base, this survives the ref being rewritten.
+pub(crate) fn fork_point(
+    cache: &mut CommitCache,
+    refname: &str,
+    commit: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut full_name = None;
+    for prefix in ["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"] {
+        let name = format!("{prefix}{refname}");
+        if refs::resolve_ref(&name)?.is_some() {
+            full_name = Some(name);
+            break;
+        }
+    }
+    let full_name = full_name.ok_or_else(|| anyhow::anyhow!("No such ref: '{refname}'"))?;
+
+    // Each reflog line starts with the old and the new value of the ref
+    let mut values = Vec::new();
+    let log = std::fs::read_to_string(format!(".git/logs/{full_name}")).unwrap_or_default();
+    for (i, entry) in log.lines().enumerate() {
+        let mut fields = entry.split(' ');
+        let (Some(old), Some(new)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if i == 0 && old.bytes().any(|b| b != b'0') {
+            values.push(old.to_string());
+        }
+        values.push(new.to_string());
+    }
+    if values.is_empty() {
+        values.extend(refs::resolve_ref(&full_name)?);
+    }
+    let mut candidates: Vec<String> = Vec::new();
+    for value in values {
+        // Entries may name commits that have since been pruned
+        if !candidates.contains(&value) && cache.get(&value).is_ok() {
+            candidates.push(value);
+        }
+    }
+
+    let bases = merge_bases(cache, commit, &candidates)?;
+    Ok(match bases.as_slice() {
+        [base] if candidates.contains(base) => Some(base.clone()),
+        _ => None,
+    })
+}
+
+/// Walks back from `one` and `twos`, highest generation and then newest commit first,
+/// marking which side reaches each commit. Commits reached from both sides are collected,
+/// and everything behind them is marked stale so the walk stops once only stale commits
+/// remain, or once it gets below `min_generation`.
+fn paint_down_to_common(
+    cache: &mut CommitCache,
+    one: &str,
+    twos: &[String],
+    min_generation: u32,
+) -> anyhow::Result<Vec<String>> {
+    let mut flags: HashMap<String, u8> = HashMap::new();
+    // Among equal generations and dates, the commit queued first
+    let mut queue: BinaryHeap<(u32, i64, Reverse<usize>, String)> = BinaryHeap::new();
+    let mut counter = 0;
+    let mut push = |queue: &mut BinaryHeap<_>, cache: &mut CommitCache, hash: &str| {
+        let generation = cache.generation(hash)?;
+        let time = cache.get(hash)?.time();
+        queue.push((generation, time, Reverse(counter), hash.to_string()));
+        counter += 1;
+        anyhow::Ok(())
+    };
+
+    flags.insert(one.to_string(), PARENT1);
+    push(&mut queue, cache, one)?;
+    for two in twos {
+        *flags.entry(two.clone()).or_default() |= PARENT2;
+        push(&mut queue, cache, two)?;
+    }
+
+    let mut result = Vec::new();
+    while queue.iter().any(|(_, _, _, hash)| flags[hash] & STALE == 0) {
+        let Some((generation, _, _, hash)) = queue.pop() else {
+            break;
+        };
+        if generation < min_generation {
+            break;
+        }
+        let mut side = flags[&hash] & (PARENT1 | PARENT2 | STALE);
+        if side == PARENT1 | PARENT2 {
+            let commit_flags = flags.get_mut(&hash).expect("queued commits have flags");
+            if *commit_flags & RESULT == 0 {
+                *commit_flags |= RESULT;
+                result.push(hash.clone());
+            }
+            side |= STALE;
+        }
+        let parents = cache.get(&hash)?.parents.clone();
+        for parent in parents {
+            let parent_flags = flags.entry(parent.clone()).or_default();
+            if *parent_flags & side == side {
+                continue;
+            }
+            *parent_flags |= side;
+            push(&mut queue, cache, &parent)?;
+        }
+    }
+
+    // Commits that became stale after being collected are ancestors of another result
+    result.retain(|hash| flags[hash] & STALE == 0);
+    let mut dated = Vec::with_capacity(result.len());
+    for hash in result {
+        dated.push((cache.get(&hash)?.time(), hash));
+    }
+    dated.sort_by_key(|(time, _)| Reverse(*time));
+    Ok(dated.into_iter().map(|(_, hash)| hash).collect())
+}
+
+/// Drops candidates that are ancestors of other candidates.
+pub(crate) fn independent(
+    cache: &mut CommitCache,
+    candidates: Vec<String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut redundant = vec![false; candidates.len()];
+    for i in 0..candidates.len() {
+        for j in 0..candidates.len() {
+            if i != j && !redundant[j] && is_ancestor(cache, &candidates[i], &candidates[j])? {
+                redundant[i] = true;
+                break;
+            }
+        }
+    }
+    Ok(candidates
+        .into_iter()
+        .zip(redundant)
+        .filter(|(_, redundant)| !redundant)
+        .map(|(hash, _)| hash)
+        .collect())
+}
@@ -0,0 +1,529 @@
+use crate::diff::lines::{self, Algorithm, Edit};
+
+/// How conflicts are written into the merged file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ConflictStyle {
+    /// `<<<<<<<`, our side, `=======`, their side, `>>>>>>>`.
+    #[default]
+    Merge,
+    /// Also shows the common ancestor's lines after `|||||||`.
+    Diff3,
+    /// Like `Diff3`, but lines both sides agree on are moved out of the conflict.
+    ZealousDiff3,
+}
+
+impl std::str::FromStr for ConflictStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            "zdiff3" => Ok(ConflictStyle::ZealousDiff3),
+            _ => anyhow::bail!("unknown conflict style '{s}'"),
+        }
+    }
+}
+
+/// Resolves conflicting hunks without markers instead of reporting them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Favor {
+    Ours,
+    Theirs,
+    /// Keeps the lines of both sides.
+    Union,
+}
+
+/// How hard to try to shrink conflicts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Level {
+    /// Every overlapping change conflicts.
+    Minimal,
+    /// Identical changes on both sides do not conflict.
+    Eager,
+    /// Conflicts are narrowed down to the lines that really differ, and conflicts
+    /// separated by three or fewer lines are joined.
+    Zealous,
+    /// As `Zealous`, also joining conflicts separated only by lines without letters or digits.
+    ZealousAlnum,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct MergeFileOptions {
+    pub(crate) style: ConflictStyle,
+    pub(crate) level: Level,
+    pub(crate) favor: Option<Favor>,
+    pub(crate) algorithm: Algorithm,
+    pub(crate) marker_size: usize,
+    /// Shown after the `<<<<<<<`, `|||||||` and `>>>>>>>` markers.
+    pub(crate) ours_label: Option<String>,
+    pub(crate) base_label: Option<String>,
+    pub(crate) theirs_label: Option<String>,
+}
+
+impl Default for MergeFileOptions {
+    fn default() -> Self {
+        MergeFileOptions {
+            style: ConflictStyle::Merge,
+            level: Level::Zealous,
+            favor: None,
+            algorithm: Algorithm::Myers,
+            marker_size: 7,
+            ours_label: None,
+            base_label: None,
+            theirs_label: None,
+        }
+    }
+}
+
+/// A region of the merge, located in the base (`i0`) and in both sides (`i1`, `i2`).
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    mode: Mode,
+    i0: isize,
+    chg0: isize,
+    i1: isize,
+    chg1: isize,
+    i2: isize,
+    chg2: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Conflict,
+    Ours,
+    Theirs,
+    Both,
+    /// A conflict found to consist of identical changes; the text is taken from our side.
+    Identical,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line. Returns the merged
+/// content, with conflict markers around overlapping changes, and the number of conflicts.
+pub(crate) fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    opts: &MergeFileOptions,
+) -> (Vec<u8>, usize) {
+    let base_lines = lines::split_lines(base);
+    let ours_lines = lines::split_lines(ours);
+    let theirs_lines = lines::split_lines(theirs);
+
+    let ours_edits = lines::edits(&lines::diff_lines_for_merge(
+        &base_lines,
+        &ours_lines,
+        opts.algorithm,
+    ));
+    let theirs_edits = lines::edits(&lines::diff_lines_for_merge(
+        &base_lines,
+        &theirs_lines,
+        opts.algorithm,
+    ));
+    if ours_edits.is_empty() {
+        return (theirs.to_vec(), 0);
+    }
+    if theirs_edits.is_empty() {
+        return (ours.to_vec(), 0);
+    }
+
+    let sides = Sides {
+        base: &base_lines,
+        ours: &ours_lines,
+        theirs: &theirs_lines,
+    };
+    let mut level = opts.level;
+    // Plain diff3 shows the base, which refining the conflict would make misleading
+    if opts.style == ConflictStyle::Diff3 && level > Level::Eager {
+        level = Level::Eager;
+    }
+
+    let mut regions = combine(&sides, &ours_edits, &theirs_edits, level);
+    if opts.style == ConflictStyle::ZealousDiff3 {
+        refine_zdiff3_conflicts(&sides, &mut regions);
+    } else if level >= Level::Zealous {
+        regions = refine_conflicts(&sides, regions, opts.algorithm);
+        simplify_non_conflicts(&sides, &mut regions, level > Level::Zealous);
+    }
+
+    // Counted after filling, which resolves conflicts when a side is favored
+    let merged = fill(&sides, &mut regions, opts);
+    let conflicts = regions.iter().filter(|r| r.mode == Mode::Conflict).count();
+    (merged, conflicts)
+}
+
+struct Sides<'a> {
+    base: &'a [&'a [u8]],
+    ours: &'a [&'a [u8]],
+    theirs: &'a [&'a [u8]],
+}
+
+fn append(regions: &mut Vec<Region>, region: Region) {
+    if let Some(m) = regions.last_mut() {
+        if region.i1 <= m.i1 + m.chg1 || region.i2 <= m.i2 + m.chg2 {
+            if region.mode != m.mode {
+                m.mode = Mode::Conflict;
+            }
+            m.chg0 = region.i0 + region.chg0 - m.i0;
+            m.chg1 = region.i1 + region.chg1 - m.i1;
+            m.chg2 = region.i2 + region.chg2 - m.i2;
+            return;
+        }
+    }
+    regions.push(region);
+}
+
+/// Walks both edit scripts together, turning edits only one side made into regions taken
+/// from that side and overlapping edits into conflicts.
+fn combine(sides: &Sides, ours: &[Edit], theirs: &[Edit], level: Level) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    let span = |e: &Edit| {
+        (
+            e.old_start as isize,
+            e.old_len as isize,
+            e.new_start as isize,
+            e.new_len as isize,
+        )
+    };
+
+    while x < ours.len() && y < theirs.len() {
+        let (o_i1, o_chg1, o_i2, o_chg2) = span(&ours[x]);
+        let (t_i1, t_chg1, t_i2, t_chg2) = span(&theirs[y]);
+
+        if o_i1 + o_chg1 < t_i1 {
+            append(
+                &mut regions,
+                Region {
+                    mode: Mode::Ours,
+                    i0: o_i1,
+                    chg0: o_chg1,
+                    i1: o_i2,
+                    chg1: o_chg2,
+                    i2: t_i2 - t_i1 + o_i1,
+                    chg2: o_chg1,
+                },
+            );
+            x += 1;
+            continue;
+        }
+        if t_i1 + t_chg1 < o_i1 {
+            append(
+                &mut regions,
+                Region {
+                    mode: Mode::Theirs,
+                    i0: t_i1,
+                    chg0: t_chg1,
+                    i1: o_i2 - o_i1 + t_i1,
+                    chg1: t_chg1,
+                    i2: t_i2,
+                    chg2: t_chg2,
+                },
+            );
+            y += 1;
+            continue;
+        }
+
+        let identical = level != Level::Minimal
+            && o_i1 == t_i1
+            && o_chg1 == t_chg1
+            && o_chg2 == t_chg2
+            && sides.ours[o_i2 as usize..(o_i2 + o_chg2) as usize]
+                == sides.theirs[t_i2 as usize..(t_i2 + t_chg2) as usize];
+        if !identical {
+            let off = o_i1 - t_i1;
+            let ffo = off + o_chg1 - t_chg1;
+            let (mut i0, mut i1, mut i2) = (o_i1, o_i2, t_i2);
+            if off > 0 {
+                i0 -= off;
+                i1 -= off;
+            } else {
+                i2 += off;
+            }
+            let mut chg0 = o_i1 + o_chg1 - i0;
+            let mut chg1 = o_i2 + o_chg2 - i1;
+            let mut chg2 = t_i2 + t_chg2 - i2;
+            if ffo < 0 {
+                chg0 -= ffo;
+                chg1 -= ffo;
+            } else {
+                chg2 += ffo;
+            }
+            append(
+                &mut regions,
+                Region {
+                    mode: Mode::Conflict,
+                    i0,
+                    chg0,
+                    i1,
+                    chg1,
+                    i2,
+                    chg2,
+                },
+            );
+        }
+
+        let ours_end = o_i1 + o_chg1;
+        let theirs_end = t_i1 + t_chg1;
+        if ours_end >= theirs_end {
+            y += 1;
+        }
+        if theirs_end >= ours_end {
+            x += 1;
+        }
+    }
+
+    let ours_shift = sides.ours.len() as isize - sides.base.len() as isize;
+    let theirs_shift = sides.theirs.len() as isize - sides.base.len() as isize;
+    for edit in &ours[x..] {
+        let (i1, chg1, i2, chg2) = span(edit);
+        append(
+            &mut regions,
+            Region {
+                mode: Mode::Ours,
+                i0: i1,
+                chg0: chg1,
+                i1: i2,
+                chg1: chg2,
+                i2: i1 + theirs_shift,
+                chg2: chg1,
+            },
+        );
+    }
+    for edit in &theirs[y..] {
+        let (i1, chg1, i2, chg2) = span(edit);
+        append(
+            &mut regions,
+            Region {
+                mode: Mode::Theirs,
+                i0: i1,
+                chg0: chg1,
+                i1: i1 + ours_shift,
+                chg1,
+                i2,
+                chg2,
+            },
+        );
+    }
+    regions
+}
+
+/// Moves lines both sides agree on from the edges of each conflict into the merged text.
+fn refine_zdiff3_conflicts(sides: &Sides, regions: &mut [Region]) {
+    for m in regions.iter_mut().filter(|m| m.mode == Mode::Conflict) {
+        while m.chg1 > 0 && m.chg2 > 0 && sides.ours[m.i1 as usize] == sides.theirs[m.i2 as usize] {
+            m.chg1 -= 1;
+            m.chg2 -= 1;
+            m.i1 += 1;
+            m.i2 += 1;
+        }
+        while m.chg1 > 0
+            && m.chg2 > 0
+            && sides.ours[(m.i1 + m.chg1 - 1) as usize]
+                == sides.theirs[(m.i2 + m.chg2 - 1) as usize]
+        {
+            m.chg1 -= 1;
+            m.chg2 -= 1;
+        }
+    }
+}
+
+/// Diffs the two sides of each conflict against each other and keeps only the parts that
+/// differ as conflicts.
+fn refine_conflicts(sides: &Sides, regions: Vec<Region>, algorithm: Algorithm) -> Vec<Region> {
+    let mut refined = Vec::with_capacity(regions.len());
+    for mut m in regions {
+        if m.mode != Mode::Conflict || m.chg1 == 0 || m.chg2 == 0 {
+            refined.push(m);
+            continue;
+        }
+        let ours = &sides.ours[m.i1 as usize..(m.i1 + m.chg1) as usize];
+        let theirs = &sides.theirs[m.i2 as usize..(m.i2 + m.chg2) as usize];
+        let edits = lines::edits(&lines::diff_lines_for_merge(ours, theirs, algorithm));
+        if edits.is_empty() {
+            m.mode = Mode::Identical;
+            refined.push(m);
+            continue;
+        }
+        let (i1, i2) = (m.i1, m.i2);
+        for edit in edits {
+            refined.push(Region {
+                mode: Mode::Conflict,
+                i1: i1 + edit.old_start as isize,
+                chg1: edit.old_len as isize,
+                i2: i2 + edit.new_start as isize,
+                chg2: edit.new_len as isize,
+                ..m
+            });
+        }
+    }
+    refined
+}
+
+/// Joins conflicts separated by at most three lines, since showing those lines inside one
+/// conflict takes no more room than two sets of markers.
+fn simplify_non_conflicts(sides: &Sides, regions: &mut Vec<Region>, simplify_if_no_alnum: bool) {
+    let mut i = 0;
+    while i + 1 < regions.len() {
+        let (m, next) = (regions[i], regions[i + 1]);
+        let begin = m.i1 + m.chg1;
+        let end = next.i1;
+        let keep = m.mode != Mode::Conflict
+            || next.mode != Mode::Conflict
+            || (end - begin > 3
+                && (!simplify_if_no_alnum
+                    || sides.ours[begin as usize..end as usize]
+                        .iter()
+                        .any(|line| line.iter().any(u8::is_ascii_alphanumeric))));
+        if keep {
+            i += 1;
+        } else {
+            regions[i].chg1 = next.i1 + next.chg1 - m.i1;
+            regions[i].chg2 = next.i2 + next.chg2 - m.i2;
+            regions.remove(i + 1);
+        }
+    }
+}
+
+/// Whether line `i` of `lines` ends in CRLF, judging a last line without a newline by the
+/// line before it. `None` when it cannot be told.
+fn is_eol_crlf(lines: &[&[u8]], i: usize) -> Option<bool> {
+    let crlf = |line: &[u8]| line.len() > 1 && line[line.len() - 2] == b'\r';
+    if i + 1 < lines.len() {
+        return Some(crlf(lines[i]));
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    if lines[i].ends_with(b"\n") {
+        return Some(crlf(lines[i]));
+    }
+    if i == 0 {
+        return None;
+    }
+    Some(crlf(lines[i - 1]))
+}
+
+/// Conflict markers use CRLF when the lines around them do.
+fn is_cr_needed(sides: &Sides, m: &Region) -> bool {
+    let before = |i: isize| (i.max(1) - 1) as usize;
+    let mut needs_cr = is_eol_crlf(sides.ours, before(m.i1));
+    if needs_cr != Some(false) {
+        needs_cr = is_eol_crlf(sides.theirs, before(m.i2));
+    }
+    if needs_cr != Some(false) {
+        needs_cr = is_eol_crlf(sides.base, 0);
+    }
+    needs_cr.unwrap_or(false)
+}
+
+/// Appends `lines[start..start + count]`, adding a newline after a last line that lacks
+/// one when `add_nl` is set.
+fn copy_lines(
+    out: &mut Vec<u8>,
+    lines: &[&[u8]],
+    start: isize,
+    count: isize,
+    needs_cr: bool,
+    add_nl: bool,
+) {
+    if count < 1 {
+        return;
+    }
+    let (start, count) = (start as usize, count as usize);
+    for line in &lines[start..start + count] {
+        out.extend_from_slice(line);
+    }
+    if add_nl && !lines[start + count - 1].ends_with(b"\n") {
+        if needs_cr {
+            out.push(b'\r');
+        }
+        out.push(b'\n');
+    }
+}
+
+fn marker(out: &mut Vec<u8>, c: u8, size: usize, label: Option<&str>, needs_cr: bool) {
+    out.extend(std::iter::repeat(c).take(size));
+    if let Some(label) = label {
+        out.push(b' ');
+        out.extend_from_slice(label.as_bytes());
+    }
+    if needs_cr {
+        out.push(b'\r');
+    }
+    out.push(b'\n');
+}
+
+fn fill(sides: &Sides, regions: &mut [Region], opts: &MergeFileOptions) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    for m in regions.iter_mut() {
+        if let (Some(favor), Mode::Conflict) = (opts.favor, m.mode) {
+            m.mode = match favor {
+                Favor::Ours => Mode::Ours,
+                Favor::Theirs => Mode::Theirs,
+                Favor::Union => Mode::Both,
+            };
+        }
+
+        match m.mode {
+            Mode::Conflict => {
+                let needs_cr = is_cr_needed(sides, m);
+                copy_lines(&mut out, sides.ours, i, m.i1 - i, false, false);
+                marker(
+                    &mut out,
+                    b'<',
+                    opts.marker_size,
+                    opts.ours_label.as_deref(),
+                    needs_cr,
+                );
+                copy_lines(&mut out, sides.ours, m.i1, m.chg1, needs_cr, true);
+                if matches!(
+                    opts.style,
+                    ConflictStyle::Diff3 | ConflictStyle::ZealousDiff3
+                ) {
+                    marker(
+                        &mut out,
+                        b'|',
+                        opts.marker_size,
+                        opts.base_label.as_deref(),
+                        needs_cr,
+                    );
+                    copy_lines(&mut out, sides.base, m.i0, m.chg0, needs_cr, true);
+                }
+                marker(&mut out, b'=', opts.marker_size, None, needs_cr);
+                copy_lines(&mut out, sides.theirs, m.i2, m.chg2, needs_cr, true);
+                marker(
+                    &mut out,
+                    b'>',
+                    opts.marker_size,
+                    opts.theirs_label.as_deref(),
+                    needs_cr,
+                );
+            }
+            Mode::Ours | Mode::Theirs | Mode::Both => {
+                copy_lines(&mut out, sides.ours, i, m.i1 - i, false, false);
+                let take_ours = matches!(m.mode, Mode::Ours | Mode::Both);
+                let take_theirs = matches!(m.mode, Mode::Theirs | Mode::Both);
+                if take_ours {
+                    let needs_cr = is_cr_needed(sides, m);
+                    copy_lines(&mut out, sides.ours, m.i1, m.chg1, needs_cr, take_theirs);
+                }
+                if take_theirs {
+                    copy_lines(&mut out, sides.theirs, m.i2, m.chg2, false, false);
+                }
+            }
+            Mode::Identical => continue,
+        }
+        i = m.i1 + m.chg1;
+    }
+    copy_lines(
+        &mut out,
+        sides.ours,
+        i,
+        sides.ours.len() as isize - i,
+        false,
+        false,
+    );
+    out
+}
@@ -0,0 +1,206 @@
+pub(crate) mod base;
+pub(crate) mod file;
+pub(crate) mod tree;
+
+use crate::commands::clone::checkout_empty::handle_file_creation;
+use crate::commands::restore::{self, remove_worktree_file, RestoreOptions};
+use crate::diff::{self, worktree};
+use crate::index::{Index, IndexEntry};
+use crate::objects;
+use crate::promisor;
+use crate::refs;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tree::{Entries, Entry};
+
+/// Refuses to check out `result` over HEAD when that would lose local changes: files
+/// that differ from HEAD in the index or the worktree, or untracked files in the way.
+/// Only paths the merge changes are looked at. `operation` is used in messages.
+pub(crate) fn check_worktree(
+    index: &Index,
+    head: &Entries,
+    result: &BTreeMap<String, Entry>,
+    operation: &str,
+) -> anyhow::Result<()> {
+    let staged: Entries = crate::diff::index_entries(index);
+    let current = worktree::worktree_entries(index)?;
+
+    let mut changed = Vec::new();
+    let mut untracked = Vec::new();
+    let touched = result
+        .iter()
+        .filter(|(path, entry)| match entry {
+            Entry::Clean(mode, hash) => head.get(*path) != Some(&(*mode, *hash)),
+            _ => true,
+        })
+        .map(|(path, _)| path)
+        .chain(head.keys().filter(|path| !result.contains_key(*path)));
+    for path in touched {
+        match (head.get(path), staged.get(path)) {
+            (Some(h), Some(s)) if h == s => {
+                if current.get(path) != Some(s) {
+                    changed.push(path.as_str());
+                }
+            }
+            (None, None) => {
+                if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+                    untracked.push(path.as_str());
+                }
+            }
+            _ => changed.push(path.as_str()),
+        }
+    }
+    changed.sort_unstable();
+    changed.dedup();
+
+    if !changed.is_empty() {
+        anyhow::bail!(
+            "Your local changes to the following files would be overwritten by {operation}:\n\
+             \t{}\nPlease commit your changes or stash them before you {operation}.\nAborting",
+            changed.join("\n\t")
+        );
+    }
+    if !untracked.is_empty() {
+        anyhow::bail!(
+            "The following untracked working tree files would be overwritten by {operation}:\n\
+             \t{}\nPlease move or remove them before you {operation}.\nAborting",
+            untracked.join("\n\t")
+        );
+    }
+    Ok(())
+}
+
+/// Writes a merge result over the HEAD checkout: clean paths are updated in the index and
+/// the worktree, conflicted ones get index stages 1-3 and their worktree version.
+pub(crate) fn checkout_result(
+    index: &mut Index,
+    head: &Entries,
+    result: &BTreeMap<String, Entry>,
+) -> anyhow::Result<()> {
+    // A partial clone fetches the files it is about to write all at once
+    promisor::prefetch(result.iter().filter_map(|(path, entry)| match *entry {
+        Entry::Clean(mode, hash) if head.get(path) != Some(&(mode, hash)) => Some((mode, hash)),
+        Entry::Clean(..) => None,
+        Entry::Conflict { worktree, .. } => worktree,
+    }))?;
+    // Removals come first so that a directory can take the place of a removed file
+    for path in head.keys().filter(|path| !result.contains_key(*path)) {
+        remove_worktree_file(path)?;
+        index.remove(path);
+    }
+    for (path, entry) in result {
+        if matches!(entry, Entry::Clean(..)) {
+            continue;
+        }
+        if let Some(&(mode, hash)) = head.get(path) {
+            let keep =
+                matches!(entry, Entry::Conflict { worktree: Some(w), .. } if *w == (mode, hash));
+            if !keep {
+                remove_worktree_file(path)?;
+            }
+        }
+    }
+
+    for (path, entry) in result {
+        match *entry {
+            Entry::Clean(mode, hash) => {
+                let unchanged = head.get(path) == Some(&(mode, hash))
+                    && index
+                        .entries
+                        .iter()
+                        .any(|e| e.path == *path && e.stage == 0 && e.hash == hash);
+                if unchanged {
+                    continue;
+                }
+                write_worktree_file(path, mode, hash)?;
+                index.add(IndexEntry::from_worktree(path, mode, hash)?);
+            }
+            Entry::Conflict { stages, worktree } => {
+                if let Some((mode, hash)) = worktree {
+                    if head.get(path) != Some(&(mode, hash)) {
+                        write_worktree_file(path, mode, hash)?;
+                    }
+                }
+                index.remove(path);
+                for (stage, side) in (1..).zip(stages) {
+                    if let Some((mode, hash)) = side {
+                        index.insert_sorted(IndexEntry::new(path, mode, hash, stage));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks out `commit` over a worktree that matches `head`, refusing to lose local changes.
+/// HEAD itself is left for the caller to move.
+pub(crate) fn checkout_commit(
+    index: &mut Index,
+    head: &Entries,
+    commit: &str,
+    operation: &str,
+) -> anyhow::Result<()> {
+    let target: BTreeMap<String, Entry> = diff::tree_entries(&objects::read_commit(commit)?.tree)?
+        .into_iter()
+        .map(|(path, (mode, hash))| (path, Entry::Clean(mode, hash)))
+        .collect();
+    check_worktree(index, head, &target, operation)?;
+    checkout_result(index, head, &target)?;
+    index.write().context("Writing the index")
+}
+
+/// Moves HEAD to `commit` and puts back the index and worktree files that differ from it,
+/// like `reset --merge`: conflicts are thrown away, unrelated worktree edits survive.
+pub(crate) fn reset_merge(commit: &str) -> anyhow::Result<()> {
+    let target = diff::tree_entries(&objects::read_commit(commit)?.tree)?;
+    let index = Index::read().context("Reading the index")?;
+    let staged = diff::index_entries(&index);
+    // Only the paths that differ from the target are reset, so unrelated edits survive
+    let mut paths: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|e| e.stage != 0 || target.get(&e.path) != Some(&(e.mode, e.hash)))
+        .map(|e| e.path.clone())
+        .collect();
+    paths.extend(
+        target
+            .keys()
+            .filter(|path| !staged.contains_key(*path))
+            .cloned(),
+    );
+    paths.sort_unstable();
+    paths.dedup();
+
+    if !paths.is_empty() {
+        restore::restore_paths(
+            &RestoreOptions {
+                source: Some(commit),
+                staged: true,
+                worktree: true,
+                overlay: false,
+            },
+            &paths,
+        )?;
+    }
+    if refs::head_commit()?.as_deref() != Some(commit) {
+        refs::update_head(commit)?;
+    }
+    Ok(())
+}
+
+fn write_worktree_file(path: &str, mode: u32, hash: [u8; 20]) -> anyhow::Result<()> {
+    if mode == 0o160000 {
+        // Submodules are not checked out, only their directory is created
+        return fs::create_dir_all(path).with_context(|| format!("Creating {path}"));
+    }
+    handle_file_creation(
+        &format!("{mode:o}"),
+        path,
+        &hex::encode(hash),
+        Path::new(""),
+    )
+    .with_context(|| format!("Writing {path}"))
+}
@@ -0,0 +1,362 @@
+use crate::diff::lines::Algorithm;
+use crate::diff::{self, patch};
+use crate::merge::base::{self, CommitCache};
+use crate::merge::file::{self, ConflictStyle, Level, MergeFileOptions};
+use crate::objects::{self, Object};
+use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A flat path -> (mode, hash) listing of a tree.
+pub(crate) type Entries = BTreeMap<String, (u32, [u8; 20])>;
+
+/// The outcome of merging one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Entry {
+    Clean(u32, [u8; 20]),
+    Conflict {
+        /// The base, our and their version, recorded in index stages 1-3.
+        stages: [Option<(u32, [u8; 20])>; 3],
+        /// What is written to the worktree, such as the file with conflict markers.
+        worktree: Option<(u32, [u8; 20])>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TreeMerge {
+    pub(crate) entries: BTreeMap<String, Entry>,
+    /// `Auto-merging` and `CONFLICT` lines with the path each is about.
+    pub(crate) messages: Vec<(String, String)>,
+}
+
+impl TreeMerge {
+    pub(crate) fn is_clean(&self) -> bool {
+        !self
+            .entries
+            .values()
+            .any(|e| matches!(e, Entry::Conflict { .. }))
+    }
+
+    pub(crate) fn conflicted_paths(&self) -> impl Iterator<Item = &str> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| matches!(e, Entry::Conflict { .. }))
+            .map(|(path, _)| path.as_str())
+    }
+
+    /// The tree a conflicted merge would have if every conflict were resolved to what was
+    /// written to the worktree; used for the virtual ancestor of criss-cross merges.
+    fn as_entries(&self) -> Entries {
+        self.entries
+            .iter()
+            .filter_map(|(path, entry)| match entry {
+                Entry::Clean(mode, hash) => Some((path.clone(), (*mode, *hash))),
+                Entry::Conflict { worktree, .. } => worktree.map(|w| (path.clone(), w)),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TreeMergeOptions {
+    pub(crate) style: ConflictStyle,
+    pub(crate) ours_label: String,
+    pub(crate) theirs_label: String,
+    pub(crate) base_label: String,
+}
+
+/// Merges the differences between `base` and `theirs` into `ours`, path by path. Paths
+/// only one side touched take that side; paths both sides changed get a content merge,
+/// or a conflict when that is not possible. Renames are not followed.
+pub(crate) fn merge_trees(
+    base: &Entries,
+    ours: &Entries,
+    theirs: &Entries,
+    opts: &TreeMergeOptions,
+) -> anyhow::Result<TreeMerge> {
+    merge_trees_at_depth(base, ours, theirs, opts, 0)
+}
+
+fn merge_trees_at_depth(
+    base: &Entries,
+    ours: &Entries,
+    theirs: &Entries,
+    opts: &TreeMergeOptions,
+    depth: usize,
+) -> anyhow::Result<TreeMerge> {
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    let mut result = TreeMerge::default();
+
+    for path in paths {
+        let b = base.get(path).copied();
+        let o = ours.get(path).copied();
+        let t = theirs.get(path).copied();
+
+        let merged = if o == t || b == t {
+            o.map(|(mode, hash)| Entry::Clean(mode, hash))
+        } else if b == o {
+            t.map(|(mode, hash)| Entry::Clean(mode, hash))
+        } else {
+            Some(merge_path(
+                path,
+                b,
+                o,
+                t,
+                opts,
+                depth,
+                &mut result.messages,
+            )?)
+        };
+        if let Some(entry) = merged {
+            result.entries.insert(path.clone(), entry);
+        }
+    }
+
+    move_files_out_of_the_way(base, ours, opts, &mut result);
+    // Git lists what happened to each path in path order
+    result.messages.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(result)
+}
+
+/// Merges a path both sides changed differently.
+fn merge_path(
+    path: &str,
+    b: Option<(u32, [u8; 20])>,
+    o: Option<(u32, [u8; 20])>,
+    t: Option<(u32, [u8; 20])>,
+    opts: &TreeMergeOptions,
+    depth: usize,
+    messages: &mut Vec<(String, String)>,
+) -> anyhow::Result<Entry> {
+    let mut note = |text: String| messages.push((path.to_string(), text));
+    let stages = [b, o, t];
+    let (o, t) = match (o, t) {
+        (Some(o), Some(t)) => (o, t),
+        (Some(kept), None) | (None, Some(kept)) => {
+            let (deleted_in, modified_in) = if t.is_none() {
+                (&opts.theirs_label, &opts.ours_label)
+            } else {
+                (&opts.ours_label, &opts.theirs_label)
+            };
+            note(format!(
+                "CONFLICT (modify/delete): {path} deleted in {deleted_in} and modified in \
+                 {modified_in}.  Version {modified_in} of {path} left in tree."
+            ));
+            return Ok(Entry::Conflict {
+                stages,
+                worktree: Some(kept),
+            });
+        }
+        (None, None) => unreachable!("paths deleted on both sides are merged cleanly"),
+    };
+
+    let kind = if b.is_some() { "content" } else { "add/add" };
+    if !is_regular(o.0) || !is_regular(t.0) {
+        note(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+        return Ok(Entry::Conflict {
+            stages,
+            worktree: Some(o),
+        });
+    }
+
+    // Of two different mode changes, ours wins but the path stays conflicted
+    let base_mode = b.map(|(mode, _)| mode);
+    let (mode, mode_conflict) = if base_mode == Some(o.0) {
+        (t.0, false)
+    } else {
+        (o.0, base_mode != Some(t.0) && o.0 != t.0)
+    };
+
+    if o.1 == t.1 {
+        if mode_conflict {
+            note(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            return Ok(Entry::Conflict {
+                stages,
+                worktree: Some((mode, o.1)),
+            });
+        }
+        return Ok(Entry::Clean(mode, o.1));
+    }
+
+    note(format!("Auto-merging {path}"));
+    let base_data = match b {
+        Some((base_mode, hash)) if is_regular(base_mode) => read_blob(&hash)?,
+        _ => Vec::new(),
+    };
+    let ours_data = read_blob(&o.1)?;
+    let theirs_data = read_blob(&t.1)?;
+    if [&base_data, &ours_data, &theirs_data]
+        .iter()
+        .any(|data| patch::is_binary(data))
+    {
+        note(format!(
+            "warning: Cannot merge binary files: {path} ({} vs. {})",
+            opts.ours_label, opts.theirs_label
+        ));
+        note(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+        return Ok(Entry::Conflict {
+            stages,
+            worktree: Some(o),
+        });
+    }
+
+    let (merged, conflicts) = file::merge(
+        &base_data,
+        &ours_data,
+        &theirs_data,
+        &MergeFileOptions {
+            style: opts.style,
+            level: Level::Zealous,
+            algorithm: Algorithm::Histogram,
+            // Markers of an inner merge must not be confused with the outer merge's
+            marker_size: 7 + 2 * depth,
+            ours_label: Some(opts.ours_label.clone()),
+            base_label: Some(opts.base_label.clone()),
+            theirs_label: Some(opts.theirs_label.clone()),
+            ..Default::default()
+        },
+    );
+    let hash = objects::write_blob(&merged).with_context(|| format!("Storing merged {path}"))?;
+    if conflicts > 0 || mode_conflict {
+        note(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+        return Ok(Entry::Conflict {
+            stages,
+            worktree: Some((mode, hash)),
+        });
+    }
+    Ok(Entry::Clean(mode, hash))
+}
+
+/// A file that ends up where the other side has a directory is moved, with its index
+/// stages, to `<path>~<branch>` so that the directory can be created.
+fn move_files_out_of_the_way(
+    base: &Entries,
+    ours: &Entries,
+    opts: &TreeMergeOptions,
+    result: &mut TreeMerge,
+) {
+    let files: Vec<String> = result
+        .entries
+        .keys()
+        .filter(|path| {
+            let dir = format!("{path}/");
+            result
+                .entries
+                .range::<String, _>(&dir..)
+                .next()
+                .is_some_and(|(next, _)| next.starts_with(&dir))
+        })
+        .cloned()
+        .collect();
+
+    for path in files {
+        let Some(entry) = result.entries.remove(&path) else {
+            continue;
+        };
+        let (stages, file) = match entry {
+            Entry::Clean(mode, hash) => {
+                let mut stages = [base.get(&path).copied(), None, None];
+                stages[if ours.get(&path) == Some(&(mode, hash)) {
+                    1
+                } else {
+                    2
+                }] = Some((mode, hash));
+                (stages, (mode, hash))
+            }
+            Entry::Conflict {
+                stages,
+                worktree: Some(file),
+            } => (stages, file),
+            Entry::Conflict { worktree: None, .. } => {
+                result.entries.insert(path, entry);
+                continue;
+            }
+        };
+        let from_ours = ours.get(&path) == Some(&file);
+        let side = if from_ours {
+            &opts.ours_label
+        } else {
+            &opts.theirs_label
+        };
+        let moved = format!("{path}~{}", side.replace('/', "_"));
+        result.messages.push((
+            path.clone(),
+            format!(
+                "CONFLICT (file/directory): directory in the way of {path} from {side}; \
+                 moving it to {moved} instead."
+            ),
+        ));
+        result.entries.insert(
+            moved,
+            Entry::Conflict {
+                stages,
+                worktree: Some(file),
+            },
+        );
+    }
+}
+
+/// Merges the commits `ours` and `theirs`, whose merge bases are `bases`. With several
+/// merge bases, they are first merged with each other into a virtual ancestor.
+pub(crate) fn merge_commits(
+    cache: &mut CommitCache,
+    bases: &[String],
+    ours_tree: &str,
+    theirs_tree: &str,
+    opts: &TreeMergeOptions,
+) -> anyhow::Result<TreeMerge> {
+    merge_trees(
+        &virtual_base(cache, bases, opts.style, 1)?,
+        &diff::tree_entries(ours_tree)?,
+        &diff::tree_entries(theirs_tree)?,
+        opts,
+    )
+}
+
+/// How the merge base is labelled in diff3-style conflicts.
+pub(crate) fn ancestor_label(bases: &[String]) -> String {
+    match bases {
+        [] => "empty tree".to_string(),
+        [base] => base[..7].to_string(),
+        _ => "merged common ancestors".to_string(),
+    }
+}
+
+fn virtual_base(
+    cache: &mut CommitCache,
+    bases: &[String],
+    style: ConflictStyle,
+    depth: usize,
+) -> anyhow::Result<Entries> {
+    let Some((first, rest)) = bases.split_first() else {
+        return Ok(Entries::new());
+    };
+    let mut merged = diff::tree_entries(&cache.get(first)?.tree.clone())?;
+    for other in rest {
+        let inner_bases = base::merge_bases(cache, first, std::slice::from_ref(other))?;
+        let inner_base = virtual_base(cache, &inner_bases, style, depth + 1)?;
+        let theirs = diff::tree_entries(&cache.get(other)?.tree.clone())?;
+        let opts = TreeMergeOptions {
+            style,
+            ours_label: "Temporary merge branch 1".to_string(),
+            theirs_label: "Temporary merge branch 2".to_string(),
+            base_label: ancestor_label(&inner_bases),
+        };
+        merged = merge_trees_at_depth(&inner_base, &merged, &theirs, &opts, depth)?.as_entries();
+    }
+    Ok(merged)
+}
+
+fn is_regular(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+fn read_blob(hash: &[u8; 20]) -> anyhow::Result<Vec<u8>> {
+    let hex = hex::encode(hash);
+    Object::read(&hex)
+        .with_context(|| format!("Reading blob {hex}"))?
+        .into_bytes()
+}
@@ -1,23 +1,26 @@
+use crate::pack;
+use crate::promisor;
 use anyhow::Context;
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
 use std::ffi::CStr;
 use std::fmt::{self};
 use std::fs;
 use std::io::BufRead;
 use std::io::Write;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{BufReader, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Kind {
     Blob,
     Tree,
     Commit,
-    RefDelta,
-    Unknown(u8),
+    Tag,
 }
 
 impl fmt::Display for Kind {
@@ -26,7 +29,7 @@ impl fmt::Display for Kind {
             Kind::Blob => write!(f, "blob"),
             Kind::Tree => write!(f, "tree"),
             Kind::Commit => write!(f, "commit"),
-            _ => write!(f, "unsupported"),
+            Kind::Tag => write!(f, "tag"),
         }
     }
 }
@@ -40,9 +43,8 @@ pub(crate) struct Object<R> {
 impl Object<()> {
     pub(crate) fn blob_from_file(file: impl AsRef<Path>) -> anyhow::Result<Object<impl Read>> {
         let file = file.as_ref();
-        let stat = std::fs::metadata(&file).with_context(|| format!("stat {}", file.display()))?;
-        let file =
-            std::fs::File::open(&file).with_context(|| format!("open {}", file.display()))?;
+        let stat = std::fs::metadata(file).with_context(|| format!("stat {}", file.display()))?;
+        let file = std::fs::File::open(file).with_context(|| format!("open {}", file.display()))?;
 
         Ok(Object {
             kind: Kind::Blob,
@@ -50,13 +52,36 @@ impl Object<()> {
             reader: file,
         })
     }
+    /// Reads an object from its loose file, or from a pack when there is none.
     pub(crate) fn read(object_hash: &str) -> anyhow::Result<Object<impl BufRead>> {
-        let f = fs::File::open(format!(
-            ".git/objects/{}/{}",
-            &object_hash[..2],
-            &object_hash[2..]
-        ))
-        .context("Read object file from .git/objects")?;
+        let f = match open_loose(object_hash) {
+            Ok(f) => f,
+            Err(e) => {
+                let hash = <[u8; 20]>::try_from(hex::decode(object_hash).unwrap_or_default());
+                let mut packed = match hash {
+                    Ok(hash) => pack::read_object(&hash)?,
+                    Err(_) => None,
+                };
+                // A partial clone fetches what it left out once it is needed
+                if let (None, Ok(hash)) = (&packed, hash) {
+                    if promisor::fetch(&[object_hash.to_string()])
+                        .with_context(|| format!("Fetching missing object {object_hash}"))?
+                    {
+                        packed = pack::read_object(&hash)?;
+                    }
+                }
+                let Some((kind, data)) = packed else {
+                    return Err(e).context("Read object file from .git/objects");
+                };
+                let size = data.len() as u64;
+                let reader: Box<dyn BufRead> = Box::new(Cursor::new(data));
+                return Ok(Object {
+                    reader,
+                    kind,
+                    expected_size: size,
+                });
+            }
+        };
         let z = ZlibDecoder::new(f);
         let mut z = BufReader::new(z);
         let mut buf = Vec::new();
@@ -78,6 +103,7 @@ impl Object<()> {
             "blob" => Kind::Blob,
             "tree" => Kind::Tree,
             "commit" => Kind::Commit,
+            "tag" => Kind::Tag,
             _ => anyhow::bail!("Not handling that kind yet: {kind }"),
         };
 
@@ -86,7 +112,7 @@ impl Object<()> {
             .context(".git/objects file has invalid size")?;
 
         // NOTE: This will not return an error if file length exceeds size
-        let z = z.take(size);
+        let z: Box<dyn BufRead> = Box::new(z.take(size));
 
         Ok(Object {
             reader: z,
@@ -96,6 +122,26 @@ impl Object<()> {
     }
 }
 
+impl<R> Object<R>
+where
+    R: Read,
+{
+    /// Reads the remaining object body into memory, checking it against the header size.
+    pub(crate) fn into_bytes(mut self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.expected_size as usize);
+        self.reader
+            .read_to_end(&mut buf)
+            .context("Reading object body")?;
+        anyhow::ensure!(
+            buf.len() as u64 == self.expected_size,
+            ".git/objects was not the expected size: expected {}, actual {}",
+            self.expected_size,
+            buf.len()
+        );
+        Ok(buf)
+    }
+}
+
 impl<R> Object<R>
 where
     R: Read,
@@ -118,24 +164,33 @@ where
     }
 
     pub(crate) fn write_to_objects(self) -> anyhow::Result<[u8; 20]> {
-        let tmp = "temporary";
-        let writer = std::fs::File::create(tmp).context("construct temp file for object")?;
+        // Next to the objects, so that renaming it into place never crosses filesystems
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let tmp = PathBuf::from(format!(
+            ".git/objects/tmp_obj_{}_{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let writer = std::fs::File::create(&tmp).context("construct temp file for object")?;
 
-        let hash = self
+        let written = self
             .write(writer)
-            .context("stream  object into  object file")?;
-
-        let hex_hash = hex::encode(hash);
-
-        fs::create_dir_all(format!(".git/objects/{}/", &hex_hash[..2]))
-            .context("create subdir of git objects")?;
-        fs::rename(
-            tmp,
-            format!(".git/objects/{}/{}", &hex_hash[..2], &hex_hash[2..]),
-        )
-        .context("renaming temp file to actual hashed name")?;
-
-        Ok(hash)
+            .context("stream  object into  object file")
+            .and_then(|hash| {
+                let hex_hash = hex::encode(hash);
+                fs::create_dir_all(format!(".git/objects/{}/", &hex_hash[..2]))
+                    .context("create subdir of git objects")?;
+                fs::rename(
+                    &tmp,
+                    format!(".git/objects/{}/{}", &hex_hash[..2], &hex_hash[2..]),
+                )
+                .context("renaming temp file to actual hashed name")?;
+                Ok(hash)
+            });
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
     }
 }
 
@@ -158,3 +213,329 @@ where
         self.writer.flush()
     }
 }
+
+/// Whether `hash` is written the way object names are, as 40 hex digits.
+pub(crate) fn is_hash(hash: &str) -> bool {
+    hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Whether the object `hash` is in the repository, loose or packed.
+pub(crate) fn has_object(hash: &str) -> anyhow::Result<bool> {
+    if !is_hash(hash) {
+        return Ok(false);
+    }
+    let loose = format!("{}/{}", &hash[..2], &hash[2..]);
+    if Path::new(".git/objects").join(&loose).is_file()
+        || alternates().iter().any(|dir| dir.join(&loose).is_file())
+    {
+        return Ok(true);
+    }
+    match <[u8; 20]>::try_from(hex::decode(hash).unwrap_or_default()) {
+        Ok(hash) => pack::contains(&hash),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Opens the loose file of object `hash`, ours or one of a repository we borrow objects
+/// from. The error is for our own file when no repository has it.
+fn open_loose(hash: &str) -> std::io::Result<fs::File> {
+    if !is_hash(hash) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("'{hash}' is not an object name"),
+        ));
+    }
+    let loose = format!("{}/{}", &hash[..2], &hash[2..]);
+    fs::File::open(Path::new(".git/objects").join(&loose)).or_else(|e| {
+        alternates()
+            .iter()
+            .find_map(|dir| fs::File::open(dir.join(&loose)).ok())
+            .ok_or(e)
+    })
+}
+
+/// The object directories of other repositories listed in `.git/objects/info/alternates`,
+/// whose objects this repository can use as its own.
+pub(crate) fn alternates() -> Vec<PathBuf> {
+    let Ok(text) = fs::read_to_string(".git/objects/info/alternates") else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Path::new(".git/objects").join(line))
+        .collect()
+}
+
+/// Stores `data` as a blob and returns its hash.
+pub(crate) fn write_blob(data: &[u8]) -> anyhow::Result<[u8; 20]> {
+    Object {
+        kind: Kind::Blob,
+        expected_size: data.len() as u64,
+        reader: std::io::Cursor::new(data),
+    }
+    .write_to_objects()
+    .context("Writing blob object")
+}
+
+/// Writes the trees for a flat path -> (mode, hash) listing, such as the merged entries of
+/// the index, and returns the hash of the root tree.
+pub(crate) fn write_tree_from_entries(
+    entries: &BTreeMap<String, (u32, [u8; 20])>,
+) -> anyhow::Result<[u8; 20]> {
+    let entries: Vec<(&str, u32, [u8; 20])> = entries
+        .iter()
+        .map(|(path, &(mode, hash))| (path.as_str(), mode, hash))
+        .collect();
+    write_tree_level(&entries)
+}
+
+fn write_tree_level(entries: &[(&str, u32, [u8; 20])]) -> anyhow::Result<[u8; 20]> {
+    // (name, mode, hash), sorted the way git orders tree entries
+    let mut items: Vec<(String, u32, [u8; 20])> = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        let (path, mode, hash) = entries[i];
+        match path.split_once('/') {
+            None => {
+                items.push((path.to_string(), mode, hash));
+                i += 1;
+            }
+            Some((dir, _)) => {
+                let prefix = format!("{dir}/");
+                let end = i + entries[i..]
+                    .iter()
+                    .take_while(|(p, _, _)| p.starts_with(&prefix))
+                    .count();
+                let children: Vec<_> = entries[i..end]
+                    .iter()
+                    .map(|&(p, mode, hash)| (&p[prefix.len()..], mode, hash))
+                    .collect();
+                let subtree =
+                    write_tree_level(&children).with_context(|| format!("Writing tree {dir}"))?;
+                items.push((dir.to_string(), 0o40000, subtree));
+                i = end;
+            }
+        }
+    }
+    items.sort_by(|a, b| {
+        let key = |(name, mode, _): &(String, u32, [u8; 20])| {
+            let mut key = name.as_bytes().to_vec();
+            if *mode == 0o40000 {
+                key.push(b'/');
+            }
+            key
+        };
+        key(a).cmp(&key(b))
+    });
+
+    let mut tree_object = Vec::new();
+    for (name, mode, hash) in items {
+        tree_object.extend(format!("{mode:o} {name}\0").as_bytes());
+        tree_object.extend(hash);
+    }
+    Object {
+        kind: Kind::Tree,
+        expected_size: tree_object.len() as u64,
+        reader: std::io::Cursor::new(tree_object),
+    }
+    .write_to_objects()
+    .context("Writing tree object")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TreeEntry {
+    pub(crate) mode: String,
+    pub(crate) name: String,
+    pub(crate) hash: [u8; 20],
+}
+
+impl TreeEntry {
+    pub(crate) fn is_tree(&self) -> bool {
+        self.mode == "40000"
+    }
+}
+
+/// Parses the entries of a tree object in the order they are stored.
+pub(crate) fn read_tree(tree_hash: &str) -> anyhow::Result<Vec<TreeEntry>> {
+    let mut obj = Object::read(tree_hash).with_context(|| format!("Reading tree {tree_hash}"))?;
+    anyhow::ensure!(
+        obj.kind == Kind::Tree,
+        "{tree_hash} is a {}, not a tree",
+        obj.kind
+    );
+
+    let mut entries = Vec::new();
+    let mut buf = Vec::new();
+    let mut hashbuf = [0; 20];
+    loop {
+        buf.clear();
+        let n = obj
+            .reader
+            .read_until(0, &mut buf)
+            .context("Reading tree header from file")?;
+        if n == 0 {
+            break;
+        }
+        obj.reader
+            .read_exact(&mut hashbuf[..])
+            .context("Read tree entry hash")?;
+
+        let mode_and_name = CStr::from_bytes_with_nul(&buf).context("Invalid tree entry")?;
+        let mut bits = mode_and_name.to_bytes().splitn(2, |b| *b == b' ');
+        let mode = bits.next().expect("Split needs to yield once");
+        let name = bits
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Tree entry has no filename"))?;
+
+        entries.push(TreeEntry {
+            mode: std::str::from_utf8(mode)
+                .context("Converting the mode into a string")?
+                .to_string(),
+            name: std::str::from_utf8(name)
+                .context("Converting the object name to string")?
+                .to_string(),
+            hash: hashbuf,
+        });
+    }
+    Ok(entries)
+}
+
+/// Recursively lists every non-tree entry below `tree_hash`, keyed by its slash-separated path.
+pub(crate) fn flatten_tree(tree_hash: &str) -> anyhow::Result<BTreeMap<String, TreeEntry>> {
+    let mut out = BTreeMap::new();
+    flatten_tree_into(tree_hash, "", &mut out)?;
+    Ok(out)
+}
+
+fn flatten_tree_into(
+    tree_hash: &str,
+    prefix: &str,
+    out: &mut BTreeMap<String, TreeEntry>,
+) -> anyhow::Result<()> {
+    for entry in read_tree(tree_hash)? {
+        let path = format!("{prefix}{}", entry.name);
+        if entry.is_tree() {
+            flatten_tree_into(&hex::encode(entry.hash), &format!("{path}/"), out)
+                .with_context(|| format!("Reading sub-tree {path}"))?;
+        } else {
+            out.insert(path, entry);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Commit {
+    pub(crate) tree: String,
+    pub(crate) parents: Vec<String>,
+    pub(crate) author: String,
+    pub(crate) committer: String,
+    pub(crate) message: String,
+}
+
+impl Commit {
+    /// The committer timestamp, which orders commits during history walks.
+    pub(crate) fn time(&self) -> i64 {
+        self.committer
+            .rsplit(' ')
+            .nth(1)
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0)
+    }
+}
+
+/// Writes a commit object; `author` and `committer` are full `Name <email> <time> <tz>` idents.
+/// The message is stored as given, so it normally ends with a newline.
+pub(crate) fn write_commit(
+    tree: &str,
+    parents: &[String],
+    author: &str,
+    committer: &str,
+    message: &str,
+) -> anyhow::Result<String> {
+    let mut body = format!("tree {tree}\n");
+    for parent in parents {
+        body.push_str(&format!("parent {parent}\n"));
+    }
+    body.push_str(&format!(
+        "author {author}\ncommitter {committer}\n\n{message}"
+    ));
+    let hash = Object {
+        kind: Kind::Commit,
+        expected_size: body.len() as u64,
+        reader: std::io::Cursor::new(body),
+    }
+    .write_to_objects()
+    .context("Writing commit object")?;
+    Ok(hex::encode(hash))
+}
+
+pub(crate) fn read_commit(commit_hash: &str) -> anyhow::Result<Commit> {
+    let obj = Object::read(commit_hash).with_context(|| format!("Reading commit {commit_hash}"))?;
+    anyhow::ensure!(
+        obj.kind == Kind::Commit,
+        "{commit_hash} is a {}, not a commit",
+        obj.kind
+    );
+    let body = obj.into_bytes()?;
+    let body = String::from_utf8_lossy(&body);
+    let (header, message) = body.split_once("\n\n").unwrap_or((&body, ""));
+
+    let mut commit = Commit {
+        tree: String::new(),
+        parents: Vec::new(),
+        author: String::new(),
+        committer: String::new(),
+        message: message.to_string(),
+    };
+    for line in header.lines() {
+        match line.split_once(' ') {
+            Some(("tree", hash)) => commit.tree = hash.to_string(),
+            Some(("parent", hash)) => commit.parents.push(hash.to_string()),
+            Some(("author", who)) => commit.author = who.to_string(),
+            Some(("committer", who)) => commit.committer = who.to_string(),
+            _ => {}
+        }
+    }
+    anyhow::ensure!(!commit.tree.is_empty(), "commit {commit_hash} has no tree");
+    Ok(commit)
+}
+
+/// An annotated tag object.
+#[derive(Debug, Clone)]
+pub(crate) struct Tag {
+    pub(crate) object: String,
+    pub(crate) name: String,
+    /// Very old tags were written without a tagger.
+    pub(crate) tagger: Option<String>,
+    pub(crate) message: String,
+}
+
+pub(crate) fn read_tag(tag_hash: &str) -> anyhow::Result<Tag> {
+    let obj = Object::read(tag_hash).with_context(|| format!("Reading tag {tag_hash}"))?;
+    anyhow::ensure!(
+        obj.kind == Kind::Tag,
+        "{tag_hash} is a {}, not a tag",
+        obj.kind
+    );
+    let body = obj.into_bytes()?;
+    let body = String::from_utf8_lossy(&body);
+    let (header, message) = body.split_once("\n\n").unwrap_or((&body, ""));
+
+    let mut tag = Tag {
+        object: String::new(),
+        name: String::new(),
+        tagger: None,
+        message: message.to_string(),
+    };
+    for line in header.lines() {
+        match line.split_once(' ') {
+            Some(("object", hash)) => tag.object = hash.to_string(),
+            Some(("tag", name)) => tag.name = name.to_string(),
+            Some(("tagger", who)) => tag.tagger = Some(who.to_string()),
+            _ => {}
+        }
+    }
+    anyhow::ensure!(!tag.object.is_empty(), "tag {tag_hash} has no object line");
+    Ok(tag)
+}
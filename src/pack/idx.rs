@@ -0,0 +1,139 @@
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8] = b"\xfftOc";
+
+/// A version 2 pack index: the hashes of the objects in a pack, sorted, and where each
+/// one starts in the pack.
+pub(crate) struct PackIndex {
+    /// How many hashes start with a byte up to each value.
+    fanout: [u32; 256],
+    hashes: Vec<[u8; 20]>,
+    offsets: Vec<u64>,
+}
+
+/// One object of a pack being indexed.
+#[derive(Debug, Clone)]
+pub(crate) struct IdxEntry {
+    pub(crate) hash: [u8; 20],
+    /// CRC-32 of the entry as stored in the pack.
+    pub(crate) crc: u32,
+    pub(crate) offset: u64,
+}
+
+impl PackIndex {
+    pub(crate) fn read(path: &Path) -> anyhow::Result<PackIndex> {
+        let data = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+        anyhow::ensure!(
+            data.len() >= 8 + 256 * 4 + 40 && data.starts_with(MAGIC),
+            "index file {} is too small or not a version 2 index",
+            path.display()
+        );
+        let version = u32::from_be_bytes(data[4..8].try_into()?);
+        anyhow::ensure!(
+            version == 2,
+            "index file {} is version {version} and is not supported",
+            path.display()
+        );
+
+        let mut fanout = [0u32; 256];
+        for (i, count) in fanout.iter_mut().enumerate() {
+            let at = 8 + i * 4;
+            *count = u32::from_be_bytes(data[at..at + 4].try_into()?);
+        }
+        let n = fanout[255] as usize;
+        let hashes_at = 8 + 256 * 4;
+        let offsets_at = hashes_at + n * 24;
+        let large_at = offsets_at + n * 4;
+        anyhow::ensure!(
+            data.len() >= large_at + 40,
+            "index file {} is truncated",
+            path.display()
+        );
+
+        let hashes = data[hashes_at..hashes_at + n * 20]
+            .chunks_exact(20)
+            .map(|hash| hash.try_into().expect("chunks are 20 bytes"))
+            .collect();
+        let mut offsets = Vec::with_capacity(n);
+        for small in data[offsets_at..large_at].chunks_exact(4) {
+            let small = u32::from_be_bytes(small.try_into()?);
+            if small & 0x8000_0000 == 0 {
+                offsets.push(u64::from(small));
+                continue;
+            }
+            // Offsets past 2 GiB live in a table of 8-byte offsets after the small ones
+            let at = large_at + (small & 0x7fff_ffff) as usize * 8;
+            let large = data
+                .get(at..at + 8)
+                .with_context(|| format!("index file {} is truncated", path.display()))?;
+            offsets.push(u64::from_be_bytes(large.try_into()?));
+        }
+        Ok(PackIndex {
+            fanout,
+            hashes,
+            offsets,
+        })
+    }
+
+    /// Where object `hash` starts in the pack, if the pack has it.
+    pub(crate) fn find(&self, hash: &[u8; 20]) -> Option<u64> {
+        let first = usize::from(hash[0]);
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let end = self.fanout[first] as usize;
+        let i = self.hashes[start..end].binary_search(hash).ok()?;
+        Some(self.offsets[start + i])
+    }
+
+    pub(crate) fn hashes(&self) -> &[[u8; 20]] {
+        &self.hashes
+    }
+}
+
+/// Writes a version 2 index of `entries` for the pack whose checksum is `pack_hash`.
+pub(crate) fn write_index(
+    path: &Path,
+    entries: &mut [IdxEntry],
+    pack_hash: &[u8; 20],
+) -> anyhow::Result<()> {
+    entries.sort_unstable_by_key(|e| e.hash);
+
+    let mut out = Vec::with_capacity(8 + 256 * 4 + entries.len() * 28 + 40);
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&2u32.to_be_bytes());
+    for byte in 0..=255u8 {
+        let count = entries.partition_point(|e| e.hash[0] <= byte);
+        out.extend_from_slice(&u32::try_from(count)?.to_be_bytes());
+    }
+    for entry in entries.iter() {
+        out.extend_from_slice(&entry.hash);
+    }
+    for entry in entries.iter() {
+        out.extend_from_slice(&entry.crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for entry in entries.iter() {
+        let small = match u32::try_from(entry.offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => offset,
+            _ => {
+                large.push(entry.offset);
+                0x8000_0000 | u32::try_from(large.len() - 1)?
+            }
+        };
+        out.extend_from_slice(&small.to_be_bytes());
+    }
+    for offset in large {
+        out.extend_from_slice(&offset.to_be_bytes());
+    }
+    out.extend_from_slice(pack_hash);
+    let checksum = Sha1::digest(&out);
+    out.extend_from_slice(&checksum);
+
+    fs::write(path, out).with_context(|| format!("Writing {}", path.display()))
+}
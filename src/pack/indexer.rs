@@ -0,0 +1,242 @@
+use super::idx::{self, IdxEntry};
+use super::{apply_delta, inflate, read_entry_header, reserve, EntryKind, PACK_DIR};
+use crate::objects::Kind;
+use anyhow::Context;
+use flate2::bufread::ZlibDecoder;
+use flate2::Crc;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Copies a pack to a file as it is read, keeping the checksum, the CRC and the offset of
+/// what has been read so far.
+struct PackStream<R> {
+    inner: BufReader<R>,
+    out: BufWriter<File>,
+    hasher: Sha1,
+    crc: Crc,
+    offset: u64,
+    /// A failed write to the file, reported once the current entry has been read.
+    error: Option<io::Error>,
+}
+
+impl<R: Read> BufRead for PackStream<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let data = &self.inner.buffer()[..amt];
+        self.hasher.update(data);
+        self.crc.update(data);
+        if self.error.is_none() {
+            if let Err(e) = self.out.write_all(data) {
+                self.error = Some(e);
+            }
+        }
+        self.offset += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+impl<R: Read> Read for PackStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+struct Entry {
+    offset: u64,
+    kind: EntryKind,
+    crc: u32,
+    /// Known as soon as the entry is read for whole objects, and once resolved for deltas.
+    hash: Option<[u8; 20]>,
+}
+
+/// Stores the pack read from `input` in `.git/objects/pack` as it arrives, checks its
+/// trailing checksum and writes an index for it. Returns the pack's checksum, which
+/// names both files.
+///
+/// Only the entries' positions are kept in memory; deltas are resolved afterwards by
+/// reading their bases back from the stored pack.
+pub(crate) fn index_pack(input: impl Read) -> anyhow::Result<String> {
+    fs::create_dir_all(PACK_DIR).context("Creating the pack directory")?;
+    let tmp = Path::new(PACK_DIR).join(format!("tmp_pack_{}", std::process::id()));
+    let tmp_idx = tmp.with_extension("idx");
+
+    let result = write_pack(input, &tmp).and_then(|(checksum, mut entries)| {
+        idx::write_index(&tmp_idx, &mut entries, &checksum)?;
+        Ok(hex::encode(checksum))
+    });
+    let hash = match result {
+        Ok(hash) => hash,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            let _ = fs::remove_file(&tmp_idx);
+            return Err(e);
+        }
+    };
+
+    // The pack goes first: it is only looked at once its index is there
+    let name = Path::new(PACK_DIR).join(format!("pack-{hash}"));
+    fs::rename(&tmp, name.with_extension("pack")).context("Renaming the pack")?;
+    fs::rename(&tmp_idx, name.with_extension("idx")).context("Renaming the pack index")?;
+    Ok(hash)
+}
+
+/// Writes the pack to `path` and returns its checksum and the index entries of its objects.
+fn write_pack(input: impl Read, path: &Path) -> anyhow::Result<([u8; 20], Vec<IdxEntry>)> {
+    let file = File::create(path).with_context(|| format!("Creating {}", path.display()))?;
+    let mut stream = PackStream {
+        inner: BufReader::new(input),
+        out: BufWriter::new(file),
+        hasher: Sha1::new(),
+        crc: Crc::new(),
+        offset: 0,
+        error: None,
+    };
+
+    let mut header = [0; 12];
+    stream
+        .read_exact(&mut header)
+        .context("Reading the pack header")?;
+    anyhow::ensure!(&header[..4] == b"PACK", "protocol error: bad pack header");
+    let version = u32::from_be_bytes(header[4..8].try_into()?);
+    anyhow::ensure!(
+        version == 2 || version == 3,
+        "pack version {version} unsupported"
+    );
+    let count = u32::from_be_bytes(header[8..12].try_into()?);
+
+    let mut entries = Vec::with_capacity(reserve(count.into()));
+    for _ in 0..count {
+        let offset = stream.offset;
+        stream.crc.reset();
+        let (kind, size) = read_entry_header(&mut stream, offset)?;
+        let mut decoder = ZlibDecoder::new(&mut stream);
+        let (hash, n) = match kind {
+            EntryKind::Object(kind) => {
+                let mut hasher = Sha1::new();
+                write!(hasher, "{kind} {size}\0")?;
+                let n = io::copy(&mut decoder, &mut hasher)
+                    .with_context(|| format!("Inflating the object at offset {offset}"))?;
+                (Some(hasher.finalize().into()), n)
+            }
+            // Deltas can only be hashed once their base is known
+            _ => {
+                let n = io::copy(&mut decoder, &mut io::sink())
+                    .with_context(|| format!("Inflating the delta at offset {offset}"))?;
+                (None, n)
+            }
+        };
+        anyhow::ensure!(
+            n == size,
+            "Decompressed size mismatch at offset {offset}: expected {size}, got {n}"
+        );
+        if let Some(e) = stream.error.take() {
+            return Err(e).context("Writing the pack");
+        }
+        entries.push(Entry {
+            offset,
+            kind,
+            crc: stream.crc.sum(),
+            hash,
+        });
+    }
+
+    let checksum: [u8; 20] = stream.hasher.clone().finalize().into();
+    let mut trailer = [0; 20];
+    stream
+        .read_exact(&mut trailer)
+        .context("Reading the pack checksum")?;
+    anyhow::ensure!(checksum == trailer, "pack is corrupted (SHA1 mismatch)");
+    anyhow::ensure!(stream.fill_buf()?.is_empty(), "pack has junk at the end");
+    if let Some(e) = stream.error.take() {
+        return Err(e).context("Writing the pack");
+    }
+    stream.out.flush().context("Writing the pack")?;
+
+    resolve_deltas(path, &mut entries)?;
+    let entries = entries
+        .into_iter()
+        .map(|e| IdxEntry {
+            hash: e.hash.expect("every entry is resolved"),
+            crc: e.crc,
+            offset: e.offset,
+        })
+        .collect();
+    Ok((checksum, entries))
+}
+
+/// Hashes the deltas in the pack at `path`, starting from each whole object and working
+/// down the deltas made against it, so that only one chain is in memory at a time.
+fn resolve_deltas(path: &Path, entries: &mut [Entry]) -> anyhow::Result<()> {
+    let mut by_offset: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut by_hash: HashMap<[u8; 20], Vec<usize>> = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.kind {
+            EntryKind::OfsDelta(base) => by_offset.entry(base).or_default().push(i),
+            EntryKind::RefDelta(base) => by_hash.entry(base).or_default().push(i),
+            EntryKind::Object(_) => {}
+        }
+    }
+    if by_offset.is_empty() && by_hash.is_empty() {
+        return Ok(());
+    }
+
+    let mut file = BufReader::new(File::open(path).context("Opening the pack")?);
+    for i in 0..entries.len() {
+        let EntryKind::Object(kind) = entries[i].kind else {
+            continue;
+        };
+        let has_deltas = by_offset.contains_key(&entries[i].offset)
+            || entries[i]
+                .hash
+                .is_some_and(|hash| by_hash.contains_key(&hash));
+        if !has_deltas {
+            continue;
+        }
+
+        let mut pending = vec![(i, read_entry(&mut file, entries[i].offset)?)];
+        while let Some((base, data)) = pending.pop() {
+            let deltas = by_offset
+                .remove(&entries[base].offset)
+                .into_iter()
+                .chain(entries[base].hash.and_then(|hash| by_hash.remove(&hash)))
+                .flatten();
+            for delta in deltas {
+                let target = apply_delta(&data, &read_entry(&mut file, entries[delta].offset)?)
+                    .with_context(|| {
+                        format!("Resolving the delta at offset {}", entries[delta].offset)
+                    })?;
+                entries[delta].hash = Some(object_hash(kind, &target));
+                pending.push((delta, target));
+            }
+        }
+    }
+
+    let unresolved = entries.iter().filter(|e| e.hash.is_none()).count();
+    anyhow::ensure!(unresolved == 0, "pack has {unresolved} unresolved deltas");
+    Ok(())
+}
+
+/// The inflated data of the entry at `offset`, which for a delta is the delta itself.
+fn read_entry(file: &mut BufReader<File>, offset: u64) -> anyhow::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))
+        .context("Seeking in the pack")?;
+    let (_, size) = read_entry_header(file, offset)?;
+    inflate(file, size)
+}
+
+fn object_hash(kind: Kind, data: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{kind} {}\0", data.len()));
+    hasher.update(data);
+    hasher.finalize().into()
+}
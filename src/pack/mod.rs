@@ -0,0 +1,274 @@
+pub(crate) mod idx;
+pub(crate) mod indexer;
+pub(crate) mod writer;
+
+use crate::objects::{self, Kind, Object};
+use anyhow::Context;
+use flate2::bufread::ZlibDecoder;
+use idx::PackIndex;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub(crate) const PACK_DIR: &str = ".git/objects/pack";
+
+/// The most a size read from pack data is trusted to reserve up front; buffers for
+/// anything bigger grow as the data actually arrives.
+const MAX_RESERVE: u64 = 1 << 20;
+
+/// How much to reserve for `size` items a pack claims are coming.
+pub(crate) fn reserve(size: u64) -> usize {
+    size.min(MAX_RESERVE) as usize
+}
+
+/// What a pack entry holds: a whole object, or a delta to apply to another object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Object(Kind),
+    /// A delta against the entry at this offset in the same pack.
+    OfsDelta(u64),
+    /// A delta against the object with this hash.
+    RefDelta([u8; 20]),
+}
+
+/// Reads the header of the entry at `offset`: what it holds and its inflated size.
+pub(crate) fn read_entry_header(
+    reader: &mut impl Read,
+    offset: u64,
+) -> anyhow::Result<(EntryKind, u64)> {
+    let mut byte = read_byte(reader)?;
+    let code = (byte >> 4) & 0x7;
+    let mut size = u64::from(byte & 0x0f);
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        anyhow::ensure!(shift < 64, "bad object size at offset {offset}");
+        byte = read_byte(reader)?;
+        size |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+    }
+
+    let kind = match code {
+        1 => EntryKind::Object(Kind::Commit),
+        2 => EntryKind::Object(Kind::Tree),
+        3 => EntryKind::Object(Kind::Blob),
+        4 => EntryKind::Object(Kind::Tag),
+        6 => {
+            // Big-endian base 128, where every byte after the first also adds one
+            let mut byte = read_byte(reader)?;
+            let mut distance = u64::from(byte & 0x7f);
+            while byte & 0x80 != 0 {
+                anyhow::ensure!(distance < 1 << 56, "delta base offset overflow");
+                byte = read_byte(reader)?;
+                distance = ((distance + 1) << 7) | u64::from(byte & 0x7f);
+            }
+            let base = offset
+                .checked_sub(distance)
+                .filter(|_| distance > 0)
+                .with_context(|| format!("delta base offset out of bound at offset {offset}"))?;
+            EntryKind::OfsDelta(base)
+        }
+        7 => {
+            let mut base = [0; 20];
+            reader
+                .read_exact(&mut base)
+                .context("Reading the delta base hash")?;
+            EntryKind::RefDelta(base)
+        }
+        _ => anyhow::bail!("unknown object type {code} at offset {offset}"),
+    };
+    Ok((kind, size))
+}
+
+fn read_byte(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut byte = [0];
+    reader
+        .read_exact(&mut byte)
+        .context("Unexpected end of pack data")?;
+    Ok(byte[0])
+}
+
+/// Inflates the zlib stream at the front of `reader` to its end, which must come after
+/// exactly `size` bytes.
+pub(crate) fn inflate(reader: impl BufRead, size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(reserve(size));
+    ZlibDecoder::new(reader)
+        .read_to_end(&mut data)
+        .context("Failed to decompress data")?;
+    anyhow::ensure!(
+        data.len() as u64 == size,
+        "Decompressed size mismatch: expected {size}, got {}",
+        data.len()
+    );
+    Ok(data)
+}
+
+/// Rebuilds an object from the `base` it was deltified against and the `delta`: two sizes,
+/// then instructions that either copy a range of the base or insert literal bytes.
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut delta = delta;
+    let source_size = read_size(&mut delta)?;
+    let target_size = read_size(&mut delta)?;
+    anyhow::ensure!(
+        source_size == base.len() as u64,
+        "Base object size mismatch: expected {source_size}, got {}",
+        base.len()
+    );
+
+    let mut target = Vec::with_capacity(reserve(target_size));
+    while let Some((&op, rest)) = delta.split_first() {
+        delta = rest;
+        if op & 0x80 != 0 {
+            // Copy: the low four bits say which offset bytes follow, the next three which
+            // size bytes do
+            let mut fields = [0u64; 2];
+            for (bit, shift) in (0..7).map(|bit| (bit, (bit % 4) * 8)) {
+                if op & (1 << bit) != 0 {
+                    let (&byte, rest) = delta.split_first().context("truncated delta")?;
+                    delta = rest;
+                    fields[bit / 4] |= u64::from(byte) << shift;
+                }
+            }
+            let [offset, size] = fields;
+            let size = if size == 0 { 0x10000 } else { size };
+            let range = usize::try_from(offset)?..usize::try_from(offset + size)?;
+            let chunk = base.get(range).with_context(|| {
+                format!(
+                    "Copy instruction out of bounds: offset {offset} + size {size} > source size {}",
+                    base.len()
+                )
+            })?;
+            target.extend_from_slice(chunk);
+        } else if op != 0 {
+            let size = usize::from(op);
+            anyhow::ensure!(delta.len() >= size, "truncated delta");
+            target.extend_from_slice(&delta[..size]);
+            delta = &delta[size..];
+        } else {
+            anyhow::bail!("unexpected delta opcode 0");
+        }
+    }
+    anyhow::ensure!(
+        target.len() as u64 == target_size,
+        "Target size mismatch: expected {target_size}, got {}",
+        target.len()
+    );
+    Ok(target)
+}
+
+/// Reads a little-endian base 128 size from the front of a delta.
+fn read_size(delta: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = delta.split_first().context("truncated delta")?;
+        *delta = rest;
+        anyhow::ensure!(shift < 64, "delta size overflow");
+        size |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+struct Pack {
+    path: PathBuf,
+    index: PackIndex,
+}
+
+/// The packs whose indexes have been read so far.
+static PACKS: Mutex<Vec<Pack>> = Mutex::new(Vec::new());
+
+/// Reads the indexes of packs that appeared since the list was last looked at, ours and
+/// those of the repositories we borrow objects from.
+fn refresh(packs: &mut Vec<Pack>) -> anyhow::Result<()> {
+    let mut dirs = vec![PathBuf::from(PACK_DIR)];
+    dirs.extend(objects::alternates().iter().map(|dir| dir.join("pack")));
+    for dir in dirs {
+        let Ok(dir) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in dir {
+            let path = entry.context("Listing packs")?.path();
+            if path.extension().map_or(true, |ext| ext != "idx") {
+                continue;
+            }
+            let pack = path.with_extension("pack");
+            if packs.iter().any(|p| p.path == pack) || !pack.exists() {
+                continue;
+            }
+            let index = PackIndex::read(&path)?;
+            packs.push(Pack { path: pack, index });
+        }
+    }
+    Ok(())
+}
+
+/// Which pack has object `hash`, and where in it.
+fn find(hash: &[u8; 20]) -> anyhow::Result<Option<(PathBuf, u64)>> {
+    let mut packs = PACKS.lock().unwrap_or_else(|e| e.into_inner());
+    let lookup = |packs: &[Pack]| {
+        packs
+            .iter()
+            .find_map(|pack| Some((pack.path.clone(), pack.index.find(hash)?)))
+    };
+    if let Some(found) = lookup(&packs) {
+        return Ok(Some(found));
+    }
+    refresh(&mut packs)?;
+    Ok(lookup(&packs))
+}
+
+/// Whether one of the packs has object `hash`.
+pub(crate) fn contains(hash: &[u8; 20]) -> anyhow::Result<bool> {
+    Ok(find(hash)?.is_some())
+}
+
+/// The kind and contents of object `hash`, if one of the packs has it.
+pub(crate) fn read_object(hash: &[u8; 20]) -> anyhow::Result<Option<(Kind, Vec<u8>)>> {
+    let Some((path, offset)) = find(hash)? else {
+        return Ok(None);
+    };
+    read_at(&path, offset)
+        .with_context(|| format!("Reading {} from {}", hex::encode(hash), path.display()))
+        .map(Some)
+}
+
+/// Reads the object at `offset` in the pack at `path`, resolving deltas.
+fn read_at(path: &Path, offset: u64) -> anyhow::Result<(Kind, Vec<u8>)> {
+    let mut file = BufReader::new(File::open(path).context("Opening pack")?);
+    file.seek(SeekFrom::Start(offset))
+        .context("Seeking in pack")?;
+    let (kind, size) = read_entry_header(&mut file, offset)?;
+    let data = inflate(&mut file, size)?;
+    match kind {
+        EntryKind::Object(kind) => Ok((kind, data)),
+        EntryKind::OfsDelta(base) => {
+            let (kind, base) = read_at(path, base)?;
+            Ok((kind, apply_delta(&base, &data)?))
+        }
+        EntryKind::RefDelta(base) => {
+            let base = Object::read(&hex::encode(base))?;
+            let kind = base.kind;
+            Ok((kind, apply_delta(&base.into_bytes()?, &data)?))
+        }
+    }
+}
+
+/// The full hashes of packed objects that start with the hex `prefix`.
+pub(crate) fn find_abbrev(prefix: &str) -> anyhow::Result<Vec<String>> {
+    let mut packs = PACKS.lock().unwrap_or_else(|e| e.into_inner());
+    refresh(&mut packs)?;
+    let mut found = Vec::new();
+    for pack in packs.iter() {
+        found.extend(
+            pack.index
+                .hashes()
+                .iter()
+                .map(hex::encode)
+                .filter(|hash| hash.starts_with(prefix)),
+        );
+    }
+    Ok(found)
+}
@@ -0,0 +1,186 @@
+use crate::merge::base::{self, CommitCache};
+use crate::objects::{self, Kind, Object};
+use crate::refs;
+use anyhow::Context;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// The objects reachable from `include` but not from `exclude`, as `rev-list --objects`
+/// lists them. Hashes in `exclude` we don't have are ignored.
+///
+/// Only the trees of the commits at the edge of `exclude` are walked to find what the
+/// other side already has, rather than its whole history.
+pub(crate) fn list_objects(include: &[String], exclude: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut cache = CommitCache::default();
+    let mut hidden_commits = Vec::new();
+    for hash in exclude {
+        if !objects::has_object(hash)? {
+            continue;
+        }
+        if let Ok(commit) = refs::peel_to_commit(hash) {
+            hidden_commits.push(commit);
+        }
+    }
+
+    let mut result = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut tips = Vec::new();
+    let mut roots = Vec::new();
+    for hash in include {
+        // Tags are sent along with what they point at
+        let mut hash = hash.clone();
+        loop {
+            let kind = Object::read(&hash)?.kind;
+            match kind {
+                Kind::Tag => {
+                    let target = objects::read_tag(&hash)?.object;
+                    if seen.insert(hash.clone()) {
+                        result.push(hash);
+                    }
+                    hash = target;
+                }
+                Kind::Commit => {
+                    tips.push(hash);
+                    break;
+                }
+                Kind::Tree => {
+                    roots.push(hash);
+                    break;
+                }
+                Kind::Blob => {
+                    if seen.insert(hash.clone()) {
+                        result.push(hash);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut commits = Vec::new();
+    for tip in &tips {
+        for commit in base::commits_between(&mut cache, &hidden_commits, tip)? {
+            if seen.insert(commit.clone()) {
+                commits.push(commit);
+            }
+        }
+    }
+
+    // Everything in the trees at the edge is already on the other side
+    let mut edge = hidden_commits.clone();
+    for commit in &commits {
+        for parent in cache.get(commit)?.parents.clone() {
+            if !seen.contains(&parent) && objects::has_object(&parent)? {
+                edge.push(parent);
+            }
+        }
+    }
+    let mut uninteresting = HashSet::new();
+    for commit in edge {
+        let tree = cache.get(&commit)?.tree.clone();
+        walk_tree(&tree, &mut uninteresting, &mut Vec::new())?;
+    }
+
+    for commit in &commits {
+        roots.push(cache.get(commit)?.tree.clone());
+    }
+    result.extend(commits);
+    seen.extend(uninteresting);
+    let mut found = Vec::new();
+    for root in roots {
+        walk_tree(&root, &mut seen, &mut found)?;
+    }
+    result.extend(found);
+    Ok(result)
+}
+
+/// Adds the tree `hash` and what it contains to `found`, skipping anything in `seen`.
+fn walk_tree(
+    hash: &str,
+    seen: &mut HashSet<String>,
+    found: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let mut stack = vec![hash.to_string()];
+    while let Some(tree) = stack.pop() {
+        if !seen.insert(tree.clone()) {
+            continue;
+        }
+        for entry in objects::read_tree(&tree)? {
+            let hash = hex::encode(entry.hash);
+            // Submodule commits live in another repository
+            if entry.is_tree() {
+                stack.push(hash);
+            } else if entry.mode != "160000" && seen.insert(hash.clone()) {
+                found.push(hash);
+            }
+        }
+        found.push(tree);
+    }
+    Ok(())
+}
+
+/// Writes a pack of the objects `hashes` to `out`, each stored whole, and returns its
+/// checksum.
+pub(crate) fn write_pack(hashes: &[String], out: impl Write) -> anyhow::Result<[u8; 20]> {
+    let mut out = HashWriter {
+        inner: out,
+        hasher: Sha1::new(),
+    };
+    out.write_all(b"PACK")?;
+    out.write_all(&2u32.to_be_bytes())?;
+    out.write_all(&u32::try_from(hashes.len())?.to_be_bytes())?;
+
+    for hash in hashes {
+        let object = Object::read(hash)?;
+        let kind = object.kind;
+        let data = object.into_bytes()?;
+        let code = match kind {
+            Kind::Commit => 1,
+            Kind::Tree => 2,
+            Kind::Blob => 3,
+            Kind::Tag => 4,
+        };
+
+        // The type and the low four bits of the size, then seven bits at a time
+        let mut size = data.len() as u64;
+        let mut header = vec![(code << 4) | (size & 0x0f) as u8];
+        size >>= 4;
+        while size > 0 {
+            *header.last_mut().expect("the header is not empty") |= 0x80;
+            header.push((size & 0x7f) as u8);
+            size >>= 7;
+        }
+        out.write_all(&header)?;
+
+        let mut encoder = ZlibEncoder::new(&mut out, Compression::default());
+        encoder
+            .write_all(&data)
+            .with_context(|| format!("Compressing {hash}"))?;
+        encoder.finish()?;
+    }
+
+    let checksum: [u8; 20] = out.hasher.finalize().into();
+    out.inner.write_all(&checksum)?;
+    Ok(checksum)
+}
+
+/// Hashes what is written through it.
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,82 @@
+/// Checks whether a repository-relative `path` is selected by any of `specs`.
+/// A spec selects a path equal to it, everything below it when it names a directory,
+/// or every path it matches as a glob (`*`, `?` and `[...]`, where `*` also crosses `/`).
+/// An empty list of specs selects everything.
+pub(crate) fn matches_any(path: &str, specs: &[String]) -> bool {
+    specs.is_empty() || specs.iter().any(|spec| matches(path, spec))
+}
+
+pub(crate) fn matches(path: &str, spec: &str) -> bool {
+    let spec = spec.trim_start_matches("./").trim_end_matches('/');
+    if spec.is_empty() || spec == "." {
+        return true;
+    }
+    if path == spec {
+        return true;
+    }
+    if path.len() > spec.len() && path.starts_with(spec) && path.as_bytes()[spec.len()] == b'/' {
+        return true;
+    }
+    spec.contains(['*', '?', '[']) && glob(spec.as_bytes(), path.as_bytes())
+}
+
+/// Matches `text` against `pattern` in one pass. On a mismatch only the last `*` seen is
+/// given more of the text: anything an earlier `*` could have taken, the later one can
+/// take too, so going back further never finds a match, and the time stays linear in
+/// the text for each star rather than growing exponentially with the number of stars.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // The pattern after the last `*` and how much of the text that star has taken so far
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, t));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(&pattern[p..], text[t]).map(|len| p + len),
+            Some(&c) => (c == text[t]).then_some(p + 1),
+            None => None,
+        };
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            (None, Some((after, taken))) => {
+                p = after;
+                t = taken + 1;
+                star = Some((after, t));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches `c` against the `[...]` class at the start of `pattern`, returning the length
+/// of the class when it matches. Without a closing `]` the `[` is an ordinary character.
+fn match_class(pattern: &[u8], c: u8) -> Option<usize> {
+    let Some(end) = pattern.iter().skip(2).position(|b| *b == b']') else {
+        return (c == b'[').then_some(1);
+    };
+    let class = &pattern[1..end + 2];
+    let (negate, class) = match class.first() {
+        Some(b'!') | Some(b'^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut hit = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            hit |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            hit |= class[i] == c;
+            i += 1;
+        }
+    }
+    (hit != negate).then_some(end + 3)
+}
@@ -0,0 +1,84 @@
+use crate::config::Config;
+use crate::objects;
+use crate::pack::{indexer, PACK_DIR};
+use crate::protocol::{Connection, FetchRequest};
+use anyhow::Context;
+use std::fs;
+
+/// Checks a `--filter` spec: `blob:none`, `blob:limit=<n>[kmg]` or `tree:<depth>`.
+pub(crate) fn check_filter(spec: &str) -> anyhow::Result<()> {
+    let valid = match spec.split_once(':') {
+        Some(("blob", "none")) => true,
+        Some(("blob", limit)) => limit.strip_prefix("limit=").is_some_and(|limit| {
+            let digits = limit.strip_suffix(['k', 'm', 'g']).unwrap_or(limit);
+            !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+        }),
+        Some(("tree", depth)) => depth.parse::<u64>().is_ok(),
+        _ => false,
+    };
+    anyhow::ensure!(valid, "invalid filter-spec '{spec}'");
+    Ok(())
+}
+
+/// Marks the pack named by `checksum` as coming from a promisor remote, which promises to
+/// hand over whatever objects the pack refers to but leaves out.
+pub(crate) fn mark_pack(checksum: &str) -> anyhow::Result<()> {
+    let path = format!("{PACK_DIR}/pack-{checksum}.promisor");
+    fs::write(&path, "").with_context(|| format!("Writing {path}"))
+}
+
+/// The remote a partial clone was made from, which missing objects are fetched from.
+pub(crate) fn remote(config: &Config) -> Option<&str> {
+    config
+        .subsections("remote", "promisor")
+        .into_iter()
+        .find(|name| config.get_bool(&format!("remote.{name}.promisor")) == Some(true))
+}
+
+/// Fetches in one go whichever of the files `entries`, as mode and hash, a partial clone
+/// left out, so that reading them takes one round trip rather than one each. Does nothing
+/// in a repository that isn't a partial clone.
+pub(crate) fn prefetch(entries: impl IntoIterator<Item = (u32, [u8; 20])>) -> anyhow::Result<()> {
+    if remote(&Config::read()?).is_none() {
+        return Ok(());
+    }
+    let mut missing = Vec::new();
+    // Absent sides have no mode, and submodule commits live in another repository
+    for (_, hash) in entries
+        .into_iter()
+        .filter(|(mode, _)| *mode != 0 && *mode != 0o160000)
+    {
+        let hash = hex::encode(hash);
+        if !missing.contains(&hash) && !objects::has_object(&hash)? {
+            missing.push(hash);
+        }
+    }
+    if !missing.is_empty() {
+        fetch(&missing).context("Fetching missing objects")?;
+    }
+    Ok(())
+}
+
+/// Fetches the objects `hashes` that a partial clone left out. Returns false, fetching
+/// nothing, when the repository is not a partial clone.
+pub(crate) fn fetch(hashes: &[String]) -> anyhow::Result<bool> {
+    let config = Config::read()?;
+    let Some(name) = remote(&config) else {
+        return Ok(false);
+    };
+    let url = config
+        .get(&format!("remote.{name}.url"))
+        .with_context(|| format!("The promisor remote '{name}' has no url"))?;
+
+    let connection = Connection::connect(url).context("Connecting to the promisor remote")?;
+    // Objects asked for by name are always sent; the filter keeps a tree from bringing
+    // every blob under it along
+    let response = connection.fetch(&FetchRequest {
+        wants: hashes.to_vec(),
+        filter: Some("blob:none".to_string()),
+        ..Default::default()
+    })?;
+    let checksum = indexer::index_pack(response.pack).context("Storing the pack")?;
+    mark_pack(&checksum)?;
+    Ok(true)
+}
@@ -0,0 +1,167 @@
+use crate::config::Config;
+use anyhow::Context;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// A username and password for a host, and where to ask for them: the configured
+/// `credential.helper`s, then an askpass program.
+#[derive(Debug, Clone)]
+pub(crate) struct Credential {
+    pub(crate) protocol: String,
+    /// The host, with the port if the URL has one.
+    pub(crate) host: String,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+    helpers: Vec<String>,
+    askpass: Option<String>,
+}
+
+impl Credential {
+    pub(crate) fn new(url: &reqwest::Url, config: &Config) -> Credential {
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        // An empty value clears the helpers configured before it
+        let mut helpers = Vec::new();
+        for helper in config.get_all("credential.helper") {
+            match helper {
+                "" => helpers.clear(),
+                helper => helpers.push(helper.to_string()),
+            }
+        }
+        let askpass = std::env::var("GIT_ASKPASS")
+            .ok()
+            .or_else(|| config.get("core.askpass").map(str::to_string))
+            .or_else(|| std::env::var("SSH_ASKPASS").ok())
+            .filter(|program| !program.is_empty());
+        Credential {
+            protocol: url.scheme().to_string(),
+            host,
+            username: None,
+            password: None,
+            helpers,
+            askpass,
+        }
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.username.is_some() && self.password.is_some()
+    }
+
+    /// Fills in the username and password from the helpers, in order, and asks the
+    /// askpass program for whatever they don't know.
+    pub(crate) fn fill(&mut self) -> anyhow::Result<()> {
+        for helper in self.helpers.clone() {
+            for (key, value) in self.run_helper(&helper, "get")? {
+                match key.as_str() {
+                    "username" if self.username.is_none() => self.username = Some(value),
+                    "password" if self.password.is_none() => self.password = Some(value),
+                    "quit" if value == "1" || value == "true" => {
+                        anyhow::bail!("credential helper '{helper}' told us to quit")
+                    }
+                    _ => {}
+                }
+            }
+            if self.is_complete() {
+                return Ok(());
+            }
+        }
+
+        let url = format!("{}://{}", self.protocol, self.host);
+        if self.username.is_none() {
+            self.username = Some(self.ask(&format!("Username for '{url}': "), "Username")?);
+        }
+        if self.password.is_none() {
+            let user = self.username.as_deref().unwrap_or_default();
+            let url = format!("{}://{user}@{}", self.protocol, self.host);
+            self.password = Some(self.ask(&format!("Password for '{url}': "), "Password")?);
+        }
+        Ok(())
+    }
+
+    /// Tells the helpers that the credential worked, so that they can store it.
+    pub(crate) fn approve(&self) -> anyhow::Result<()> {
+        for helper in &self.helpers {
+            self.run_helper(helper, "store")?;
+        }
+        Ok(())
+    }
+
+    /// Tells the helpers that the credential was turned down, so that they forget it.
+    pub(crate) fn reject(&self) -> anyhow::Result<()> {
+        for helper in &self.helpers {
+            self.run_helper(helper, "erase")?;
+        }
+        Ok(())
+    }
+
+    /// Runs `helper` for `action` with the credential's `key=value` lines on stdin, and
+    /// returns those it answers with.
+    fn run_helper(&self, helper: &str, action: &str) -> anyhow::Result<Vec<(String, String)>> {
+        // `!` runs a shell snippet, a path runs that program and a name `git credential-<name>`
+        let command = if let Some(snippet) = helper.strip_prefix('!') {
+            format!("{snippet} {action}")
+        } else if helper.starts_with('/') {
+            format!("{helper} {action}")
+        } else {
+            format!("git credential-{helper} {action}")
+        };
+
+        let mut input = format!("protocol={}\nhost={}\n", self.protocol, self.host);
+        if let Some(username) = &self.username {
+            input.push_str(&format!("username={username}\n"));
+        }
+        if let Some(password) = self.password.as_ref().filter(|_| action != "get") {
+            input.push_str(&format!("password={password}\n"));
+        }
+        input.push('\n');
+
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Running credential helper '{helper}'"))?;
+        // A helper may exit without reading what it doesn't need
+        let _ = child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(input.as_bytes());
+        let output = child
+            .wait_with_output()
+            .with_context(|| format!("Running credential helper '{helper}'"))?;
+        if action != "get" {
+            return Ok(Vec::new());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+
+    /// Asks the askpass program for the `what` that `prompt` asks for.
+    fn ask(&self, prompt: &str, what: &str) -> anyhow::Result<String> {
+        let url = format!("{}://{}", self.protocol, self.host);
+        let Some(askpass) = &self.askpass else {
+            anyhow::bail!(
+                "could not read {what} for '{url}': no credential helper or askpass program \
+                 provided it"
+            );
+        };
+        let output = Command::new(askpass)
+            .arg(prompt)
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("Running askpass program '{askpass}'"))?;
+        anyhow::ensure!(
+            output.status.success(),
+            "could not read {what} for '{url}': askpass program '{askpass}' failed"
+        );
+        let answer = String::from_utf8_lossy(&output.stdout);
+        Ok(answer.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
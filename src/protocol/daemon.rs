@@ -0,0 +1,68 @@
+use super::pkt_line::PktWriter;
+use anyhow::Context;
+use reqwest::Url;
+use std::io::{BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+
+/// The port `git daemon` listens on unless told otherwise.
+pub(crate) const DEFAULT_PORT: u16 = 9418;
+
+/// The `git://` transport: one TCP connection to a `git daemon`, which runs the service
+/// asked for on it and holds the whole conversation, so that requests go out one after
+/// another on the same stream.
+pub(crate) struct Daemon {
+    stream: TcpStream,
+}
+
+impl Daemon {
+    /// Connects to the daemon serving `url` and asks it for `service` on the repository,
+    /// in protocol version 2 if it speaks that.
+    pub(crate) fn connect(url: &str, service: &str) -> anyhow::Result<Daemon> {
+        let parsed = Url::parse(url).with_context(|| format!("invalid URL '{url}'"))?;
+        let host = parsed
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .with_context(|| format!("no host in '{url}'"))?;
+        let port = parsed.port().unwrap_or(DEFAULT_PORT);
+        let stream = TcpStream::connect((host.trim_matches(['[', ']']), port))
+            .with_context(|| format!("unable to connect to {host}:{port}"))?;
+
+        // The daemon is told the host as the client knows it, as HTTP servers are
+        let host = match parsed.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let request = format!("{service} {}\0host={host}\0\0version=2\0", parsed.path());
+        let mut writer = PktWriter::new(&stream);
+        writer.data(request.as_bytes())?;
+        writer.flush()?;
+        Ok(Daemon { stream })
+    }
+
+    /// Sends `body`, a request in pkt-lines.
+    pub(crate) fn send(&self, body: &[u8]) -> anyhow::Result<()> {
+        (&self.stream)
+            .write_all(body)
+            .context("Sending the request")
+    }
+
+    /// Ends what is sent, for services that read until the end of their input.
+    pub(crate) fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Write);
+    }
+
+    /// Reads what the server sends next. The server waits for each request before it
+    /// answers, so nothing read ahead belongs to a later answer.
+    pub(crate) fn reader(&self) -> anyhow::Result<BufReader<TcpStream>> {
+        let stream = self.stream.try_clone().context("Cloning the connection")?;
+        Ok(BufReader::new(stream))
+    }
+}
+
+impl Drop for Daemon {
+    /// Says goodbye with a flush, so that the server ends quietly rather than finding the
+    /// connection gone. One that has already finished its answer has left.
+    fn drop(&mut self) {
+        let _ = (&self.stream).write_all(b"0000");
+    }
+}
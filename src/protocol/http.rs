@@ -0,0 +1,211 @@
+use super::credential::Credential;
+use crate::config::Config;
+use anyhow::Context;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{Certificate, Proxy, StatusCode, Url};
+use std::cell::{Cell, RefCell};
+
+/// The smart HTTP transport: one GET for the advertisement, then one POST per request.
+pub(crate) struct Http {
+    /// The URL without any username and password in it.
+    url: String,
+    client: Client,
+    /// Who to authenticate as, once the server has asked or the URL said.
+    credential: RefCell<Credential>,
+    /// Whether the credential has been seen to work and handed to the helpers.
+    approved: Cell<bool>,
+    /// The `http.extraHeader` settings, sent with every request.
+    extra_headers: HeaderMap,
+}
+
+impl Http {
+    /// Sets up a client for the repository at `url` as the `http.*` settings and the
+    /// `GIT_SSL_*` and proxy variables say.
+    pub(crate) fn new(url: &str) -> anyhow::Result<Http> {
+        let config = Config::read()?;
+        let mut parsed = Url::parse(url).with_context(|| format!("invalid URL '{url}'"))?;
+        let mut credential = Credential::new(&parsed, &config);
+        if !parsed.username().is_empty() {
+            credential.username = Some(decode(parsed.username()));
+        }
+        credential.password = parsed.password().map(decode);
+        // They are sent as a header instead, and kept out of messages that show the URL
+        let _ = parsed.set_username("");
+        let _ = parsed.set_password(None);
+
+        let user_agent = std::env::var("GIT_HTTP_USER_AGENT")
+            .ok()
+            .or_else(|| config.get("http.useragent").map(str::to_string))
+            .unwrap_or_else(|| super::AGENT.to_string());
+        // Proxies in HTTPS_PROXY, HTTP_PROXY, ALL_PROXY and NO_PROXY are used by default
+        let mut builder = Client::builder().user_agent(user_agent);
+        if let Some(proxy) = config.get("http.proxy").filter(|proxy| !proxy.is_empty()) {
+            let proxy = Proxy::all(proxy).with_context(|| format!("invalid proxy '{proxy}'"))?;
+            builder = builder.proxy(proxy);
+        }
+        let ca_info = std::env::var("GIT_SSL_CAINFO")
+            .ok()
+            .or_else(|| config.get("http.sslcainfo").map(str::to_string));
+        if let Some(path) = ca_info {
+            let pem = std::fs::read(&path).with_context(|| format!("Reading {path}"))?;
+            for certificate in Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Reading the certificates in {path}"))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        let no_verify = std::env::var_os("GIT_SSL_NO_VERIFY").is_some()
+            || config.get_bool("http.sslverify") == Some(false);
+        builder = builder.danger_accept_invalid_certs(no_verify);
+
+        let client = builder.build().context("Setting up the HTTP client")?;
+        Ok(Http {
+            url: parsed.as_str().trim_end_matches('/').to_string(),
+            client,
+            credential: RefCell::new(credential),
+            approved: Cell::new(false),
+            extra_headers: extra_headers(&config)?,
+        })
+    }
+
+    /// `GET info/refs?service=<service>`, asking for protocol v2 with `Git-Protocol`.
+    pub(crate) fn advertisement(&self, service: &str) -> anyhow::Result<Response> {
+        let url = format!("{}/info/refs?service={service}", self.url);
+        let response = self.send(|| self.client.get(&url).header("Git-Protocol", "version=2"))?;
+        let response = check_status(response, &self.url)?;
+
+        let expected = format!("application/x-{service}-advertisement");
+        anyhow::ensure!(
+            content_type(&response) == expected,
+            "{}/info/refs not valid: is this a git repository?",
+            self.url
+        );
+        Ok(response)
+    }
+
+    /// `POST <service>` with a request body in pkt-lines.
+    pub(crate) fn post(&self, service: &str, v2: bool, body: Vec<u8>) -> anyhow::Result<Response> {
+        let url = format!("{}/{service}", self.url);
+        let response = self.send(|| {
+            let request = self
+                .client
+                .post(&url)
+                .header(CONTENT_TYPE, format!("application/x-{service}-request"))
+                .header("Accept", format!("application/x-{service}-result"))
+                .body(body.clone());
+            match v2 {
+                true => request.header("Git-Protocol", "version=2"),
+                false => request,
+            }
+        })?;
+        let response = check_status(response, &self.url)?;
+
+        let expected = format!("application/x-{service}-result");
+        anyhow::ensure!(
+            content_type(&response) == expected,
+            "invalid content-type: '{}'",
+            content_type(&response)
+        );
+        Ok(response)
+    }
+
+    /// Sends the request `build` makes, with the credential if there is one. When the
+    /// server asks for one, it is filled in from the helpers or askpass and the request
+    /// is sent again; one that gets turned down is passed on to the helpers to forget.
+    fn send(&self, build: impl Fn() -> RequestBuilder) -> anyhow::Result<Response> {
+        let mut retried = false;
+        loop {
+            let credential = self.credential.borrow().clone();
+            let mut request = build();
+            // Added one by one, as a header can be given more than once
+            for (name, value) in &self.extra_headers {
+                request = request.header(name, value);
+            }
+            if let Some(username) = &credential.username {
+                request = request.basic_auth(username, credential.password.as_deref());
+            }
+            let response = request
+                .send()
+                .with_context(|| format!("unable to access '{}'", self.url))?;
+
+            if response.status() != StatusCode::UNAUTHORIZED {
+                if credential.is_complete() && !self.approved.get() {
+                    credential.approve()?;
+                    self.approved.set(true);
+                }
+                return Ok(response);
+            }
+            if credential.is_complete() || retried {
+                credential.reject()?;
+                anyhow::bail!("Authentication failed for '{}/'", self.url);
+            }
+            self.credential.borrow_mut().fill()?;
+            retried = true;
+        }
+    }
+}
+
+/// The `http.extraHeader` settings, `Name: value` each. An empty one drops those before it.
+fn extra_headers(config: &Config) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for header in config.get_all("http.extraheader") {
+        if header.is_empty() {
+            headers.clear();
+            continue;
+        }
+        let (name, value) = header
+            .split_once(':')
+            .with_context(|| format!("invalid http.extraHeader '{header}'"))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("invalid http.extraHeader '{header}'"))?;
+        let value = HeaderValue::from_str(value.trim())
+            .with_context(|| format!("invalid http.extraHeader '{header}'"))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// Undoes the percent-encoding of a username or password in a URL.
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn check_status(response: Response, url: &str) -> anyhow::Result<Response> {
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        anyhow::bail!("repository '{url}/' not found");
+    }
+    anyhow::ensure!(
+        status.is_success(),
+        "unable to access '{url}/': The requested URL returned error: {}",
+        status.as_u16()
+    );
+    Ok(response)
+}
+
+fn content_type(response: &Response) -> &str {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
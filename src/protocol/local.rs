@@ -0,0 +1,237 @@
+use super::RemoteRef;
+use anyhow::Context;
+use flate2::read::ZlibDecoder;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const OBJECTS: &str = ".git/objects";
+const ALTERNATES: &str = ".git/objects/info/alternates";
+
+/// A repository on this machine, reached through its files rather than a server.
+pub(crate) struct LocalRepo {
+    git_dir: PathBuf,
+}
+
+impl LocalRepo {
+    /// The repository at `url` when it is a `file://` URL or a path, with or without a
+    /// worktree around it. `None` for URLs of other transports.
+    pub(crate) fn open(url: &str) -> anyhow::Result<Option<LocalRepo>> {
+        let Some(path) = local_path(url) else {
+            return Ok(None);
+        };
+        let git_dir = match path.join(".git") {
+            dot_git if dot_git.is_dir() => dot_git,
+            _ => path,
+        };
+        anyhow::ensure!(
+            git_dir.join("HEAD").is_file() && git_dir.join("objects").is_dir(),
+            "'{url}' does not appear to be a git repository"
+        );
+        Ok(Some(LocalRepo { git_dir }))
+    }
+
+    /// The repository's refs as a server would advertise them: `HEAD` first, then the
+    /// rest sorted by name, with what annotated tags point at when that can be told.
+    pub(crate) fn refs(&self) -> anyhow::Result<Vec<RemoteRef>> {
+        // Name to value, where a value is a hash or `ref: <target>`
+        let mut values: BTreeMap<String, String> = BTreeMap::new();
+        let mut peeled: BTreeMap<String, String> = BTreeMap::new();
+        if let Ok(packed) = fs::read_to_string(self.git_dir.join("packed-refs")) {
+            let mut last: Option<String> = None;
+            for line in packed.lines() {
+                if let Some(hash) = line.strip_prefix('^') {
+                    if let Some(name) = &last {
+                        peeled.insert(name.clone(), hash.to_string());
+                    }
+                } else if let Some((hash, name)) = line.split_once(' ') {
+                    if !line.starts_with('#') {
+                        values.insert(name.to_string(), hash.to_string());
+                        last = Some(name.to_string());
+                    }
+                }
+            }
+        }
+        // Loose refs win over packed ones
+        let mut dirs = vec![self.git_dir.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry.context("Listing refs")?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let name = path
+                    .strip_prefix(&self.git_dir)
+                    .expect("refs are inside the git dir")
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                // A ref being updated is still what it was until its lock file is renamed
+                if name.ends_with(".lock") {
+                    continue;
+                }
+                let value = fs::read_to_string(&path)
+                    .with_context(|| format!("Reading {}", path.display()))?;
+                values.insert(name.clone(), value.trim().to_string());
+                peeled.remove(&name);
+            }
+        }
+
+        let resolve = |value: &str| {
+            let mut value = value.to_string();
+            // Symbolic refs can chain, but never legitimately more than a handful of times
+            for _ in 0..5 {
+                match value.strip_prefix("ref: ") {
+                    Some(target) => value = values.get(target.trim())?.clone(),
+                    None => return Some(value),
+                }
+            }
+            None
+        };
+
+        let mut refs = Vec::new();
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).context("Reading HEAD")?;
+        let head = head.trim();
+        // An unborn HEAD is left out, as servers do
+        if let Some(hash) = resolve(head) {
+            refs.push(RemoteRef {
+                hash,
+                name: "HEAD".to_string(),
+                symref_target: head.strip_prefix("ref: ").map(str::to_string),
+                peeled: None,
+            });
+        }
+        for (name, value) in &values {
+            let Some(hash) = resolve(value) else {
+                continue;
+            };
+            let peeled = match peeled.get(name) {
+                Some(peeled) => Some(peeled.clone()),
+                None => self.peel_loose_tag(&hash),
+            };
+            refs.push(RemoteRef {
+                hash,
+                name: name.clone(),
+                symref_target: value.strip_prefix("ref: ").map(str::to_string),
+                peeled,
+            });
+        }
+        Ok(refs)
+    }
+
+    /// What `hash` points at when it is an annotated tag stored as a loose object. Tags in
+    /// packs are peeled in `packed-refs` once their refs are packed too.
+    fn peel_loose_tag(&self, hash: &str) -> Option<String> {
+        let mut hash = hash.to_string();
+        let mut peeled = None;
+        for _ in 0..5 {
+            let path = self
+                .git_dir
+                .join("objects")
+                .join(&hash[..2])
+                .join(&hash[2..]);
+            let mut data = Vec::new();
+            ZlibDecoder::new(fs::File::open(path).ok()?)
+                .read_to_end(&mut data)
+                .ok()?;
+            if !data.starts_with(b"tag ") {
+                break;
+            }
+            let body = data.splitn(2, |b| *b == 0).nth(1)?;
+            let target = String::from_utf8_lossy(body)
+                .lines()
+                .next()?
+                .strip_prefix("object ")?
+                .to_string();
+            peeled = Some(target.clone());
+            hash = target;
+        }
+        peeled
+    }
+
+    /// Copies every object the repository has, and the repositories it borrows objects
+    /// from, into ours. Hard links are made instead of copies where that works.
+    pub(crate) fn copy_objects(&self, hard_links: bool) -> anyhow::Result<()> {
+        let source = self.git_dir.join("objects");
+        for entry in fs::read_dir(&source).context("Listing the objects to copy")? {
+            let entry = entry.context("Listing the objects to copy")?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let loose = name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit());
+            if !loose && name != "pack" {
+                continue;
+            }
+            for file in fs::read_dir(entry.path()).context("Listing the objects to copy")? {
+                let file = file.context("Listing the objects to copy")?;
+                let file_name = file.file_name();
+                // Packs being written are not ready to be used
+                if name == "pack" && !file_name.to_string_lossy().starts_with("pack-") {
+                    continue;
+                }
+                let target = Path::new(OBJECTS).join(&name).join(&file_name);
+                if target.exists() {
+                    continue;
+                }
+                fs::create_dir_all(target.parent().expect("objects are in a directory"))
+                    .context("Creating the object directory")?;
+                if hard_links && fs::hard_link(file.path(), &target).is_ok() {
+                    continue;
+                }
+                fs::copy(file.path(), &target)
+                    .with_context(|| format!("Copying {}", file.path().display()))?;
+            }
+        }
+
+        let borrowed: Vec<PathBuf> = match fs::read_to_string(source.join("info/alternates")) {
+            Ok(text) => text
+                .lines()
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| source.join(line))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        add_alternates(&borrowed)
+    }
+
+    /// Makes our repository borrow the objects of this one instead of copying them.
+    pub(crate) fn share_objects(&self) -> anyhow::Result<()> {
+        add_alternates(&[self.git_dir.join("objects")])
+    }
+}
+
+/// Adds object directories to `.git/objects/info/alternates`, as absolute paths.
+fn add_alternates(dirs: &[PathBuf]) -> anyhow::Result<()> {
+    if dirs.is_empty() {
+        return Ok(());
+    }
+    let mut text = fs::read_to_string(ALTERNATES).unwrap_or_default();
+    for dir in dirs {
+        let dir = fs::canonicalize(dir)
+            .with_context(|| format!("Finding the object directory {}", dir.display()))?;
+        let line = dir.to_string_lossy();
+        if !text.lines().any(|existing| existing == line) {
+            text.push_str(&format!("{line}\n"));
+        }
+    }
+    fs::create_dir_all(".git/objects/info").context("Creating .git/objects/info")?;
+    fs::write(ALTERNATES, text).context("Writing .git/objects/info/alternates")
+}
+
+/// The path a `file://` URL or a plain path names. Like git, `host:path` with no `/`
+/// before the colon is taken to be SSH rather than a path.
+pub(crate) fn local_path(url: &str) -> Option<PathBuf> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return Some(PathBuf::from(path));
+    }
+    if url.contains("://") {
+        return None;
+    }
+    match (url.find(':'), url.find('/')) {
+        (Some(colon), Some(slash)) if colon < slash => None,
+        (Some(_), None) => None,
+        _ => Some(PathBuf::from(url)),
+    }
+}
@@ -0,0 +1,642 @@
+pub(crate) mod credential;
+pub(crate) mod daemon;
+pub(crate) mod http;
+pub(crate) mod local;
+pub(crate) mod pkt_line;
+pub(crate) mod push;
+pub(crate) mod sideband;
+pub(crate) mod ssh;
+
+use anyhow::Context;
+use daemon::Daemon;
+use http::Http;
+use local::LocalRepo;
+use pkt_line::{Packet, PktReader, PktWriter};
+use sideband::Demux;
+use ssh::Ssh;
+use std::io::{BufReader, IsTerminal, Read};
+
+/// How we introduce ourselves to servers, as the HTTP user agent and the `agent` capability.
+pub(crate) const AGENT: &str = concat!("git/custom-git-", env!("CARGO_PKG_VERSION"));
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+
+/// The protocol a server answered with. Version 1 is version 0 with a `version 1` line in
+/// front, so it is handled as version 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Version {
+    V0,
+    V2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RemoteRef {
+    pub(crate) hash: String,
+    pub(crate) name: String,
+    /// The ref a symbolic ref such as `HEAD` points at, when the server says.
+    pub(crate) symref_target: Option<String>,
+    /// What an annotated tag points at, when the server says.
+    pub(crate) peeled: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FetchRequest {
+    pub(crate) wants: Vec<String>,
+    /// Commits we already have, so that the server can leave out what they reach.
+    pub(crate) haves: Vec<String>,
+    /// The commits our history is cut off at, from `.git/shallow`.
+    pub(crate) shallow: Vec<String>,
+    pub(crate) deepen: Option<Deepen>,
+    /// Which objects to leave out, such as `blob:none`, for a partial clone.
+    pub(crate) filter: Option<String>,
+}
+
+/// How much history a shallow fetch asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Deepen {
+    /// This many commits from each wanted tip.
+    Depth(u32),
+    /// This many more commits past the current shallow boundary.
+    Relative(u32),
+    /// Commits made after this Unix time.
+    Since(i64),
+}
+
+/// The depth that stands for all of history, used to unshallow a repository.
+pub(crate) const INFINITE_DEPTH: u32 = 0x7fff_ffff;
+
+/// The pack a fetch returned, and how the shallow boundary moved.
+pub(crate) struct FetchResponse {
+    pub(crate) pack: Box<dyn Read>,
+    /// Commits whose parents were left out.
+    pub(crate) shallow: Vec<String>,
+    /// Commits that were shallow and now have their parents.
+    pub(crate) unshallow: Vec<String>,
+}
+
+/// How a remote repository is reached: through its server, or through its files when it
+/// is on this machine.
+pub(crate) enum Transport {
+    Smart(Box<Connection>),
+    Local(LocalRepo),
+}
+
+impl Transport {
+    /// Opens the repository at `url` directly when it is a path or a `file://` URL, and
+    /// connects to its server otherwise.
+    pub(crate) fn connect(url: &str) -> anyhow::Result<Transport> {
+        match LocalRepo::open(url)? {
+            Some(repo) => Ok(Transport::Local(repo)),
+            None => Ok(Transport::Smart(Box::new(Connection::connect(url)?))),
+        }
+    }
+
+    /// The remote refs whose names start with one of `prefixes`, or all of them.
+    pub(crate) fn ls_refs(&self, prefixes: &[&str]) -> anyhow::Result<Vec<RemoteRef>> {
+        match self {
+            Transport::Smart(connection) => connection.ls_refs(prefixes),
+            Transport::Local(repo) => Ok(repo
+                .refs()?
+                .into_iter()
+                .filter(|r| prefixes.is_empty() || prefixes.iter().any(|p| r.name.starts_with(p)))
+                .collect()),
+        }
+    }
+}
+
+/// A connection to the upload-pack service of a remote repository, over smart HTTP or
+/// `git://`.
+pub(crate) struct Connection {
+    channel: Channel,
+    version: Version,
+    capabilities: Vec<String>,
+    /// The refs a version 0 server advertises up front; version 2 lists them on request.
+    advertised: Vec<RemoteRef>,
+}
+
+impl Connection {
+    /// Fetches the advertisement of the repository at `url`, asking for protocol version 2
+    /// and falling back to what the server speaks instead.
+    pub(crate) fn connect(url: &str) -> anyhow::Result<Connection> {
+        let (channel, advertisement) = Channel::open(url, UPLOAD_PACK)?;
+        let mut reader = PktReader::new(advertisement);
+
+        let mut first = read_announcement(&mut reader)?;
+        if first.line() == Some("version 2") {
+            let (capabilities, end) = reader.read_lines()?;
+            anyhow::ensure!(
+                end == Packet::Flush,
+                "protocol error: expected flush after the capability advertisement"
+            );
+            return Ok(Connection {
+                channel,
+                version: Version::V2,
+                capabilities,
+                advertised: Vec::new(),
+            });
+        }
+        if first.line() == Some("version 1") {
+            first = reader.expect()?;
+        }
+        let (capabilities, mut advertised) = read_advertisement(first, &mut reader)?;
+        // Symbolic refs are told as capabilities such as `symref=HEAD:refs/heads/main`
+        for symref in capabilities
+            .iter()
+            .filter_map(|c| c.strip_prefix("symref="))
+        {
+            if let Some((name, target)) = symref.split_once(':') {
+                for remote_ref in advertised.iter_mut().filter(|r| r.name == name) {
+                    remote_ref.symref_target = Some(target.to_string());
+                }
+            }
+        }
+        Ok(Connection {
+            channel,
+            version: Version::V0,
+            capabilities,
+            advertised,
+        })
+    }
+
+    /// The value of capability `name`: empty for one without a value, `None` when the
+    /// server does not have it.
+    pub(crate) fn capability(&self, name: &str) -> Option<&str> {
+        find_capability(&self.capabilities, name)
+    }
+
+    /// The remote refs whose names start with one of `prefixes`, or all of them.
+    pub(crate) fn ls_refs(&self, prefixes: &[&str]) -> anyhow::Result<Vec<RemoteRef>> {
+        let matches =
+            |name: &str| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p));
+        if self.version == Version::V0 {
+            return Ok(self
+                .advertised
+                .iter()
+                .filter(|r| matches(&r.name))
+                .cloned()
+                .collect());
+        }
+
+        let mut request = self.command("ls-refs")?;
+        request.line("symrefs")?;
+        request.line("peel")?;
+        for prefix in prefixes {
+            request.line(&format!("ref-prefix {prefix}"))?;
+        }
+        request.flush_pkt()?;
+        let mut reader = self.post(request)?;
+        let (lines, end) = reader.read_lines()?;
+        anyhow::ensure!(
+            end == Packet::Flush,
+            "protocol error: expected flush after ref listing"
+        );
+
+        let mut refs = Vec::new();
+        for line in lines {
+            let mut fields = line.split(' ');
+            let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+                anyhow::bail!("invalid ls-refs response: {line}");
+            };
+            let mut remote_ref = RemoteRef {
+                hash: hash.to_string(),
+                name: name.to_string(),
+                symref_target: None,
+                peeled: None,
+            };
+            for attribute in fields {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    remote_ref.symref_target = Some(target.to_string());
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    remote_ref.peeled = Some(peeled.to_string());
+                }
+            }
+            // A server may not filter by prefix, which is only a hint
+            if matches(name) {
+                refs.push(remote_ref);
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Asks for a pack with the objects reachable from the wanted commits but not from the
+    /// ones we have, and returns a reader that streams it.
+    pub(crate) fn fetch(&self, request: &FetchRequest) -> anyhow::Result<FetchResponse> {
+        anyhow::ensure!(!request.wants.is_empty(), "no objects to fetch");
+        let shallow = request.deepen.is_some() || !request.shallow.is_empty();
+        anyhow::ensure!(
+            !shallow || self.supports("shallow"),
+            "Server does not support shallow clients"
+        );
+        // Like git, fall back to fetching everything from servers that can't filter
+        let filter = request.filter.as_deref();
+        let filter = match filter {
+            Some(_) if !self.supports("filter") => {
+                eprintln!("warning: filtering not recognized by server, ignoring");
+                None
+            }
+            _ => filter,
+        };
+        match self.version {
+            Version::V0 => self.fetch_v0(request, filter),
+            Version::V2 => self.fetch_v2(request, filter),
+        }
+    }
+
+    /// Whether the server's fetch can do `feature`, a capability in version 0 and part of
+    /// the `fetch` command's value in version 2.
+    fn supports(&self, feature: &str) -> bool {
+        match self.version {
+            Version::V0 => self.capability(feature).is_some(),
+            Version::V2 => self
+                .capability("fetch")
+                .is_some_and(|features| features.split(' ').any(|f| f == feature)),
+        }
+    }
+
+    /// Offers our commits in rounds of growing size until the server has found enough
+    /// common ground, or we run out, and then asks for the pack.
+    fn fetch_v2(
+        &self,
+        request: &FetchRequest,
+        filter: Option<&str>,
+    ) -> anyhow::Result<FetchResponse> {
+        let mut common: Vec<String> = Vec::new();
+        let mut haves = request.haves.iter();
+        let mut round = 16;
+        loop {
+            let batch: Vec<&String> = haves.by_ref().take(round).collect();
+            round *= 2;
+            let done = batch.is_empty();
+
+            let mut body = self.command("fetch")?;
+            body.line("ofs-delta")?;
+            if !show_progress() {
+                body.line("no-progress")?;
+            }
+            for want in &request.wants {
+                body.line(&format!("want {want}"))?;
+            }
+            for line in shallow_lines(request) {
+                body.line(&line)?;
+            }
+            if let Some(Deepen::Relative(_)) = request.deepen {
+                body.line("deepen-relative")?;
+            }
+            if let Some(filter) = filter {
+                body.line(&format!("filter {filter}"))?;
+            }
+            // Each request stands alone, so what was found in common is sent again
+            for have in common.iter().chain(batch) {
+                body.line(&format!("have {have}"))?;
+            }
+            if done {
+                body.line("done")?;
+            }
+            body.flush_pkt()?;
+
+            let mut reader = self.post(body)?;
+            let mut header = reader.expect()?;
+            if header.line() == Some("acknowledgments") {
+                let (lines, end) = reader.read_lines()?;
+                for line in lines {
+                    if let Some(hash) = line.strip_prefix("ACK ") {
+                        if !common.iter().any(|c| c == hash) {
+                            common.push(hash.to_string());
+                        }
+                    }
+                }
+                // Without `ready` the response ends here and we go on to the next round
+                if end == Packet::Flush {
+                    anyhow::ensure!(
+                        !done,
+                        "expected 'packfile', received the end of the response"
+                    );
+                    continue;
+                }
+                header = reader.expect()?;
+            }
+            return read_sections(header, reader);
+        }
+    }
+
+    fn fetch_v0(
+        &self,
+        request: &FetchRequest,
+        filter: Option<&str>,
+    ) -> anyhow::Result<FetchResponse> {
+        // The pack comes multiplexed with progress and errors when the server can do that
+        let band = ["side-band-64k", "side-band"]
+            .into_iter()
+            .find(|band| self.capability(band).is_some());
+        let mut capabilities: Vec<String> = band.into_iter().map(str::to_string).collect();
+        if self.capability("ofs-delta").is_some() {
+            capabilities.push("ofs-delta".to_string());
+        }
+        // Acknowledgments with a status until the final one, so that we can tell where they
+        // end however many commits the server finds in common
+        let multi_ack = ["multi_ack_detailed", "multi_ack"]
+            .into_iter()
+            .find(|ack| self.capability(ack).is_some());
+        capabilities.extend(multi_ack.map(str::to_string));
+        if !show_progress() && self.capability("no-progress").is_some() {
+            capabilities.push("no-progress".to_string());
+        }
+        match request.deepen {
+            Some(Deepen::Relative(_)) => {
+                anyhow::ensure!(
+                    self.capability("deepen-relative").is_some(),
+                    "Server does not support --deepen"
+                );
+                capabilities.push("deepen-relative".to_string());
+            }
+            Some(Deepen::Since(_)) => anyhow::ensure!(
+                self.capability("deepen-since").is_some(),
+                "Server does not support --shallow-since"
+            ),
+            _ => {}
+        }
+        if self.capability("agent").is_some() {
+            capabilities.push(format!("agent={AGENT}"));
+        }
+        if filter.is_some() {
+            capabilities.push("filter".to_string());
+        }
+
+        let mut body = PktWriter::new(Vec::new());
+        for (i, want) in request.wants.iter().enumerate() {
+            if i == 0 && !capabilities.is_empty() {
+                body.line(&format!("want {want} {}", capabilities.join(" ")))?;
+            } else {
+                body.line(&format!("want {want}"))?;
+            }
+        }
+        for line in shallow_lines(request) {
+            body.line(&line)?;
+        }
+        if let Some(filter) = filter {
+            body.line(&format!("filter {filter}"))?;
+        }
+        body.flush_pkt()?;
+        for have in &request.haves {
+            body.line(&format!("have {have}"))?;
+        }
+        body.line("done")?;
+
+        let mut reader = self.post(body)?;
+        let mut response = FetchResponse {
+            pack: Box::new(std::io::empty()),
+            shallow: Vec::new(),
+            unshallow: Vec::new(),
+        };
+        // Asking for more history is answered with the new boundary first
+        if request.deepen.is_some() {
+            let (lines, end) = reader.read_lines()?;
+            anyhow::ensure!(
+                end == Packet::Flush,
+                "protocol error: expected flush after the shallow update"
+            );
+            read_shallow_info(&lines, &mut response)?;
+        }
+        // The server acknowledges common commits as it reads our haves, as `ACK <hash>
+        // common` and the like with multi_ack, and answers `done` with a plain ACK for the
+        // last one, or a NAK when there were none. Then comes the pack.
+        loop {
+            let packet = reader.expect()?;
+            match packet.line() {
+                Some("NAK") => break,
+                Some(line) if line.starts_with("ACK ") => {
+                    if multi_ack.is_none() || line.split(' ').count() == 2 {
+                        break;
+                    }
+                }
+                _ => anyhow::bail!("git fetch-pack: expected ACK/NAK, got {packet:?}"),
+            }
+        }
+        response.pack = match band {
+            Some(_) => Box::new(Demux::new(reader)),
+            None => Box::new(reader.into_inner()),
+        };
+        Ok(response)
+    }
+
+    /// Starts a version 2 request for `command`, up to its arguments.
+    fn command(&self, command: &str) -> anyhow::Result<PktWriter<Vec<u8>>> {
+        let mut request = PktWriter::new(Vec::new());
+        request.line(&format!("command={command}"))?;
+        if self.capability("agent").is_some() {
+            request.line(&format!("agent={AGENT}"))?;
+        }
+        if self.capability("object-format").is_some() {
+            request.line("object-format=sha1")?;
+        }
+        request.delim()?;
+        Ok(request)
+    }
+
+    fn post(&self, request: PktWriter<Vec<u8>>) -> anyhow::Result<PktReader<Box<dyn Read>>> {
+        let v2 = self.version == Version::V2;
+        let reader = self.channel.post(UPLOAD_PACK, v2, request.into_inner())?;
+        Ok(PktReader::new(reader))
+    }
+}
+
+/// How requests reach the server: one HTTP request each, or one after another on the
+/// connection to a `git daemon` or to the service ssh runs.
+enum Channel {
+    Http(Box<Http>),
+    Daemon(Daemon),
+    Ssh(Ssh),
+}
+
+impl Channel {
+    /// Reaches `service` on the repository at `url`, which the scheme says how to, and
+    /// returns a reader for its advertisement.
+    fn open(url: &str, service: &str) -> anyhow::Result<(Channel, Box<dyn Read>)> {
+        if url.starts_with("git://") {
+            let daemon = Daemon::connect(url, service)?;
+            let reader = daemon.reader()?;
+            return Ok((Channel::Daemon(daemon), Box::new(reader)));
+        }
+        if let Some(ssh) = Ssh::connect(url, service)? {
+            let reader = ssh.reader()?;
+            return Ok((Channel::Ssh(ssh), Box::new(reader)));
+        }
+        let http = Box::new(Http::new(url)?);
+        let response = http.advertisement(service)?;
+        Ok((Channel::Http(http), Box::new(BufReader::new(response))))
+    }
+
+    /// Sends `body` to `service` and returns a reader for the answer: the response to a
+    /// POST over HTTP, or what comes next on a connection that holds the conversation.
+    fn post(&self, service: &str, v2: bool, body: Vec<u8>) -> anyhow::Result<Box<dyn Read>> {
+        match self {
+            Channel::Http(http) => {
+                let response = http.post(service, v2, body)?;
+                Ok(Box::new(BufReader::new(response)))
+            }
+            Channel::Daemon(daemon) => {
+                daemon.send(&body)?;
+                Ok(Box::new(daemon.reader()?))
+            }
+            Channel::Ssh(ssh) => {
+                ssh.send(&body)?;
+                Ok(Box::new(ssh.reader()?))
+            }
+        }
+    }
+
+    /// Tells a service that holds the conversation that nothing more is coming, for those
+    /// that read until the end of their input, as `receive-pack` does for the pack. Each
+    /// HTTP request ends by itself.
+    fn close(&self) {
+        match self {
+            Channel::Http(_) => {}
+            Channel::Daemon(daemon) => daemon.close(),
+            Channel::Ssh(ssh) => ssh.close(),
+        }
+    }
+}
+
+fn find_capability<'a>(capabilities: &'a [String], name: &str) -> Option<&'a str> {
+    capabilities
+        .iter()
+        .find_map(|capability| match capability.split_once('=') {
+            Some((key, value)) if key == name => Some(value),
+            None if capability == name => Some(""),
+            _ => None,
+        })
+}
+
+/// Reads the first packet of an advertisement, after the service announcement servers
+/// may put in front of it.
+fn read_announcement(reader: &mut PktReader<impl Read>) -> anyhow::Result<Packet> {
+    let first = reader.expect()?;
+    if !first
+        .line()
+        .is_some_and(|line| line.starts_with("# service="))
+    {
+        return Ok(first);
+    }
+    anyhow::ensure!(
+        reader.expect()? == Packet::Flush,
+        "protocol error: expected flush after the service announcement"
+    );
+    reader.expect()
+}
+
+/// Parses a version 0 ref advertisement, whose first line also carries the capabilities
+/// after a NUL, starting from its `first` packet.
+fn read_advertisement(
+    first: Packet,
+    reader: &mut PktReader<impl Read>,
+) -> anyhow::Result<(Vec<String>, Vec<RemoteRef>)> {
+    let mut capabilities = Vec::new();
+    let mut refs: Vec<RemoteRef> = Vec::new();
+    let mut packet = first;
+    while let Some(line) = packet.line() {
+        let line = match line.split_once('\0') {
+            Some((line, caps)) => {
+                capabilities = caps.split_whitespace().map(str::to_string).collect();
+                line
+            }
+            None => line,
+        };
+        let (hash, name) = line
+            .split_once(' ')
+            .with_context(|| format!("protocol error: unexpected '{line}'"))?;
+        match name.strip_suffix("^{}") {
+            // An empty repository advertises its capabilities on a made-up ref
+            Some("capabilities") => {}
+            // What a tag points at comes right after it
+            Some(tag) => {
+                if let Some(last) = refs.last_mut().filter(|r| r.name == tag) {
+                    last.peeled = Some(hash.to_string());
+                }
+            }
+            // The commits a shallow remote is cut off at are not refs either
+            None if hash == "shallow" => {}
+            None => refs.push(RemoteRef {
+                hash: hash.to_string(),
+                name: name.to_string(),
+                symref_target: None,
+                peeled: None,
+            }),
+        }
+        packet = reader.expect()?;
+    }
+    anyhow::ensure!(
+        packet == Packet::Flush,
+        "protocol error: unexpected {packet:?} in the ref advertisement"
+    );
+    Ok((capabilities, refs))
+}
+
+/// Reads the sections of a fetch response from `header` on up to the pack, which comes
+/// last, and returns a reader for the pack.
+fn read_sections(
+    mut header: Packet,
+    mut reader: PktReader<Box<dyn Read>>,
+) -> anyhow::Result<FetchResponse> {
+    let mut response = FetchResponse {
+        pack: Box::new(std::io::empty()),
+        shallow: Vec::new(),
+        unshallow: Vec::new(),
+    };
+    loop {
+        match header.line() {
+            Some("packfile") => {
+                response.pack = Box::new(Demux::new(reader));
+                return Ok(response);
+            }
+            // Sections for features we don't ask for are skipped
+            Some(section) => {
+                let shallow_info = section == "shallow-info";
+                let (lines, end) = reader.read_lines()?;
+                anyhow::ensure!(
+                    end == Packet::Delim,
+                    "expected 'packfile', received the end of the response"
+                );
+                if shallow_info {
+                    read_shallow_info(&lines, &mut response)?;
+                }
+            }
+            None => anyhow::bail!("expected 'packfile', received {header:?}"),
+        }
+        header = reader.expect()?;
+    }
+}
+
+/// The lines that tell the server where our history is cut off, and how much more of it
+/// to send. Whether the depth counts from the cut is said differently in each version.
+fn shallow_lines(request: &FetchRequest) -> Vec<String> {
+    let mut lines: Vec<String> = request
+        .shallow
+        .iter()
+        .map(|hash| format!("shallow {hash}"))
+        .collect();
+    match request.deepen {
+        Some(Deepen::Depth(depth) | Deepen::Relative(depth)) => {
+            lines.push(format!("deepen {depth}"))
+        }
+        Some(Deepen::Since(time)) => lines.push(format!("deepen-since {time}")),
+        None => {}
+    }
+    lines
+}
+
+/// Parses the `shallow <hash>` and `unshallow <hash>` lines of a shallow update.
+fn read_shallow_info(lines: &[String], response: &mut FetchResponse) -> anyhow::Result<()> {
+    for line in lines {
+        match line.split_once(' ') {
+            Some(("shallow", hash)) => response.shallow.push(hash.to_string()),
+            Some(("unshallow", hash)) => response.unshallow.push(hash.to_string()),
+            _ => anyhow::bail!("protocol error: expected shallow/unshallow, got '{line}'"),
+        }
+    }
+    Ok(())
+}
+
+/// Progress is only worth asking for when someone is there to see it.
+fn show_progress() -> bool {
+    std::io::stderr().is_terminal()
+}
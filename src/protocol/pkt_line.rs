@@ -0,0 +1,160 @@
+use anyhow::Context;
+use std::io::{self, Read, Write};
+
+/// The most data one packet can carry: 65520 bytes less the 4-byte length prefix.
+pub(crate) const MAX_DATA: usize = 65516;
+
+/// One pkt-line: a hex length prefix followed by that many bytes (length included), or
+/// one of the special lengths below 4.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Packet {
+    Data(Vec<u8>),
+    /// `0000`: the end of a message.
+    Flush,
+    /// `0001`: the end of a section within a v2 message.
+    Delim,
+    /// `0002`: the end of a v2 response over a stateless connection.
+    ResponseEnd,
+}
+
+impl Packet {
+    /// The payload as a line of text, without its trailing newline.
+    pub(crate) fn line(&self) -> Option<&str> {
+        match self {
+            Packet::Data(data) => {
+                let text = std::str::from_utf8(data).ok()?;
+                Some(text.strip_suffix('\n').unwrap_or(text))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct PktReader<R> {
+    inner: R,
+}
+
+impl<R: Read> PktReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        PktReader { inner }
+    }
+
+    /// The next packet, or `None` once the stream ends between packets. An `ERR` packet
+    /// from the server becomes an error.
+    pub(crate) fn read(&mut self) -> anyhow::Result<Option<Packet>> {
+        let mut prefix = [0u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.inner.read(&mut prefix[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => anyhow::bail!("the remote end hung up unexpectedly"),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).context("Reading a pkt-line length"),
+            }
+        }
+        let len = std::str::from_utf8(&prefix)
+            .ok()
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .with_context(|| {
+                format!(
+                    "protocol error: bad line length character: {}",
+                    String::from_utf8_lossy(&prefix)
+                )
+            })?;
+
+        let packet = match len {
+            0 => Packet::Flush,
+            1 => Packet::Delim,
+            2 => Packet::ResponseEnd,
+            3 => anyhow::bail!("protocol error: bad line length {len}"),
+            _ => {
+                let mut data = vec![0; len - 4];
+                self.inner
+                    .read_exact(&mut data)
+                    .context("the remote end hung up unexpectedly")?;
+                if let Some(message) = data.strip_prefix(b"ERR ") {
+                    anyhow::bail!(
+                        "remote error: {}",
+                        String::from_utf8_lossy(message).trim_end()
+                    );
+                }
+                Packet::Data(data)
+            }
+        };
+        Ok(Some(packet))
+    }
+
+    /// The next packet, treating the end of the stream as an error.
+    pub(crate) fn expect(&mut self) -> anyhow::Result<Packet> {
+        self.read()?.context("the remote end hung up unexpectedly")
+    }
+
+    /// The text lines up to the next flush or delim packet, and which of the two ended them.
+    pub(crate) fn read_lines(&mut self) -> anyhow::Result<(Vec<String>, Packet)> {
+        let mut lines = Vec::new();
+        loop {
+            match self.expect()? {
+                Packet::Data(data) => {
+                    let text = String::from_utf8(data).context("protocol error: non-UTF-8 line")?;
+                    lines.push(text.strip_suffix('\n').unwrap_or(&text).to_string());
+                }
+                end => return Ok((lines, end)),
+            }
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+pub(crate) struct PktWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> PktWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        PktWriter { inner }
+    }
+
+    pub(crate) fn data(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            data.len() <= MAX_DATA,
+            "protocol error: impossibly long line"
+        );
+        write!(self.inner, "{:04x}", data.len() + 4).context("Writing a pkt-line")?;
+        self.inner.write_all(data).context("Writing a pkt-line")
+    }
+
+    /// Writes `line` with the newline that text packets end with.
+    pub(crate) fn line(&mut self, line: &str) -> anyhow::Result<()> {
+        self.data(format!("{line}\n").as_bytes())
+    }
+
+    pub(crate) fn flush_pkt(&mut self) -> anyhow::Result<()> {
+        self.inner
+            .write_all(b"0000")
+            .context("Writing a flush packet")
+    }
+
+    pub(crate) fn delim(&mut self) -> anyhow::Result<()> {
+        self.inner
+            .write_all(b"0001")
+            .context("Writing a delim packet")
+    }
+
+    /// The writer underneath, for what is sent without pkt-lines around it, such as a pack.
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Sends what has been written on its way, for the other side to act on.
+    pub(crate) fn flush(&mut self) -> anyhow::Result<()> {
+        self.inner.flush().context("Flushing pkt-lines")
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
@@ -0,0 +1,146 @@
+use super::pkt_line::{Packet, PktReader, PktWriter};
+use super::sideband::Demux;
+use super::{find_capability, read_advertisement, read_announcement, show_progress, Channel};
+use super::{RemoteRef, AGENT};
+use anyhow::Context;
+use std::io::Read;
+
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// The hash that stands for a ref that does not exist, on either side of an update.
+pub(crate) const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// A connection to the receive-pack service of a remote repository, over smart HTTP,
+/// `git://` or SSH. Pushing has no version 2, so this always speaks version 0.
+pub(crate) struct PushConnection {
+    channel: Channel,
+    capabilities: Vec<String>,
+    advertised: Vec<RemoteRef>,
+}
+
+/// A ref to change on the remote, from the value we saw to a new one. Either may be
+/// `ZERO_HASH`, to create or delete the ref.
+#[derive(Debug, Clone)]
+pub(crate) struct RefCommand {
+    pub(crate) old: String,
+    pub(crate) new: String,
+    pub(crate) name: String,
+}
+
+/// What the remote did with a push.
+#[derive(Debug)]
+pub(crate) struct PushReport {
+    /// Why the pack could not be stored, if it could not.
+    pub(crate) unpack_error: Option<String>,
+    /// Each ref the remote reported on, with why it was not updated if it was not.
+    pub(crate) refs: Vec<(String, Option<String>)>,
+}
+
+impl PushConnection {
+    pub(crate) fn connect(url: &str) -> anyhow::Result<PushConnection> {
+        anyhow::ensure!(
+            super::local::local_path(url).is_none(),
+            "pushing to '{url}' is not supported: only remotes over HTTP(S), git:// or SSH \
+             can be pushed to"
+        );
+        let (channel, advertisement) = Channel::open(url, RECEIVE_PACK)?;
+        let mut reader = PktReader::new(advertisement);
+        let mut first = read_announcement(&mut reader)?;
+        if first.line() == Some("version 1") {
+            first = reader.expect()?;
+        }
+        let (capabilities, advertised) = read_advertisement(first, &mut reader)?;
+        Ok(PushConnection {
+            channel,
+            capabilities,
+            advertised,
+        })
+    }
+
+    pub(crate) fn capability(&self, name: &str) -> Option<&str> {
+        find_capability(&self.capabilities, name)
+    }
+
+    /// The refs the remote has.
+    pub(crate) fn refs(&self) -> &[RemoteRef] {
+        &self.advertised
+    }
+
+    /// Sends the ref `commands` followed by the `pack` of objects they need, and reads
+    /// back what the remote made of them. Pushes that only delete refs have no pack.
+    pub(crate) fn push(
+        &self,
+        commands: &[RefCommand],
+        pack: Option<Vec<u8>>,
+    ) -> anyhow::Result<PushReport> {
+        anyhow::ensure!(!commands.is_empty(), "no refs to push");
+        let band = self.capability("side-band-64k").is_some();
+        let mut capabilities = vec!["report-status".to_string()];
+        if band {
+            capabilities.push("side-band-64k".to_string());
+        }
+        if !show_progress() && self.capability("quiet").is_some() {
+            capabilities.push("quiet".to_string());
+        }
+        if self.capability("agent").is_some() {
+            capabilities.push(format!("agent={AGENT}"));
+        }
+
+        let mut body = PktWriter::new(Vec::new());
+        for (i, command) in commands.iter().enumerate() {
+            let line = format!("{} {} {}", command.old, command.new, command.name);
+            // Capabilities go after a NUL on the first command
+            if i == 0 {
+                body.line(&format!("{line}\0{}", capabilities.join(" ")))?;
+            } else {
+                body.line(&line)?;
+            }
+        }
+        body.flush_pkt()?;
+        let mut body = body.into_inner();
+        if let Some(pack) = pack {
+            body.extend(pack);
+        }
+
+        let reader = PktReader::new(self.channel.post(RECEIVE_PACK, false, body)?);
+        // The pack goes on to the end of the input
+        self.channel.close();
+        if band {
+            read_report(PktReader::new(Demux::new(reader)))
+        } else {
+            read_report(reader)
+        }
+    }
+}
+
+/// Parses a `report-status` response: how unpacking went, then `ok <ref>` or
+/// `ng <ref> <reason>` for each ref.
+fn read_report(mut reader: PktReader<impl Read>) -> anyhow::Result<PushReport> {
+    let (lines, end) = reader.read_lines()?;
+    anyhow::ensure!(
+        end == Packet::Flush,
+        "protocol error: unexpected {end:?} in the push report"
+    );
+    let mut lines = lines.into_iter();
+    let unpack = lines
+        .next()
+        .context("the remote end hung up before reporting the push status")?;
+    let unpack_error = match unpack.strip_prefix("unpack ") {
+        Some("ok") => None,
+        Some(error) => Some(error.to_string()),
+        None => anyhow::bail!("protocol error: expected unpack status, got '{unpack}'"),
+    };
+
+    let mut refs = Vec::new();
+    for line in lines {
+        if let Some(name) = line.strip_prefix("ok ") {
+            refs.push((name.to_string(), None));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            refs.push((name.to_string(), Some(reason.to_string())));
+        } else {
+            anyhow::bail!("protocol error: invalid ref status from remote: {line}");
+        }
+    }
+    Ok(PushReport { unpack_error, refs })
+}
@@ -0,0 +1,172 @@
+use super::pkt_line::{Packet, PktReader, PktWriter};
+use std::io::{self, IsTerminal, Read, Write};
+
+const PREFIX: &str = "remote: ";
+
+/// Reads the data sent on band 1 of a side-band stream, up to its closing flush. Progress
+/// on band 2 is shown on stderr and a message on band 3 fails the read.
+pub(crate) struct Demux<R> {
+    reader: PktReader<R>,
+    /// The band 1 packet being read, with its band byte at the front.
+    data: Vec<u8>,
+    pos: usize,
+    /// A progress line the server has not finished yet.
+    progress: Vec<u8>,
+    /// Written after each progress message, to wipe what is left of a longer previous one.
+    suffix: &'static str,
+    done: bool,
+}
+
+impl<R: Read> Demux<R> {
+    pub(crate) fn new(reader: PktReader<R>) -> Self {
+        let stderr = io::stderr();
+        let dumb = std::env::var("TERM").map_or(true, |term| term == "dumb");
+        Demux {
+            reader,
+            data: Vec::new(),
+            pos: 0,
+            progress: Vec::new(),
+            suffix: if stderr.is_terminal() && !dumb {
+                "\x1b[K"
+            } else {
+                "        "
+            },
+            done: false,
+        }
+    }
+
+    /// Shows `message` line by line, each with a `remote: ` prefix. Lines end at `\r` as
+    /// well as `\n` so that counters can update in place.
+    fn show_progress(&mut self, message: &[u8]) -> io::Result<()> {
+        let mut stderr = io::stderr().lock();
+        let mut rest = message;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n' || b == b'\r') {
+            let line = &rest[..end];
+            if self.progress.is_empty() {
+                self.progress.extend_from_slice(PREFIX.as_bytes());
+            }
+            if !line.is_empty() {
+                self.progress.extend_from_slice(line);
+                self.progress.extend_from_slice(self.suffix.as_bytes());
+            }
+            self.progress.push(rest[end]);
+            stderr.write_all(&self.progress)?;
+            self.progress.clear();
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            if self.progress.is_empty() {
+                self.progress.extend_from_slice(PREFIX.as_bytes());
+            }
+            self.progress.extend_from_slice(rest);
+        }
+        Ok(())
+    }
+
+    /// Ends a progress line the server left unfinished.
+    fn finish_progress(&mut self) -> io::Result<()> {
+        if !self.progress.is_empty() {
+            self.progress.push(b'\n');
+            io::stderr().write_all(&self.progress)?;
+            self.progress.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Demux<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match self.reader.expect().map_err(io::Error::other)? {
+                Packet::Data(data) => match data.first() {
+                    Some(1) => {
+                        self.data = data;
+                        self.pos = 1;
+                    }
+                    Some(2) => self.show_progress(&data[1..])?,
+                    Some(3) => {
+                        self.finish_progress()?;
+                        return Err(io::Error::other(format!(
+                            "remote error: {}",
+                            String::from_utf8_lossy(&data[1..]).trim_end()
+                        )));
+                    }
+                    band => {
+                        return Err(io::Error::other(format!(
+                            "protocol error: bad band #{}",
+                            band.unwrap_or(&0)
+                        )))
+                    }
+                },
+                Packet::Flush => {
+                    self.finish_progress()?;
+                    self.done = true;
+                }
+                packet => {
+                    return Err(io::Error::other(format!(
+                        "protocol error: unexpected {packet:?} in the side-band stream"
+                    )))
+                }
+            }
+        }
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Writes data on band 1 of a side-band stream, in packets of at most `max` bytes of
+/// data each, with messages for the other side on bands 2 and 3.
+pub(crate) struct Mux<W: Write> {
+    writer: PktWriter<W>,
+    max: usize,
+}
+
+impl<W: Write> Mux<W> {
+    /// `max` is 999 for `side-band` and 65515 for `side-band-64k` and protocol v2.
+    pub(crate) fn new(writer: PktWriter<W>, max: usize) -> Self {
+        Mux { writer, max }
+    }
+
+    /// Sends `message` on band 2, which shows it to the user as progress.
+    pub(crate) fn progress(&mut self, message: &str) -> anyhow::Result<()> {
+        self.band(2, message.as_bytes())
+    }
+
+    /// Sends `message` on band 3, which fails the transfer with it.
+    pub(crate) fn error(&mut self, message: &str) -> anyhow::Result<()> {
+        self.band(3, message.as_bytes())
+    }
+
+    /// Ends the stream with a flush packet.
+    pub(crate) fn finish(mut self) -> anyhow::Result<PktWriter<W>> {
+        self.writer.flush_pkt()?;
+        Ok(self.writer)
+    }
+
+    fn band(&mut self, band: u8, data: &[u8]) -> anyhow::Result<()> {
+        for chunk in data.chunks(self.max) {
+            let mut packet = Vec::with_capacity(chunk.len() + 1);
+            packet.push(band);
+            packet.extend_from_slice(chunk);
+            self.writer.data(&packet)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Mux<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.max);
+        self.band(1, &buf[..n]).map_err(io::Error::other)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::config::Config;
+use anyhow::Context;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::os::fd::OwnedFd;
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// The SSH transport: `ssh` runs the service on the remote host, and the conversation is
+/// held with it over the child's stdin and stdout, one request after another.
+pub(crate) struct Ssh {
+    child: Child,
+    /// Taken once nothing more is to be sent, which the service sees as the end of input.
+    stdin: RefCell<Option<ChildStdin>>,
+    stdout: OwnedFd,
+}
+
+/// Where an SSH URL points.
+#[derive(Debug)]
+struct Address {
+    /// The host, with the user to log in as in front if the URL names one.
+    host: String,
+    port: Option<String>,
+    /// The repository on the host, absolute or relative to the user's home directory.
+    path: String,
+}
+
+impl Ssh {
+    /// Runs `service` on the repository `url` points at over SSH, asking for protocol
+    /// version 2 when fetching. `None` when `url` is not an SSH URL: `ssh://[user@]host
+    /// [:port]/path` or the scp-like `[user@]host:path`.
+    pub(crate) fn connect(url: &str, service: &str) -> anyhow::Result<Option<Ssh>> {
+        let Some(address) = parse(url) else {
+            return Ok(None);
+        };
+        // Either would be taken as an option, such as `-oProxyCommand=...`, which runs
+        // whatever it is set to
+        anyhow::ensure!(
+            !address.host.starts_with('-'),
+            "strange hostname '{}' blocked",
+            address.host
+        );
+        anyhow::ensure!(
+            !address.path.starts_with('-'),
+            "strange pathname '{}' blocked",
+            address.path
+        );
+        let (mut command, openssh) = ssh_command()?;
+        let v2 = service == "git-upload-pack";
+        if v2 {
+            // OpenSSH passes it on to servers that accept it; wrappers just inherit it
+            if openssh {
+                command.args(["-o", "SendEnv=GIT_PROTOCOL"]);
+            }
+            command.env("GIT_PROTOCOL", "version=2");
+        }
+        if let Some(port) = &address.port {
+            command.args(["-p", port]);
+        }
+        if openssh {
+            command.arg("--");
+        }
+        let mut child = command
+            .arg(&address.host)
+            .arg(format!("{service} {}", quote(&address.path)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("unable to fork ssh")?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Some(Ssh {
+            child,
+            stdin: RefCell::new(Some(stdin)),
+            stdout: OwnedFd::from(stdout),
+        }))
+    }
+
+    /// Sends `body`, a request in pkt-lines.
+    pub(crate) fn send(&self, body: &[u8]) -> anyhow::Result<()> {
+        let mut stdin = self.stdin.borrow_mut();
+        let stdin = stdin
+            .as_mut()
+            .context("protocol error: the request was already complete")?;
+        stdin.write_all(body).context("Sending the request")?;
+        stdin.flush().context("Sending the request")
+    }
+
+    /// Ends what is sent, for services that read until the end of their input.
+    pub(crate) fn close(&self) {
+        self.stdin.borrow_mut().take();
+    }
+
+    /// Reads what the service sends next. It waits for each request before it answers,
+    /// so nothing read ahead belongs to a later answer.
+    pub(crate) fn reader(&self) -> anyhow::Result<BufReader<File>> {
+        let stdout = self
+            .stdout
+            .try_clone()
+            .context("Duplicating ssh's output")?;
+        Ok(BufReader::new(File::from(stdout)))
+    }
+}
+
+impl Drop for Ssh {
+    /// Says goodbye with a flush, unless the input has been closed already, and waits for
+    /// ssh to exit.
+    fn drop(&mut self) {
+        if let Some(mut stdin) = self.stdin.get_mut().take() {
+            let _ = stdin.write_all(b"0000");
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// The host and path of an SSH URL, or `None` for any other kind of URL.
+fn parse(url: &str) -> Option<Address> {
+    let rest = ["ssh://", "git+ssh://", "ssh+git://"]
+        .into_iter()
+        .find_map(|scheme| url.strip_prefix(scheme));
+    let Some(rest) = rest else {
+        // `host:path`, which a local path can't be mistaken for as long as the colon
+        // comes before any slash
+        if url.contains("://") || super::local::local_path(url).is_some() {
+            return None;
+        }
+        let (host, path) = url.split_once(':')?;
+        return Some(Address {
+            host: host.to_string(),
+            port: None,
+            path: path.to_string(),
+        });
+    };
+
+    let (authority, path) = rest.split_at(rest.find('/')?);
+    // `ssh://host/~user/repo` is relative to a home directory, like `host:~user/repo`
+    let path = match path.strip_prefix("/~") {
+        Some(home) => format!("~{home}"),
+        None => path.to_string(),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            (host, Some(port.to_string()))
+        }
+        _ => (authority, None),
+    };
+    Some(Address {
+        host: host.to_string(),
+        port,
+        path,
+    })
+}
+
+/// The command that runs ssh: `GIT_SSH_COMMAND` or `core.sshCommand` run by the shell,
+/// `GIT_SSH` run as is, or `ssh`. Also says whether it looks like OpenSSH, which takes
+/// options other ssh programs may not.
+fn ssh_command() -> anyhow::Result<(Command, bool)> {
+    let config = Config::read()?;
+    let shell_command = std::env::var("GIT_SSH_COMMAND")
+        .ok()
+        .filter(|line| !line.is_empty())
+        .or_else(|| config.get("core.sshcommand").map(str::to_string));
+    let (command, program) = match shell_command {
+        Some(line) => {
+            let program = line
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            let mut command = Command::new("sh");
+            // The arguments are added to the command line through "$@"
+            command.arg("-c").arg(format!("{line} \"$@\"")).arg(&line);
+            (command, program)
+        }
+        None => {
+            let program = std::env::var("GIT_SSH")
+                .ok()
+                .filter(|program| !program.is_empty())
+                .unwrap_or_else(|| "ssh".to_string());
+            (Command::new(&program), program)
+        }
+    };
+    let name = Path::new(&program).file_name().unwrap_or_default();
+    Ok((command, name == "ssh"))
+}
+
+/// Quotes `text` for the remote shell, which runs the command ssh is given.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
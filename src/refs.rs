@@ -0,0 +1,525 @@
+use crate::objects::{self, Kind, Object};
+use crate::pack;
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Follows `name` through symbolic refs, loose refs and `packed-refs` to an object hash.
+/// Returns `None` for refs that do not exist, such as the branch of a freshly initialised repo,
+/// and for files under `.git` that hold something other than an object name.
+pub(crate) fn resolve_ref(name: &str) -> anyhow::Result<Option<String>> {
+    let mut name = name.to_string();
+    // Symbolic refs can chain, but never legitimately more than a handful of times
+    for _ in 0..5 {
+        let path = Path::new(".git").join(&name);
+        if path.is_file() {
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Reading ref {}", path.display()))?;
+            let content = content.trim();
+            if let Some(target) = content.strip_prefix("ref: ") {
+                name = target.to_string();
+                continue;
+            }
+            let value = match name.as_str() {
+                "FETCH_HEAD" => fetch_head_line(content).and_then(|line| line.split('\t').next()),
+                _ => Some(content),
+            };
+            return Ok(value.filter(|v| objects::is_hash(v)).map(str::to_string));
+        }
+        return read_packed_ref(&name);
+    }
+    anyhow::bail!("Symbolic ref loop while resolving {name}")
+}
+
+/// The line of `FETCH_HEAD` that `merge FETCH_HEAD` takes: the first one not marked
+/// `not-for-merge`, or the first of all when every line is.
+pub(crate) fn fetch_head_line(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .find(|line| line.split('\t').nth(1) == Some(""))
+        .or_else(|| content.lines().next())
+}
+
+fn read_packed_ref(name: &str) -> anyhow::Result<Option<String>> {
+    let Ok(packed) = fs::read_to_string(".git/packed-refs") else {
+        return Ok(None);
+    };
+    for line in packed.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        if let Some((hash, refname)) = line.split_once(' ') {
+            if refname == name {
+                return Ok(Some(hash.to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `name` is a ref name a remote may ask us to create: under `refs/`, and free of
+/// what `git check-ref-format` forbids.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let bad_char = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    name.starts_with("refs/")
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.chars().any(bad_char)
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"))
+        && !name.ends_with('.')
+}
+
+/// The refs whose names start with `prefix` (such as `refs/remotes/origin/`), loose or
+/// packed, with the hashes they point at, sorted by name. Symbolic refs are left out.
+pub(crate) fn list_refs(prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut refs = BTreeMap::new();
+    if let Ok(packed) = fs::read_to_string(".git/packed-refs") {
+        for line in packed.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((hash, name)) = line.split_once(' ') {
+                if name.starts_with(prefix) {
+                    refs.insert(name.to_string(), hash.to_string());
+                }
+            }
+        }
+    }
+
+    // Loose refs take precedence over packed ones
+    let mut dirs = vec![PathBuf::from(".git/refs")];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry.context("Listing refs")?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Ok(name) = path.strip_prefix(".git") else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+            if !name.starts_with(prefix) || name.ends_with(".lock") {
+                continue;
+            }
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Reading ref {}", path.display()))?;
+            if content.starts_with("ref: ") {
+                refs.remove(&name);
+            } else {
+                refs.insert(name, content.trim().to_string());
+            }
+        }
+    }
+    Ok(refs.into_iter().collect())
+}
+
+/// The ref HEAD is attached to (such as `refs/heads/main`), or `None` when it is detached.
+pub(crate) fn head_ref() -> anyhow::Result<Option<String>> {
+    let head = fs::read_to_string(".git/HEAD").context("Reading .git/HEAD")?;
+    Ok(head
+        .trim()
+        .strip_prefix("ref: ")
+        .map(|target| target.to_string()))
+}
+
+/// Points the loose ref `name` (or `HEAD`) at `hash`, creating it if needed.
+pub(crate) fn update_ref(name: &str, hash: &str) -> anyhow::Result<()> {
+    lock_ref(name, Some(hash))?.commit()
+}
+
+/// A ref held for an update by its `.lock` file, which holds the new value. The update is
+/// made by `commit`; a lock dropped before that is removed and leaves the ref as it was.
+pub(crate) struct RefLock {
+    name: String,
+    path: PathBuf,
+    lock: PathBuf,
+    /// Whether the ref is deleted rather than pointed somewhere new.
+    delete: bool,
+    held: bool,
+}
+
+/// Locks the loose ref `name` (or `HEAD`) to point it at `new`, or to delete it when that
+/// is `None`. Only one process can hold the lock, so this fails while another does.
+pub(crate) fn lock_ref(name: &str, new: Option<&str>) -> anyhow::Result<RefLock> {
+    let path = Path::new(".git").join(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+    }
+    let lock = lock_path(&path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .with_context(|| format!("Unable to create '{}'", lock.display()))?;
+    let ref_lock = RefLock {
+        name: name.to_string(),
+        path,
+        lock,
+        delete: new.is_none(),
+        held: true,
+    };
+    if let Some(hash) = new {
+        writeln!(file, "{hash}").with_context(|| format!("Writing {}", ref_lock.lock.display()))?;
+    }
+    Ok(ref_lock)
+}
+
+impl RefLock {
+    /// Makes the update and releases the lock.
+    pub(crate) fn commit(mut self) -> anyhow::Result<()> {
+        self.held = false;
+        if self.delete {
+            let deleted = delete_unlocked(&self.name);
+            let _ = fs::remove_file(&self.lock);
+            return deleted;
+        }
+        let renamed = fs::rename(&self.lock, &self.path);
+        if renamed.is_err() {
+            let _ = fs::remove_file(&self.lock);
+        }
+        renamed.with_context(|| format!("Updating {}", self.name))
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if self.held {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Moves the current branch, or HEAD itself when detached, to `hash`.
+pub(crate) fn update_head(hash: &str) -> anyhow::Result<()> {
+    match head_ref()? {
+        Some(branch) => update_ref(&branch, hash),
+        None => update_ref("HEAD", hash),
+    }
+}
+
+/// Attaches HEAD to the branch `name`, such as `refs/heads/main`.
+pub(crate) fn attach_head(name: &str) -> anyhow::Result<()> {
+    write_symref("HEAD", name)
+}
+
+/// Makes `name` a symbolic ref to `target`, as `refs/remotes/origin/HEAD` is to the
+/// remote's default branch.
+pub(crate) fn write_symref(name: &str, target: &str) -> anyhow::Result<()> {
+    let path = Path::new(".git").join(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+    }
+    fs::write(&path, format!("ref: {target}\n"))
+        .with_context(|| format!("Writing {}", path.display()))
+}
+
+/// Deletes the ref `name`, loose or packed, along with its reflog.
+pub(crate) fn delete_ref(name: &str) -> anyhow::Result<()> {
+    lock_ref(name, None)?.commit()
+}
+
+/// Deletes the ref `name` once its lock is held.
+fn delete_unlocked(name: &str) -> anyhow::Result<()> {
+    for path in [
+        Path::new(".git").join(name),
+        Path::new(".git/logs").join(name),
+    ] {
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Removing {}", path.display()))?;
+        }
+    }
+
+    let Ok(packed) = fs::read_to_string(".git/packed-refs") else {
+        return Ok(());
+    };
+    let mut kept = String::new();
+    let mut removed = false;
+    for line in packed.lines() {
+        // A peeled line belongs to the ref before it
+        let dropped = match line.strip_prefix('^') {
+            Some(_) => removed,
+            None => {
+                removed = line.split_once(' ').is_some_and(|(_, n)| n == name);
+                removed
+            }
+        };
+        if !dropped {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if kept.len() != packed.len() {
+        // Written through a lock of its own, as other refs may be deleted at the same time
+        let lock = Path::new(".git/packed-refs.lock");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(lock)
+            .context("Unable to create '.git/packed-refs.lock'")?;
+        let written = file
+            .write_all(kept.as_bytes())
+            .and_then(|()| fs::rename(lock, ".git/packed-refs"));
+        if written.is_err() {
+            let _ = fs::remove_file(lock);
+        }
+        written.context("Writing .git/packed-refs")?;
+    }
+    Ok(())
+}
+
+/// One line of a reflog: the ref moved from `old` to `new`, by `ident` (with the time).
+#[derive(Debug, Clone)]
+pub(crate) struct ReflogEntry {
+    pub(crate) old: String,
+    pub(crate) new: String,
+    pub(crate) ident: String,
+    pub(crate) message: String,
+}
+
+/// The reflog of `name`, oldest entry first; empty when the ref has none.
+pub(crate) fn read_reflog(name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+    let Ok(log) = fs::read_to_string(Path::new(".git/logs").join(name)) else {
+        return Ok(Vec::new());
+    };
+    let mut entries = Vec::new();
+    for line in log.lines() {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+        let (Some(old), Some(new), Some(ident)) = (fields.next(), fields.next(), fields.next())
+        else {
+            anyhow::bail!("Malformed reflog entry for {name}: {line}");
+        };
+        entries.push(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            ident: ident.to_string(),
+            message: message.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Replaces the reflog of `name` with `entries`, oldest first.
+pub(crate) fn write_reflog(name: &str, entries: &[ReflogEntry]) -> anyhow::Result<()> {
+    let path = Path::new(".git/logs").join(name);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+    }
+    let log: String = entries
+        .iter()
+        .map(|e| format!("{} {} {}\t{}\n", e.old, e.new, e.ident, e.message))
+        .collect();
+    fs::write(&path, log).with_context(|| format!("Writing {}", path.display()))
+}
+
+/// The commit HEAD points at, or `None` while the current branch is unborn.
+pub(crate) fn head_commit() -> anyhow::Result<Option<String>> {
+    resolve_ref("HEAD")
+}
+
+/// Turns a revision such as `main`, `HEAD~2`, `v1.0^{tree}`, an abbreviated hash or
+/// `<rev>:<path>` into the full hash of the object it names.
+pub(crate) fn resolve(rev: &str) -> anyhow::Result<String> {
+    if let Some((rev, path)) = rev.split_once(':') {
+        let rev = if rev.is_empty() { "HEAD" } else { rev };
+        let tree = peel_to_tree(&resolve(rev)?)?;
+        return lookup_path(&tree, path)
+            .with_context(|| format!("path '{path}' does not exist in '{rev}'"));
+    }
+
+    let split = rev.find(['^', '~']).unwrap_or(rev.len());
+    let (base, mut suffix) = rev.split_at(split);
+    let mut hash = resolve_base(base)?;
+
+    while !suffix.is_empty() {
+        if let Some(rest) = suffix.strip_prefix("^{") {
+            let (target, rest) = rest
+                .split_once('}')
+                .with_context(|| format!("unterminated ^{{...}} in {rev}"))?;
+            hash = match target {
+                "" => peel(&hash)?,
+                "commit" => peel_to_commit(&hash)?,
+                "tree" => peel_to_tree(&hash)?,
+                _ => anyhow::bail!("unsupported peel target ^{{{target}}} in {rev}"),
+            };
+            suffix = rest;
+            continue;
+        }
+
+        let op = suffix.as_bytes()[0];
+        let digits = suffix[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(suffix.len(), |i| i + 1);
+        let count = &suffix[1..digits];
+        suffix = &suffix[digits..];
+
+        match op {
+            b'^' => {
+                let n: usize = if count.is_empty() { 1 } else { count.parse()? };
+                if n == 0 {
+                    hash = peel_to_commit(&hash)?;
+                    continue;
+                }
+                let commit = objects::read_commit(&peel_to_commit(&hash)?)?;
+                hash = commit
+                    .parents
+                    .get(n - 1)
+                    .cloned()
+                    .with_context(|| format!("{rev}: commit has no parent {n}"))?;
+            }
+            _ => {
+                let n: usize = if count.is_empty() { 1 } else { count.parse()? };
+                for _ in 0..n {
+                    let commit = objects::read_commit(&peel_to_commit(&hash)?)?;
+                    hash = commit
+                        .parents
+                        .first()
+                        .cloned()
+                        .with_context(|| format!("{rev}: ran out of first parents"))?;
+                }
+            }
+        }
+    }
+    Ok(hash)
+}
+
+fn resolve_base(name: &str) -> anyhow::Result<String> {
+    let name = if name.is_empty() || name == "@" {
+        "HEAD"
+    } else {
+        name
+    };
+
+    for candidate in [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ] {
+        if let Some(hash) = resolve_ref(&candidate)? {
+            return Ok(hash);
+        }
+    }
+
+    // `<ref>@{<n>}`: the value the ref had `n` updates ago
+    if let Some((refname, n)) = name
+        .strip_suffix('}')
+        .and_then(|rest| rest.rsplit_once("@{"))
+        .and_then(|(refname, n)| Some((refname, n.parse::<usize>().ok()?)))
+    {
+        return resolve_reflog(if refname.is_empty() { "HEAD" } else { refname }, n);
+    }
+
+    if name.len() >= 4 && name.len() <= 40 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return expand_abbrev(&name.to_ascii_lowercase());
+    }
+    anyhow::bail!("unknown revision '{name}'")
+}
+
+fn resolve_reflog(name: &str, n: usize) -> anyhow::Result<String> {
+    for candidate in [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+    ] {
+        let Some(current) = resolve_ref(&candidate)? else {
+            continue;
+        };
+        let entries = read_reflog(&candidate)?;
+        if n == 0 && entries.is_empty() {
+            return Ok(current);
+        }
+        anyhow::ensure!(
+            n < entries.len(),
+            "log for '{name}' only has {} entries",
+            entries.len()
+        );
+        return Ok(entries[entries.len() - 1 - n].new.clone());
+    }
+    anyhow::bail!("unknown revision '{name}@{{{n}}}'")
+}
+
+fn expand_abbrev(prefix: &str) -> anyhow::Result<String> {
+    let dir = Path::new(".git/objects").join(&prefix[..2]);
+    let mut found = pack::find_abbrev(prefix)?;
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with(&prefix[2..]) {
+                found.push(format!("{}{}", &prefix[..2], name));
+            }
+        }
+    }
+    found.sort_unstable();
+    found.dedup();
+    match found.len() {
+        0 => anyhow::bail!("unknown revision '{prefix}'"),
+        1 => Ok(found.remove(0)),
+        _ => anyhow::bail!("short object ID {prefix} is ambiguous"),
+    }
+}
+
+/// Strips annotated tags until reaching a non-tag object.
+pub(crate) fn peel(hash: &str) -> anyhow::Result<String> {
+    let mut hash = hash.to_string();
+    loop {
+        let obj = Object::read(&hash).with_context(|| format!("Reading object {hash}"))?;
+        if obj.kind != Kind::Tag {
+            return Ok(hash);
+        }
+        hash = objects::read_tag(&hash)?.object;
+    }
+}
+
+pub(crate) fn peel_to_commit(hash: &str) -> anyhow::Result<String> {
+    let hash = peel(hash)?;
+    let obj = Object::read(&hash)?;
+    anyhow::ensure!(
+        obj.kind == Kind::Commit,
+        "{hash} is a {}, not a commit",
+        obj.kind
+    );
+    Ok(hash)
+}
+
+/// Resolves a tree-ish (a tree, a commit or a tag pointing at either) to its tree.
+pub(crate) fn peel_to_tree(hash: &str) -> anyhow::Result<String> {
+    let hash = peel(hash)?;
+    let obj = Object::read(&hash)?;
+    match obj.kind {
+        Kind::Tree => Ok(hash),
+        Kind::Commit => Ok(objects::read_commit(&hash)?.tree),
+        _ => anyhow::bail!("{hash} is a {}, not a tree-ish", obj.kind),
+    }
+}
+
+fn lookup_path(tree_hash: &str, path: &str) -> anyhow::Result<String> {
+    let mut hash = tree_hash.to_string();
+    for part in path.split('/').filter(|p| !p.is_empty()) {
+        let entry = objects::read_tree(&hash)?
+            .into_iter()
+            .find(|e| e.name == part)
+            .with_context(|| format!("no entry named {part}"))?;
+        hash = hex::encode(entry.hash);
+    }
+    Ok(hash)
+}
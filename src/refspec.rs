@@ -0,0 +1,111 @@
+/// A refspec such as `+refs/heads/*:refs/remotes/origin/*`: which refs on the source side
+/// go to which refs on the destination side, and whether they may be moved to a commit that
+/// does not descend from their old one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Refspec {
+    pub(crate) force: bool,
+    pub(crate) src: String,
+    /// Where `src` goes; `None` for a refspec like `main` that names a ref but stores it
+    /// nowhere.
+    pub(crate) dst: Option<String>,
+}
+
+/// The refs a short name like `main` can stand for, in the order they are tried.
+pub(crate) fn expand_short_name(name: &str) -> Vec<String> {
+    if name.starts_with("refs/") || name == "HEAD" {
+        return vec![name.to_string()];
+    }
+    vec![
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ]
+}
+
+/// `refs/heads/main` as `main`, and likewise for tags and remote-tracking branches.
+pub(crate) fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl Refspec {
+    pub(crate) fn parse(spec: &str) -> anyhow::Result<Refspec> {
+        let (force, rest) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = match rest.split_once(':') {
+            Some((src, dst)) => (src, (!dst.is_empty()).then_some(dst)),
+            None => (rest, None),
+        };
+        let globs = src.matches('*').count();
+        // An empty source, as in `:refs/heads/old`, deletes the destination when pushing
+        anyhow::ensure!(
+            (!src.is_empty() || dst.is_some())
+                && globs <= 1
+                && dst.map_or(true, |dst| dst.matches('*').count() == globs),
+            "invalid refspec '{spec}'"
+        );
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst: dst.map(str::to_string),
+        })
+    }
+
+    pub(crate) fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// Whether `name` on the source side is one this refspec picks: any ref the pattern
+    /// matches for a glob, or one the short or full name stands for.
+    pub(crate) fn matches_src(&self, name: &str) -> bool {
+        if self.is_glob() {
+            return expand_glob(&self.src, &self.src, name).is_some();
+        }
+        expand_short_name(&self.src).iter().any(|full| full == name)
+    }
+
+    /// Where the source ref `name` goes on the destination side, if anywhere.
+    pub(crate) fn dst_for(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_deref()?;
+        if self.is_glob() {
+            return expand_glob(&self.src, dst, name);
+        }
+        if !self.matches_src(name) {
+            return None;
+        }
+        Some(match dst {
+            "HEAD" => dst.to_string(),
+            dst if dst.starts_with("refs/") => dst.to_string(),
+            dst if name.starts_with("refs/tags/") => format!("refs/tags/{dst}"),
+            dst => format!("refs/heads/{dst}"),
+        })
+    }
+
+    /// The source ref that would go to the destination ref `name`, for glob refspecs.
+    pub(crate) fn src_for(&self, name: &str) -> Option<String> {
+        expand_glob(self.dst.as_deref()?, &self.src, name)
+    }
+
+    /// What to ask the remote to list so that every ref this refspec picks is included.
+    pub(crate) fn prefixes(&self) -> Vec<String> {
+        match self.src.split_once('*') {
+            Some((prefix, _)) => vec![prefix.to_string()],
+            None => expand_short_name(&self.src),
+        }
+    }
+}
+
+/// `name` matched against a `pattern` with one `*`, and what the `*` matched put into
+/// `replacement`.
+fn expand_glob(pattern: &str, replacement: &str, name: &str) -> Option<String> {
+    let (prefix, suffix) = pattern.split_once('*')?;
+    let middle = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    Some(replacement.replacen('*', middle, 1))
+}
@@ -0,0 +1,776 @@
+use crate::commands::merge::conflict_style;
+use crate::config::Config;
+use crate::diff::patch::{self, PatchOptions};
+use crate::diff::rename::{self, RenameOptions};
+use crate::diff::{self, tree};
+use crate::ident;
+use crate::index::Index;
+use crate::merge;
+use crate::merge::base::{self, CommitCache};
+use crate::merge::tree::{self as tree_merge, Entries, TreeMerge, TreeMergeOptions};
+use crate::objects::{self, Commit};
+use crate::refs;
+use anyhow::Context;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+const SEQUENCER_DIR: &str = ".git/sequencer";
+const CHERRY_PICK_HEAD: &str = ".git/CHERRY_PICK_HEAD";
+const REVERT_HEAD: &str = ".git/REVERT_HEAD";
+const MERGE_MSG: &str = ".git/MERGE_MSG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// Apply the changes a commit made.
+    Pick,
+    /// Undo the changes a commit made.
+    Revert,
+}
+
+impl Action {
+    /// The command, as named in messages.
+    fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The word for this action in `.git/sequencer/todo`.
+    fn todo_word(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The pseudo-ref naming the commit whose application stopped.
+    fn head_file(self) -> &'static str {
+        match self {
+            Action::Pick => CHERRY_PICK_HEAD,
+            Action::Revert => REVERT_HEAD,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ReplayOptions {
+    /// Apply the changes to the index and worktree without committing them.
+    pub(crate) no_commit: bool,
+    /// Append `(cherry picked from commit <hash>)` to picked messages.
+    pub(crate) record_origin: bool,
+    /// For merge commits, the parent (counting from 1) whose diff is replayed.
+    pub(crate) mainline: Option<usize>,
+}
+
+/// One line of `.git/sequencer/todo`.
+#[derive(Debug, Clone)]
+struct TodoItem {
+    action: Action,
+    commit: String,
+}
+
+/// How applying a single commit ended.
+enum Outcome {
+    /// The changes were applied, and committed unless `--no-commit` was given.
+    Applied,
+    /// The changes conflict; the conflicts are left in the index and worktree.
+    Conflict,
+    /// The changes were applied cleanly but are already in HEAD.
+    Empty,
+}
+
+/// Applies (or undoes) `names` one after the other on top of HEAD. `A..B` ranges expand to
+/// the commits in between, oldest first. A single commit is applied directly; a series is
+/// tracked in `.git/sequencer` so that it can be continued, skipped or aborted when one of
+/// its commits conflicts.
+pub(crate) fn replay(action: Action, names: &[String], opts: &ReplayOptions) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !Path::new(SEQUENCER_DIR).exists(),
+        "a cherry-pick or revert is already in progress\n\
+         hint: try \"{} (--continue | --abort | --skip)\"",
+        action.command()
+    );
+    let index = Index::read().context("Reading the index")?;
+    anyhow::ensure!(
+        !index.entries.iter().any(|e| e.stage != 0),
+        "{} is not possible because you have unmerged files.",
+        action.command()
+    );
+
+    let mut cache = CommitCache::default();
+    let mut todo = Vec::new();
+    for name in names {
+        if let Some((from, to)) = name.split_once("..") {
+            let exclude = resolve_commit(if from.is_empty() { "HEAD" } else { from })?;
+            let include = resolve_commit(if to.is_empty() { "HEAD" } else { to })?;
+            let mut range = base::commits_between(&mut cache, &[exclude], &include)?;
+            range.reverse();
+            todo.extend(range);
+        } else {
+            todo.push(resolve_commit(name)?);
+        }
+    }
+    anyhow::ensure!(!todo.is_empty(), "empty commit set passed");
+    let todo: Vec<TodoItem> = todo
+        .into_iter()
+        .map(|commit| TodoItem { action, commit })
+        .collect();
+
+    let config = Config::read()?;
+    if todo.len() == 1 && !names.iter().any(|name| name.contains("..")) {
+        let item = &todo[0];
+        return match apply(&mut cache, &config, item, opts)? {
+            Outcome::Applied => Ok(()),
+            Outcome::Conflict => Err(conflict_error(&mut cache, item, opts)),
+            Outcome::Empty => Err(empty_error(action)),
+        };
+    }
+
+    let head = refs::head_commit()?
+        .with_context(|| format!("cannot {} onto an unborn branch", action.command()))?;
+    fs::create_dir_all(SEQUENCER_DIR).context("Creating .git/sequencer")?;
+    write_state("head", &format!("{head}\n"))?;
+    write_state("abort-safety", &format!("{head}\n"))?;
+    let mut options = String::new();
+    if opts.no_commit {
+        options.push_str("\tno-commit = true\n");
+    }
+    if opts.record_origin {
+        options.push_str("\trecord-origin = true\n");
+    }
+    if let Some(mainline) = opts.mainline {
+        options.push_str(&format!("\tmainline = {mainline}\n"));
+    }
+    if !options.is_empty() {
+        write_state("opts", &format!("[options]\n{options}"))?;
+    }
+    run_todo(&mut cache, &config, &todo, opts)
+}
+
+/// `--continue`: commits the resolved conflicts of the commit that stopped, then applies
+/// the rest of the series.
+pub(crate) fn continue_replay(action: Action) -> anyhow::Result<()> {
+    let in_sequence = Path::new(SEQUENCER_DIR).exists();
+    let stopped = stopped_commit()?;
+    anyhow::ensure!(
+        in_sequence || stopped.is_some(),
+        "no cherry-pick or revert in progress"
+    );
+
+    let todo = if in_sequence {
+        read_todo(action)?
+    } else {
+        Vec::new()
+    };
+    let mut cache = CommitCache::default();
+    let config = Config::read()?;
+    if let Some((stopped_action, commit)) = stopped {
+        check_action(action, stopped_action)?;
+        commit_resolved(&config, stopped_action, &commit)?;
+    }
+    if !in_sequence {
+        return Ok(());
+    }
+    // The first item is the one that stopped, which is now committed
+    run_todo(
+        &mut cache,
+        &config,
+        todo.get(1..).unwrap_or(&[]),
+        &read_options()?,
+    )
+}
+
+/// `--skip`: drops the changes of the commit that stopped and goes on with the rest of the
+/// series.
+pub(crate) fn skip_replay(action: Action) -> anyhow::Result<()> {
+    let in_sequence = Path::new(SEQUENCER_DIR).exists();
+    match stopped_commit()? {
+        Some((stopped_action, _)) => {
+            check_action(action, stopped_action)?;
+            let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+            merge::reset_merge(&head)?;
+            remove_stop_state()?;
+        }
+        None if in_sequence => anyhow::bail!(
+            "there is nothing to skip\n\
+             hint: have you committed already?\n\
+             hint: try \"{} --continue\"",
+            action.command()
+        ),
+        None => anyhow::bail!("no {} in progress", action.command()),
+    }
+    if !in_sequence {
+        return Ok(());
+    }
+
+    let todo = read_todo(action)?;
+    let mut cache = CommitCache::default();
+    let config = Config::read()?;
+    run_todo(
+        &mut cache,
+        &config,
+        todo.get(1..).unwrap_or(&[]),
+        &read_options()?,
+    )
+}
+
+/// `--abort`: goes back to where HEAD was before the series started, unless HEAD has been
+/// moved by something else since.
+pub(crate) fn abort_replay() -> anyhow::Result<()> {
+    if !Path::new(SEQUENCER_DIR).exists() {
+        anyhow::ensure!(
+            stopped_commit()?.is_some(),
+            "no cherry-pick or revert in progress"
+        );
+        let head = refs::head_commit()?.context("HEAD does not point at a commit")?;
+        merge::reset_merge(&head)?;
+        return remove_stop_state();
+    }
+
+    let original = read_state("head")?;
+    let expected = read_state("abort-safety").ok();
+    let head = refs::head_commit()?;
+    if expected.is_some() && head != expected {
+        eprintln!("warning: You seem to have moved HEAD. Not rewinding, check your HEAD!");
+    } else {
+        merge::reset_merge(&original)?;
+    }
+    remove_stop_state()?;
+    fs::remove_dir_all(SEQUENCER_DIR).context("Removing .git/sequencer")
+}
+
+/// Applies each item of `todo` in turn. When one stops, the items from it onwards are saved
+/// for `--continue` and `--skip`; once all are applied the sequencer state is removed.
+fn run_todo(
+    cache: &mut CommitCache,
+    config: &Config,
+    todo: &[TodoItem],
+    opts: &ReplayOptions,
+) -> anyhow::Result<()> {
+    for (i, item) in todo.iter().enumerate() {
+        let outcome = apply(cache, config, item, opts);
+        if let Some(head) = refs::head_commit()? {
+            write_state("abort-safety", &format!("{head}\n"))?;
+        }
+        let error = match outcome {
+            Ok(Outcome::Applied) => continue,
+            Ok(Outcome::Conflict) => conflict_error(cache, item, opts),
+            Ok(Outcome::Empty) => empty_error(item.action),
+            Err(error) => error,
+        };
+        save_todo(cache, &todo[i..])?;
+        return Err(error);
+    }
+    fs::remove_dir_all(SEQUENCER_DIR).context("Removing .git/sequencer")
+}
+
+/// Merges the changes `item` made (or their reverse) into HEAD, or into the index with
+/// `--no-commit`, and commits the result when it is clean.
+fn apply(
+    cache: &mut CommitCache,
+    config: &Config,
+    item: &TodoItem,
+    opts: &ReplayOptions,
+) -> anyhow::Result<Outcome> {
+    let hash = item.commit.as_str();
+    let commit = cache.get(hash)?.clone();
+    let parent = replayed_parent(hash, &commit, opts.mainline)?;
+
+    let mut index = Index::read().context("Reading the index")?;
+    let head = refs::head_commit()?;
+    let head_tree = match &head {
+        Some(head) => Some(cache.get(head)?.tree.clone()),
+        None => None,
+    };
+    let head_entries = match &head_tree {
+        Some(tree) => diff::tree_entries(tree)?,
+        None => Entries::new(),
+    };
+    // Without committing, the changes pile up in the index
+    let ours = if opts.no_commit {
+        diff::index_entries(&index)
+    } else {
+        anyhow::ensure!(
+            diff::index_entries(&index) == head_entries,
+            "your local changes would be overwritten by {}.\n\
+             hint: commit your changes or stash them to proceed.",
+            item.action.command()
+        );
+        head_entries
+    };
+
+    let result = merge_changes(
+        cache,
+        config,
+        item.action,
+        hash,
+        parent.as_deref(),
+        &ours,
+        &mut index,
+    )?;
+    for (_, message) in &result.messages {
+        println!("{message}");
+    }
+
+    let message = commit_message(item.action, hash, &commit, parent.as_deref(), opts);
+    fs::write(MERGE_MSG, format!("{message}{}", conflicts_footer(&result)))
+        .context("Writing MERGE_MSG")?;
+
+    // A picked commit is only recorded when it is to be committed, a reverted one always
+    let record_stop = !opts.no_commit || item.action == Action::Revert;
+    if !result.is_clean() || opts.no_commit {
+        if record_stop {
+            fs::write(item.action.head_file(), format!("{hash}\n"))
+                .with_context(|| format!("Writing {}", item.action.head_file()))?;
+        }
+        return Ok(if result.is_clean() {
+            Outcome::Applied
+        } else {
+            Outcome::Conflict
+        });
+    }
+
+    let tree = hex::encode(objects::write_tree_from_entries(&diff::index_entries(
+        &index,
+    ))?);
+    let parent_tree = match &parent {
+        Some(parent) => Some(cache.get(parent)?.tree.clone()),
+        None => None,
+    };
+    if head_tree.as_deref() == Some(tree.as_str())
+        && parent_tree.as_deref() != Some(commit.tree.as_str())
+    {
+        fs::write(item.action.head_file(), format!("{hash}\n"))
+            .with_context(|| format!("Writing {}", item.action.head_file()))?;
+        return Ok(Outcome::Empty);
+    }
+    let author = match item.action {
+        Action::Pick => commit.author.clone(),
+        Action::Revert => ident::author(config)?,
+    };
+    commit_tree(config, &tree, head, &author, &message, true)?;
+    Ok(Outcome::Applied)
+}
+
+/// The parent whose changes to `commit` are replayed: the only one, none for a root
+/// commit, or for merges the `mainline` one.
+pub(crate) fn replayed_parent(
+    hash: &str,
+    commit: &Commit,
+    mainline: Option<usize>,
+) -> anyhow::Result<Option<String>> {
+    match (commit.parents.as_slice(), mainline) {
+        ([], _) => Ok(None),
+        ([_, _, ..], None) => {
+            anyhow::bail!("commit {hash} is a merge but no -m option was given.")
+        }
+        (parents, mainline) => {
+            // The first parent of an ordinary commit may be named explicitly
+            let mainline = mainline.unwrap_or(1);
+            anyhow::ensure!(
+                (1..=parents.len()).contains(&mainline),
+                "commit {hash} does not have parent {mainline}"
+            );
+            Ok(Some(parents[mainline - 1].clone()))
+        }
+    }
+}
+
+/// Three-way merges the changes `hash` made relative to `parent` (or, for a revert, their
+/// reverse) into `ours`, and writes the result to the index and worktree. The messages of
+/// the merge are left for the caller to print.
+pub(crate) fn merge_changes(
+    cache: &mut CommitCache,
+    config: &Config,
+    action: Action,
+    hash: &str,
+    parent: Option<&str>,
+    ours: &Entries,
+    index: &mut Index,
+) -> anyhow::Result<TreeMerge> {
+    let commit = cache.get(hash)?.clone();
+    let parent_tree = match parent {
+        Some(parent) => Some(cache.get(parent)?.tree.clone()),
+        None => None,
+    };
+    let parent_entries = match &parent_tree {
+        Some(tree) => diff::tree_entries(tree)?,
+        None => Entries::new(),
+    };
+    let commit_entries = diff::tree_entries(&commit.tree)?;
+    let label = format!("{} ({})", &hash[..7], subject(&commit.message));
+    let parent_label = match parent {
+        Some(_) => format!("parent of {label}"),
+        None => "(empty tree)".to_string(),
+    };
+    let (base_entries, theirs, base_label, theirs_label) = match action {
+        Action::Pick => (parent_entries, commit_entries, parent_label, label),
+        Action::Revert => (commit_entries, parent_entries, label, parent_label),
+    };
+    let result = tree_merge::merge_trees(
+        &base_entries,
+        ours,
+        &theirs,
+        &TreeMergeOptions {
+            style: conflict_style(config)?,
+            ours_label: "HEAD".to_string(),
+            theirs_label,
+            base_label,
+        },
+    )
+    .context("Merging trees")?;
+
+    merge::check_worktree(index, ours, &result.entries, "merge")?;
+    merge::checkout_result(index, ours, &result.entries)?;
+    index.write().context("Writing the index")?;
+    Ok(result)
+}
+
+/// The `# Conflicts:` list git appends to the message of a conflicted commit.
+pub(crate) fn conflicts_footer(result: &TreeMerge) -> String {
+    if result.is_clean() {
+        return String::new();
+    }
+    let mut footer = String::from("\n# Conflicts:\n");
+    for path in result.conflicted_paths() {
+        footer.push_str(&format!("#\t{path}\n"));
+    }
+    footer
+}
+
+/// Commits the index once the conflicts of `commit` have been resolved, with the message
+/// left in MERGE_MSG.
+fn commit_resolved(config: &Config, action: Action, commit: &str) -> anyhow::Result<()> {
+    let index = Index::read().context("Reading the index")?;
+    anyhow::ensure!(
+        !index.entries.iter().any(|e| e.stage != 0),
+        "Committing is not possible because you have unmerged files."
+    );
+    let message: String = fs::read_to_string(MERGE_MSG)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let message = format!("{}\n", message.trim());
+    anyhow::ensure!(
+        !message.trim().is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+
+    let head = refs::head_commit()?;
+    let tree = hex::encode(objects::write_tree_from_entries(&diff::index_entries(
+        &index,
+    ))?);
+    if let Some(head) = &head {
+        if objects::read_commit(head)?.tree == tree {
+            return Err(empty_error(action));
+        }
+    }
+    // Picked commits keep their author, reverts are authored anew
+    let author = match action {
+        Action::Pick => objects::read_commit(commit)?.author,
+        Action::Revert => ident::author(config)?,
+    };
+    commit_tree(
+        config,
+        &tree,
+        head,
+        &author,
+        &message,
+        action == Action::Pick,
+    )
+}
+
+/// Commits `tree` on top of HEAD, clears the state of a stopped pick and prints a summary
+/// of the new commit, including the author date when `show_date` is set.
+fn commit_tree(
+    config: &Config,
+    tree: &str,
+    head: Option<String>,
+    author: &str,
+    message: &str,
+    show_date: bool,
+) -> anyhow::Result<()> {
+    let parents: Vec<String> = head.into_iter().collect();
+    let commit =
+        objects::write_commit(tree, &parents, author, &ident::committer(config)?, message)?;
+    refs::update_head(&commit)?;
+    remove_stop_state()?;
+    print_summary(&commit, show_date)
+}
+
+/// `[branch abc1234] subject` followed by the author when it is not the committer, the
+/// author date if asked for and the `--shortstat --summary` of the commit.
+pub(crate) fn print_summary(hash: &str, show_date: bool) -> anyhow::Result<()> {
+    let commit = objects::read_commit(hash)?;
+    let branch = match refs::head_ref()? {
+        Some(branch) => branch.trim_start_matches("refs/heads/").to_string(),
+        None => "detached HEAD".to_string(),
+    };
+    let root = if commit.parents.is_empty() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    println!(
+        "[{branch}{root} {}] {}",
+        &hash[..7],
+        subject(&commit.message)
+    );
+    let author = ident::split(&commit.author);
+    let committer = ident::split(&commit.committer);
+    if let Some((who, date)) = author {
+        if committer.map(|(committer, _)| committer) != Some(who) {
+            println!(" Author: {who}");
+        }
+        if let Some(date) = date.filter(|_| show_date) {
+            println!(" Date: {date}");
+        }
+    }
+
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(objects::read_commit(parent)?.tree),
+        None => None,
+    };
+    let changes = tree::diff_trees(parent_tree.as_deref(), Some(&commit.tree), true)?;
+    let changes = rename::detect(changes, Vec::new(), &RenameOptions::default())?;
+    let opts = PatchOptions::default();
+    let stats = changes
+        .iter()
+        .map(|c| patch::file_stat(c, &opts))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    patch::write_shortstat(&mut stdout, &stats)?;
+    patch::write_summary(&mut stdout, &changes)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The message of the commit that picks or reverts `commit`.
+fn commit_message(
+    action: Action,
+    hash: &str,
+    commit: &Commit,
+    parent: Option<&str>,
+    opts: &ReplayOptions,
+) -> String {
+    match action {
+        Action::Pick => {
+            let mut message = commit.message.clone();
+            if opts.record_origin {
+                if !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                if !ends_with_trailers(&message) {
+                    message.push('\n');
+                }
+                message.push_str(&format!("(cherry picked from commit {hash})\n"));
+            }
+            message
+        }
+        Action::Revert => {
+            let mut message = format!(
+                "Revert \"{}\"\n\nThis reverts commit {hash}",
+                subject(&commit.message)
+            );
+            if let (true, Some(parent)) = (commit.parents.len() > 1, parent) {
+                message.push_str(&format!(", reversing\nchanges made to {parent}"));
+            }
+            message.push_str(".\n");
+            message
+        }
+    }
+}
+
+/// Whether the last paragraph of a message, other than its subject, consists of trailers
+/// such as `Signed-off-by: ...`, so that another one can go right below them.
+fn ends_with_trailers(message: &str) -> bool {
+    let message = message.trim_end();
+    let Some((_, last)) = message.rsplit_once("\n\n") else {
+        return false;
+    };
+    last.lines().all(|line| {
+        line.starts_with("(cherry picked from commit ")
+            || line.split_once(": ").is_some_and(|(key, _)| {
+                !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            })
+    })
+}
+
+/// The first line of a commit message.
+pub(crate) fn subject(message: &str) -> &str {
+    message
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("")
+}
+
+fn conflict_error(cache: &mut CommitCache, item: &TodoItem, opts: &ReplayOptions) -> anyhow::Error {
+    let subject = cache
+        .get(&item.commit)
+        .map(|commit| subject(&commit.message).to_string())
+        .unwrap_or_default();
+    let verb = match item.action {
+        Action::Pick => "apply",
+        Action::Revert => "revert",
+    };
+    let command = item.action.command();
+    let hints = if opts.no_commit {
+        "hint: after resolving the conflicts, mark the corrected paths\n\
+         hint: with 'add <paths>'"
+            .to_string()
+    } else {
+        format!(
+            "hint: After resolving the conflicts, mark them with\n\
+             hint: \"add <pathspec>\", then run\n\
+             hint: \"{command} --continue\".\n\
+             hint: You can instead skip this commit with \"{command} --skip\".\n\
+             hint: To abort and get back to the state before \"{command}\",\n\
+             hint: run \"{command} --abort\"."
+        )
+    };
+    anyhow::anyhow!(
+        "could not {verb} {}... {subject}\n{hints}",
+        &item.commit[..7]
+    )
+}
+
+fn empty_error(action: Action) -> anyhow::Error {
+    anyhow::anyhow!(
+        "The previous {} is now empty, possibly due to conflict resolution.\n\
+         Please use '{} --skip' to drop it.",
+        action.command(),
+        action.command()
+    )
+}
+
+/// Cleans up an edited commit message like git's default `strip` mode: comment lines and
+/// trailing whitespace go, runs of blank lines shrink to one and the message is trimmed.
+pub(crate) fn cleanup_message(text: &str) -> String {
+    let mut message = String::new();
+    let mut blank = false;
+    for line in text.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !message.is_empty() {
+            message.push('\n');
+        }
+        blank = false;
+        message.push_str(line);
+        message.push('\n');
+    }
+    message
+}
+
+/// The commit whose application stopped, from CHERRY_PICK_HEAD or REVERT_HEAD.
+fn stopped_commit() -> anyhow::Result<Option<(Action, String)>> {
+    for action in [Action::Pick, Action::Revert] {
+        if let Ok(hash) = fs::read_to_string(action.head_file()) {
+            return Ok(Some((action, hash.trim().to_string())));
+        }
+    }
+    Ok(None)
+}
+
+fn remove_stop_state() -> anyhow::Result<()> {
+    for path in [CHERRY_PICK_HEAD, REVERT_HEAD, MERGE_MSG] {
+        if Path::new(path).exists() {
+            fs::remove_file(path).with_context(|| format!("Removing {path}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn resolve_commit(name: &str) -> anyhow::Result<String> {
+    refs::resolve(name)
+        .and_then(|hash| refs::peel_to_commit(&hash))
+        .with_context(|| format!("bad revision '{name}'"))
+}
+
+fn write_state(name: &str, contents: &str) -> anyhow::Result<()> {
+    fs::write(Path::new(SEQUENCER_DIR).join(name), contents)
+        .with_context(|| format!("Writing .git/sequencer/{name}"))
+}
+
+fn read_state(name: &str) -> anyhow::Result<String> {
+    let contents = fs::read_to_string(Path::new(SEQUENCER_DIR).join(name))
+        .with_context(|| format!("Reading .git/sequencer/{name}"))?;
+    Ok(contents.trim().to_string())
+}
+
+/// Writes the items still to do as `pick <abbrev> <subject>` lines.
+fn save_todo(cache: &mut CommitCache, todo: &[TodoItem]) -> anyhow::Result<()> {
+    let mut contents = String::new();
+    for item in todo {
+        let subject = subject(&cache.get(&item.commit)?.message).to_string();
+        contents.push_str(&format!(
+            "{} {} {subject}\n",
+            item.action.todo_word(),
+            &item.commit[..7]
+        ));
+    }
+    write_state("todo", &contents)
+}
+
+/// Reads `.git/sequencer/todo`, refusing to go on with a series started by the other
+/// command.
+fn read_todo(action: Action) -> anyhow::Result<Vec<TodoItem>> {
+    let mut todo = Vec::new();
+    for line in read_state("todo")?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let item_action = match words.next() {
+            Some("pick" | "p") => Action::Pick,
+            Some("revert") => Action::Revert,
+            _ => anyhow::bail!("invalid line in .git/sequencer/todo: {line}"),
+        };
+        let name = words
+            .next()
+            .with_context(|| format!("missing commit in .git/sequencer/todo: {line}"))?;
+        todo.push(TodoItem {
+            action: item_action,
+            commit: resolve_commit(name)?,
+        });
+    }
+    if let Some(first) = todo.first() {
+        check_action(action, first.action)?;
+    }
+    Ok(todo)
+}
+
+fn check_action(action: Action, in_progress: Action) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        action == in_progress,
+        "cannot {} during a {}.",
+        action.command(),
+        in_progress.command()
+    );
+    Ok(())
+}
+
+fn read_options() -> anyhow::Result<ReplayOptions> {
+    let config = Config::read_from(&Path::new(SEQUENCER_DIR).join("opts"))?;
+    let flag = |key: &str| config.get(key).is_some_and(|value| value == "true");
+    Ok(ReplayOptions {
+        no_commit: flag("options.no-commit"),
+        record_origin: flag("options.record-origin"),
+        mainline: config
+            .get("options.mainline")
+            .map(str::parse)
+            .transpose()
+            .context("Parsing the mainline in .git/sequencer/opts")?,
+    })
+}
@@ -0,0 +1,96 @@
+use super::resolve;
+use crate::protocol::pkt_line::{PktReader, PktWriter};
+use anyhow::Context;
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+
+/// Answers `git://` requests for the repositories under `base` that connect to
+/// `listener`, each on a thread of its own, until the process is stopped. Only
+/// repositories with a `git-daemon-export-ok` file are served, unless `export_all`.
+pub(crate) fn run(listener: TcpListener, base: PathBuf, export_all: bool) -> anyhow::Result<()> {
+    for stream in listener.incoming() {
+        // As with HTTP, one connection failing to come in doesn't stop the others
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("error: Accepting a connection: {e}");
+                continue;
+            }
+        };
+        let base = base.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &base, export_all) {
+                eprintln!("error: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Reads the request a client opens with, `<service> <path>\0host=<host>\0` with any
+/// extra parameters such as `version=2` after another NUL, and hands the connection to
+/// the service.
+fn handle(stream: TcpStream, base: &Path, export_all: bool) -> anyhow::Result<()> {
+    // Read a packet at a time, so that nothing meant for the service is taken here
+    let Some(request) = PktReader::new(&stream).read()? else {
+        return Ok(());
+    };
+    let request = request
+        .line()
+        .context("protocol error: expected a request")?;
+    let mut fields = request.split('\0');
+    let (service, path) = fields
+        .next()
+        .and_then(|command| command.split_once(' '))
+        .with_context(|| format!("protocol error: bad request '{request}'"))?;
+    // The parameters after the empty field are what `GIT_PROTOCOL` carries
+    let protocol: Vec<&str> = fields
+        .skip_while(|field| !field.is_empty())
+        .filter(|field| !field.is_empty())
+        .collect();
+
+    if service != UPLOAD_PACK {
+        return refuse(&stream, &format!("service not enabled: '{service}'"));
+    }
+    let repository = resolve(base, path).filter(|dir| export_all || is_exported(dir));
+    let Some(repository) = repository else {
+        return refuse(
+            &stream,
+            &format!("access denied or repository not exported: {path}"),
+        );
+    };
+
+    let exe = std::env::current_exe().context("Finding this program")?;
+    let input = OwnedFd::from(stream.try_clone().context("Cloning the connection")?);
+    let status = Command::new(exe)
+        .arg("upload-pack")
+        .arg(&repository)
+        .env("GIT_PROTOCOL", protocol.join(":"))
+        .stdin(Stdio::from(input))
+        .stdout(Stdio::from(OwnedFd::from(stream)))
+        .status()
+        .context("Starting upload-pack")?;
+    anyhow::ensure!(status.success(), "upload-pack for {path} failed");
+    Ok(())
+}
+
+/// Whether the repository at `dir` says it may be served, with `git-daemon-export-ok` in
+/// its git directory.
+fn is_exported(dir: &Path) -> bool {
+    let git_dir = match dir.join(".git").is_dir() {
+        true => dir.join(".git"),
+        false => dir.to_path_buf(),
+    };
+    git_dir.join("git-daemon-export-ok").is_file()
+}
+
+/// Tells the client why it gets nothing.
+fn refuse(stream: &TcpStream, message: &str) -> anyhow::Result<()> {
+    let mut writer = PktWriter::new(stream);
+    writer.line(&format!("ERR {message}"))?;
+    writer.flush()
+}
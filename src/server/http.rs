@@ -0,0 +1,249 @@
+use super::{protocol_version, resolve};
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// A request as far as serving repositories needs it.
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    /// Header names in lower case, with their values.
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Answers smart HTTP requests for the repositories under `base` that connect to
+/// `listener`, each on a thread of its own, until the process is stopped. Pushes are
+/// turned away unless `receive_pack` says otherwise.
+pub(crate) fn run(listener: TcpListener, base: PathBuf, receive_pack: bool) -> anyhow::Result<()> {
+    for stream in listener.incoming() {
+        // A connection that fails to come in, say for want of file descriptors, is only
+        // that connection's problem
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("error: Accepting a connection: {e}");
+                continue;
+            }
+        };
+        let base = base.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle(stream, &base, receive_pack) {
+                eprintln!("error: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, base: &Path, receive_pack: bool) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone().context("Cloning the connection")?);
+    let mut stream = stream;
+    let Some(request) = read_request(&mut reader)? else {
+        return Ok(());
+    };
+
+    // `<repository>/info/refs?service=<service>` or `<repository>/<service>`
+    let route = match request.path.strip_suffix("/info/refs") {
+        Some(repository) => request
+            .query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("service="))
+            .map(|service| (repository, service, "GET", true)),
+        None => [UPLOAD_PACK, RECEIVE_PACK].into_iter().find_map(|service| {
+            let repository = request.path.strip_suffix(&format!("/{service}"))?;
+            Some((repository, service, "POST", false))
+        }),
+    };
+    let Some((repository, service, method, advertise)) = route else {
+        // Only the smart protocol is spoken
+        return respond(&mut stream, "404 Not Found", "Not Found");
+    };
+    let enabled = service == UPLOAD_PACK || service == RECEIVE_PACK && receive_pack;
+    if !enabled {
+        return respond(&mut stream, "403 Forbidden", "Service not enabled");
+    }
+    if request.method != method {
+        return respond(&mut stream, "405 Method Not Allowed", "Method Not Allowed");
+    }
+    let Some(repository) = resolve(base, repository) else {
+        return respond(&mut stream, "404 Not Found", "Repository not found");
+    };
+
+    if request.header("expect") == Some("100-continue") {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    let mut body = body_reader(reader, &request)?;
+    let protocol = request.header("git-protocol").unwrap_or_default();
+    let version = protocol_version(protocol);
+
+    let exe = std::env::current_exe().context("Finding this program")?;
+    let mut command = Command::new(exe);
+    let subcommand = service.strip_prefix("git-").unwrap_or(service);
+    command.arg(subcommand).arg("--stateless-rpc");
+    if advertise {
+        command.arg("--advertise-refs");
+    }
+    let mut child = command
+        .arg(&repository)
+        .env("GIT_PROTOCOL", protocol)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Starting {subcommand}"))?;
+    // Fed from a thread of its own as the body comes in, so that neither side waits on
+    // the other to read and a large push is never held in memory
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let feeder = std::thread::spawn(move || io::copy(&mut body, &mut stdin));
+
+    let kind = if advertise { "advertisement" } else { "result" };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/x-{service}-{kind}\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    // Version 2 clients get the capabilities straight away; pushing has no version 2
+    if advertise && (version != 2 || service == RECEIVE_PACK) {
+        let line = format!("# service={service}\n");
+        write!(stream, "{:04x}{line}0000", line.len() + 4)?;
+    }
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    io::copy(&mut stdout, &mut stream).context("Sending the response")?;
+    let _ = feeder.join();
+    let status = child
+        .wait()
+        .with_context(|| format!("Waiting for {subcommand}"))?;
+    anyhow::ensure!(status.success(), "{subcommand} for {} failed", request.path);
+    Ok(())
+}
+
+/// Reads the request line and headers, or `None` when the connection closes first.
+fn read_request(reader: &mut impl BufRead) -> anyhow::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("bad request line: {}", line.trim_end());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers: Vec::new(),
+    };
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(Some(request));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+}
+
+/// The body of `request` as it comes in after the headers on `reader`, sent in one piece
+/// or in chunks, and unzipped if it was zipped.
+fn body_reader(
+    reader: BufReader<TcpStream>,
+    request: &Request,
+) -> anyhow::Result<Box<dyn Read + Send>> {
+    let body: Box<dyn Read + Send> = if request.header("transfer-encoding") == Some("chunked") {
+        Box::new(Chunked {
+            inner: reader,
+            left: 0,
+            done: false,
+        })
+    } else if let Some(length) = request.header("content-length") {
+        let length: u64 = length.parse().context("bad Content-Length")?;
+        Box::new(reader.take(length))
+    } else {
+        Box::new(io::empty())
+    };
+    Ok(match request.header("content-encoding") == Some("gzip") {
+        true => Box::new(GzDecoder::new(body)),
+        false => body,
+    })
+}
+
+/// A body sent in chunks, read as one stream.
+struct Chunked<R> {
+    inner: R,
+    /// What is left of the chunk being read.
+    left: u64,
+    done: bool,
+}
+
+impl<R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.left == 0 {
+            let mut size = String::new();
+            self.inner.read_line(&mut size)?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            self.left = u64::from_str_radix(size, 16).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad chunk size: {size}"),
+                )
+            })?;
+            if self.left == 0 {
+                // Trailers, up to the blank line that ends them
+                let mut line = String::new();
+                while self.inner.read_line(&mut line)? > 2 {
+                    line.clear();
+                }
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let wanted = buf
+            .len()
+            .min(usize::try_from(self.left).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..wanted])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.left -= n as u64;
+        if self.left == 0 {
+            // The line break that ends the chunk
+            let mut end = [0; 2];
+            self.inner.read_exact(&mut end)?;
+        }
+        Ok(n)
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) -> anyhow::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{message}\n",
+        message.len() + 1
+    )
+    .context("Sending the response")
+}
@@ -0,0 +1,86 @@
+pub(crate) mod daemon;
+pub(crate) mod http;
+pub(crate) mod receive_pack;
+pub(crate) mod upload_pack;
+
+use anyhow::Context;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The repository `dir` names: `dir` itself or `dir.git`, either a worktree or a bare
+/// repository, as `git-upload-pack` finds it.
+pub(crate) fn find_repository(dir: &Path) -> Option<PathBuf> {
+    let mut with_suffix = dir.as_os_str().to_owned();
+    with_suffix.push(".git");
+    [dir.to_path_buf(), PathBuf::from(with_suffix)]
+        .into_iter()
+        .find(|candidate| candidate.join(".git").is_dir() || is_bare(candidate))
+}
+
+/// The repository under `base` a request path names, if there is one. Paths that would
+/// lead out of `base` name nothing.
+pub(crate) fn resolve(base: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    let inside = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !inside || relative.as_os_str().is_empty() {
+        return None;
+    }
+    find_repository(&base.join(relative))
+}
+
+fn is_bare(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir()
+}
+
+/// Makes the repository `dir` names the one the rest of the program works on, by changing
+/// to its directory. Everything else finds a repository through `.git` in the current
+/// directory, so a bare repository is given a directory of its own outside it with `.git`
+/// linked to it, which lives as long as what is returned. Nothing is written to the
+/// repository just to serve it, so a read-only one can be served.
+pub(crate) fn enter_repository(dir: &Path) -> anyhow::Result<Option<BareLink>> {
+    let repository = find_repository(dir)
+        .with_context(|| format!("'{}' does not appear to be a git repository", dir.display()))?;
+    if !is_bare(&repository) {
+        std::env::set_current_dir(&repository)
+            .with_context(|| format!("Changing to {}", repository.display()))?;
+        return Ok(None);
+    }
+
+    let target = fs::canonicalize(&repository)
+        .with_context(|| format!("Finding {}", repository.display()))?;
+    let link = BareLink {
+        dir: std::env::temp_dir().join(format!("custom-git-{}", std::process::id())),
+    };
+    // A process killed before it could clean up may have had the same id
+    let _ = fs::remove_dir_all(&link.dir);
+    fs::create_dir_all(&link.dir).context("Creating a directory to serve from")?;
+    std::os::unix::fs::symlink(&target, link.dir.join(".git"))
+        .context("Linking the repository to serve")?;
+    std::env::set_current_dir(&link.dir).context("Changing to the directory to serve from")?;
+    Ok(Some(link))
+}
+
+/// The directory a bare repository is served from, removed when dropped.
+pub(crate) struct BareLink {
+    dir: PathBuf,
+}
+
+impl Drop for BareLink {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The protocol version a client asks for with a `GIT_PROTOCOL` value such as `version=2`:
+/// the highest of those it names, or 0.
+pub(crate) fn protocol_version(value: &str) -> u8 {
+    value
+        .split(':')
+        .filter_map(|entry| entry.strip_prefix("version="))
+        .filter_map(|version| version.parse().ok())
+        .filter(|version| *version <= 2)
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,407 @@
+use crate::config::{parse_bool, Config};
+use crate::merge::base::{self, CommitCache};
+use crate::objects;
+use crate::pack::{indexer, writer, PACK_DIR};
+use crate::protocol::pkt_line::{Packet, PktReader, PktWriter, MAX_DATA};
+use crate::protocol::sideband::Mux;
+use crate::protocol::AGENT;
+use crate::refs;
+use anyhow::Context;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const ZERO: &str = "0000000000000000000000000000000000000000";
+const NO_REFS: &str = "0000000000000000000000000000000000000000 capabilities^{}";
+
+/// One ref the client asked to update, and why that was turned down if it was.
+struct RefUpdate {
+    old: String,
+    new: String,
+    name: String,
+    error: Option<String>,
+}
+
+impl RefUpdate {
+    fn line(&self) -> String {
+        format!("{} {} {}", self.old, self.new, self.name)
+    }
+}
+
+/// What the client asked for with the capabilities on its first command.
+#[derive(Debug, Default)]
+struct Options {
+    report_status: bool,
+    side_band: bool,
+    /// Update all of the refs or none of them.
+    atomic: bool,
+}
+
+/// Sends the refs of the current repository with what we can do. Pushing has no version 2,
+/// so a client asking for it is answered in version 0.
+pub(crate) fn advertise(out: &mut PktWriter<impl Write>, version: u8) -> anyhow::Result<()> {
+    if version == 1 {
+        out.line("version 1")?;
+    }
+    let capabilities = [
+        "report-status",
+        "delete-refs",
+        "side-band-64k",
+        "atomic",
+        // Deltas against objects the pack leaves out can't be resolved here
+        "no-thin",
+        "object-format=sha1",
+        &format!("agent={AGENT}"),
+    ]
+    .join(" ");
+
+    let lines: Vec<String> = refs::list_refs("refs/")?
+        .into_iter()
+        .map(|(name, hash)| format!("{hash} {name}"))
+        .collect();
+    let first = lines.first().map_or(NO_REFS, String::as_str);
+    out.data(format!("{first}\0{capabilities}\n").as_bytes())?;
+    for line in lines.iter().skip(1) {
+        out.line(line)?;
+    }
+    out.flush_pkt()
+}
+
+/// Reads the ref updates the client sends on `input` after the advertisement, stores the
+/// pack that follows them and makes the updates that pass the checks and hooks. What came
+/// of each is reported back when the client asks for that.
+pub(crate) fn serve(input: impl Read, output: impl Write) -> anyhow::Result<()> {
+    let mut reader = PktReader::new(input);
+    let mut updates = Vec::new();
+    let mut opts = Options::default();
+    while let Some(Packet::Data(data)) = reader.read()? {
+        let line = String::from_utf8(data).context("protocol error: non-UTF-8 line")?;
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let (command, capabilities) = line.split_once('\0').unwrap_or((line, ""));
+        if updates.is_empty() && !capabilities.is_empty() {
+            opts = read_options(capabilities);
+        }
+        // A shallow client says where its history stops, which only matters to
+        // repositories that keep track of that
+        if command.starts_with("shallow ") {
+            continue;
+        }
+        let mut fields = command.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            anyhow::bail!("protocol error: expected old/new/ref, got '{command}'");
+        };
+        // A value that isn't an object name fails its own update rather than the push
+        let error = match (objects::is_hash(old), objects::is_hash(new)) {
+            (false, _) => Some("invalid old value".to_string()),
+            (_, false) => Some("invalid new value".to_string()),
+            _ => None,
+        };
+        updates.push(RefUpdate {
+            old: old.to_string(),
+            new: new.to_string(),
+            name: name.to_string(),
+            error,
+        });
+    }
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    // Pushes that only delete refs, or update nothing, have no pack
+    let mut messages = Vec::new();
+    let deleting = |u: &RefUpdate| u.error.is_some() || u.new == ZERO;
+    let unpacked = match updates.iter().all(deleting) {
+        true => Ok(None),
+        false => indexer::index_pack(reader.into_inner()).map(Some),
+    };
+    let unpack_status = match &unpacked {
+        Ok(_) => "unpack ok".to_string(),
+        Err(e) => {
+            for update in updates.iter_mut().filter(|u| u.error.is_none()) {
+                update.error = Some("unpacker error".to_string());
+            }
+            format!("unpack {e:#}")
+        }
+    };
+    if unpacked.is_ok() {
+        execute(&mut updates, &opts, &mut messages)?;
+    }
+
+    // Objects that no ref took up are not kept, as when git holds them in quarantine
+    if let Ok(Some(checksum)) = &unpacked {
+        if updates.iter().all(|u| u.error.is_some()) {
+            let pack = Path::new(PACK_DIR).join(format!("pack-{checksum}"));
+            let _ = fs::remove_file(pack.with_extension("idx"));
+            let _ = fs::remove_file(pack.with_extension("pack"));
+        }
+    }
+
+    let mut report = PktWriter::new(Vec::new());
+    if opts.report_status {
+        report.line(&unpack_status)?;
+        for update in &updates {
+            match &update.error {
+                None => report.line(&format!("ok {}", update.name))?,
+                Some(error) => report.line(&format!("ng {} {error}", update.name))?,
+            }
+        }
+        report.flush_pkt()?;
+    }
+    let mut out = PktWriter::new(output);
+    if opts.side_band {
+        let mut mux = Mux::new(PktWriter::new(out.get_mut()), MAX_DATA - 1);
+        if !messages.is_empty() {
+            mux.progress(&String::from_utf8_lossy(&messages))?;
+        }
+        mux.write_all(&report.into_inner())?;
+        mux.finish()?;
+    } else {
+        std::io::stderr().write_all(&messages)?;
+        out.get_mut().write_all(&report.into_inner())?;
+    }
+    out.flush()
+}
+
+fn read_options(capabilities: &str) -> Options {
+    let mut opts = Options::default();
+    for capability in capabilities.split(' ') {
+        match capability {
+            "report-status" => opts.report_status = true,
+            "side-band-64k" => opts.side_band = true,
+            "atomic" => opts.atomic = true,
+            _ => {}
+        }
+    }
+    opts
+}
+
+/// Checks the updates, runs the hooks around them and makes those that pass, recording
+/// why the others failed. What the hooks print goes to `messages`.
+fn execute(
+    updates: &mut [RefUpdate],
+    opts: &Options,
+    messages: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let config = Config::read()?;
+    let current: Vec<(String, String)> = refs::list_refs("refs/")?;
+    let tips: Vec<String> = current.iter().map(|(_, hash)| hash.clone()).collect();
+    for update in updates.iter_mut().filter(|u| u.error.is_none()) {
+        let now = current
+            .iter()
+            .find(|(name, _)| *name == update.name)
+            .map_or(ZERO, |(_, hash)| hash.as_str());
+        update.error = check(update, now, &tips, &config)?;
+    }
+    fail_atomic(updates, opts);
+
+    let hooks = Hooks::new(&config)?;
+    let lines: Vec<String> = updates
+        .iter()
+        .filter(|u| u.error.is_none())
+        .map(RefUpdate::line)
+        .collect();
+    if !lines.is_empty() && !hooks.run("pre-receive", &[], &lines, messages)? {
+        for update in updates.iter_mut().filter(|u| u.error.is_none()) {
+            update.error = Some("pre-receive hook declined".to_string());
+        }
+    }
+    for update in updates.iter_mut().filter(|u| u.error.is_none()) {
+        let args = [update.name.as_str(), &update.old, &update.new];
+        if !hooks.run("update", &args, &[], messages)? {
+            update.error = Some("hook declined".to_string());
+        }
+    }
+    fail_atomic(updates, opts);
+
+    // Every ref is locked, and checked again under its lock, before any of them moves.
+    // Of two pushes racing for a ref only one gets it, and an atomic push that can't
+    // lock everything backs out without having changed anything.
+    let mut locks = Vec::new();
+    for (i, update) in updates.iter_mut().enumerate() {
+        if update.error.is_some() {
+            continue;
+        }
+        match lock(update) {
+            Ok(lock) => locks.push((i, lock)),
+            Err((reason, e)) => {
+                messages.extend_from_slice(format!("error: {e:#}\n").as_bytes());
+                update.error = Some(reason.to_string());
+            }
+        }
+    }
+    if opts.atomic && updates.iter().any(|u| u.error.is_some()) {
+        // Dropping the locks removes them
+        locks.clear();
+        fail_atomic(updates, opts);
+    }
+
+    let mut done = Vec::new();
+    for (i, lock) in locks {
+        let update = &mut updates[i];
+        match lock.commit() {
+            Ok(()) => done.push(update.line()),
+            Err(e) => {
+                messages.extend_from_slice(format!("error: {e:#}\n").as_bytes());
+                update.error = Some("failed to update ref".to_string());
+            }
+        }
+    }
+    if !done.is_empty() {
+        // Too late to turn anything down, so what it says is all that matters
+        hooks.run("post-receive", &[], &done, messages)?;
+    }
+    Ok(())
+}
+
+/// Why `update` of a ref that is now at `now` can't be made, if it can't. Repositories
+/// with a worktree refuse to move the branch it has checked out.
+fn check(
+    update: &RefUpdate,
+    now: &str,
+    tips: &[String],
+    config: &Config,
+) -> anyhow::Result<Option<String>> {
+    let denied = |key: &str| config.get_bool(key) == Some(true);
+    let deleting = update.new == ZERO;
+    if !refs::is_valid_name(&update.name) {
+        return Ok(Some("funny refname".to_string()));
+    }
+    if update.old != now {
+        return Ok(Some("failed to update ref".to_string()));
+    }
+    let current_branch = config.get_bool("core.bare") != Some(true)
+        && refs::head_ref()?.as_deref() == Some(update.name.as_str());
+    if deleting {
+        if denied("receive.denydeletes") {
+            return Ok(Some("deletion prohibited".to_string()));
+        }
+        if current_branch && config.get("receive.denydeletecurrent") != Some("ignore") {
+            return Ok(Some(
+                "deletion of the current branch prohibited".to_string(),
+            ));
+        }
+        return Ok(None);
+    }
+
+    if current_branch {
+        let refuse = config
+            .get("receive.denycurrentbranch")
+            .map_or(true, |value| {
+                value == "refuse" || parse_bool(value) == Some(true)
+            });
+        if refuse {
+            return Ok(Some("branch is currently checked out".to_string()));
+        }
+    }
+    // Everything the new value leads to has to be here, or be behind a ref we have
+    if !objects::has_object(&update.new)?
+        || writer::list_objects(std::slice::from_ref(&update.new), tips).is_err()
+    {
+        return Ok(Some("missing necessary objects".to_string()));
+    }
+    if now != ZERO && denied("receive.denynonfastforwards") {
+        let mut cache = CommitCache::default();
+        let commits =
+            refs::peel_to_commit(now).and_then(|old| Ok((old, refs::peel_to_commit(&update.new)?)));
+        if let Ok((old, new)) = commits {
+            if !base::is_ancestor(&mut cache, &old, &new)? {
+                return Ok(Some("non-fast-forward".to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Locks the ref `update` changes, with its new value ready to take its place, as long as
+/// the ref is still where the client saw it. Otherwise, the reason to report and why.
+fn lock(update: &RefUpdate) -> Result<refs::RefLock, (&'static str, anyhow::Error)> {
+    let new = (update.new != ZERO).then_some(update.new.as_str());
+    let lock = refs::lock_ref(&update.name, new).map_err(|e| ("failed to lock", e))?;
+    let now = refs::resolve_ref(&update.name)
+        .map_err(|e| ("failed to lock", e))?
+        .unwrap_or_else(|| ZERO.to_string());
+    if now != update.old {
+        let e = anyhow::anyhow!(
+            "cannot lock ref '{}': is at {now} but expected {}",
+            update.name,
+            update.old
+        );
+        return Err(("failed to update ref", e));
+    }
+    Ok(lock)
+}
+
+/// In an atomic push, one update failing fails them all.
+fn fail_atomic(updates: &mut [RefUpdate], opts: &Options) {
+    if opts.atomic && updates.iter().any(|u| u.error.is_some()) {
+        for update in updates.iter_mut().filter(|u| u.error.is_none()) {
+            update.error = Some("atomic push failure".to_string());
+        }
+    }
+}
+
+/// The programs in `.git/hooks`, or `core.hooksPath`, run from the repository with
+/// `GIT_DIR` pointing at it.
+struct Hooks {
+    dir: PathBuf,
+    git_dir: PathBuf,
+    /// Where hooks run: the worktree, or the repository itself when it is bare.
+    cwd: PathBuf,
+}
+
+impl Hooks {
+    fn new(config: &Config) -> anyhow::Result<Hooks> {
+        let git_dir = fs::canonicalize(".git").context("Finding the repository")?;
+        let cwd = match config.get_bool("core.bare") == Some(true) {
+            true => git_dir.clone(),
+            false => std::env::current_dir().context("Finding the worktree")?,
+        };
+        let dir = match config.get("core.hookspath") {
+            Some(path) => cwd.join(path),
+            None => git_dir.join("hooks"),
+        };
+        Ok(Hooks { dir, git_dir, cwd })
+    }
+
+    /// Runs hook `name` with `args` and `lines` on stdin, and returns whether it
+    /// succeeded. A hook that isn't there, or isn't executable, succeeds.
+    fn run(
+        &self,
+        name: &str,
+        args: &[&str],
+        lines: &[String],
+        messages: &mut Vec<u8>,
+    ) -> anyhow::Result<bool> {
+        let path = self.dir.join(name);
+        let executable =
+            fs::metadata(&path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+        if !executable {
+            return Ok(true);
+        }
+        let mut child = Command::new(&path)
+            .args(args)
+            .current_dir(&self.cwd)
+            .env("GIT_DIR", &self.git_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Running the {name} hook"))?;
+        let input: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        // A hook may exit without reading what it doesn't need
+        let _ = child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(input.as_bytes());
+        let output = child
+            .wait_with_output()
+            .with_context(|| format!("Running the {name} hook"))?;
+        messages.extend_from_slice(&output.stdout);
+        messages.extend_from_slice(&output.stderr);
+        Ok(output.status.success())
+    }
+}
@@ -0,0 +1,391 @@
+use crate::objects;
+use crate::pack::writer;
+use crate::protocol::local::LocalRepo;
+use crate::protocol::pkt_line::{Packet, PktReader, PktWriter, MAX_DATA};
+use crate::protocol::sideband::Mux;
+use crate::protocol::{RemoteRef, AGENT};
+use anyhow::Context;
+use std::collections::HashSet;
+use std::io::{BufWriter, Read, Write};
+
+const NO_REFS: &str = "0000000000000000000000000000000000000000 capabilities^{}";
+
+/// What the client asked for with the capabilities on its first `want` line, or the
+/// arguments of a v2 `fetch`.
+#[derive(Debug, Default)]
+struct Options {
+    /// The most data a side-band packet carries, if the pack is to be multiplexed.
+    band: Option<usize>,
+    multi_ack: bool,
+    multi_ack_detailed: bool,
+    no_progress: bool,
+    /// Send the annotated tags that point at what is sent along with it.
+    include_tag: bool,
+}
+
+/// Sends the refs of the current repository with what we can do, in the way protocol
+/// `version` starts a conversation.
+pub(crate) fn advertise(out: &mut PktWriter<impl Write>, version: u8) -> anyhow::Result<()> {
+    if version == 2 {
+        out.line("version 2")?;
+        out.line(&format!("agent={AGENT}"))?;
+        out.line("ls-refs")?;
+        out.line("fetch")?;
+        out.line("server-option")?;
+        out.line("object-format=sha1")?;
+        return out.flush_pkt();
+    }
+    if version == 1 {
+        out.line("version 1")?;
+    }
+
+    let refs = repository_refs()?;
+    let mut capabilities = vec![
+        "multi_ack",
+        "multi_ack_detailed",
+        "side-band",
+        "side-band-64k",
+        "no-progress",
+        "include-tag",
+        "allow-tip-sha1-in-want",
+        "allow-reachable-sha1-in-want",
+        "object-format=sha1",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+    if let Some(target) = refs.first().and_then(|r| r.symref_target.as_ref()) {
+        capabilities.push(format!("symref=HEAD:{target}"));
+    }
+    capabilities.push(format!("agent={AGENT}"));
+
+    let mut lines = Vec::new();
+    for remote_ref in &refs {
+        lines.push(format!("{} {}", remote_ref.hash, remote_ref.name));
+        if let Some(peeled) = &remote_ref.peeled {
+            lines.push(format!("{peeled} {}^{{}}", remote_ref.name));
+        }
+    }
+    // An empty repository still has to say what it can do
+    let first = lines.first().map_or(NO_REFS, String::as_str);
+    out.data(format!("{first}\0{}\n", capabilities.join(" ")).as_bytes())?;
+    for line in lines.iter().skip(1) {
+        out.line(line)?;
+    }
+    out.flush_pkt()
+}
+
+/// Answers the requests the client sends on `input` after the advertisement. A stateless
+/// connection, as over HTTP, carries one request and ends with its answer.
+pub(crate) fn serve(
+    input: impl Read,
+    output: impl Write,
+    version: u8,
+    stateless: bool,
+) -> anyhow::Result<()> {
+    let mut reader = PktReader::new(input);
+    let mut out = PktWriter::new(output);
+    let result = match version {
+        2 => serve_v2(&mut reader, &mut out, stateless),
+        _ => serve_v0(&mut reader, &mut out, stateless),
+    };
+    // The client is told why it gets nothing, as far as it still listens
+    if let Err(e) = &result {
+        let _ = out.line(&format!("ERR {e}"));
+    }
+    let _ = out.flush();
+    result
+}
+
+fn serve_v0(
+    reader: &mut PktReader<impl Read>,
+    out: &mut PktWriter<impl Write>,
+    stateless: bool,
+) -> anyhow::Result<()> {
+    // A client that only wanted the advertisement leaves without asking for anything
+    let mut wants = Vec::new();
+    let mut opts = Options::default();
+    loop {
+        let Some(Packet::Data(data)) = reader.read()? else {
+            break;
+        };
+        let line = String::from_utf8(data).context("protocol error: non-UTF-8 line")?;
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let Some(want) = line.strip_prefix("want ") else {
+            anyhow::bail!("protocol error: expected want, got '{line}'");
+        };
+        let (hash, capabilities) = want.split_once(' ').unwrap_or((want, ""));
+        if wants.is_empty() {
+            opts = v0_options(capabilities);
+        }
+        wants.push(hash.to_string());
+    }
+    if wants.is_empty() {
+        return Ok(());
+    }
+    check_wants(&wants)?;
+
+    let mut common: Vec<String> = Vec::new();
+    loop {
+        let Some(packet) = reader.read()? else {
+            // The client found what it wanted to know and hung up
+            return Ok(());
+        };
+        match packet.line() {
+            None => {
+                if common.is_empty() || opts.multi_ack {
+                    out.line("NAK")?;
+                }
+                out.flush()?;
+                // The client sends everything again, with more, in its next request
+                if stateless {
+                    return Ok(());
+                }
+            }
+            Some("done") => {
+                match common.last() {
+                    Some(last) if opts.multi_ack => out.line(&format!("ACK {last}"))?,
+                    Some(_) => {}
+                    None => out.line("NAK")?,
+                }
+                break;
+            }
+            Some(line) => {
+                let Some(hash) = line.strip_prefix("have ") else {
+                    anyhow::bail!("protocol error: expected have or done, got '{line}'");
+                };
+                anyhow::ensure!(
+                    objects::is_hash(hash),
+                    "protocol error: expected an object name, got '{hash}'"
+                );
+                if !objects::has_object(hash)? {
+                    continue;
+                }
+                common.push(hash.to_string());
+                if opts.multi_ack_detailed {
+                    out.line(&format!("ACK {hash} common"))?;
+                } else if opts.multi_ack {
+                    out.line(&format!("ACK {hash} continue"))?;
+                } else if common.len() == 1 {
+                    out.line(&format!("ACK {hash}"))?;
+                }
+            }
+        }
+    }
+
+    send_pack(out, &wants, &common, &opts)
+}
+
+/// The capabilities on the first `want` line that change what we send.
+fn v0_options(capabilities: &str) -> Options {
+    let mut opts = Options::default();
+    for capability in capabilities.split(' ') {
+        match capability {
+            "side-band-64k" => opts.band = Some(MAX_DATA - 1),
+            "side-band" if opts.band.is_none() => opts.band = Some(999),
+            "multi_ack" => opts.multi_ack = true,
+            "multi_ack_detailed" => {
+                opts.multi_ack = true;
+                opts.multi_ack_detailed = true;
+            }
+            "no-progress" => opts.no_progress = true,
+            "include-tag" => opts.include_tag = true,
+            _ => {}
+        }
+    }
+    opts
+}
+
+fn serve_v2(
+    reader: &mut PktReader<impl Read>,
+    out: &mut PktWriter<impl Write>,
+    stateless: bool,
+) -> anyhow::Result<()> {
+    loop {
+        let Some(packet) = reader.read()? else {
+            return Ok(());
+        };
+        let Some(command) = packet.line().and_then(|l| l.strip_prefix("command=")) else {
+            // A flush on its own is a client saying goodbye
+            if packet == Packet::Flush {
+                return Ok(());
+            }
+            anyhow::bail!("protocol error: expected a command, got {packet:?}");
+        };
+        let command = command.to_string();
+
+        // The capabilities the client uses, such as its agent, change nothing here
+        let (_, end) = reader.read_lines()?;
+        let arguments = match end {
+            Packet::Delim => reader.read_lines()?.0,
+            _ => Vec::new(),
+        };
+        match command.as_str() {
+            "ls-refs" => ls_refs(out, &arguments)?,
+            "fetch" => fetch(out, &arguments)?,
+            _ => anyhow::bail!("invalid command '{command}'"),
+        }
+        out.flush()?;
+        if stateless {
+            return Ok(());
+        }
+    }
+}
+
+fn ls_refs(out: &mut PktWriter<impl Write>, arguments: &[String]) -> anyhow::Result<()> {
+    let symrefs = arguments.iter().any(|a| a == "symrefs");
+    let peel = arguments.iter().any(|a| a == "peel");
+    let prefixes: Vec<&str> = arguments
+        .iter()
+        .filter_map(|a| a.strip_prefix("ref-prefix "))
+        .collect();
+    for remote_ref in repository_refs()? {
+        let name = &remote_ref.name;
+        if !prefixes.is_empty() && !prefixes.iter().any(|p| name.starts_with(p)) {
+            continue;
+        }
+        let mut line = format!("{} {name}", remote_ref.hash);
+        if let Some(target) = remote_ref.symref_target.as_ref().filter(|_| symrefs) {
+            line.push_str(&format!(" symref-target:{target}"));
+        }
+        if let Some(peeled) = remote_ref.peeled.as_ref().filter(|_| peel) {
+            line.push_str(&format!(" peeled:{peeled}"));
+        }
+        out.line(&line)?;
+    }
+    out.flush_pkt()
+}
+
+fn fetch(out: &mut PktWriter<impl Write>, arguments: &[String]) -> anyhow::Result<()> {
+    // Version 2 always multiplexes the pack
+    let mut opts = Options {
+        band: Some(MAX_DATA - 1),
+        ..Default::default()
+    };
+    let mut wants = Vec::new();
+    let mut haves = Vec::new();
+    let mut done = false;
+    for argument in arguments {
+        let (name, value) = argument.split_once(' ').unwrap_or((argument, ""));
+        match name {
+            "want" => wants.push(value.to_string()),
+            "have" => haves.push(value.to_string()),
+            "done" => done = true,
+            "no-progress" => opts.no_progress = true,
+            "include-tag" => opts.include_tag = true,
+            "thin-pack" | "ofs-delta" => {}
+            _ => anyhow::bail!("unexpected line: '{argument}'"),
+        }
+    }
+    check_wants(&wants)?;
+
+    let mut common = Vec::new();
+    for have in haves {
+        anyhow::ensure!(
+            objects::is_hash(&have),
+            "protocol error: expected an object name, got '{have}'"
+        );
+        if objects::has_object(&have)? {
+            common.push(have);
+        }
+    }
+    // Until the client is done offering commits, it only learns which we have
+    if !done {
+        out.line("acknowledgments")?;
+        if common.is_empty() {
+            out.line("NAK")?;
+        }
+        for hash in &common {
+            out.line(&format!("ACK {hash}"))?;
+        }
+        return out.flush_pkt();
+    }
+    out.line("packfile")?;
+    send_pack(out, &wants, &common, &opts)
+}
+
+/// Fails unless every object the client wants is one we have and can reach from a ref,
+/// as allow-reachable-sha1-in-want promises.
+fn check_wants(wants: &[String]) -> anyhow::Result<()> {
+    for want in wants {
+        anyhow::ensure!(
+            objects::is_hash(want) && objects::has_object(want)?,
+            "upload-pack: not our ref {want}"
+        );
+    }
+    let mut tips: Vec<String> = repository_refs()?
+        .into_iter()
+        .flat_map(|r| [Some(r.hash), r.peeled])
+        .flatten()
+        .collect();
+    tips.sort();
+    tips.dedup();
+    // Walking the history is only needed for what isn't at the tip of a ref
+    let hidden: Vec<&String> = wants.iter().filter(|w| !tips.contains(w)).collect();
+    if hidden.is_empty() {
+        return Ok(());
+    }
+    let reachable: HashSet<String> = writer::list_objects(&tips, &[])?.into_iter().collect();
+    match hidden.into_iter().find(|want| !reachable.contains(*want)) {
+        Some(want) => anyhow::bail!("upload-pack: not our ref {want}"),
+        None => Ok(()),
+    }
+}
+
+/// Sends a pack of what is reachable from `wants` but not from `common`, on band 1 of a
+/// side-band stream with progress on band 2 if the client asked for that.
+fn send_pack(
+    out: &mut PktWriter<impl Write>,
+    wants: &[String],
+    common: &[String],
+    opts: &Options,
+) -> anyhow::Result<()> {
+    let mut include = wants.to_vec();
+    let mut hashes = writer::list_objects(&include, common)?;
+    if opts.include_tag {
+        let sent: HashSet<&String> = hashes.iter().collect();
+        let tags: Vec<String> = repository_refs()?
+            .into_iter()
+            .filter(|r| r.name.starts_with("refs/tags/") && !sent.contains(&r.hash))
+            .filter(|r| r.peeled.as_ref().is_some_and(|p| sent.contains(p)))
+            .map(|r| r.hash)
+            .collect();
+        if !tags.is_empty() {
+            include.extend(tags);
+            hashes = writer::list_objects(&include, common)?;
+        }
+    }
+
+    let Some(band) = opts.band else {
+        writer::write_pack(&hashes, out.get_mut()).context("Writing the pack")?;
+        return Ok(());
+    };
+    let mut mux = Mux::new(PktWriter::new(out.get_mut()), band);
+    if !opts.no_progress {
+        mux.progress(&format!("Enumerating objects: {}, done.\n", hashes.len()))?;
+    }
+    // Whole packets rather than a packet per write that goes into the pack
+    let mut buffered = BufWriter::with_capacity(band, mux);
+    let written = writer::write_pack(&hashes, &mut buffered);
+    let mut mux = buffered.into_inner().map_err(|e| e.into_error())?;
+    if let Err(e) = written {
+        mux.error(&format!("{e:#}"))?;
+        mux.finish()?;
+        return Err(e).context("Writing the pack");
+    }
+    if !opts.no_progress {
+        mux.progress(&format!(
+            "Total {} (delta 0), reused 0 (delta 0), pack-reused 0\n",
+            hashes.len()
+        ))?;
+    }
+    mux.finish()?;
+    Ok(())
+}
+
+/// The refs of the repository we serve, `HEAD` first.
+fn repository_refs() -> anyhow::Result<Vec<RemoteRef>> {
+    LocalRepo::open(".")?
+        .context("Opening the repository to serve")?
+        .refs()
+}
@@ -0,0 +1,61 @@
+use anyhow::Context;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Mutex;
+
+const SHALLOW: &str = ".git/shallow";
+
+/// The commits in `.git/shallow`, once read.
+static COMMITS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+fn with_commits<T>(f: impl FnOnce(&HashSet<String>) -> T) -> anyhow::Result<T> {
+    let mut commits = COMMITS.lock().unwrap_or_else(|e| e.into_inner());
+    if commits.is_none() {
+        let content = match fs::read_to_string(SHALLOW) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context("Reading .git/shallow"),
+        };
+        *commits = Some(content.lines().map(str::to_string).collect());
+    }
+    Ok(f(commits
+        .as_ref()
+        .expect("the shallow commits were just read")))
+}
+
+/// Whether history is cut off at commit `hash`, whose parents we don't have.
+pub(crate) fn is_shallow(hash: &str) -> anyhow::Result<bool> {
+    with_commits(|commits| commits.contains(hash))
+}
+
+/// The commits history is cut off at, sorted. Empty for a complete repository.
+pub(crate) fn commits() -> anyhow::Result<Vec<String>> {
+    let mut commits = with_commits(|commits| commits.iter().cloned().collect::<Vec<_>>())?;
+    commits.sort();
+    Ok(commits)
+}
+
+/// Moves the boundary: history is now cut off at the commits in `add`, and no longer at
+/// those in `remove`. The file goes away once the repository is complete.
+pub(crate) fn update(add: &[String], remove: &[String]) -> anyhow::Result<()> {
+    let mut commits: HashSet<String> = commits()?.into_iter().collect();
+    commits.extend(add.iter().cloned());
+    for hash in remove {
+        commits.remove(hash);
+    }
+
+    let mut sorted: Vec<&String> = commits.iter().collect();
+    sorted.sort();
+    if sorted.is_empty() {
+        if let Err(e) = fs::remove_file(SHALLOW) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e).context("Removing .git/shallow");
+            }
+        }
+    } else {
+        let content: String = sorted.iter().map(|hash| format!("{hash}\n")).collect();
+        fs::write(SHALLOW, content).context("Writing .git/shallow")?;
+    }
+    *COMMITS.lock().unwrap_or_else(|e| e.into_inner()) = Some(commits);
+    Ok(())
+}
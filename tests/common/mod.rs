@@ -0,0 +1,166 @@
+//! Helpers for the end-to-end tests, which drive this program and stock git side by side
+//! in scratch directories.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+
+pub const BIN: &str = env!("CARGO_BIN_EXE_codecrafters-git");
+
+/// A directory of its own for one test, removed when the test is done.
+pub struct Scratch {
+    pub path: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        let path = std::env::temp_dir().join(format!(
+            "codecrafters-git-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("creating the scratch directory");
+        Scratch { path }
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A command run in `dir` with nothing from the user's own git setup: no config files
+/// and a fixed identity.
+fn isolated(program: &str, dir: &Path) -> Command {
+    let mut command = Command::new(program);
+    command
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_AUTHOR_NAME", "A U Thor")
+        .env("GIT_AUTHOR_EMAIL", "author@example.com")
+        .env("GIT_COMMITTER_NAME", "C O Mitter")
+        .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+        .env("RUST_BACKTRACE", "0")
+        .env_remove("GIT_DIR")
+        .env_remove("GIT_SSH_COMMAND");
+    command
+}
+
+/// This program, run in `dir`.
+pub fn ours(dir: &Path) -> Command {
+    isolated(BIN, dir)
+}
+
+/// Stock git, run in `dir`.
+pub fn git(dir: &Path) -> Command {
+    let mut command = isolated("git", dir);
+    command.args(["-c", "init.defaultBranch=main"]);
+    command
+}
+
+/// Runs `command`, failing the test with its output unless it succeeds, and returns
+/// what it printed on stdout.
+pub fn run(command: &mut Command) -> String {
+    let output = command.output().expect("starting the command");
+    assert!(
+        output.status.success(),
+        "{command:?} failed with {}\nstdout:\n{}\nstderr:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Runs `command` expecting it to fail, and returns its output.
+pub fn run_failing(command: &mut Command) -> Output {
+    let output = command.output().expect("starting the command");
+    assert!(
+        !output.status.success(),
+        "{command:?} succeeded\nstdout:\n{}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// What `rev` names in the repository at `dir`, as stock git sees it.
+pub fn rev_parse(dir: &Path, rev: &str) -> String {
+    run(git(dir).args(["rev-parse", rev])).trim().to_string()
+}
+
+/// A repository at `dir` with `commits` commits on `main`, each adding a file.
+pub fn repository(dir: &Path, commits: usize) {
+    fs::create_dir_all(dir).expect("creating the repository directory");
+    run(git(dir).arg("init").arg("-q"));
+    for i in 0..commits {
+        commit_file(dir, &format!("file{i}.txt"), &format!("content {i}\n"));
+    }
+}
+
+/// Writes `path` in the worktree at `dir` and commits it.
+pub fn commit_file(dir: &Path, path: &str, content: &str) {
+    fs::write(dir.join(path), content).expect("writing a file to commit");
+    run(git(dir).args(["add", path]));
+    run(git(dir).args(["commit", "-q", "-m", &format!("Write {path}")]));
+}
+
+/// A bare repository at `dir` with nothing in it.
+pub fn bare_repository(dir: &Path) {
+    fs::create_dir_all(dir).expect("creating the repository directory");
+    run(git(dir).args(["init", "-q", "--bare"]));
+}
+
+/// A server this program runs in the background, stopped when dropped.
+pub struct Server {
+    child: Child,
+    /// Where it listens, as `host:port`.
+    pub address: String,
+}
+
+impl Server {
+    /// Starts `ours <args> --port 0` in `dir`, and waits for the address it listens on,
+    /// which it announces as `Serving <path> on <scheme>://<address>/`.
+    pub fn start(dir: &Path, args: &[&str]) -> Server {
+        let mut child = ours(dir)
+            .args(args)
+            .args(["--port", "0"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("starting the server");
+        let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped"));
+        let mut line = String::new();
+        stderr
+            .read_line(&mut line)
+            .expect("reading what the server says");
+        let address = line
+            .trim_end()
+            .rsplit_once("://")
+            .map(|(_, url)| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| panic!("the server said '{line}' rather than where it listens"));
+        // Whatever else it says goes to the test's output, without ever filling the pipe
+        std::thread::spawn(move || {
+            let mut rest = String::new();
+            let _ = stderr.read_to_string(&mut rest);
+            eprint!("{rest}");
+        });
+        Server { child, address }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,64 @@
+//! `daemon` serving `git://` on localhost, to stock git and to this program.
+
+mod common;
+
+use common::{commit_file, git, ours, repository, rev_parse, run, run_failing, Scratch, Server};
+use std::fs;
+
+#[test]
+fn git_and_this_program_clone_and_fetch_over_git_protocol() {
+    let scratch = Scratch::new("daemon-clone");
+    let src = scratch.join("repos/src");
+    repository(&src, 3);
+    let daemon = Server::start(&scratch.path, &["daemon", "--export-all", "repos"]);
+    let url = format!("git://{}/src", daemon.address);
+
+    run(git(&scratch.path).args(["clone", "-q", &url, "by-git"]));
+    assert_eq!(
+        rev_parse(&scratch.join("by-git"), "HEAD"),
+        rev_parse(&src, "HEAD")
+    );
+    // Version 0, where the daemon advertises its refs before being asked
+    let v0 = ["-c", "protocol.version=0", "clone", "-q", &url, "by-git-v0"];
+    run(git(&scratch.path).args(v0));
+    assert_eq!(
+        rev_parse(&scratch.join("by-git-v0"), "HEAD"),
+        rev_parse(&src, "HEAD")
+    );
+
+    run(ours(&scratch.path).args(["clone", &url, "by-us"]));
+    let cloned = scratch.join("by-us");
+    assert_eq!(rev_parse(&cloned, "HEAD"), rev_parse(&src, "HEAD"));
+    assert_eq!(
+        fs::read_to_string(cloned.join("file2.txt")).expect("reading a checked out file"),
+        "content 2\n"
+    );
+
+    commit_file(&src, "later.txt", "later\n");
+    run(ours(&cloned).args(["fetch", "origin"]));
+    assert_eq!(rev_parse(&cloned, "origin/main"), rev_parse(&src, "main"));
+}
+
+#[test]
+fn only_exported_repositories_are_served() {
+    let scratch = Scratch::new("daemon-export");
+    repository(&scratch.join("repos/private"), 1);
+    repository(&scratch.join("repos/public"), 1);
+    fs::write(scratch.join("repos/public/.git/git-daemon-export-ok"), "")
+        .expect("exporting the repository");
+    let daemon = Server::start(&scratch.path, &["daemon", "repos"]);
+
+    let private = format!("git://{}/private", daemon.address);
+    let output = run_failing(git(&scratch.path).args(["clone", "-q", &private, "private"]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("access denied or repository not exported"),
+        "stderr:\n{stderr}"
+    );
+
+    let public = format!("git://{}/public", daemon.address);
+    run(git(&scratch.path).args(["clone", "-q", &public, "public"]));
+    // Paths may not lead out of the base path
+    let outside = format!("git://{}/../repos/public", daemon.address);
+    run_failing(git(&scratch.path).args(["clone", "-q", &outside, "outside"]));
+}
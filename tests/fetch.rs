@@ -0,0 +1,33 @@
+//! `fetch` from a local repository, and merging what it fetched through `FETCH_HEAD`.
+
+mod common;
+
+use common::{commit_file, git, ours, repository, rev_parse, run, Scratch};
+
+#[test]
+fn merge_takes_what_fetch_left_in_fetch_head() {
+    let scratch = Scratch::new("fetch-merge");
+    let src = scratch.join("src");
+    repository(&src, 1);
+    run(ours(&scratch.path).args(["clone", "src", "work"]));
+    let work = scratch.join("work");
+    commit_file(&src, "theirs.txt", "theirs\n");
+    commit_file(&work, "ours.txt", "ours\n");
+
+    run(ours(&work).args(["fetch", "origin"]));
+    let fetched = rev_parse(&src, "main");
+    assert_eq!(rev_parse(&work, "FETCH_HEAD"), fetched);
+    assert_eq!(rev_parse(&work, "origin/main"), fetched);
+
+    let ours_before = rev_parse(&work, "HEAD");
+    run(ours(&work).args(["merge", "FETCH_HEAD"]));
+    assert_eq!(rev_parse(&work, "HEAD^1"), ours_before);
+    assert_eq!(rev_parse(&work, "HEAD^2"), fetched);
+    let subject = run(git(&work).args(["log", "-1", "--format=%s"]));
+    assert_eq!(
+        subject.trim(),
+        format!("Merge branch 'main' of {}", src.display())
+    );
+    assert!(work.join("theirs.txt").is_file());
+    run(ours(&work).args(["show", "FETCH_HEAD"]));
+}
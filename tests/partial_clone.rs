@@ -0,0 +1,73 @@
+//! Partial clones with `--filter=blob:none`, from stock git's upload-pack reached through a
+//! stand-in for ssh, and the missing blobs they fetch later.
+
+mod common;
+
+use common::{commit_file, git, ours, run, Scratch};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// How many packs of objects fetched from the promisor remote the repository at `dir` has.
+fn promisor_packs(dir: &Path) -> usize {
+    fs::read_dir(dir.join(".git/objects/pack"))
+        .expect("listing the packs")
+        .filter(|entry| {
+            let name = entry.as_ref().expect("listing the packs").file_name();
+            name.to_string_lossy().ends_with(".promisor")
+        })
+        .count()
+}
+
+#[test]
+fn missing_blobs_are_fetched_once_per_command() {
+    let scratch = Scratch::new("partial-clone");
+    let src = scratch.join("src");
+    fs::create_dir_all(&src).expect("creating the repository directory");
+    run(git(&src).args(["init", "-q"]));
+    run(git(&src).args(["config", "uploadpack.allowFilter", "true"]));
+    run(git(&src).args(["config", "uploadpack.allowAnySHA1InWant", "true"]));
+    for i in 0..3 {
+        commit_file(&src, &format!("file{i}.txt"), &format!("content {i}\n"));
+    }
+    for branch in ["one", "two"] {
+        run(git(&src).args(["checkout", "-q", "-b", branch, "main"]));
+        for i in 0..3 {
+            commit_file(&src, &format!("file{i}.txt"), &format!("{branch} {i}\n"));
+        }
+    }
+    run(git(&src).args(["checkout", "-q", "main"]));
+
+    // Runs what it is asked to on "the other host", which is stock git here
+    let ssh = scratch.join("fake-ssh");
+    fs::write(
+        &ssh,
+        "#!/bin/sh\nwhile [ $# -gt 1 ]; do shift; done\nexec sh -c \"$1\"\n",
+    )
+    .expect("writing the fake ssh");
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).expect("making it runnable");
+
+    let url = format!("example.com:{}", src.display());
+    let clone = ["clone", "--filter=blob:none", &url, "work"];
+    run(ours(&scratch.path).env("GIT_SSH_COMMAND", &ssh).args(clone));
+    let work = scratch.join("work");
+    // The pack of the clone itself, and the files of main checked out
+    assert_eq!(promisor_packs(&work), 2);
+
+    // Any spelling of true makes the remote a promisor
+    run(git(&work).args(["config", "remote.origin.promisor", "yes"]));
+    let diff = run(ours(&work)
+        .env("GIT_SSH_COMMAND", &ssh)
+        .args(["diff", "HEAD", "origin/one"]));
+    assert!(diff.contains("+one 2"), "diff:\n{diff}");
+    assert_eq!(promisor_packs(&work), 3);
+
+    run(ours(&work)
+        .env("GIT_SSH_COMMAND", &ssh)
+        .args(["checkout", "origin/two", "--", "."]));
+    assert_eq!(
+        fs::read_to_string(work.join("file1.txt")).expect("reading a checked out file"),
+        "two 1\n"
+    );
+    assert_eq!(promisor_packs(&work), 4);
+}
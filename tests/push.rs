@@ -0,0 +1,55 @@
+//! `push` over smart HTTP, to this program's own server.
+
+mod common;
+
+use common::{bare_repository, commit_file, git, ours, repository, rev_parse, run, Scratch};
+use common::{run_failing, Server};
+
+#[test]
+fn push_creates_and_fast_forwards_a_branch() {
+    let scratch = Scratch::new("push-fast-forward");
+    bare_repository(&scratch.join("srv/dst.git"));
+    let work = scratch.join("work");
+    repository(&work, 2);
+    let server = Server::start(&scratch.path, &["serve", "--enable-receive-pack", "srv"]);
+    let url = format!("http://{}/dst.git", server.address);
+
+    run(ours(&work).args(["push", &url, "main"]));
+    assert_eq!(
+        rev_parse(&scratch.join("srv/dst.git"), "main"),
+        rev_parse(&work, "HEAD")
+    );
+
+    commit_file(&work, "more.txt", "more\n");
+    run(ours(&work).args(["push", &url, "main:refs/heads/main"]));
+    assert_eq!(
+        rev_parse(&scratch.join("srv/dst.git"), "main"),
+        rev_parse(&work, "HEAD")
+    );
+    run(git(&scratch.join("srv/dst.git")).args(["fsck", "--strict"]));
+}
+
+#[test]
+fn push_refuses_a_non_fast_forward_unless_forced() {
+    let scratch = Scratch::new("push-non-fast-forward");
+    bare_repository(&scratch.join("srv/dst.git"));
+    let work = scratch.join("work");
+    repository(&work, 2);
+    let server = Server::start(&scratch.path, &["serve", "--enable-receive-pack", "srv"]);
+    let url = format!("http://{}/dst.git", server.address);
+    run(ours(&work).args(["push", &url, "main"]));
+    let pushed = rev_parse(&work, "HEAD");
+
+    // Rewrite the last commit, so that the remote's is no longer an ancestor
+    run(git(&work).args(["commit", "-q", "--amend", "-m", "Rewritten"]));
+    let output = run_failing(ours(&work).args(["push", &url, "main"]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("non-fast-forward"), "stderr:\n{stderr}");
+    assert_eq!(rev_parse(&scratch.join("srv/dst.git"), "main"), pushed);
+
+    run(ours(&work).args(["push", "--force", &url, "main"]));
+    assert_eq!(
+        rev_parse(&scratch.join("srv/dst.git"), "main"),
+        rev_parse(&work, "HEAD")
+    );
+}
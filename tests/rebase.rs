@@ -0,0 +1,29 @@
+//! `rebase` stopping at a conflict.
+
+mod common;
+
+use common::{commit_file, git, ours, repository, run, run_failing, Scratch};
+
+#[test]
+fn a_conflict_is_reported_once() {
+    let scratch = Scratch::new("rebase-conflict");
+    let work = scratch.join("work");
+    repository(&work, 1);
+    run(git(&work).args(["checkout", "-q", "-b", "topic"]));
+    commit_file(&work, "shared.txt", "topic\n");
+    commit_file(&work, "other.txt", "other\n");
+    run(git(&work).args(["checkout", "-q", "main"]));
+    commit_file(&work, "shared.txt", "main\n");
+    run(git(&work).args(["checkout", "-q", "topic"]));
+
+    let output = run_failing(ours(&work).args(["rebase", "main"]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.to_lowercase().matches("could not apply").count(),
+        1,
+        "stderr:\n{stderr}"
+    );
+    // Progress is for terminals only
+    assert!(!stderr.contains("Rebasing ("), "stderr:\n{stderr}");
+    assert!(work.join(".git/rebase-merge").is_dir());
+}
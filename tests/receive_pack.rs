@@ -0,0 +1,149 @@
+//! `receive-pack` taking pushes from stock git over stdin and stdout.
+
+mod common;
+
+use common::{
+    bare_repository, commit_file, git, ours, repository, rev_parse, run, run_failing, Scratch,
+};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Stdio;
+
+fn receive_pack() -> String {
+    format!("--receive-pack={} receive-pack", common::BIN)
+}
+
+/// A bare repository at `dst` with the history of `work`'s `main` on `main` and `other`.
+fn pushed_to(work: &Path, dst: &Path) {
+    bare_repository(dst);
+    let dst = dst.to_str().expect("the scratch path is UTF-8");
+    run(git(work).args(["push", "-q", &receive_pack(), dst, "main", "main:other"]));
+}
+
+#[test]
+fn git_pushes_branches_and_tags() {
+    let scratch = Scratch::new("receive-pack-push");
+    let work = scratch.join("work");
+    repository(&work, 3);
+    run(git(&work).args(["tag", "-a", "-m", "First", "v1", "HEAD~1"]));
+    let dst = scratch.join("dst.git");
+    bare_repository(&dst);
+
+    let dst_arg = dst.to_str().expect("the scratch path is UTF-8");
+    run(git(&work).args(["push", "-q", &receive_pack(), dst_arg, "main", "v1"]));
+    assert_eq!(rev_parse(&dst, "main"), rev_parse(&work, "main"));
+    assert_eq!(rev_parse(&dst, "v1"), rev_parse(&work, "v1"));
+    run(git(&dst).args(["fsck", "--strict"]));
+
+    // Deleting a ref needs no pack, only the command
+    run(git(&work).args(["push", "-q", &receive_pack(), dst_arg, ":v1"]));
+    run_failing(git(&dst).args(["rev-parse", "--verify", "-q", "v1"]));
+}
+
+#[test]
+fn pre_receive_hook_refuses_the_push() {
+    let scratch = Scratch::new("receive-pack-hook");
+    let work = scratch.join("work");
+    repository(&work, 1);
+    let dst = scratch.join("dst.git");
+    pushed_to(&work, &dst);
+    let before = rev_parse(&dst, "main");
+
+    let hook = dst.join("hooks/pre-receive");
+    fs::write(&hook, "#!/bin/sh\necho 'no pushes today' >&2\nexit 1\n").expect("writing the hook");
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).expect("making it runnable");
+    commit_file(&work, "more.txt", "more\n");
+    let dst_arg = dst.to_str().expect("the scratch path is UTF-8");
+    let output = run_failing(git(&work).args(["push", &receive_pack(), dst_arg, "main"]));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("no pushes today"), "stderr:\n{stderr}");
+    assert!(
+        stderr.contains("pre-receive hook declined"),
+        "stderr:\n{stderr}"
+    );
+    assert_eq!(rev_parse(&dst, "main"), before);
+}
+
+#[test]
+fn atomic_push_updates_nothing_when_a_ref_is_locked() {
+    let scratch = Scratch::new("receive-pack-atomic");
+    let work = scratch.join("work");
+    repository(&work, 1);
+    let dst = scratch.join("dst.git");
+    pushed_to(&work, &dst);
+    let before = rev_parse(&dst, "main");
+
+    // Someone else holds `other`
+    fs::write(dst.join("refs/heads/other.lock"), "").expect("writing the lock");
+    commit_file(&work, "more.txt", "more\n");
+    let dst_arg = dst.to_str().expect("the scratch path is UTF-8");
+    let push = [
+        "push",
+        "--atomic",
+        &receive_pack(),
+        dst_arg,
+        "main",
+        "main:other",
+    ];
+    let output = run_failing(git(&work).args(push));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to lock"), "stderr:\n{stderr}");
+    assert!(stderr.contains("atomic push failure"), "stderr:\n{stderr}");
+    assert_eq!(rev_parse(&dst, "main"), before);
+    assert_eq!(rev_parse(&dst, "other"), before);
+    assert!(dst.join("refs/heads/other.lock").exists());
+}
+
+#[test]
+fn plain_push_updates_the_refs_it_can_lock() {
+    let scratch = Scratch::new("receive-pack-partial");
+    let work = scratch.join("work");
+    repository(&work, 1);
+    let dst = scratch.join("dst.git");
+    pushed_to(&work, &dst);
+    let before = rev_parse(&dst, "main");
+
+    fs::write(dst.join("refs/heads/other.lock"), "").expect("writing the lock");
+    commit_file(&work, "more.txt", "more\n");
+    let dst_arg = dst.to_str().expect("the scratch path is UTF-8");
+    run_failing(git(&work).args(["push", &receive_pack(), dst_arg, "main", "main:other"]));
+
+    assert_eq!(rev_parse(&dst, "main"), rev_parse(&work, "main"));
+    assert_eq!(rev_parse(&dst, "other"), before);
+}
+
+#[test]
+fn malformed_values_fail_their_update() {
+    let scratch = Scratch::new("receive-pack-invalid");
+    let dst = scratch.join("dst.git");
+    bare_repository(&dst);
+
+    let zero = "0".repeat(40);
+    let command = format!("{zero} x refs/heads/main\0report-status\n");
+    let request = format!("{:04x}{command}0000", command.len() + 4);
+    let mut child = ours(&dst)
+        .args(["receive-pack", "."])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("starting receive-pack");
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(request.as_bytes())
+        .expect("writing the request");
+    drop(stdin);
+    let output = child.wait_with_output().expect("waiting for receive-pack");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("ng refs/heads/main invalid new value"),
+        "stdout:\n{stdout}\nstderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    run_failing(git(&dst).args(["rev-parse", "--verify", "-q", "main"]));
+}
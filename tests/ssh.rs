@@ -0,0 +1,87 @@
+//! The SSH transport, with `GIT_SSH_COMMAND` set to a script that runs the service here
+//! instead of on another host.
+
+mod common;
+
+use common::{
+    bare_repository, commit_file, ours, repository, rev_parse, run, run_failing, Scratch,
+};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Writes the stand-in for ssh into `scratch`, which logs its arguments to `ssh.log` and
+/// runs the command it is given, `git-<service> '<path>'`, as `<this program> <service>
+/// '<path>'`. Returns the path of the script.
+fn fake_ssh(scratch: &Scratch) -> PathBuf {
+    let script = scratch.join("fake-ssh");
+    let log = scratch.join("ssh.log");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\n\
+             echo \"$@\" >> '{log}'\n\
+             while [ $# -gt 2 ]; do shift; done\n\
+             command=$2\n\
+             exec sh -c \"'{bin}' ${{command#git-}}\"\n",
+            log = log.display(),
+            bin = common::BIN,
+        ),
+    )
+    .expect("writing the fake ssh");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("making it runnable");
+    script
+}
+
+/// This program, run in `dir` with `ssh` standing in for ssh.
+fn ours_over(ssh: &Path, dir: &Path) -> Command {
+    let mut command = ours(dir);
+    command.env("GIT_SSH_COMMAND", ssh);
+    command
+}
+
+#[test]
+fn clone_fetch_and_push_over_ssh() {
+    let scratch = Scratch::new("ssh-round-trip");
+    let ssh = fake_ssh(&scratch);
+    let src = scratch.join("src");
+    repository(&src, 2);
+
+    let url = format!("ssh://git@example.com:2222{}", src.display());
+    run(ours_over(&ssh, &scratch.path).args(["clone", &url, "clone"]));
+    let cloned = scratch.join("clone");
+    assert_eq!(rev_parse(&cloned, "HEAD"), rev_parse(&src, "HEAD"));
+    let log = fs::read_to_string(scratch.join("ssh.log")).expect("reading what ssh was run with");
+    let expected = format!(
+        "-p 2222 git@example.com git-upload-pack '{}'",
+        src.display()
+    );
+    assert_eq!(log.lines().next(), Some(expected.as_str()));
+
+    commit_file(&src, "later.txt", "later\n");
+    run(ours_over(&ssh, &cloned).args(["fetch", "origin"]));
+    assert_eq!(rev_parse(&cloned, "origin/main"), rev_parse(&src, "main"));
+
+    // Pushing to an scp-like address
+    let dst = scratch.join("dst.git");
+    bare_repository(&dst);
+    let url = format!("example.com:{}", dst.display());
+    run(ours_over(&ssh, &cloned).args(["push", &url, "origin/main:refs/heads/main"]));
+    assert_eq!(rev_parse(&dst, "main"), rev_parse(&src, "main"));
+    let log = fs::read_to_string(scratch.join("ssh.log")).expect("reading what ssh was run with");
+    let expected = format!("example.com git-receive-pack '{}'", dst.display());
+    assert_eq!(log.lines().last(), Some(expected.as_str()));
+}
+
+#[test]
+fn hosts_that_look_like_options_are_refused() {
+    let scratch = Scratch::new("ssh-option-host");
+    let ssh = fake_ssh(&scratch);
+
+    let url = "-oProxyCommand=touch:repo";
+    let output = run_failing(ours_over(&ssh, &scratch.path).args(["clone", "--", url, "x"]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("strange hostname"), "stderr:\n{stderr}");
+    assert!(!scratch.join("ssh.log").exists(), "ssh was run");
+}
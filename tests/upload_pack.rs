@@ -0,0 +1,111 @@
+//! `upload-pack --stateless-rpc` answering requests written by hand, as a client that
+//! doesn't follow the protocol could send them.
+
+mod common;
+
+use common::{git, ours, repository, rev_parse, run, Scratch};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Output, Stdio};
+
+fn pkt(line: &str) -> String {
+    format!("{:04x}{line}", line.len() + 4)
+}
+
+/// Sends `request` to `upload-pack --stateless-rpc` in `dir` and returns its output.
+fn upload_pack(dir: &Path, request: &str) -> Output {
+    let mut child = ours(dir)
+        .args(["upload-pack", "--stateless-rpc", "."])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("starting upload-pack");
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(request.as_bytes())
+        .expect("writing the request");
+    drop(stdin);
+    child.wait_with_output().expect("waiting for upload-pack")
+}
+
+#[test]
+fn malformed_haves_are_refused() {
+    let scratch = Scratch::new("upload-pack-have");
+    let repo = scratch.join("repo");
+    repository(&repo, 2);
+    let want = rev_parse(&repo, "HEAD");
+
+    let request = format!(
+        "{}0000{}{}",
+        pkt(&format!("want {want}\n")),
+        pkt("have x\n"),
+        pkt("done\n")
+    );
+    let output = upload_pack(&repo, &request);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("ERR protocol error: expected an object name, got 'x'"),
+        "stdout:\n{stdout}"
+    );
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("panicked"));
+}
+
+#[test]
+fn only_objects_reachable_from_a_ref_are_sent() {
+    let scratch = Scratch::new("upload-pack-reachable");
+    let repo = scratch.join("repo");
+    repository(&repo, 2);
+    let blob = run(git(&repo)
+        .args(["hash-object", "-w", "--stdin"])
+        .stdin(Stdio::null()));
+    let reachable = rev_parse(&repo, "HEAD~1");
+
+    let request = format!(
+        "{}0000{}",
+        pkt(&format!("want {reachable}\n")),
+        pkt("done\n")
+    );
+    let output = upload_pack(&repo, &request);
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stdout.windows(4).any(|w| w == b"PACK"));
+
+    let unreachable = blob.trim();
+    let request = format!(
+        "{}0000{}",
+        pkt(&format!("want {unreachable}\n")),
+        pkt("done\n")
+    );
+    let output = upload_pack(&repo, &request);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains(&format!("ERR upload-pack: not our ref {unreachable}")),
+        "stdout:\n{stdout}"
+    );
+}
+
+#[test]
+fn serving_a_bare_repository_leaves_it_as_it_was() {
+    let scratch = Scratch::new("upload-pack-bare");
+    let work = scratch.join("work");
+    repository(&work, 2);
+    run(git(&scratch.path).args(["clone", "-q", "--bare", "work", "src.git"]));
+    let src = scratch.join("src.git");
+    // Even a directory made and removed again inside it would change when it was modified
+    let modified = || {
+        std::fs::metadata(&src)
+            .and_then(|m| m.modified())
+            .expect("reading when the repository was modified")
+    };
+    let before = modified();
+
+    let upload_pack = format!("--upload-pack={} upload-pack", common::BIN);
+    run(git(&scratch.path).args(["clone", "-q", &upload_pack, "src.git", "clone"]));
+    assert_eq!(
+        rev_parse(&scratch.join("clone"), "HEAD"),
+        rev_parse(&work, "HEAD")
+    );
+    assert_eq!(modified(), before);
+}